@@ -1,19 +1,35 @@
+use vm::analysis::cfg::Cfg;
 use vm::assembler::Assembler;
+use vm::decompile::decompile;
 use vm::error::Error;
 use vm::instructions::call::CallIndex;
 use vm::instructions::Operand;
+use vm::profiler::{CostModel, Profiler};
 use vm::register::Width;
 use vm::Vm;
 
-fn main() -> Result<(), Error> {
-    let mut vm = Vm::new();
+use std::sync::Arc;
+
+/// Iterations of the counting loop when running without a profiler.
+const RUN_ITERATIONS: u64 = 1_000_000_000;
+
+/// Iterations of the counting loop when profiling, the observer makes every step costlier.
+const PROFILE_ITERATIONS: u64 = 1_000_000;
+
+/// Printed when given an unknown command.
+const USAGE: &str =
+    "usage: vm-cli [cfg [path] | decompile [path] | profile [path | iterations] [--folded <path>]]";
 
-    let compiled = Assembler::new()
+/// Builds the counting loop, incrementing rq0 until it reaches the given iteration count.
+fn program(iterations: u64) -> Assembler {
+    Assembler::new()
+        .label("main")
         .mov(Operand::Value(0), Operand::Register(Width::QWord(0))) // mov 0, rq0
         .mov(
-            Operand::Value(1_000_000_000),
+            Operand::Value(iterations),
             Operand::Register(Width::QWord(1)),
         ) // mov 42, rq1
+        .label("loop")
         .add(
             Operand::Value(1),
             Operand::Register(Width::QWord(0)),
@@ -24,8 +40,14 @@ fn main() -> Result<(), Error> {
             Operand::Register(Width::QWord(1)),
         ) // cmp rq0, rq1
         .jnz(Operand::Value(1)) // jnz 2
+        .label("exit")
         .call(Operand::Value(CallIndex::PrintProcessor as u64)) // call 0
-        .compile();
+}
+
+fn run() -> Result<(), Error> {
+    let mut vm = Vm::new();
+
+    let compiled = program(RUN_ITERATIONS).compile();
 
     vm.load_instructions(compiled)?;
 
@@ -36,3 +58,108 @@ fn main() -> Result<(), Error> {
 
     Ok(())
 }
+
+/// Runs the given assembly file, or the counting loop, under a [`Profiler`], printing the report
+/// and optionally writing folded stacks.
+///
+/// Usage: `vm-cli profile [path | iterations] [--folded <path>]`
+fn profile(mut args: impl Iterator<Item = String>) -> Result<(), Error> {
+    let mut path = None;
+    let mut iterations = PROFILE_ITERATIONS;
+    let mut folded_path = None;
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--folded" => folded_path = args.next(),
+            _ => match arg.parse() {
+                Ok(value) => iterations = value,
+                Err(_) => path = Some(arg),
+            },
+        }
+    }
+
+    let assembler = source(path, iterations)?;
+    let instructions = assembler.instructions().to_vec();
+    let labels = assembler.labels().clone();
+
+    let mut vm = Vm::new();
+    let profiler = Arc::new(Profiler::new());
+
+    vm.register_observer(profiler.clone());
+    vm.load_instructions(assembler.compile())?;
+
+    let handle = vm.new_processor();
+    vm.processor_mut(handle)?.start()?;
+
+    let report = profiler.report(&instructions, &labels, &CostModel::default());
+
+    print!("{report}");
+
+    if let Some(path) = folded_path {
+        if let Err(error) = std::fs::write(&path, report.folded()) {
+            eprintln!("failed to write {path}: {error}");
+            std::process::exit(1);
+        }
+    }
+
+    Ok(())
+}
+
+/// Parses the given assembly file, or builds the counting loop with the given iterations without one.
+fn source(path: Option<String>, iterations: u64) -> Result<Assembler, Error> {
+    match path {
+        Some(path) => match std::fs::read_to_string(&path) {
+            Ok(source) => Assembler::parse(&source),
+            Err(error) => {
+                eprintln!("failed to read {path}: {error}");
+                std::process::exit(1);
+            }
+        },
+        None => Ok(program(iterations)),
+    }
+}
+
+/// Prints the control-flow graph of the given assembly file, or of the counting loop, as Graphviz DOT.
+///
+/// Usage: `vm-cli cfg [path]`
+fn cfg(mut args: impl Iterator<Item = String>) -> Result<(), Error> {
+    let assembler = source(args.next(), RUN_ITERATIONS)?;
+
+    print!(
+        "{}",
+        Cfg::new(assembler.instructions()).dot(assembler.labels())
+    );
+
+    Ok(())
+}
+
+/// Prints the given assembly file, or the counting loop, decompiled into C-like pseudocode.
+///
+/// Usage: `vm-cli decompile [path]`
+fn decompile_program(mut args: impl Iterator<Item = String>) -> Result<(), Error> {
+    let assembler = source(args.next(), RUN_ITERATIONS)?;
+
+    print!(
+        "{}",
+        decompile(assembler.instructions(), assembler.labels())
+    );
+
+    Ok(())
+}
+
+fn main() -> Result<(), Error> {
+    let mut args = std::env::args().skip(1);
+
+    match args.next().as_deref() {
+        Some("cfg") => cfg(args),
+        Some("decompile") => decompile_program(args),
+        Some("profile") => profile(args),
+        Some(command) => {
+            eprintln!("unknown command: {command}");
+            eprintln!("{USAGE}");
+            std::process::exit(2);
+        }
+
+        None => run(),
+    }
+}
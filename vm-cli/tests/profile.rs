@@ -0,0 +1,57 @@
+//! Profiles an assembly file through `vm-cli profile` and checks the reported label totals.
+
+use std::process::Command;
+
+/// Counts rq0 to 10 in `loop`, between single instructions in `main` and `exit`.
+const PROGRAM: &str = "main: mov 0, rq0\n\
+                       loop: add 1, rq0, rq0\n\
+                       cmp rq0, 10\n\
+                       jnz loop\n\
+                       exit: mov rq0, rq1\n";
+
+/// Returns the hits and cycles of the given label in a printed report.
+fn label(report: &str, name: &str) -> Option<(u64, u64)> {
+    report.lines().find_map(|line| {
+        let mut columns = line.split_whitespace();
+
+        if columns.next()? != name {
+            return None;
+        }
+
+        Some((columns.next()?.parse().ok()?, columns.next()?.parse().ok()?))
+    })
+}
+
+#[test]
+pub fn profile_assembly_file() {
+    let path = std::env::temp_dir().join(format!("vm-cli-profile-{}.asm", std::process::id()));
+
+    std::fs::write(&path, PROGRAM).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_vm-cli"))
+        .arg("profile")
+        .arg(&path)
+        .output()
+        .unwrap();
+
+    std::fs::remove_file(&path).unwrap();
+
+    assert!(output.status.success());
+
+    let report = String::from_utf8(output.stdout).unwrap();
+
+    assert!(report.starts_with("32 steps, 32 cycles\n"));
+    assert_eq!(label(&report, "main"), Some((1, 1)));
+    assert_eq!(label(&report, "loop"), Some((30, 30)));
+    assert_eq!(label(&report, "exit"), Some((1, 1)));
+}
+
+#[test]
+pub fn profile_unknown_command() {
+    let output = Command::new(env!("CARGO_BIN_EXE_vm-cli"))
+        .arg("profil")
+        .output()
+        .unwrap();
+
+    assert_eq!(output.status.code(), Some(2));
+}
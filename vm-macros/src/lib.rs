@@ -0,0 +1,319 @@
+//! Procedural macros assembling programs for the [`vm`] crate at compile time.
+
+use proc_macro::{Delimiter, Group, Ident, Literal, Punct, Spacing, Span, TokenStream, TokenTree};
+use vm::assembler::Assembler;
+use vm::error::Error;
+use vm::instructions::{Instruction, Operand};
+use vm::register::Width;
+
+/// Assembles the given source at compile time into a `Vec<Instruction>`.
+///
+/// Takes the syntax of [`Assembler::parse`], statements being separated by newlines or `;`. A
+/// malformed statement or an undeclared label fails compilation at its line. Labels resolve to
+/// indices within the returned instructions, so labels can't be imported or exported.
+///
+/// Newlines are recovered from the lines of the input tokens. Called from a `macro_rules!` that
+/// substitutes fragments into the input, or with every token on one line, the whole input is a
+/// single line, so statements there must be separated by `;`.
+///
+/// # Example
+/// ```
+/// use vm_macros::wednesday_asm;
+/// let instructions = wednesday_asm! {
+///     mov 0, rq0
+///     loop: add 1, rq0, rq0; cmp rq0, 10
+///     jnz loop
+/// };
+/// assert_eq!(instructions.len(), 4);
+/// ```
+///
+/// A malformed statement fails compilation with `malformed statement` at its line:
+/// ```compile_fail
+/// use vm_macros::wednesday_asm;
+/// let instructions = wednesday_asm! {
+///     mov 0, rq0
+///
+///     mov 0
+/// };
+/// ```
+///
+/// So does a jump to an undeclared label, with `undeclared label`:
+/// ```compile_fail
+/// use vm_macros::wednesday_asm;
+/// let instructions = wednesday_asm! {
+///     mov 0, rq0
+///     jmp nowhere
+/// };
+/// ```
+///
+/// On a single line, the error is reported at that line whichever statement is at fault:
+/// ```compile_fail
+/// use vm_macros::wednesday_asm;
+/// let instructions = wednesday_asm!(start: mov 0, rq0; jmp start; jz end);
+/// ```
+#[proc_macro]
+pub fn wednesday_asm(input: TokenStream) -> TokenStream {
+    let source = Source::new(input);
+
+    match assemble(&source.text) {
+        Ok(code) => code.parse().expect("generated code is valid Rust"),
+        Err((line, message)) => {
+            let span = line
+                .and_then(|line| source.lines.get(line - 1))
+                .copied()
+                .unwrap_or_else(Span::call_site);
+
+            error(span, &message)
+        }
+    }
+}
+
+/// Assembly source rebuilt from the tokens of a macro input.
+#[derive(Default)]
+struct Source {
+    text: String,
+    /// Span of the first token of every line of the text.
+    lines: Vec<Span>,
+}
+
+impl Source {
+    /// Rebuilds the source of the given macro input.
+    ///
+    /// Comments are gone by now, but newlines must be kept since they separate statements, so
+    /// tokens are laid out by the lines and columns of their spans. A `macro_rules!` substituting
+    /// fragments into the input mixes tokens of its definition with tokens of its call site, whose
+    /// lines say nothing about the statements. Then the whole input is a single line.
+    fn new(input: TokenStream) -> Self {
+        let mut tokens = Vec::new();
+
+        collect(input, &mut tokens);
+
+        let ordered = tokens
+            .windows(2)
+            .all(|pair| pair[0].0.end().line() <= pair[1].0.line());
+        let mut source = Source::default();
+        let mut end: Option<(usize, usize)> = None;
+
+        for (span, text) in tokens {
+            let (line, column) = (span.line(), span.column());
+
+            match end {
+                Some((end, _)) if ordered && line > end => {
+                    for _ in end..line {
+                        source.text.push('\n');
+                        source.lines.push(span);
+                    }
+                }
+                Some((end, end_column)) if line != end || column != end_column => {
+                    source.text.push(' ');
+                }
+                Some(_) => {}
+                None => source.lines.push(span),
+            }
+
+            source.text.push_str(&text);
+            end = Some((span.end().line(), span.end().column()));
+        }
+
+        source
+    }
+}
+
+/// Appends the span and text of every token of the given stream, delimiters of groups included.
+fn collect(stream: TokenStream, tokens: &mut Vec<(Span, String)>) {
+    for token in stream {
+        match token {
+            TokenTree::Group(group) => {
+                let (open, close) = match group.delimiter() {
+                    Delimiter::Parenthesis => ("(", ")"),
+                    Delimiter::Brace => ("{", "}"),
+                    Delimiter::Bracket => ("[", "]"),
+                    Delimiter::None => ("", ""),
+                };
+
+                tokens.push((group.span_open(), open.to_string()));
+                collect(group.stream(), tokens);
+                tokens.push((group.span_close(), close.to_string()));
+            }
+
+            token => tokens.push((token.span(), token.to_string())),
+        }
+    }
+}
+
+/// Assembles the given source into a `Vec<Instruction>` expression.
+///
+/// On failure returns the 1-based line at fault, when there is one, and a message.
+fn assemble(source: &str) -> Result<String, (Option<usize>, String)> {
+    let assembler = Assembler::parse(source).map_err(|error| match error {
+        Error::Syntax(line) if undeclared(source, line) => {
+            (Some(line), "undeclared label".to_string())
+        }
+        Error::Syntax(line) => (Some(line), "malformed statement".to_string()),
+
+        error => (None, format!("{error:?}")),
+    })?;
+    let lines = assembler.lines().to_vec();
+    let module = assembler.module();
+
+    if let Some(import) = module.imports().first() {
+        return Err((
+            Some(lines[import.index]),
+            format!("`{}::{}` can't be imported", import.module, import.symbol),
+        ));
+    }
+
+    if !module.exports().is_empty() {
+        return Err((None, "labels can't be exported".to_string()));
+    }
+
+    let instructions = module
+        .instructions()
+        .iter()
+        .map(instruction)
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    Ok(format!(
+        "::std::vec::Vec::<::vm::instructions::Instruction>::from([{instructions}])"
+    ))
+}
+
+/// Returns whether the given source only fails to parse at the given line for referencing labels
+/// it doesn't declare.
+///
+/// [`Assembler::parse`] reports both as a [`Syntax`](Error::Syntax) error, so the words of the line
+/// are declared as labels and the source parsed again.
+fn undeclared(source: &str, line: usize) -> bool {
+    let Some(text) = source.lines().nth(line - 1) else {
+        return false;
+    };
+    let declarations: String = text
+        .split(|character: char| {
+            !(character.is_ascii_alphanumeric() || character == '_' || character == '.')
+        })
+        .filter(|word| Assembler::parse(&format!("{word}:")).is_ok())
+        .map(|word| format!("\n{word}:"))
+        .collect();
+
+    Assembler::parse(&format!("{source}{declarations}")).is_ok()
+}
+
+fn instruction(instruction: &Instruction) -> String {
+    let (name, operands) = match instruction {
+        Instruction::Call(index) => ("Call", vec![index]),
+        Instruction::Mov(source, destination) => ("Mov", vec![source, destination]),
+        Instruction::Jmp(source) => ("Jmp", vec![source]),
+        Instruction::Jz(source) => ("Jz", vec![source]),
+        Instruction::Jnz(source) => ("Jnz", vec![source]),
+        Instruction::Cmp(value, comparator) => ("Cmp", vec![value, comparator]),
+        Instruction::Add(value, source, destination) => ("Add", vec![value, source, destination]),
+    };
+    let operands = operands
+        .into_iter()
+        .map(operand)
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    format!("::vm::instructions::Instruction::{name}({operands})")
+}
+
+fn operand(operand: &Operand) -> String {
+    match operand {
+        Operand::None => "::vm::instructions::Operand::None".to_string(),
+        Operand::Value(value) => format!("::vm::instructions::Operand::Value({value}u64)"),
+        Operand::Register(register) => {
+            format!("::vm::instructions::Operand::Register({})", width(register))
+        }
+        Operand::Memory(memory) => {
+            format!("::vm::instructions::Operand::Memory({})", width(memory))
+        }
+        Operand::MemoryRegister(memory_register) => format!(
+            "::vm::instructions::Operand::MemoryRegister({})",
+            width(memory_register)
+        ),
+    }
+}
+
+fn width(width: &Width) -> String {
+    let (name, index) = match width {
+        Width::Byte(index) => ("Byte", index),
+        Width::Word(index) => ("Word", index),
+        Width::DWord(index) => ("DWord", index),
+        Width::QWord(index) => ("QWord", index),
+    };
+
+    format!("::vm::register::Width::{name}({index}usize)")
+}
+
+/// Returns a `compile_error!` invocation reporting the given message at the given span.
+fn error(span: Span, message: &str) -> TokenStream {
+    let mut literal = Literal::string(message);
+    let mut punct = Punct::new('!', Spacing::Alone);
+
+    literal.set_span(span);
+    punct.set_span(span);
+
+    let mut group = Group::new(Delimiter::Parenthesis, TokenTree::from(literal).into());
+
+    group.set_span(span);
+
+    [
+        TokenTree::from(Ident::new("compile_error", span)),
+        punct.into(),
+        group.into(),
+    ]
+    .into_iter()
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn macros_assemble() {
+        assert_eq!(
+            assemble("start: mov [rq3], mb16\njnz start").unwrap(),
+            "::std::vec::Vec::<::vm::instructions::Instruction>::from([\
+             ::vm::instructions::Instruction::Mov(\
+             ::vm::instructions::Operand::MemoryRegister(::vm::register::Width::QWord(3usize)), \
+             ::vm::instructions::Operand::Memory(::vm::register::Width::Byte(16usize))), \
+             ::vm::instructions::Instruction::Jnz(\
+             ::vm::instructions::Operand::Value(18446744073709551615u64))])"
+        );
+        assert_eq!(
+            assemble("").unwrap(),
+            "::std::vec::Vec::<::vm::instructions::Instruction>::from([])"
+        );
+    }
+
+    #[test]
+    pub fn macros_errors() {
+        assert_eq!(
+            assemble("mov 0, rq0\njmp nowhere").unwrap_err(),
+            (Some(2), "undeclared label".to_string())
+        );
+        assert_eq!(
+            assemble("start: mov 0, rq0\njmp start; jz end").unwrap_err(),
+            (Some(2), "undeclared label".to_string())
+        );
+        assert_eq!(
+            assemble("mov 0, rq0\n\nmov 0").unwrap_err(),
+            (Some(3), "malformed statement".to_string())
+        );
+        assert_eq!(
+            assemble("jmp nowhere\nmov rq0, [rq16]").unwrap_err(),
+            (Some(2), "malformed statement".to_string())
+        );
+        assert_eq!(assemble("mov 0, rq16").unwrap_err().0, Some(1));
+        assert_eq!(
+            assemble("mov 0, rq0\n\ncall lib::print").unwrap_err(),
+            (Some(3), "`lib::print` can't be imported".to_string())
+        );
+        assert_eq!(
+            assemble(".export main\nmain: mov 0, rq0").unwrap_err().0,
+            None
+        );
+    }
+}
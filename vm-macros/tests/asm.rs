@@ -0,0 +1,86 @@
+//! Checks programs assembled by [`wednesday_asm`] against [`Assembler::parse`] and runs them.
+
+use vm::assembler::Assembler;
+use vm::instructions::Instruction;
+use vm::Vm;
+use vm_macros::wednesday_asm;
+
+/// Counts the given register down from the given value to 0, statements separated by `;` since
+/// the substituted fragments come from another line.
+macro_rules! countdown {
+    ($start:literal, $register:ident) => {
+        wednesday_asm! {
+            mov $start, $register;
+            again: add -1, $register, $register;
+            cmp $register, 0;
+            jnz again
+        }
+    };
+}
+
+#[test]
+pub fn macros_parse() {
+    let instructions = wednesday_asm! {
+        // Sums 1 to 10 into memory.
+        mov 0, rq0; mov 0x100, rq1
+        loop: add 1, rq0, rq0
+        add rq0, [rq1], rq2
+        mov rq2, [rq1]
+        cmp rq0, 10; jnz loop
+        add -1, rq0, rd3
+        mov mq0x100, rq4
+    };
+
+    assert_eq!(
+        instructions,
+        Assembler::parse(
+            "mov 0, rq0; mov 0x100, rq1\n\
+             loop: add 1, rq0, rq0\n\
+             add rq0, [rq1], rq2\n\
+             mov rq2, [rq1]\n\
+             cmp rq0, 10; jnz loop\n\
+             add -1, rq0, rd3\n\
+             mov mq0x100, rq4"
+        )
+        .unwrap()
+        .instructions()
+    );
+    assert_eq!(wednesday_asm! {}, Vec::<Instruction>::new());
+}
+
+#[test]
+pub fn macros_run() {
+    let mut vm = Vm::new();
+
+    vm.load_instructions(
+        wednesday_asm! {
+            mov 5, rq0; mov 1, rq1
+            // Multiplies rq1 by 3 five times.
+            loop: add rq1, rq1, rq2
+            add rq2, rq1, rq1
+            add -1, rq0, rq0
+            cmp rq0, 0
+            jnz loop
+        }
+        .into_iter()
+        .map(|instruction| instruction.executable())
+        .collect(),
+    )
+    .unwrap();
+
+    let handle = vm.new_processor();
+    let processor = vm.processor_mut(handle).unwrap();
+
+    processor.start().unwrap();
+    assert_eq!(processor.register(1).unwrap().as_u64(), 243);
+}
+
+#[test]
+pub fn macros_from_macro_rules() {
+    assert_eq!(
+        countdown!(5, rq2),
+        Assembler::parse("mov 5, rq2\nagain: add -1, rq2, rq2\ncmp rq2, 0\njnz again")
+            .unwrap()
+            .instructions()
+    );
+}
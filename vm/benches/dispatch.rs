@@ -0,0 +1,136 @@
+//! Compares dispatch through `Box<dyn Execute>` with the decoded program run by `Processor::start`,
+//! with and without fusion, on the counting loop run by `vm-cli`. With the `jit` feature the loop is
+//! also compiled to machine code.
+//!
+//! Usage: `cargo bench --bench dispatch [--features jit] -- [iterations]`
+
+use vm::assembler::Assembler;
+use vm::register::ReservedIndex;
+use vm::Vm;
+
+use std::time::{Duration, Instant};
+
+/// Iterations of the counting loop when none are given; `vm-cli` runs a billion.
+const DEFAULT_ITERATIONS: u64 = 10_000_000;
+
+/// Builds the counting loop of `vm-cli`, without the final call.
+fn program(iterations: u64) -> Assembler {
+    Assembler::parse(&format!(
+        "mov 0, rq0\n\
+         mov {iterations}, rq1\n\
+         loop: add 1, rq0, rq0\n\
+         cmp rq0, rq1\n\
+         jnz loop"
+    ))
+    .unwrap()
+}
+
+/// Runs the loop calling every executable through its virtual call, as every step did before decoding.
+fn boxed(iterations: u64) -> Duration {
+    let executables = program(iterations).compile();
+
+    let mut vm = Vm::new();
+    let handle = vm.new_processor();
+    let processor = vm.processor_mut(handle).unwrap();
+    let counter = ReservedIndex::InstructionCounter as usize;
+
+    let start = Instant::now();
+
+    while let Some(executable) =
+        executables.get(processor.register(counter).unwrap().as_u64() as usize)
+    {
+        executable.execute(processor).unwrap();
+
+        let register = processor.register_mut(counter).unwrap();
+        register.assign_u64(register.as_u64().wrapping_add(1));
+    }
+
+    let elapsed = start.elapsed();
+
+    assert_eq!(processor.register(0).unwrap().as_u64(), iterations);
+
+    elapsed
+}
+
+/// Runs the loop through [`Processor::start`](vm::processor::Processor::start), fusing its tail if asked.
+fn decoded(iterations: u64, fusion: bool) -> Duration {
+    let mut vm = Vm::new();
+
+    vm.set_fusion(fusion);
+
+    vm.load_instructions(program(iterations).compile()).unwrap();
+
+    let handle = vm.new_processor();
+    let processor = vm.processor_mut(handle).unwrap();
+
+    let start = Instant::now();
+
+    processor.start().unwrap();
+
+    let elapsed = start.elapsed();
+
+    assert_eq!(processor.register(0).unwrap().as_u64(), iterations);
+
+    elapsed
+}
+
+/// Runs the loop through [`Processor::start`](vm::processor::Processor::start) with the JIT enabled.
+#[cfg(feature = "jit")]
+fn jit(iterations: u64) -> Duration {
+    let mut vm = Vm::new();
+
+    vm.set_jit(true);
+
+    vm.load_instructions(program(iterations).compile()).unwrap();
+
+    let handle = vm.new_processor();
+    let processor = vm.processor_mut(handle).unwrap();
+
+    let start = Instant::now();
+
+    processor.start().unwrap();
+
+    let elapsed = start.elapsed();
+
+    assert_eq!(processor.register(0).unwrap().as_u64(), iterations);
+
+    elapsed
+}
+
+fn main() {
+    // `cargo bench` passes its own flags, e.g. `--bench`, before any given after `--`.
+    let iterations = std::env::args()
+        .skip(1)
+        .find_map(|arg| arg.parse().ok())
+        .unwrap_or(DEFAULT_ITERATIONS);
+
+    let boxed = boxed(iterations);
+    let fused = decoded(iterations, true);
+    let decoded = decoded(iterations, false);
+
+    let rate = |elapsed: Duration| (iterations * 3) as f64 / elapsed.as_secs_f64() / 1e6;
+
+    println!("iterations: {iterations}");
+    println!("boxed:      {boxed:>12.3?} {:>8.1} Minstr/s", rate(boxed));
+    println!(
+        "decoded:    {decoded:>12.3?} {:>8.1} Minstr/s",
+        rate(decoded)
+    );
+    println!("fused:      {fused:>12.3?} {:>8.1} Minstr/s", rate(fused));
+    println!(
+        "speedup:    {:>11.2}x decoded, {:.2}x fused",
+        boxed.as_secs_f64() / decoded.as_secs_f64(),
+        boxed.as_secs_f64() / fused.as_secs_f64()
+    );
+
+    #[cfg(feature = "jit")]
+    {
+        let jit = jit(iterations);
+
+        println!("jit:        {jit:>12.3?} {:>8.1} Minstr/s", rate(jit));
+        println!(
+            "speedup:    {:>11.2}x jit",
+            boxed.as_secs_f64() / jit.as_secs_f64()
+        );
+    }
+}
@@ -0,0 +1,388 @@
+//! Basic blocks and the control-flow graph between them.
+//!
+//! A block starts at the first instruction, at the instruction a constant jump continues at, and
+//! after every jump. Jumps through registers or memory, and writes to the instruction counter,
+//! have an [`Unknown`](Target::Unknown) target. Calls are assumed to return to the next instruction.
+
+use crate::assembler::label_names;
+use crate::instructions::{Instruction, Operand};
+use crate::register::{Width, COUNTER};
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// How control reaches the target of an [`Edge`].
+pub enum EdgeKind {
+    /// Continuing with the next instruction, including a conditional jump not taken.
+    Fallthrough,
+    /// Taking a jump.
+    Branch,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Where an [`Edge`] leads.
+pub enum Target {
+    /// The block at the given index in [`Cfg::blocks`].
+    Block(usize),
+    /// Outside of the program, which stops the processor.
+    Exit,
+    /// Anywhere, as computed at runtime.
+    Unknown,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// A possible transfer of control from the end of a [`Block`].
+pub struct Edge {
+    /// Whether the edge takes a jump.
+    pub kind: EdgeKind,
+    /// Where the edge leads.
+    pub target: Target,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// A run of instructions entered only at its first and left only after its last.
+pub struct Block {
+    /// Index of the first instruction.
+    pub start: usize,
+    /// Index after the last instruction.
+    pub end: usize,
+    /// Transfers of control after the last instruction, branches first.
+    pub successors: Vec<Edge>,
+    /// Indices of the blocks with an edge to this one.
+    pub predecessors: Vec<usize>,
+}
+
+#[derive(Debug, Clone, Default)]
+/// Control-flow graph of a program.
+pub struct Cfg {
+    instructions: Vec<Instruction>,
+    blocks: Vec<Block>,
+
+    /// Index of the block holding each instruction.
+    block_of: Vec<usize>,
+}
+
+/// Returns the index a jump to the given value continues at, the counter moving past it first.
+fn continues_at(value: u64, length: usize) -> Target {
+    match usize::try_from(value.wrapping_add(1)) {
+        Ok(index) if index < length => Target::Block(index),
+        _ => Target::Exit,
+    }
+}
+
+/// Returns whether the given operand is the instruction counter register.
+pub(crate) fn is_counter(operand: &Operand) -> bool {
+    matches!(
+        operand,
+        Operand::Register(
+            Width::Byte(COUNTER)
+                | Width::Word(COUNTER)
+                | Width::DWord(COUNTER)
+                | Width::QWord(COUNTER)
+        )
+    )
+}
+
+/// Returns the edges leaving the given instruction, with targets as instruction indices, or [`None`]
+/// when it always continues with the next one.
+fn transfers(instruction: &Instruction, index: usize, length: usize) -> Option<Vec<Edge>> {
+    let branch = |source: &Operand| Edge {
+        kind: EdgeKind::Branch,
+        target: match source {
+            Operand::Value(value) => continues_at(*value, length),
+            _ => Target::Unknown,
+        },
+    };
+    let fallthrough = Edge {
+        kind: EdgeKind::Fallthrough,
+        target: continues_at(index as u64, length),
+    };
+
+    match instruction {
+        Instruction::Jmp(source) => Some(vec![branch(source)]),
+        Instruction::Jz(source) | Instruction::Jnz(source) => {
+            Some(vec![branch(source), fallthrough])
+        }
+        Instruction::Mov(_, destination) | Instruction::Add(_, _, destination)
+            if is_counter(destination) =>
+        {
+            Some(vec![Edge {
+                kind: EdgeKind::Branch,
+                target: Target::Unknown,
+            }])
+        }
+
+        _ => None,
+    }
+}
+
+impl Cfg {
+    #[must_use]
+    /// Splits the given program into basic blocks and connects them.
+    ///
+    /// # Example
+    /// ```
+    /// use vm::analysis::cfg::{Cfg, Target};
+    /// use vm::assembler::Assembler;
+    /// let assembler = Assembler::parse("mov 0, rq0\nloop: add 1, rq0, rq0\ncmp rq0, 10\njnz loop").unwrap();
+    /// let cfg = Cfg::new(assembler.instructions());
+    /// assert_eq!(cfg.blocks().len(), 2);
+    /// assert_eq!(cfg.blocks()[1].successors[0].target, Target::Block(1));
+    /// ```
+    pub fn new(instructions: &[Instruction]) -> Self {
+        let length = instructions.len();
+        let edges: Vec<Option<Vec<Edge>>> = instructions
+            .iter()
+            .enumerate()
+            .map(|(index, instruction)| transfers(instruction, index, length))
+            .collect();
+
+        let mut leaders = BTreeSet::new();
+
+        if length > 0 {
+            leaders.insert(0);
+        }
+
+        for (index, edges) in edges.iter().enumerate() {
+            let Some(edges) = edges else { continue };
+
+            if index + 1 < length {
+                leaders.insert(index + 1);
+            }
+
+            for edge in edges {
+                if let Target::Block(target) = edge.target {
+                    leaders.insert(target);
+                }
+            }
+        }
+
+        let starts: Vec<usize> = leaders.into_iter().collect();
+        let numbers: BTreeMap<usize, usize> = starts
+            .iter()
+            .enumerate()
+            .map(|(number, start)| (*start, number))
+            .collect();
+
+        let mut blocks: Vec<Block> = starts
+            .iter()
+            .enumerate()
+            .map(|(number, start)| {
+                let end = starts.get(number + 1).copied().unwrap_or(length);
+                let last = end - 1;
+
+                let successors = match &edges[last] {
+                    Some(edges) => edges.clone(),
+                    None => vec![Edge {
+                        kind: EdgeKind::Fallthrough,
+                        target: continues_at(last as u64, length),
+                    }],
+                };
+
+                Block {
+                    start: *start,
+                    end,
+                    successors: successors
+                        .into_iter()
+                        .map(|edge| Edge {
+                            kind: edge.kind,
+                            target: match edge.target {
+                                Target::Block(index) => Target::Block(numbers[&index]),
+                                target => target,
+                            },
+                        })
+                        .collect(),
+                    predecessors: Vec::new(),
+                }
+            })
+            .collect();
+
+        for number in 0..blocks.len() {
+            for edge in blocks[number].successors.clone() {
+                if let Target::Block(target) = edge.target {
+                    if !blocks[target].predecessors.contains(&number) {
+                        blocks[target].predecessors.push(number);
+                    }
+                }
+            }
+        }
+
+        let mut block_of = Vec::with_capacity(length);
+
+        for (number, block) in blocks.iter().enumerate() {
+            block_of.extend(std::iter::repeat_n(number, block.end - block.start));
+        }
+
+        Cfg {
+            instructions: instructions.to_vec(),
+            blocks,
+            block_of,
+        }
+    }
+
+    #[must_use]
+    /// Returns the instructions self was built from.
+    pub fn instructions(&self) -> &[Instruction] {
+        &self.instructions
+    }
+
+    #[must_use]
+    /// Returns the blocks of self, in program order.
+    pub fn blocks(&self) -> &[Block] {
+        &self.blocks
+    }
+
+    #[must_use]
+    /// Returns the index of the block holding the instruction at the given index.
+    pub fn block_of(&self, index: usize) -> Option<usize> {
+        self.block_of.get(index).copied()
+    }
+
+    #[must_use]
+    /// Returns whether any block has an [`Unknown`](Target::Unknown) successor.
+    pub fn has_unknown(&self) -> bool {
+        self.blocks.iter().any(|block| {
+            block
+                .successors
+                .iter()
+                .any(|edge| edge.target == Target::Unknown)
+        })
+    }
+
+    #[must_use]
+    /// Formats self as a Graphviz DOT digraph, naming blocks after the labels pointing at them.
+    pub fn dot(&self, labels: &BTreeMap<String, usize>) -> String {
+        let names = label_names(labels);
+
+        let mut dot = String::from("digraph cfg {\n    node [shape=box, fontname=monospace];\n");
+        let mut exit = false;
+        let mut unknown = false;
+
+        for (number, block) in self.blocks.iter().enumerate() {
+            let mut label = match names.get(&block.start) {
+                Some(name) => format!("{name}:\\l"),
+                None => String::new(),
+            };
+
+            for index in block.start..block.end {
+                label += &format!("{index}: {:?}\\l", self.instructions[index]);
+            }
+
+            _ = writeln!(
+                dot,
+                "    b{number} [label=\"{}\"];",
+                label.replace('"', "\\\"")
+            );
+        }
+
+        for (number, block) in self.blocks.iter().enumerate() {
+            for edge in &block.successors {
+                let target = match edge.target {
+                    Target::Block(target) => format!("b{target}"),
+                    Target::Exit => {
+                        exit = true;
+
+                        "exit".to_string()
+                    }
+                    Target::Unknown => {
+                        unknown = true;
+
+                        "unknown".to_string()
+                    }
+                };
+                let style = match edge.kind {
+                    EdgeKind::Branch => " [label=\"branch\"]",
+                    EdgeKind::Fallthrough => "",
+                };
+
+                _ = writeln!(dot, "    b{number} -> {target}{style};");
+            }
+        }
+
+        if exit {
+            dot += "    exit [shape=doublecircle];\n";
+        }
+
+        if unknown {
+            dot += "    unknown [shape=diamond, label=\"?\"];\n";
+        }
+
+        dot += "}\n";
+
+        dot
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::analysis::cfg::{Cfg, Edge, EdgeKind, Target};
+    use crate::assembler::Assembler;
+
+    #[test]
+    pub fn cfg_blocks_and_edges() {
+        let assembler = Assembler::parse(
+            "mov 0, rq0\n\
+             loop: add 1, rq0, rq0\n\
+             cmp rq0, 10\n\
+             jz done\n\
+             jmp loop\n\
+             done: jmp rq1\n\
+             mov rq2, rq15",
+        )
+        .unwrap();
+        let cfg = Cfg::new(assembler.instructions());
+
+        let edge = |kind, target| Edge { kind, target };
+        let ranges: Vec<(usize, usize)> = cfg
+            .blocks()
+            .iter()
+            .map(|block| (block.start, block.end))
+            .collect();
+
+        assert_eq!(ranges, [(0, 1), (1, 4), (4, 5), (5, 6), (6, 7)]);
+        assert_eq!(
+            cfg.blocks()[0].successors,
+            [edge(EdgeKind::Fallthrough, Target::Block(1))]
+        );
+        assert_eq!(
+            cfg.blocks()[1].successors,
+            [
+                edge(EdgeKind::Branch, Target::Block(3)),
+                edge(EdgeKind::Fallthrough, Target::Block(2))
+            ]
+        );
+        assert_eq!(
+            cfg.blocks()[2].successors,
+            [edge(EdgeKind::Branch, Target::Block(1))]
+        );
+        assert_eq!(
+            cfg.blocks()[3].successors,
+            [edge(EdgeKind::Branch, Target::Unknown)]
+        );
+        assert_eq!(
+            cfg.blocks()[4].successors,
+            [edge(EdgeKind::Branch, Target::Unknown)]
+        );
+        assert_eq!(cfg.blocks()[1].predecessors, [0, 2]);
+        assert_eq!(cfg.block_of(3), Some(1));
+        assert!(cfg.has_unknown());
+    }
+
+    #[test]
+    pub fn cfg_dot() {
+        let assembler = Assembler::parse("start: mov 1, rq0\njnz start").unwrap();
+        let cfg = Cfg::new(assembler.instructions());
+
+        assert_eq!(
+            cfg.dot(assembler.labels()),
+            "digraph cfg {\n    \
+             node [shape=box, fontname=monospace];\n    \
+             b0 [label=\"start:\\l0: Mov(Value(1), Register(QWord(0)))\\l1: Jnz(Value(18446744073709551615))\\l\"];\n    \
+             b0 -> b0 [label=\"branch\"];\n    \
+             b0 -> exit;\n    \
+             exit [shape=doublecircle];\n\
+             }\n"
+        );
+    }
+}
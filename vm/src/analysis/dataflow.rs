@@ -0,0 +1,568 @@
+//! Generic dataflow analysis over a [`Cfg`], with register liveness and reaching definitions.
+//!
+//! Facts are computed per instruction from the [`Effects`] of each one on the 16 registers, flags
+//! and instruction counter included. An [`Unknown`](Target::Unknown) edge may lead to any block or
+//! leave the program.
+
+use crate::analysis::cfg::{Cfg, Target};
+use crate::instructions::{Instruction, Operand};
+use crate::register::{Width, COUNTER, FLAGS, REGISTERS};
+
+use std::collections::{BTreeSet, VecDeque};
+use std::fmt::{self, Debug, Formatter};
+
+#[derive(Clone, Copy, Default, PartialEq, Eq, Hash)]
+/// Set of register indices, flags and instruction counter included.
+pub struct RegisterSet(u16);
+
+impl RegisterSet {
+    /// No register.
+    pub const EMPTY: Self = RegisterSet(0);
+    /// Every register.
+    pub const ALL: Self = RegisterSet(u16::MAX);
+
+    /// Adds the register at the given index, ignoring indices past the last register.
+    pub fn insert(&mut self, index: usize) {
+        if index < REGISTERS {
+            self.0 |= 1 << index;
+        }
+    }
+
+    /// Removes the register at the given index.
+    pub fn remove(&mut self, index: usize) {
+        if index < REGISTERS {
+            self.0 &= !(1 << index);
+        }
+    }
+
+    #[must_use]
+    /// Returns whether the register at the given index is in self.
+    pub fn contains(&self, index: usize) -> bool {
+        index < REGISTERS && self.0 & (1 << index) != 0
+    }
+
+    #[must_use]
+    /// Returns the registers in self or the other set.
+    pub fn union(self, other: Self) -> Self {
+        RegisterSet(self.0 | other.0)
+    }
+
+    #[must_use]
+    /// Returns the registers in self but not the other set.
+    pub fn difference(self, other: Self) -> Self {
+        RegisterSet(self.0 & !other.0)
+    }
+
+    #[must_use]
+    /// Returns whether self holds no register.
+    pub fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+
+    /// Returns the indices in self, lowest first.
+    pub fn iter(&self) -> impl Iterator<Item = usize> + '_ {
+        (0..REGISTERS).filter(|index| self.contains(*index))
+    }
+}
+
+impl FromIterator<usize> for RegisterSet {
+    fn from_iter<I: IntoIterator<Item = usize>>(iter: I) -> Self {
+        let mut set = RegisterSet::EMPTY;
+
+        for index in iter {
+            set.insert(index);
+        }
+
+        set
+    }
+}
+
+impl Debug for RegisterSet {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_set().entries(self.iter()).finish()
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+/// What a single [`Instruction`] reads and writes.
+pub struct Effects {
+    /// Registers whose value may be read.
+    pub reads: RegisterSet,
+    /// Registers that may be written, even in part.
+    pub writes: RegisterSet,
+    /// Registers overwritten entirely every time, a subset of the writes.
+    pub kills: RegisterSet,
+    /// Whether memory may be read.
+    pub reads_memory: bool,
+    /// Whether memory may be written.
+    pub writes_memory: bool,
+}
+
+impl Effects {
+    /// Records reading the given source operand.
+    fn read(&mut self, operand: &Operand) {
+        match operand {
+            Operand::Register(register) => self.reads.insert(register.index()),
+            Operand::Memory(_) => self.reads_memory = true,
+            Operand::MemoryRegister(register) => {
+                self.reads.insert(register.index());
+                self.reads_memory = true;
+            }
+
+            Operand::Value(_) | Operand::None => {}
+        }
+    }
+
+    /// Records writing the given destination operand.
+    fn write(&mut self, operand: &Operand) {
+        match operand {
+            Operand::Register(register) => {
+                self.writes.insert(register.index());
+
+                // Narrower writes keep the upper bytes of the register.
+                if let Width::QWord(index) = register {
+                    self.kills.insert(*index);
+                }
+            }
+            Operand::Memory(_) => self.writes_memory = true,
+            Operand::MemoryRegister(register) => {
+                self.reads.insert(register.index());
+                self.writes_memory = true;
+            }
+
+            Operand::Value(_) | Operand::None => {}
+        }
+    }
+}
+
+#[must_use]
+/// Returns the [`Effects`] of the given [`Instruction`].
+///
+/// `cmp` writes the zero and greater flags, keeping overflow, and `add` may set overflow, so both
+/// write the flags register without killing it. Jumps write the instruction counter, `jz` also
+/// clears the zero flag when taken. A `call` may read and write anything.
+///
+/// # Example
+/// ```
+/// use vm::analysis::dataflow::effects;
+/// use vm::assembler::Assembler;
+/// let assembler = Assembler::parse("add rq1, rq2, rq3").unwrap();
+/// let effects = effects(&assembler.instructions()[0]);
+/// assert_eq!(effects.reads.iter().collect::<Vec<_>>(), [1, 2, 14]);
+/// assert_eq!(effects.writes.iter().collect::<Vec<_>>(), [3, 14]);
+/// assert_eq!(effects.kills.iter().collect::<Vec<_>>(), [3]);
+/// ```
+pub fn effects(instruction: &Instruction) -> Effects {
+    let mut effects = Effects::default();
+
+    match instruction {
+        Instruction::Call(_) => {
+            return Effects {
+                reads: RegisterSet::ALL,
+                writes: RegisterSet::ALL,
+                kills: RegisterSet::EMPTY,
+                reads_memory: true,
+                writes_memory: true,
+            };
+        }
+        Instruction::Mov(source, destination) => {
+            effects.read(source);
+            effects.write(destination);
+        }
+        Instruction::Add(value, source, destination) => {
+            effects.read(value);
+            effects.read(source);
+            effects.reads.insert(FLAGS);
+            effects.writes.insert(FLAGS);
+            effects.write(destination);
+        }
+        Instruction::Cmp(value, comparator) => {
+            effects.read(value);
+            effects.read(comparator);
+            effects.reads.insert(FLAGS);
+            effects.writes.insert(FLAGS);
+        }
+        Instruction::Jmp(source) => {
+            effects.read(source);
+            effects.writes.insert(COUNTER);
+            effects.kills.insert(COUNTER);
+        }
+        Instruction::Jz(source) | Instruction::Jnz(source) => {
+            effects.read(source);
+            effects.reads.insert(FLAGS);
+            effects.writes.insert(COUNTER);
+
+            if let Instruction::Jz(_) = instruction {
+                effects.writes.insert(FLAGS);
+            }
+        }
+    }
+
+    effects
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Which way facts flow through the [`Cfg`].
+pub enum Direction {
+    /// From the entry towards the exits, facts describe the past.
+    Forward,
+    /// From the exits towards the entry, facts describe the future.
+    Backward,
+}
+
+/// A dataflow problem solved by [`solve`].
+pub trait Analysis {
+    /// What is known at a program point.
+    type Fact: Clone + PartialEq;
+
+    /// Which way facts flow.
+    const DIRECTION: Direction;
+
+    /// Returns the fact at points no path has reached yet.
+    fn bottom(&self) -> Self::Fact;
+
+    /// Returns the fact at the program entry when forward, or when leaving the program when backward.
+    fn boundary(&self) -> Self::Fact;
+
+    /// Merges the fact of another path into the given fact.
+    fn join(&self, fact: &mut Self::Fact, other: &Self::Fact);
+
+    /// Applies the instruction at the given index to the given fact, in the direction of the analysis.
+    fn transfer(&self, index: usize, instruction: &Instruction, fact: &mut Self::Fact);
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// Facts of a solved [`Analysis`], per instruction.
+pub struct Results<F> {
+    before: Vec<F>,
+    after: Vec<F>,
+}
+
+impl<F> Results<F> {
+    #[must_use]
+    /// Returns the fact right before the instruction at the given index runs.
+    pub fn before(&self, index: usize) -> &F {
+        &self.before[index]
+    }
+
+    #[must_use]
+    /// Returns the fact right after the instruction at the given index ran.
+    pub fn after(&self, index: usize) -> &F {
+        &self.after[index]
+    }
+}
+
+/// Returns the blocks following each block, plus whether it may leave the program.
+fn successors(cfg: &Cfg) -> Vec<(Vec<usize>, bool)> {
+    let every: Vec<usize> = (0..cfg.blocks().len()).collect();
+
+    cfg.blocks()
+        .iter()
+        .map(|block| {
+            let mut blocks = BTreeSet::new();
+            let mut exits = false;
+
+            for edge in &block.successors {
+                match edge.target {
+                    Target::Block(target) => {
+                        blocks.insert(target);
+                    }
+                    Target::Exit => exits = true,
+                    Target::Unknown => {
+                        blocks.extend(&every);
+                        exits = true;
+                    }
+                }
+            }
+
+            (blocks.into_iter().collect(), exits)
+        })
+        .collect()
+}
+
+/// Solves the given [`Analysis`] over the given [`Cfg`] to a fixed point.
+///
+/// Instructions in blocks no path reaches keep the [`bottom`](Analysis::bottom) fact.
+pub fn solve<A: Analysis>(cfg: &Cfg, analysis: &A) -> Results<A::Fact> {
+    let blocks = cfg.blocks();
+    let successors = successors(cfg);
+    let mut predecessors = vec![Vec::new(); blocks.len()];
+
+    for (number, (targets, _)) in successors.iter().enumerate() {
+        for target in targets {
+            predecessors[*target].push(number);
+        }
+    }
+
+    // Facts flowing into and out of each block, in the direction of the analysis.
+    let mut inputs = vec![analysis.bottom(); blocks.len()];
+    let mut outputs = vec![analysis.bottom(); blocks.len()];
+    let mut queue: VecDeque<usize> = (0..blocks.len()).collect();
+    let mut queued = vec![true; blocks.len()];
+
+    while let Some(number) = queue.pop_front() {
+        queued[number] = false;
+
+        let block = &blocks[number];
+        let mut input = analysis.bottom();
+
+        match A::DIRECTION {
+            Direction::Forward => {
+                if number == 0 {
+                    input = analysis.boundary();
+                }
+
+                for predecessor in &predecessors[number] {
+                    analysis.join(&mut input, &outputs[*predecessor]);
+                }
+            }
+            Direction::Backward => {
+                let (targets, exits) = &successors[number];
+
+                if *exits {
+                    input = analysis.boundary();
+                }
+
+                for target in targets {
+                    analysis.join(&mut input, &outputs[*target]);
+                }
+            }
+        }
+
+        let mut output = input.clone();
+        let mut indices: Vec<usize> = (block.start..block.end).collect();
+
+        if A::DIRECTION == Direction::Backward {
+            indices.reverse();
+        }
+
+        for index in indices {
+            analysis.transfer(index, &cfg.instructions()[index], &mut output);
+        }
+
+        inputs[number] = input;
+
+        if output == outputs[number] {
+            continue;
+        }
+
+        outputs[number] = output;
+
+        let dependents = match A::DIRECTION {
+            Direction::Forward => &successors[number].0,
+            Direction::Backward => &predecessors[number],
+        };
+
+        for dependent in dependents {
+            if !queued[*dependent] {
+                queued[*dependent] = true;
+                queue.push_back(*dependent);
+            }
+        }
+    }
+
+    let length = cfg.instructions().len();
+    let mut before = vec![analysis.bottom(); length];
+    let mut after = vec![analysis.bottom(); length];
+
+    for (number, block) in blocks.iter().enumerate() {
+        let mut fact = inputs[number].clone();
+
+        match A::DIRECTION {
+            Direction::Forward => {
+                for index in block.start..block.end {
+                    before[index] = fact.clone();
+                    analysis.transfer(index, &cfg.instructions()[index], &mut fact);
+                    after[index] = fact.clone();
+                }
+            }
+            Direction::Backward => {
+                for index in (block.start..block.end).rev() {
+                    after[index] = fact.clone();
+                    analysis.transfer(index, &cfg.instructions()[index], &mut fact);
+                    before[index] = fact.clone();
+                }
+            }
+        }
+    }
+
+    Results { before, after }
+}
+
+#[derive(Debug, Clone, Copy)]
+/// Registers whose current value may still be read, a backward [`Analysis`].
+pub struct Liveness {
+    exit: RegisterSet,
+}
+
+impl Liveness {
+    #[must_use]
+    /// Constructs a new [`Liveness`], with the given registers read after the program leaves.
+    ///
+    /// Pass [`RegisterSet::ALL`] when the final registers matter, [`RegisterSet::EMPTY`] when they don't.
+    pub fn new(exit: RegisterSet) -> Self {
+        Liveness { exit }
+    }
+}
+
+impl Analysis for Liveness {
+    type Fact = RegisterSet;
+
+    const DIRECTION: Direction = Direction::Backward;
+
+    fn bottom(&self) -> RegisterSet {
+        RegisterSet::EMPTY
+    }
+
+    fn boundary(&self) -> RegisterSet {
+        self.exit
+    }
+
+    fn join(&self, fact: &mut RegisterSet, other: &RegisterSet) {
+        *fact = fact.union(*other);
+    }
+
+    fn transfer(&self, _index: usize, instruction: &Instruction, fact: &mut RegisterSet) {
+        let effects = effects(instruction);
+
+        *fact = fact.difference(effects.kills).union(effects.reads);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+/// A write of a register by the instruction at an index.
+pub struct Definition {
+    /// Index of the writing instruction.
+    pub index: usize,
+    /// Index of the written register.
+    pub register: usize,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+/// Writes that may still be the latest of their register, a forward [`Analysis`].
+///
+/// Initial register values aren't definitions, so a read no definition reaches may see one.
+pub struct ReachingDefinitions;
+
+impl Analysis for ReachingDefinitions {
+    type Fact = BTreeSet<Definition>;
+
+    const DIRECTION: Direction = Direction::Forward;
+
+    fn bottom(&self) -> Self::Fact {
+        BTreeSet::new()
+    }
+
+    fn boundary(&self) -> Self::Fact {
+        BTreeSet::new()
+    }
+
+    fn join(&self, fact: &mut Self::Fact, other: &Self::Fact) {
+        fact.extend(other);
+    }
+
+    fn transfer(&self, index: usize, instruction: &Instruction, fact: &mut Self::Fact) {
+        let effects = effects(instruction);
+
+        fact.retain(|definition| !effects.kills.contains(definition.register));
+        fact.extend(
+            effects
+                .writes
+                .iter()
+                .map(|register| Definition { index, register }),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::analysis::cfg::Cfg;
+    use crate::analysis::dataflow::{
+        effects, solve, Definition, Liveness, ReachingDefinitions, RegisterSet,
+    };
+    use crate::assembler::Assembler;
+
+    fn registers(indices: &[usize]) -> RegisterSet {
+        indices.iter().copied().collect()
+    }
+
+    #[test]
+    pub fn dataflow_effects() {
+        let assembler = Assembler::parse(
+            "mov rb1, [rq2]\n\
+             cmp mq0, rq3\n\
+             jz rq4\n\
+             mov 1, rw5\n\
+             call 0",
+        )
+        .unwrap();
+        let [mov, cmp, jz, narrow, call] = assembler.instructions() else {
+            unreachable!()
+        };
+
+        assert_eq!(effects(mov).reads, registers(&[1, 2]));
+        assert!(effects(mov).writes.is_empty() && effects(mov).writes_memory);
+        assert_eq!(effects(cmp).reads, registers(&[3, 14]));
+        assert_eq!(effects(cmp).kills, RegisterSet::EMPTY);
+        assert_eq!(effects(jz).writes, registers(&[14, 15]));
+        assert_eq!(effects(narrow).writes, registers(&[5]));
+        assert_eq!(effects(narrow).kills, RegisterSet::EMPTY);
+        assert_eq!(effects(call).writes, RegisterSet::ALL);
+    }
+
+    #[test]
+    pub fn dataflow_liveness() {
+        let assembler = Assembler::parse(
+            "mov 0, rq0\n\
+             mov 10, rq1\n\
+             mov 7, rq2\n\
+             loop: add 1, rq0, rq0\n\
+             cmp rq0, rq1\n\
+             jnz loop\n\
+             mov rq0, mq0",
+        )
+        .unwrap();
+        let cfg = Cfg::new(assembler.instructions());
+        let live = solve(&cfg, &Liveness::new(RegisterSet::EMPTY));
+
+        // rq2 is never read, rq1 stays live around the loop.
+        assert_eq!(*live.after(2), registers(&[0, 1, 14]));
+        assert_eq!(*live.before(3), registers(&[0, 1, 14]));
+        assert_eq!(*live.after(5), registers(&[0, 1, 14]));
+        assert_eq!(*live.after(6), RegisterSet::EMPTY);
+        assert!(!live.after(2).contains(2));
+    }
+
+    #[test]
+    pub fn dataflow_reaching_definitions() {
+        let assembler = Assembler::parse(
+            "mov 0, rq0\n\
+             loop: add 1, rq0, rq0\n\
+             cmp rq0, 10\n\
+             jnz loop\n\
+             jmp rq1",
+        )
+        .unwrap();
+        let cfg = Cfg::new(assembler.instructions());
+        let reaching = solve(&cfg, &ReachingDefinitions);
+
+        let of = |index: usize, register| {
+            reaching
+                .before(index)
+                .iter()
+                .filter(|definition| definition.register == register)
+                .map(|definition| definition.index)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(of(1, 0), [0, 1]);
+        assert_eq!(of(2, 0), [1]);
+        assert_eq!(of(4, 14), [1, 2]);
+
+        // The computed jump may lead back into the loop.
+        assert!(reaching.before(1).contains(&Definition {
+            index: 4,
+            register: 15
+        }));
+    }
+}
@@ -0,0 +1,750 @@
+//! Abstract interpretation over unsigned register ranges, warning about memory accesses outside a
+//! region and additions that may not fit their destination.
+//!
+//! Memory isn't tracked, reading it gives every value of the access width. Jumps only observe the
+//! zero flag, so conditional branches refine registers by the equality of the last comparison;
+//! loops bounded by `!=` widen to the full range.
+
+use crate::analysis::cfg::{Cfg, EdgeKind, Target};
+use crate::instructions::{Instruction, Operand};
+use crate::register::{mask, COUNTER, FLAGS};
+
+use std::collections::VecDeque;
+use std::fmt::{self, Display, Formatter};
+use std::ops::Range;
+
+/// Visits of a block before its input widens instead of joining.
+const WIDEN_AFTER: usize = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Inclusive range of unsigned values.
+pub struct Interval {
+    /// Smallest value.
+    pub min: u64,
+    /// Largest value.
+    pub max: u64,
+}
+
+impl Interval {
+    /// Every value.
+    pub const FULL: Self = Interval {
+        min: 0,
+        max: u64::MAX,
+    };
+
+    #[must_use]
+    /// Constructs a new [`Interval`] from `min` to `max` inclusive.
+    ///
+    /// # Panics
+    /// When `min` is greater than `max`.
+    pub fn new(min: u64, max: u64) -> Self {
+        assert!(min <= max, "empty interval {min}..={max}");
+
+        Interval { min, max }
+    }
+
+    #[must_use]
+    /// Constructs a new [`Interval`] holding only the given value.
+    pub fn constant(value: u64) -> Self {
+        Interval {
+            min: value,
+            max: value,
+        }
+    }
+
+    #[must_use]
+    /// Returns whether self holds the given value.
+    pub fn contains(&self, value: u64) -> bool {
+        (self.min..=self.max).contains(&value)
+    }
+
+    #[must_use]
+    /// Returns the smallest interval holding both self and other.
+    pub fn join(self, other: Self) -> Self {
+        Interval {
+            min: self.min.min(other.min),
+            max: self.max.max(other.max),
+        }
+    }
+
+    #[must_use]
+    /// Returns the values in both self and other, [`None`] when there are none.
+    pub fn meet(self, other: Self) -> Option<Self> {
+        let min = self.min.max(other.min);
+        let max = self.max.min(other.max);
+
+        (min <= max).then_some(Interval { min, max })
+    }
+
+    /// Returns self joined with other, sending each bound that moved to its extreme.
+    fn widen(self, other: Self) -> Self {
+        Interval {
+            min: if other.min < self.min { 0 } else { self.min },
+            max: if other.max > self.max {
+                u64::MAX
+            } else {
+                self.max
+            },
+        }
+    }
+
+    /// Returns the values self takes in the low `size` bytes.
+    fn truncate(self, size: usize) -> Self {
+        let mask = mask(size);
+
+        if self.max <= mask {
+            self
+        } else if self.min & !mask == self.max & !mask {
+            Interval::new(self.min & mask, self.max & mask)
+        } else {
+            Interval::new(0, mask)
+        }
+    }
+}
+
+impl Display for Interval {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}..={}", self.min, self.max)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// A possible problem with a single operand of a program.
+pub struct Warning {
+    /// Index of the instruction in the program.
+    pub index: usize,
+    /// Position of the operand in the instruction, starting at 0.
+    pub operand: usize,
+    /// What may go wrong with the operand.
+    pub kind: WarningKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// What may go wrong with an operand.
+pub enum WarningKind {
+    /// Memory accessed may leave the region; holds the bytes that may be accessed.
+    MemoryOutOfBounds(Interval),
+    /// An addition written here may not fit the given number of bytes.
+    Overflow(usize),
+}
+
+impl Display for Warning {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "instruction {}, operand {}: ", self.index, self.operand)?;
+
+        match self.kind {
+            WarningKind::MemoryOutOfBounds(bytes) => {
+                write!(
+                    f,
+                    "bytes {bytes} may be accessed, outside the memory region"
+                )
+            }
+            WarningKind::Overflow(size) => write!(f, "sum may not fit in {size} bytes"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// An operand of the last comparison.
+enum Side {
+    /// A register read with the given size in bytes.
+    Register(usize, usize),
+    /// Values read when comparing.
+    Fixed(Interval),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// Registers at a point of the program, with the comparison the zero flag still reflects.
+struct State {
+    registers: [Interval; 16],
+    comparison: Option<(Side, Side)>,
+}
+
+impl State {
+    /// Returns the values the given source operand may read at the given index.
+    fn read(&self, index: usize, operand: &Operand) -> Interval {
+        match operand {
+            Operand::Value(value) => Interval::constant(*value),
+            Operand::Register(width) => match (width.index(), width.size()) {
+                (COUNTER, size) => Interval::constant(index as u64).truncate(size),
+                // A register past the last one may hold anything, as far as the analysis knows.
+                (register, size) => self
+                    .registers
+                    .get(register)
+                    .map_or(Interval::FULL, |values| values.truncate(size)),
+            },
+            Operand::Memory(width) | Operand::MemoryRegister(width) => {
+                Interval::new(0, mask(width.size()))
+            }
+
+            Operand::None => Interval::FULL,
+        }
+    }
+
+    /// Writes the given values through the given destination operand.
+    fn write(&mut self, operand: &Operand, values: Interval) {
+        let Operand::Register(width) = operand else {
+            return;
+        };
+        let (register, size) = (width.index(), width.size());
+
+        if register == COUNTER || register >= self.registers.len() {
+            return;
+        }
+
+        let values = values.truncate(size);
+        let old = self.registers[register];
+        let high = old.min & !mask(size);
+
+        self.registers[register] = if size == 8 {
+            values
+        } else if high == old.max & !mask(size) {
+            Interval::new(high | values.min, high | values.max)
+        } else {
+            Interval::FULL
+        };
+
+        let compares = |side: &Side| matches!(side, Side::Register(index, _) if *index == register);
+
+        if register == FLAGS
+            || self
+                .comparison
+                .is_some_and(|(value, comparator)| compares(&value) || compares(&comparator))
+        {
+            self.comparison = None;
+        }
+    }
+
+    /// Returns the given operand as a side of a comparison.
+    ///
+    /// The comparison itself clobbers the flags, so they are compared by the values they held.
+    fn side(&self, index: usize, operand: &Operand) -> Side {
+        match operand {
+            Operand::Register(width) if width.index() < FLAGS => {
+                let (register, size) = (width.index(), width.size());
+
+                Side::Register(register, size)
+            }
+            operand => Side::Fixed(self.read(index, operand)),
+        }
+    }
+
+    /// Returns the values of the given side, and whether its register holds exactly them.
+    fn values(&self, side: Side) -> (Interval, Option<usize>) {
+        match side {
+            Side::Register(register, size) => {
+                let values = self.registers[register];
+
+                if size == 8 || values.max <= mask(size) {
+                    (values, Some(register))
+                } else {
+                    (values.truncate(size), None)
+                }
+            }
+            Side::Fixed(values) => (values, None),
+        }
+    }
+
+    /// Narrows self to the last comparison being equal or not, [`None`] when it can't be.
+    fn refine(mut self, equal: bool) -> Option<Self> {
+        let Some((value, comparator)) = self.comparison else {
+            return Some(self);
+        };
+        let (value, value_register) = self.values(value);
+        let (comparator, comparator_register) = self.values(comparator);
+
+        if equal {
+            let both = value.meet(comparator)?;
+
+            for register in [value_register, comparator_register].into_iter().flatten() {
+                self.registers[register] = both;
+            }
+        } else {
+            if value.min == value.max && value == comparator {
+                return None;
+            }
+
+            // Only a single value can be taken off, and only at either end.
+            let exclude = |values: Interval, other: Interval| {
+                if other.min != other.max {
+                    return Some(values);
+                }
+
+                let mut values = values;
+
+                if values.min == other.min {
+                    values.min = values.min.checked_add(1)?;
+                }
+
+                if values.max == other.min {
+                    values.max = values.max.checked_sub(1)?;
+                }
+
+                (values.min <= values.max).then_some(values)
+            };
+
+            if let Some(register) = value_register {
+                self.registers[register] = exclude(value, comparator)?;
+            }
+
+            if let Some(register) = comparator_register {
+                self.registers[register] = exclude(comparator, value)?;
+            }
+        }
+
+        Some(self)
+    }
+
+    /// Applies the given instruction at the given index.
+    fn transfer(&mut self, index: usize, instruction: &Instruction) {
+        match instruction {
+            Instruction::Call(_) => {
+                self.registers = [Interval::FULL; 16];
+                self.comparison = None;
+            }
+            Instruction::Mov(source, destination) => {
+                let values = self.read(index, source);
+
+                self.write(destination, values);
+            }
+            Instruction::Add(value, source, destination) => {
+                let sum = sum(self.read(index, value), self.read(index, source));
+
+                // The overflow flag may be set, the zero flag the comparison left stays.
+                self.registers[FLAGS] = Interval::FULL;
+                self.write(destination, sum.unwrap_or(Interval::FULL));
+            }
+            Instruction::Cmp(value, comparator) => {
+                self.comparison = Some((self.side(index, value), self.side(index, comparator)));
+                self.registers[FLAGS] = Interval::FULL;
+            }
+            Instruction::Jz(_) => {
+                self.comparison = None;
+                self.registers[FLAGS] = Interval::FULL;
+            }
+            Instruction::Jmp(_) | Instruction::Jnz(_) => {}
+        }
+    }
+
+    /// Returns self joined with other, or widened after enough visits.
+    fn merge(&self, other: &Self, widen: bool) -> Self {
+        let mut registers = self.registers;
+
+        for (register, other) in registers.iter_mut().zip(other.registers) {
+            *register = if widen {
+                register.widen(other)
+            } else {
+                register.join(other)
+            };
+        }
+
+        State {
+            registers,
+            comparison: self
+                .comparison
+                .filter(|_| self.comparison == other.comparison),
+        }
+    }
+}
+
+/// Returns the sums of the given intervals, [`None`] when only some of them overflow.
+fn sum(value: Interval, source: Interval) -> Option<Interval> {
+    match (
+        source.min.checked_add(value.min),
+        source.max.checked_add(value.max),
+    ) {
+        (Some(min), Some(max)) => Some(Interval::new(min, max)),
+        (None, None) => Some(Interval::new(
+            source.min.wrapping_add(value.min),
+            source.max.wrapping_add(value.max),
+        )),
+        _ => None,
+    }
+}
+
+#[derive(Debug, Clone)]
+/// Builder for an interval analysis, checking memory accesses against a region.
+pub struct IntervalAnalysis {
+    memory: Range<u64>,
+    entry: [Interval; 16],
+}
+
+#[derive(Debug, Clone)]
+/// Register ranges before every instruction, and the warnings found.
+pub struct Intervals {
+    before: Vec<Option<[Interval; 16]>>,
+    warnings: Vec<Warning>,
+}
+
+impl Intervals {
+    #[must_use]
+    /// Returns the ranges of every register before the instruction at the given index, [`None`]
+    /// when no path reaches it.
+    pub fn before(&self, index: usize) -> Option<&[Interval; 16]> {
+        self.before.get(index)?.as_ref()
+    }
+
+    #[must_use]
+    /// Returns every warning, in program order.
+    pub fn warnings(&self) -> &[Warning] {
+        &self.warnings
+    }
+}
+
+impl IntervalAnalysis {
+    #[must_use]
+    /// Constructs a new [`IntervalAnalysis`] allowing accesses to the given bytes, with every
+    /// register holding any value at the entry.
+    pub fn new(memory: Range<u64>) -> Self {
+        IntervalAnalysis {
+            memory,
+            entry: [Interval::FULL; 16],
+        }
+    }
+
+    #[must_use]
+    /// Sets the values the register at the given index holds at the entry, unless it is the
+    /// instruction counter or past the last register.
+    pub fn register(mut self, index: usize, values: Interval) -> Self {
+        if index < COUNTER {
+            self.entry[index] = values;
+        }
+
+        self
+    }
+
+    #[must_use]
+    /// Interprets the given program from index 0 to a fixed point, then checks every operand.
+    ///
+    /// # Example
+    /// ```
+    /// use vm::analysis::cfg::Cfg;
+    /// use vm::analysis::interval::{Interval, IntervalAnalysis, WarningKind};
+    /// use vm::assembler::Assembler;
+    /// let assembler = Assembler::parse("mov 250, rq0\nadd rq0, rb1, rb2\nmov rb2, [rq0]").unwrap();
+    /// let intervals = IntervalAnalysis::new(0..256).run(&Cfg::new(assembler.instructions()));
+    /// assert_eq!(intervals.before(2).unwrap()[0], Interval::constant(250));
+    /// assert_eq!(intervals.warnings()[0].kind, WarningKind::Overflow(1));
+    /// assert_eq!(intervals.warnings()[1].kind, WarningKind::MemoryOutOfBounds(Interval::new(250, 257)));
+    /// ```
+    pub fn run(&self, cfg: &Cfg) -> Intervals {
+        let blocks = cfg.blocks();
+        let mut inputs: Vec<Option<State>> = vec![None; blocks.len()];
+        let mut visits = vec![0; blocks.len()];
+        let mut queue = VecDeque::new();
+
+        if !blocks.is_empty() {
+            inputs[0] = Some(State {
+                registers: self.entry,
+                comparison: None,
+            });
+            queue.push_back(0);
+        }
+
+        while let Some(number) = queue.pop_front() {
+            let Some(mut state) = inputs[number].clone() else {
+                continue;
+            };
+            let block = &blocks[number];
+            let last = &cfg.instructions()[block.end - 1];
+
+            for index in block.start..block.end - 1 {
+                state.transfer(index, &cfg.instructions()[index]);
+            }
+
+            let before = state.clone();
+
+            state.transfer(block.end - 1, last);
+
+            for edge in &block.successors {
+                // Taking `jz`, or not taking `jnz`, means the comparison was equal.
+                let output = match last {
+                    Instruction::Jz(_) | Instruction::Jnz(_) => {
+                        let equal =
+                            (edge.kind == EdgeKind::Branch) == matches!(last, Instruction::Jz(_));
+                        let Some(mut refined) = before.clone().refine(equal) else {
+                            continue;
+                        };
+
+                        refined.transfer(block.end - 1, last);
+                        refined
+                    }
+                    _ => state.clone(),
+                };
+
+                let targets: Vec<usize> = match edge.target {
+                    Target::Block(target) => vec![target],
+                    Target::Exit => Vec::new(),
+                    Target::Unknown => (0..blocks.len()).collect(),
+                };
+
+                for target in targets {
+                    let merged = match &inputs[target] {
+                        Some(input) => input.merge(&output, visits[target] >= WIDEN_AFTER),
+                        None => output.clone(),
+                    };
+
+                    if inputs[target].as_ref() != Some(&merged) {
+                        inputs[target] = Some(merged);
+                        visits[target] += 1;
+
+                        if !queue.contains(&target) {
+                            queue.push_back(target);
+                        }
+                    }
+                }
+            }
+        }
+
+        // Blocks cover the program in order.
+        let mut before = Vec::with_capacity(cfg.instructions().len());
+        let mut warnings = Vec::new();
+
+        for (block, input) in blocks.iter().zip(inputs) {
+            let Some(mut state) = input else {
+                before.extend(std::iter::repeat_n(None, block.end - block.start));
+                continue;
+            };
+
+            for index in block.start..block.end {
+                let instruction = &cfg.instructions()[index];
+
+                self.check(index, instruction, &state, &mut warnings);
+                before.push(Some(state.registers));
+                state.transfer(index, instruction);
+            }
+        }
+
+        warnings.sort_by_key(|warning| (warning.index, warning.operand));
+
+        Intervals { before, warnings }
+    }
+
+    /// Appends the warnings of the instruction at the given index.
+    fn check(
+        &self,
+        index: usize,
+        instruction: &Instruction,
+        state: &State,
+        warnings: &mut Vec<Warning>,
+    ) {
+        let operands: Vec<&Operand> = match instruction {
+            Instruction::Call(call_index) => vec![call_index],
+            Instruction::Mov(source, destination) => vec![source, destination],
+            Instruction::Jmp(source) | Instruction::Jz(source) | Instruction::Jnz(source) => {
+                vec![source]
+            }
+            Instruction::Cmp(value, comparator) => vec![value, comparator],
+            Instruction::Add(value, source, destination) => vec![value, source, destination],
+        };
+
+        for (position, operand) in operands.iter().enumerate() {
+            let mut report = |kind| {
+                warnings.push(Warning {
+                    index,
+                    operand: position,
+                    kind,
+                });
+            };
+
+            let (addresses, size) = match operand {
+                Operand::Memory(width) => {
+                    let (address, size) = (width.index(), width.size());
+
+                    (Interval::constant(address as u64), size)
+                }
+                Operand::MemoryRegister(width) => {
+                    let size = width.size();
+
+                    (state.read(index, &Operand::Register(width.clone())), size)
+                }
+                _ => continue,
+            };
+
+            let bytes = Interval {
+                min: addresses.min,
+                max: addresses.max.saturating_add(size as u64 - 1),
+            };
+            if bytes.min < self.memory.start || bytes.max >= self.memory.end {
+                report(WarningKind::MemoryOutOfBounds(bytes));
+            }
+        }
+
+        if let Instruction::Add(value, source, destination) = instruction {
+            let size = match destination {
+                Operand::Register(width)
+                | Operand::Memory(width)
+                | Operand::MemoryRegister(width) => width.size(),
+                _ => return,
+            };
+
+            let value = state.read(index, value);
+            let source = state.read(index, source);
+            let fits = source
+                .max
+                .checked_add(value.max)
+                .is_some_and(|max| max <= mask(size));
+
+            if !fits {
+                warnings.push(Warning {
+                    index,
+                    operand: 2,
+                    kind: WarningKind::Overflow(size),
+                });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::analysis::cfg::Cfg;
+    use crate::analysis::interval::{Interval, IntervalAnalysis, Intervals, Warning, WarningKind};
+    use crate::assembler::Assembler;
+    use crate::instructions::{Instruction, Operand};
+    use crate::register::Width;
+
+    fn run(source: &str, analysis: IntervalAnalysis) -> Intervals {
+        analysis.run(&Cfg::new(Assembler::parse(source).unwrap().instructions()))
+    }
+
+    #[test]
+    pub fn interval_ranges() {
+        let intervals = run(
+            "mov 10, rq0\n\
+             add rq0, rq1, rq2\n\
+             cmp rq1, 5\n\
+             jz five\n\
+             mov 300, rb3\n\
+             jmp end\n\
+             five: mov 2, rq3\n\
+             end: cmp rq3, 2",
+            IntervalAnalysis::new(0..0).register(1, Interval::new(0, 20)),
+        );
+        let before = |index| intervals.before(index).unwrap();
+
+        assert_eq!(before(2)[2], Interval::new(10, 30));
+        // Narrow writes keep the upper bytes, unknown here.
+        assert_eq!(before(5)[3], Interval::FULL);
+        assert_eq!(before(6)[1], Interval::constant(5));
+        assert_eq!(before(7)[3], Interval::FULL);
+        assert!(intervals.warnings().is_empty());
+
+        let intervals = run(
+            "mov 0, rq3\n\
+             cmp rq1, 5\n\
+             jnz other\n\
+             mov 44, rb3\n\
+             other: mov rq1, rq0",
+            IntervalAnalysis::new(0..0).register(1, Interval::new(5, 9)),
+        );
+
+        assert_eq!(intervals.before(3).unwrap()[1], Interval::constant(5));
+        assert_eq!(intervals.before(4).unwrap()[1], Interval::new(5, 9));
+        assert_eq!(intervals.before(4).unwrap()[3], Interval::new(0, 44));
+    }
+
+    #[test]
+    pub fn interval_compares_flags() {
+        let intervals = run(
+            "mov 5, rq14\n\
+             cmp rq14, 5\n\
+             jz equal\n\
+             mov 0, rq0\n\
+             equal: mov rq14, rq1",
+            IntervalAnalysis::new(0..0),
+        );
+
+        assert_eq!(intervals.before(3), None);
+        assert_eq!(intervals.before(4).unwrap()[14], Interval::FULL);
+    }
+
+    #[test]
+    pub fn interval_unknown_registers() {
+        let instructions = [
+            Instruction::Mov(Operand::Value(1), Operand::Register(Width::QWord(20))),
+            Instruction::Mov(
+                Operand::Register(Width::QWord(20)),
+                Operand::Register(Width::QWord(0)),
+            ),
+            Instruction::Cmp(Operand::Register(Width::QWord(20)), Operand::Value(1)),
+            Instruction::Mov(Operand::Value(0), Operand::MemoryRegister(Width::QWord(20))),
+        ];
+        let intervals = IntervalAnalysis::new(0..8)
+            .register(20, Interval::constant(1))
+            .run(&Cfg::new(&instructions));
+
+        // Registers past the last one hold anything, and writes to them are ignored.
+        assert_eq!(intervals.before(2).unwrap()[0], Interval::FULL);
+        assert_eq!(
+            intervals.warnings(),
+            [Warning {
+                index: 3,
+                operand: 1,
+                kind: WarningKind::MemoryOutOfBounds(Interval::new(0, u64::MAX)),
+            }]
+        );
+    }
+
+    #[test]
+    pub fn interval_loops_terminate() {
+        let intervals = run(
+            "mov 0, rq0\n\
+             loop: mov rb0, [rb0]\n\
+             add 1, rq0, rq0\n\
+             cmp rq0, 256\n\
+             jnz loop\n\
+             mov rq0, rq1",
+            IntervalAnalysis::new(0..256),
+        );
+
+        // Only `!=` bounds the counter, which widening loses, so its increment may overflow.
+        assert_eq!(intervals.before(2).unwrap()[0], Interval::FULL);
+        assert_eq!(intervals.before(5).unwrap()[0], Interval::constant(256));
+        assert_eq!(
+            intervals.warnings(),
+            [Warning {
+                index: 2,
+                operand: 2,
+                kind: WarningKind::Overflow(8),
+            }]
+        );
+    }
+
+    #[test]
+    pub fn interval_warnings() {
+        let intervals = run(
+            "mov 100, rq0\n\
+             mov mq96, [rq0]\n\
+             add 1, rq1, rq2\n\
+             add 1, rb1, rw2\n\
+             add 200, rb1, mb0\n\
+             cmp [rw1], 0",
+            IntervalAnalysis::new(0..104),
+        );
+
+        let warning = |index, operand, kind| Warning {
+            index,
+            operand,
+            kind,
+        };
+
+        assert_eq!(
+            intervals.warnings(),
+            [
+                warning(
+                    1,
+                    1,
+                    WarningKind::MemoryOutOfBounds(Interval::new(100, 107))
+                ),
+                warning(2, 2, WarningKind::Overflow(8)),
+                warning(4, 2, WarningKind::Overflow(1)),
+                warning(
+                    5,
+                    0,
+                    WarningKind::MemoryOutOfBounds(Interval::new(0, 65536))
+                ),
+            ]
+        );
+    }
+}
@@ -0,0 +1,5 @@
+//! Static analyses over a program's [`Instruction`](crate::instructions::Instruction) list.
+
+pub mod cfg;
+pub mod dataflow;
+pub mod interval;
@@ -1,9 +1,19 @@
+mod parser;
+
 use crate::instructions::{Execute, Instruction, Operand};
+use crate::module::{Import, Module};
+
+use std::collections::{BTreeMap, BTreeSet};
 
 #[derive(Debug, Default)]
 /// Self-contained type for the creation and processing of instructions.
 pub struct Assembler {
     instructions: Vec<Instruction>,
+    labels: BTreeMap<String, usize>,
+    lines: Vec<usize>,
+
+    exports: BTreeSet<String>,
+    imports: Vec<Import>,
 }
 
 impl Assembler {
@@ -13,6 +23,56 @@ impl Assembler {
         Assembler::default()
     }
 
+    #[must_use]
+    /// Consumes [`self`](Assembler) naming the index of the next pushed instruction.
+    pub fn label(mut self, name: &str) -> Self {
+        self.labels
+            .insert(name.to_string(), self.instructions.len());
+
+        self
+    }
+
+    #[must_use]
+    /// Consumes [`self`](Assembler) naming the index of the next pushed instruction, and exporting
+    /// the name to other [`Module`]s.
+    pub fn export(mut self, name: &str) -> Self {
+        self.exports.insert(name.to_string());
+
+        self.label(name)
+    }
+
+    #[must_use]
+    /// Consumes [`self`](Assembler) making the first operand of the last pushed instruction refer
+    /// to a symbol exported by another [`Module`].
+    pub fn import(mut self, module: &str, symbol: &str) -> Self {
+        self.imports.push(Import {
+            index: self.instructions.len().saturating_sub(1),
+            operand: 0,
+            module: module.to_string(),
+            symbol: symbol.to_string(),
+        });
+
+        self
+    }
+
+    #[must_use]
+    /// Returns the instructions pushed so far.
+    pub fn instructions(&self) -> &[Instruction] {
+        &self.instructions
+    }
+
+    #[must_use]
+    /// Returns the labels declared so far, mapped to the index of the instruction they name.
+    pub fn labels(&self) -> &BTreeMap<String, usize> {
+        &self.labels
+    }
+
+    #[must_use]
+    /// Returns the 1-based source line of each instruction, empty unless self was [`parsed`](Assembler::parse).
+    pub fn lines(&self) -> &[usize] {
+        &self.lines
+    }
+
     #[must_use]
     /// Consumes [`self`](Assembler) pushing a new [`Call`](Instruction::Call) into self.
     pub fn call(mut self, index: Operand) -> Self {
@@ -72,6 +132,17 @@ impl Assembler {
         self
     }
 
+    #[must_use]
+    /// Consumes [`self`](Assembler) into a relocatable [`Module`].
+    pub fn module(self) -> Module {
+        Module {
+            instructions: self.instructions,
+            labels: self.labels,
+            exports: self.exports,
+            imports: self.imports,
+        }
+    }
+
     #[must_use]
     /// Returns a list of [`Execute`] traits derived from self's instruction list.
     pub fn compile(self) -> Vec<Box<dyn Execute>> {
@@ -82,6 +153,20 @@ impl Assembler {
     }
 }
 
+#[must_use]
+/// Inverts the given [`labels`](Assembler::labels), naming each labelled index.
+///
+/// Several labels may name the same index; the first one in name order wins.
+pub fn label_names(labels: &BTreeMap<String, usize>) -> BTreeMap<usize, &str> {
+    let mut names = BTreeMap::new();
+
+    for (name, index) in labels {
+        names.entry(*index).or_insert(name.as_str());
+    }
+
+    names
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -112,4 +197,15 @@ mod tests {
             Instruction::Mov(Operand::Value(0), Operand::Value(1))
         );
     }
+
+    #[test]
+    pub fn assembler_label() {
+        let assembler = Assembler::new()
+            .label("start")
+            .call(Operand::Value(0))
+            .label("end");
+
+        assert_eq!(assembler.labels()["start"], 0);
+        assert_eq!(assembler.labels()["end"], 1);
+    }
 }
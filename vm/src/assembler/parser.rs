@@ -0,0 +1,335 @@
+use crate::assembler::Assembler;
+use crate::error::Error;
+use crate::instructions::{Instruction, Operand};
+use crate::module::Import;
+use crate::register::Width;
+
+/// Operand as written in source, before label references are resolved.
+enum Parsed {
+    Operand(Operand),
+    Label(String),
+    Import(String, String),
+}
+
+/// Instruction as written in source, kept until every label is known.
+struct Statement {
+    line: usize,
+    mnemonic: String,
+    operands: Vec<Parsed>,
+}
+
+/// Returns whether the given string is a valid label name.
+fn is_label(name: &str) -> bool {
+    let mut characters = name.chars();
+
+    matches!(characters.next(), Some(character) if character.is_ascii_alphabetic() || character == '_' || character == '.')
+        && characters.all(|character| {
+            character.is_ascii_alphanumeric() || character == '_' || character == '.'
+        })
+}
+
+/// Parses a decimal or `0x` prefixed hexadecimal number.
+fn parse_number(text: &str) -> Option<u64> {
+    match text.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
+
+/// Parses a width prefix followed by a number, e.g. `q0` or `d0x10`.
+fn parse_width(text: &str) -> Option<Width> {
+    let mut characters = text.chars();
+    let width = characters.next()?;
+    let index = usize::try_from(parse_number(characters.as_str())?).ok()?;
+
+    match width {
+        'b' => Some(Width::Byte(index)),
+        'w' => Some(Width::Word(index)),
+        'd' => Some(Width::DWord(index)),
+        'q' => Some(Width::QWord(index)),
+
+        _ => None,
+    }
+}
+
+/// Parses a register name, e.g. `rq0`, rejecting indices outside of the register file.
+fn parse_register(text: &str) -> Option<Width> {
+    let width = parse_width(text.strip_prefix('r')?)?;
+
+    match width {
+        Width::Byte(index) | Width::Word(index) | Width::DWord(index) | Width::QWord(index)
+            if index < 16 =>
+        {
+            Some(width)
+        }
+
+        _ => None,
+    }
+}
+
+fn parse_operand(text: &str, line: usize) -> Result<Parsed, Error> {
+    let text = text.trim();
+
+    if let Some(inner) = text
+        .strip_prefix('[')
+        .and_then(|text| text.strip_suffix(']'))
+    {
+        return parse_register(inner.trim())
+            .map(|width| Parsed::Operand(Operand::MemoryRegister(width)))
+            .ok_or(Error::Syntax(line));
+    }
+
+    if let Some(width) = parse_register(text) {
+        return Ok(Parsed::Operand(Operand::Register(width)));
+    }
+
+    if let Some(width) = text.strip_prefix('m').and_then(parse_width) {
+        return Ok(Parsed::Operand(Operand::Memory(width)));
+    }
+
+    if let Some(value) = parse_number(text) {
+        return Ok(Parsed::Operand(Operand::Value(value)));
+    }
+
+    if let Some(value) = text.strip_prefix('-').and_then(parse_number) {
+        return Ok(Parsed::Operand(Operand::Value(value.wrapping_neg())));
+    }
+
+    if is_label(text) {
+        return Ok(Parsed::Label(text.to_string()));
+    }
+
+    if let Some((module, symbol)) = text.split_once("::") {
+        if is_label(module) && is_label(symbol) {
+            return Ok(Parsed::Import(module.to_string(), symbol.to_string()));
+        }
+    }
+
+    Err(Error::Syntax(line))
+}
+
+impl Assembler {
+    /// Parses assembly source into a new [`Assembler`], recording the source line of every instruction.
+    ///
+    /// Statements are separated by newlines or `;`, and `//` starts a comment. A statement may be
+    /// preceded by any number of `label:` declarations. Operands are written as:
+    ///
+    /// * `42`, `0x2a`, `-1` for a [`Value`](Operand::Value),
+    /// * `rq0`, `rd1`, `rw2`, `rb3` for a [`Register`](Operand::Register) of the given width,
+    /// * `mq16` for [`Memory`](Operand::Memory) at the given address,
+    /// * `[rq0]` for [`MemoryRegister`](Operand::MemoryRegister),
+    /// * `name` for a label,
+    /// * `module::name` for a label exported by another [`Module`](crate::module::Module).
+    ///
+    /// `.export name, ...` exports labels of self to other modules.
+    ///
+    /// Jumps resume execution at the instruction *after* their target, hence a label reference
+    /// resolves to the index preceding the label.
+    ///
+    /// # Example
+    /// ```
+    /// use vm::assembler::Assembler;
+    /// let assembler = Assembler::parse("mov 0, rq0\nloop: add 1, rq0, rq0; cmp rq0, 10\njnz loop").unwrap();
+    /// assert_eq!(assembler.instructions().len(), 4);
+    /// ```
+    ///
+    /// # Errors
+    /// When a statement is malformed or references an undeclared label, [`Syntax`](Error::Syntax)
+    /// is returned with its 1-based line number.
+    pub fn parse(source: &str) -> Result<Self, Error> {
+        let mut assembler = Assembler::new();
+        let mut statements = Vec::new();
+        let mut exports = Vec::new();
+
+        for (line_index, line) in source.lines().enumerate() {
+            let line_number = line_index + 1;
+            let line = line.split("//").next().unwrap_or_default();
+
+            for statement in line.split(';') {
+                let mut statement = statement.trim();
+
+                while let Some((label, rest)) = statement.split_once(':') {
+                    let label = label.trim();
+
+                    // `module::symbol` is an import, not a label declaration.
+                    if rest.starts_with(':') {
+                        break;
+                    }
+
+                    if !is_label(label) {
+                        return Err(Error::Syntax(line_number));
+                    }
+
+                    assembler.labels.insert(label.to_string(), statements.len());
+                    statement = rest.trim();
+                }
+
+                if statement.is_empty() {
+                    continue;
+                }
+
+                let (mnemonic, operands) = statement
+                    .split_once(char::is_whitespace)
+                    .unwrap_or((statement, ""));
+
+                if mnemonic == ".export" {
+                    for name in operands.split(',').map(str::trim) {
+                        if !is_label(name) {
+                            return Err(Error::Syntax(line_number));
+                        }
+
+                        exports.push((name.to_string(), line_number));
+                    }
+
+                    continue;
+                }
+
+                let operands = if operands.trim().is_empty() {
+                    Vec::new()
+                } else {
+                    operands
+                        .split(',')
+                        .map(|operand| parse_operand(operand, line_number))
+                        .collect::<Result<_, _>>()?
+                };
+
+                statements.push(Statement {
+                    line: line_number,
+                    mnemonic: mnemonic.to_ascii_lowercase(),
+                    operands,
+                });
+            }
+        }
+
+        for statement in statements {
+            let line = statement.line;
+
+            let index = assembler.instructions.len();
+
+            let mut operands = statement
+                .operands
+                .into_iter()
+                .enumerate()
+                .map(|(position, operand)| match operand {
+                    Parsed::Operand(operand) => Ok(operand),
+                    Parsed::Import(module, symbol) => {
+                        assembler.imports.push(Import {
+                            index,
+                            operand: position,
+                            module,
+                            symbol,
+                        });
+
+                        Ok(Operand::None)
+                    }
+                    Parsed::Label(label) => assembler
+                        .labels
+                        .get(&label)
+                        .map(|index| Operand::Value((*index as u64).wrapping_sub(1)))
+                        .ok_or(Error::Syntax(line)),
+                })
+                .collect::<Result<Vec<_>, _>>()?
+                .into_iter();
+
+            let mut next = || operands.next().ok_or(Error::Syntax(line));
+
+            let instruction = match statement.mnemonic.as_str() {
+                "call" => Instruction::Call(next()?),
+                "mov" => Instruction::Mov(next()?, next()?),
+                "jmp" => Instruction::Jmp(next()?),
+                "jz" => Instruction::Jz(next()?),
+                "jnz" => Instruction::Jnz(next()?),
+                "cmp" => Instruction::Cmp(next()?, next()?),
+                "add" => Instruction::Add(next()?, next()?, next()?),
+
+                _ => return Err(Error::Syntax(line)),
+            };
+
+            if operands.next().is_some() {
+                return Err(Error::Syntax(line));
+            }
+
+            assembler.instructions.push(instruction);
+            assembler.lines.push(line);
+        }
+
+        for (name, line) in exports {
+            if !assembler.labels.contains_key(&name) {
+                return Err(Error::Syntax(line));
+            }
+
+            assembler.exports.insert(name);
+        }
+
+        Ok(assembler)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn parser_operands() {
+        let assembler = Assembler::parse("mov [rq3], mb0x10\nadd -1, rd15, rw2").unwrap();
+
+        assert_eq!(
+            assembler.instructions(),
+            [
+                Instruction::Mov(
+                    Operand::MemoryRegister(Width::QWord(3)),
+                    Operand::Memory(Width::Byte(16))
+                ),
+                Instruction::Add(
+                    Operand::Value(u64::MAX),
+                    Operand::Register(Width::DWord(15)),
+                    Operand::Register(Width::Word(2))
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    pub fn parser_labels() {
+        let source = "// counts to ten\n\
+                      start:\n\
+                      mov 0, rq0\n\
+                      loop: add 1, rq0, rq0; cmp rq0, 10\n\
+                      jnz loop\n\
+                      jmp start";
+
+        let assembler = Assembler::parse(source).unwrap();
+
+        assert_eq!(assembler.labels()["start"], 0);
+        assert_eq!(assembler.labels()["loop"], 1);
+        assert_eq!(assembler.lines(), [3, 4, 4, 5, 6]);
+        assert_eq!(
+            assembler.instructions()[3],
+            Instruction::Jnz(Operand::Value(0))
+        );
+        assert_eq!(
+            assembler.instructions()[4],
+            Instruction::Jmp(Operand::Value(u64::MAX))
+        );
+    }
+
+    #[test]
+    pub fn parser_errors() {
+        assert_eq!(
+            Assembler::parse("mov 0, rq0\nmov 0").unwrap_err(),
+            Error::Syntax(2)
+        );
+        assert_eq!(
+            Assembler::parse("jmp nowhere").unwrap_err(),
+            Error::Syntax(1)
+        );
+        assert_eq!(
+            Assembler::parse("mov 0, rq16").unwrap_err(),
+            Error::Syntax(1)
+        );
+        assert_eq!(
+            Assembler::parse("\n\nfoo rq0").unwrap_err(),
+            Error::Syntax(3)
+        );
+    }
+}
@@ -0,0 +1,269 @@
+//! A [Brainfuck](https://esolangs.org/wiki/Brainfuck) frontend compiling to an [`Assembler`].
+//!
+//! The tape is made of byte cells, each kept in a word of memory from [`TAPE`] up, so that the
+//! pointer in `rq1` addresses the current cell as `[rq1]`. Cells wrap around at 256 and start at 0.
+//! `.` calls [`WRITE`] with the cell in `rb0`, `,` calls [`READ`] expecting the next input byte in
+//! `rq0`, or 0 at the end of input. Every other character is a comment.
+//!
+//! Runs of `+` and `-` or of `<` and `>` are folded into a single addition, and `[-]` or `[+]` into
+//! clearing the cell.
+
+use crate::assembler::Assembler;
+use crate::error::Error;
+use crate::instructions::Operand;
+use crate::register::Width;
+use crate::Vm;
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex, PoisonError};
+
+/// Address of the first cell of the tape.
+pub const TAPE: u64 = 0x1000;
+
+/// Host call writing the byte in `rb0` to the output.
+pub const WRITE: u64 = 1;
+
+/// Host call reading the next byte of input into `rq0`, or 0 at the end of input.
+pub const READ: u64 = 2;
+
+/// Register holding the address of the current cell.
+const POINTER: usize = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    /// Adds to the current cell, modulo 256.
+    Add(u8),
+    /// Moves the pointer by the given number of cells.
+    Move(i64),
+    Clear,
+    Write,
+    Read,
+    /// Starts a loop closed by the op at the given index.
+    Open(usize),
+    /// Ends a loop opened by the op at the given index.
+    Close(usize),
+}
+
+impl Op {
+    /// Returns the number of instructions the op compiles to.
+    fn length(self) -> usize {
+        match self {
+            Op::Add(0) | Op::Move(0) => 0,
+            Op::Move(_) | Op::Clear => 1,
+
+            _ => 2,
+        }
+    }
+}
+
+/// Parses the given source into ops, folding runs and matching brackets.
+fn parse(source: &str) -> Result<Vec<Op>, Error> {
+    let mut ops = Vec::new();
+    // Index and line of every loop still open.
+    let mut open: Vec<(usize, usize)> = Vec::new();
+
+    for (number, line) in source.lines().enumerate() {
+        for character in line.chars() {
+            match (character, ops.last_mut()) {
+                ('+', Some(Op::Add(value))) => *value = value.wrapping_add(1),
+                ('-', Some(Op::Add(value))) => *value = value.wrapping_sub(1),
+                ('>', Some(Op::Move(count))) => *count += 1,
+                ('<', Some(Op::Move(count))) => *count -= 1,
+
+                ('+', _) => ops.push(Op::Add(1)),
+                ('-', _) => ops.push(Op::Add(u8::MAX)),
+                ('>', _) => ops.push(Op::Move(1)),
+                ('<', _) => ops.push(Op::Move(-1)),
+                ('.', _) => ops.push(Op::Write),
+                (',', _) => ops.push(Op::Read),
+                ('[', _) => {
+                    open.push((ops.len(), number + 1));
+                    ops.push(Op::Open(0));
+                }
+                (']', _) => {
+                    let (start, _) = open.pop().ok_or(Error::Syntax(number + 1))?;
+
+                    if ops[start + 1..] == [Op::Add(1)] || ops[start + 1..] == [Op::Add(u8::MAX)] {
+                        ops.truncate(start);
+                        ops.push(Op::Clear);
+                    } else {
+                        ops[start] = Op::Open(ops.len());
+                        ops.push(Op::Close(start));
+                    }
+                }
+
+                _ => {}
+            }
+        }
+    }
+
+    match open.pop() {
+        Some((_, line)) => Err(Error::Syntax(line)),
+        None => Ok(ops),
+    }
+}
+
+/// Compiles the given source into an [`Assembler`], running until the end of the program.
+///
+/// # Example
+/// ```
+/// use vm::brainfuck::compile;
+/// let assembler = compile("++[>+<-]").unwrap();
+/// assert!(!assembler.instructions().is_empty());
+/// ```
+///
+/// # Errors
+/// When a bracket is unmatched, [`Syntax`](Error::Syntax) is returned with its 1-based line number.
+pub fn compile(source: &str) -> Result<Assembler, Error> {
+    let ops = parse(source)?;
+
+    // Index of the first instruction of each op, after the one setting up the pointer.
+    let starts: Vec<u64> = ops
+        .iter()
+        .scan(1, |index, op| {
+            let start = *index;
+            *index += op.length() as u64;
+
+            Some(start)
+        })
+        .collect();
+
+    let pointer = || Operand::Register(Width::QWord(POINTER));
+    let cell = || Operand::MemoryRegister(Width::QWord(POINTER));
+    let value = || Operand::Register(Width::QWord(0));
+
+    let assembler = Assembler::new().mov(Operand::Value(TAPE), pointer());
+
+    // Jumps continue after their target, so each loop jumps to the last instruction of the other end.
+    Ok(ops.iter().fold(assembler, |assembler, op| match *op {
+        Op::Add(0) | Op::Move(0) => assembler,
+        Op::Add(amount) => assembler
+            .add(Operand::Value(amount.into()), cell(), value())
+            .mov(Operand::Register(Width::Byte(0)), cell()),
+        Op::Move(count) => assembler.add(
+            Operand::Value(count.wrapping_mul(8) as u64),
+            pointer(),
+            pointer(),
+        ),
+        Op::Clear => assembler.mov(Operand::Value(0), cell()),
+        Op::Write => assembler.mov(cell(), value()).call(Operand::Value(WRITE)),
+        Op::Read => assembler.call(Operand::Value(READ)).mov(value(), cell()),
+        Op::Open(close) => assembler
+            .cmp(cell(), Operand::Value(0))
+            .jz(Operand::Value(starts[close] + 1)),
+        Op::Close(open) => assembler
+            .cmp(cell(), Operand::Value(0))
+            .jnz(Operand::Value(starts[open] + 1)),
+    }))
+}
+
+/// Compiles and runs the given source on a new [`Vm`] with the given input, returning its output.
+///
+/// # Example
+/// ```
+/// use vm::brainfuck::run;
+/// assert_eq!(run(",[+.,]", b"HAL").unwrap(), b"IBM");
+/// ```
+///
+/// # Errors
+/// When the source doesn't compile, its error is returned, as is any error from running it.
+pub fn run(source: &str, input: &[u8]) -> Result<Vec<u8>, Error> {
+    let output = Arc::new(Mutex::new(Vec::new()));
+    let written = Arc::clone(&output);
+    let input = Mutex::new(VecDeque::from(input.to_vec()));
+    let mut vm = Vm::new();
+
+    vm.register_call(WRITE, move |processor| {
+        let byte = processor.register(0)?.as_u8();
+
+        written
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(byte);
+
+        Ok(())
+    })?;
+    vm.register_call(READ, move |processor| {
+        let byte = input
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .pop_front()
+            .unwrap_or(0);
+
+        processor.register_mut(0)?.assign_u64(byte.into());
+
+        Ok(())
+    })?;
+    vm.load_instructions(compile(source)?.compile())?;
+
+    let handle = vm.new_processor();
+
+    vm.processor_mut(handle)?.start()?;
+
+    let output = std::mem::take(&mut *output.lock().unwrap_or_else(PoisonError::into_inner));
+
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::brainfuck::{compile, run};
+    use crate::error::Error;
+    use crate::instructions::{Instruction, Operand};
+    use crate::register::Width;
+
+    #[test]
+    pub fn brainfuck_folding() {
+        let cell = Operand::MemoryRegister(Width::QWord(1));
+
+        // A single pointer setup, one folded addition, and a cleared cell.
+        assert_eq!(
+            compile("+++-- >><<< [-] +-")
+                .unwrap()
+                .instructions()
+                .iter()
+                .skip(1)
+                .cloned()
+                .collect::<Vec<_>>(),
+            vec![
+                Instruction::Add(
+                    Operand::Value(1),
+                    cell.clone(),
+                    Operand::Register(Width::QWord(0))
+                ),
+                Instruction::Mov(Operand::Register(Width::Byte(0)), cell.clone()),
+                Instruction::Add(
+                    Operand::Value(u64::MAX - 7),
+                    Operand::Register(Width::QWord(1)),
+                    Operand::Register(Width::QWord(1))
+                ),
+                Instruction::Mov(Operand::Value(0), cell),
+            ]
+        );
+    }
+
+    #[test]
+    pub fn brainfuck_cells() {
+        // Cells wrap around in both directions.
+        assert_eq!(run("-.+.", b"").unwrap(), [255, 0]);
+        assert_eq!(run(&format!("{}.", "+".repeat(300)), b"").unwrap(), [44]);
+        // Cells left of the start are addressable too.
+        assert_eq!(run("<<+++>>+<<[>>+<<-]>>.", b"").unwrap(), [4]);
+        // Nested loops and loops skipped on entry.
+        assert_eq!(run("[.]+++[>++[>+<-]<-]>>.", b"").unwrap(), [6]);
+    }
+
+    #[test]
+    pub fn brainfuck_input() {
+        assert_eq!(run(",[.,]", b"echo").unwrap(), b"echo");
+        // The end of input reads as 0.
+        assert_eq!(run(",,,+.", b"a").unwrap(), [1]);
+    }
+
+    #[test]
+    pub fn brainfuck_errors() {
+        assert_eq!(compile("+[\n[-]").unwrap_err(), Error::Syntax(1));
+        assert_eq!(compile("+\n\n]").unwrap_err(), Error::Syntax(3));
+        assert_eq!(run("[]]", b"").unwrap_err(), Error::Syntax(1));
+    }
+}
@@ -0,0 +1,243 @@
+use crate::instructions::Instruction;
+use crate::observer::VmObserver;
+use crate::processor::Processor;
+use crate::register::{Flag, ReservedIndex};
+
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+/// Execution counts of a single instruction.
+pub struct Hits {
+    /// Times the instruction was executed.
+    pub executed: u64,
+    /// Times the instruction branched: a jump deciding to jump, or any other instruction changing
+    /// the [`InstructionCounter`](ReservedIndex::InstructionCounter).
+    pub taken: u64,
+}
+
+#[derive(Debug, Default)]
+/// [`VmObserver`] collecting instruction and branch coverage.
+///
+/// A jump counts as taken when it decides to jump, even onto the next instruction. Any other
+/// instruction counts as taken when it wrote a new value into the
+/// [`InstructionCounter`](ReservedIndex::InstructionCounter).
+pub struct Coverage {
+    hits: Mutex<Vec<Hits>>,
+    /// Decision of the jump about to run, taken before it clears the flag it decides on. Processors
+    /// of a [`Vm`](crate::Vm) run one at a time, so only a single jump is ever pending.
+    jump: Mutex<Option<bool>>,
+}
+
+/// Returns whether the given [`Instruction`] conditionally branches.
+fn is_conditional(instruction: &Instruction) -> bool {
+    matches!(instruction, Instruction::Jz(_) | Instruction::Jnz(_))
+}
+
+impl Coverage {
+    #[must_use]
+    /// Constructs a new [`Coverage`] with nothing covered.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    #[must_use]
+    /// Returns the recorded [`Hits`] indexed by instruction index.
+    pub fn hits(&self) -> Vec<Hits> {
+        self.hits
+            .lock()
+            .map(|hits| hits.clone())
+            .unwrap_or_default()
+    }
+
+    #[must_use]
+    /// Returns how many of the given instructions were executed at least once.
+    pub fn covered(&self, instructions: &[Instruction]) -> usize {
+        let hits = self.hits();
+
+        (0..instructions.len())
+            .filter(|index| hits.get(*index).is_some_and(|hits| hits.executed > 0))
+            .count()
+    }
+
+    #[must_use]
+    /// Exports the coverage of the given instructions as an lcov tracefile for `source_file`.
+    ///
+    /// `lines` maps each instruction to its 1-based source line, as returned by
+    /// [`Assembler::lines`](crate::assembler::Assembler::lines). Instructions without a line
+    /// are reported at their index plus one.
+    pub fn lcov(&self, source_file: &str, instructions: &[Instruction], lines: &[usize]) -> String {
+        let hits = self.hits();
+
+        let mut line_hits: BTreeMap<usize, u64> = BTreeMap::new();
+        let mut branches = String::new();
+        let (mut branches_found, mut branches_hit) = (0, 0);
+
+        for (index, instruction) in instructions.iter().enumerate() {
+            let line = lines.get(index).copied().unwrap_or(index + 1);
+            let hits = hits.get(index).copied().unwrap_or_default();
+
+            // Statements sharing a line report the most executed one.
+            let count = line_hits.entry(line).or_default();
+            *count = (*count).max(hits.executed);
+
+            if !is_conditional(instruction) {
+                continue;
+            }
+
+            for (branch, count) in [hits.taken, hits.executed - hits.taken]
+                .into_iter()
+                .enumerate()
+            {
+                branches_found += 1;
+
+                if count > 0 {
+                    branches_hit += 1;
+                }
+
+                if hits.executed == 0 {
+                    branches += &format!("BRDA:{line},{index},{branch},-\n");
+                } else {
+                    branches += &format!("BRDA:{line},{index},{branch},{count}\n");
+                }
+            }
+        }
+
+        let mut lcov = format!("TN:\nSF:{source_file}\n");
+
+        for (line, count) in &line_hits {
+            lcov += &format!("DA:{line},{count}\n");
+        }
+
+        lcov += &format!(
+            "LF:{}\nLH:{}\n",
+            line_hits.len(),
+            line_hits.values().filter(|count| **count > 0).count()
+        );
+        lcov += &branches;
+        lcov += &format!("BRF:{branches_found}\nBRH:{branches_hit}\nend_of_record\n");
+
+        lcov
+    }
+}
+
+impl VmObserver for Coverage {
+    fn before_instruction(&self, processor: &Processor, index: usize) {
+        let decision = match processor.instruction(index) {
+            Some(Instruction::Jmp(_)) => Some(true),
+            Some(Instruction::Jz(_)) => Some(processor.flag(Flag::Zero)),
+            Some(Instruction::Jnz(_)) => Some(!processor.flag(Flag::Zero)),
+
+            _ => None,
+        };
+
+        if let Ok(mut jump) = self.jump.lock() {
+            *jump = decision;
+        }
+    }
+
+    fn after_instruction(&self, processor: &Processor, index: usize) {
+        let Ok(mut hits) = self.hits.lock() else {
+            return;
+        };
+
+        if hits.len() <= index {
+            hits.resize(index + 1, Hits::default());
+        }
+
+        let jump = self.jump.lock().ok().and_then(|mut jump| jump.take());
+        let taken = jump.unwrap_or_else(|| {
+            processor
+                .register(ReservedIndex::InstructionCounter as usize)
+                .is_ok_and(|register| register.as_u64() != index as u64)
+        });
+
+        hits[index].executed += 1;
+
+        if taken {
+            hits[index].taken += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::Assembler;
+    use crate::Vm;
+
+    use std::sync::Arc;
+
+    #[test]
+    pub fn coverage_lcov() {
+        let source = "mov 0, rq0\n\
+                      loop: add 1, rq0, rq0\n\
+                      cmp rq0, 3\n\
+                      jnz loop\n\
+                      cmp rq0, 3\n\
+                      jz done\n\
+                      mov 1, rq1\n\
+                      done: mov 2, rq1";
+
+        let assembler = Assembler::parse(source).unwrap();
+        let instructions = assembler.instructions().to_vec();
+        let lines = assembler.lines().to_vec();
+
+        let mut vm = Vm::new();
+        let coverage = Arc::new(Coverage::new());
+
+        vm.register_observer(coverage.clone());
+        vm.load_instructions(assembler.compile()).unwrap();
+
+        let handle = vm.new_processor();
+        vm.processor_mut(handle).unwrap().start().unwrap();
+
+        assert_eq!(coverage.covered(&instructions), 7);
+        assert_eq!(
+            coverage.lcov("count.s", &instructions, &lines),
+            "TN:\n\
+             SF:count.s\n\
+             DA:1,1\n\
+             DA:2,3\n\
+             DA:3,3\n\
+             DA:4,3\n\
+             DA:5,1\n\
+             DA:6,1\n\
+             DA:7,0\n\
+             DA:8,1\n\
+             LF:8\n\
+             LH:7\n\
+             BRDA:4,3,0,2\n\
+             BRDA:4,3,1,1\n\
+             BRDA:6,5,0,1\n\
+             BRDA:6,5,1,0\n\
+             BRF:4\n\
+             BRH:3\n\
+             end_of_record\n"
+        );
+    }
+
+    #[test]
+    pub fn coverage_jump_to_next() {
+        // Each jump targets its own index, so it continues at the next instruction either way. The
+        // taken `jz` clears the zero flag, so `jnz` is taken too.
+        let assembler = Assembler::parse("cmp rq0, 0\njz 1\njnz 2\njmp 3\nmov 1, rq0").unwrap();
+
+        let mut vm = Vm::new();
+        let coverage = Arc::new(Coverage::new());
+
+        vm.register_observer(coverage.clone());
+        vm.load_instructions(assembler.compile()).unwrap();
+
+        let handle = vm.new_processor();
+        vm.processor_mut(handle).unwrap().start().unwrap();
+
+        let taken = coverage
+            .hits()
+            .iter()
+            .map(|hits| hits.taken)
+            .collect::<Vec<_>>();
+
+        assert_eq!(taken, [0, 1, 1, 1, 0]);
+    }
+}
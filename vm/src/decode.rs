@@ -0,0 +1,797 @@
+use crate::error::Error;
+use crate::instructions::{call, Execute, Instruction, Operand};
+use crate::processor::Processor;
+use crate::register::{Flag, Width, COUNTER, FLAGS};
+
+/// Operand read by a decoded instruction, with register indices and access sizes resolved.
+#[derive(Debug)]
+enum Source {
+    Value(u64),
+    Register {
+        index: usize,
+        size: usize,
+    },
+    Memory {
+        address: usize,
+        size: usize,
+    },
+    MemoryRegister {
+        index: usize,
+        size: usize,
+    },
+
+    /// Operand that fails with the given error once read, same as its [`Execute`] counterpart.
+    Invalid(Error),
+}
+
+/// Operand written by a decoded instruction, with register indices and access sizes resolved.
+#[derive(Debug)]
+enum Destination {
+    Register {
+        index: usize,
+        size: usize,
+    },
+    Memory {
+        address: usize,
+        size: usize,
+    },
+    MemoryRegister {
+        index: usize,
+        size: usize,
+    },
+
+    /// Operand that fails with the given error once written, same as its [`Execute`] counterpart.
+    Invalid(Error),
+}
+
+/// Instruction decoded ahead of time, executed without virtual calls or operand matching.
+#[derive(Debug)]
+enum Op {
+    Call(Source),
+    Mov(Source, Destination),
+    Jmp(Source),
+    Jz(Source),
+    Jnz(Source),
+    Cmp(Source, Source),
+    Add(Source, Source, Destination),
+
+    // Specializations of the forms above found in hot loops, with every operand a register or a value.
+    MovValueRegister {
+        value: u64,
+        index: usize,
+        size: usize,
+    },
+    AddValueRegister(AddValueRegister),
+    CmpRegisters(CmpRegisters),
+    JumpValue(JumpValue),
+
+    /// User defined [`Execute`] without an [`Instruction`], executed through its virtual call.
+    Extension,
+}
+
+/// Flag state a [`JumpValue`] is taken on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Condition {
+    Always,
+    Zero,
+    NotZero,
+}
+
+/// `add` of a value to a register, into a register of the same width.
+#[derive(Debug, Clone, Copy)]
+struct AddValueRegister {
+    value: u64,
+    source: usize,
+    destination: usize,
+    size: usize,
+}
+
+/// `cmp` of two registers of the same width.
+#[derive(Debug, Clone, Copy)]
+struct CmpRegisters {
+    value: usize,
+    comparator: usize,
+    size: usize,
+}
+
+/// `jmp`, `jz` or `jnz` to a value.
+#[derive(Debug, Clone, Copy)]
+struct JumpValue {
+    condition: Condition,
+    target: u64,
+}
+
+/// Loop tail of an optional [`AddValueRegister`], a [`CmpRegisters`] and a conditional [`JumpValue`],
+/// executed in a single dispatch.
+#[derive(Debug)]
+struct Fused {
+    add: Option<AddValueRegister>,
+    cmp: CmpRegisters,
+    jump: JumpValue,
+}
+
+/// Returns the given register index if it exists in the register file.
+fn resolve_register(width: &Width) -> Result<(usize, usize), Error> {
+    let (index, size) = (width.index(), width.size());
+
+    if index < 16 {
+        Ok((index, size))
+    } else {
+        Err(Error::RegisterIndexOutOfBounds)
+    }
+}
+
+fn decode_source(operand: &Operand) -> Source {
+    let resolved = match operand {
+        Operand::Value(value) => Ok(Source::Value(*value)),
+        Operand::Register(width) => {
+            resolve_register(width).map(|(index, size)| Source::Register { index, size })
+        }
+        Operand::Memory(width) => {
+            let (address, size) = (width.index(), width.size());
+
+            Ok(Source::Memory { address, size })
+        }
+        Operand::MemoryRegister(width) => {
+            resolve_register(width).map(|(index, size)| Source::MemoryRegister { index, size })
+        }
+
+        Operand::None => Err(Error::InvalidOperand),
+    };
+
+    resolved.unwrap_or_else(Source::Invalid)
+}
+
+fn decode_destination(operand: &Operand) -> Destination {
+    let resolved = match operand {
+        Operand::Register(width) => {
+            resolve_register(width).map(|(index, size)| Destination::Register { index, size })
+        }
+        Operand::Memory(width) => {
+            let (address, size) = (width.index(), width.size());
+
+            Ok(Destination::Memory { address, size })
+        }
+        Operand::MemoryRegister(width) => {
+            resolve_register(width).map(|(index, size)| Destination::MemoryRegister { index, size })
+        }
+
+        Operand::None | Operand::Value(_) => Err(Error::InvalidOperand),
+    };
+
+    resolved.unwrap_or_else(Destination::Invalid)
+}
+
+/// Returns the specialized form of the given instruction, if it has one.
+fn specialize(instruction: &Instruction) -> Option<Op> {
+    let register = |operand: &Operand| match operand {
+        Operand::Register(width) => resolve_register(width).ok(),
+
+        _ => None,
+    };
+    // Fused loop tails don't update the instruction counter per instruction, so it is left out.
+    let general = |operand: &Operand| register(operand).filter(|(index, _)| *index != COUNTER);
+
+    match instruction {
+        Instruction::Mov(Operand::Value(value), destination) => {
+            let (index, size) = register(destination)?;
+
+            Some(Op::MovValueRegister {
+                value: *value,
+                index,
+                size,
+            })
+        }
+        Instruction::Add(Operand::Value(value), source, destination) => {
+            let (source, size) = general(source)?;
+            let (destination, destination_size) = general(destination)?;
+
+            (size == destination_size).then_some(Op::AddValueRegister(AddValueRegister {
+                value: *value,
+                source,
+                destination,
+                size,
+            }))
+        }
+        Instruction::Cmp(value, comparator) => {
+            let (value, size) = general(value)?;
+            let (comparator, comparator_size) = general(comparator)?;
+
+            (size == comparator_size).then_some(Op::CmpRegisters(CmpRegisters {
+                value,
+                comparator,
+                size,
+            }))
+        }
+        Instruction::Jmp(Operand::Value(target)) => Some(Op::JumpValue(JumpValue {
+            condition: Condition::Always,
+            target: *target,
+        })),
+        Instruction::Jz(Operand::Value(target)) => Some(Op::JumpValue(JumpValue {
+            condition: Condition::Zero,
+            target: *target,
+        })),
+        Instruction::Jnz(Operand::Value(target)) => Some(Op::JumpValue(JumpValue {
+            condition: Condition::NotZero,
+            target: *target,
+        })),
+
+        _ => None,
+    }
+}
+
+fn decode(instruction: &Instruction) -> Op {
+    if let Some(op) = specialize(instruction) {
+        return op;
+    }
+
+    match instruction {
+        Instruction::Call(index) => Op::Call(match index {
+            Operand::Value(_) | Operand::Register(_) => decode_source(index),
+
+            _ => Source::Invalid(Error::InvalidOperand),
+        }),
+        Instruction::Mov(source, destination) => {
+            Op::Mov(decode_source(source), decode_destination(destination))
+        }
+        Instruction::Jmp(source) => Op::Jmp(decode_source(source)),
+        Instruction::Jz(source) => Op::Jz(decode_source(source)),
+        Instruction::Jnz(source) => Op::Jnz(decode_source(source)),
+        Instruction::Cmp(value, comparator) => {
+            Op::Cmp(decode_source(value), decode_source(comparator))
+        }
+        Instruction::Add(value, source, destination) => Op::Add(
+            decode_source(value),
+            decode_source(source),
+            decode_destination(destination),
+        ),
+    }
+}
+
+impl Source {
+    #[inline]
+    fn read(&self, processor: &mut Processor) -> Result<u64, Error> {
+        match self {
+            Source::Value(value) => Ok(*value),
+            Source::Register { index, size } => Ok(processor.read_register(*index, *size)),
+            Source::Memory { address, size } => processor.load(*address, *size),
+            Source::MemoryRegister { index, size } => {
+                let address = processor.read_register(*index, *size);
+
+                processor.load(address as usize, *size)
+            }
+
+            Source::Invalid(error) => Err(error.clone()),
+        }
+    }
+}
+
+impl Destination {
+    #[inline]
+    fn write(&self, processor: &mut Processor, value: u64) -> Result<(), Error> {
+        match self {
+            Destination::Register { index, size } => {
+                processor.write_register(*index, *size, value);
+
+                Ok(())
+            }
+            Destination::Memory { address, size } => processor.store(*address, *size, value),
+            Destination::MemoryRegister { index, size } => {
+                let address = processor.read_register(*index, *size);
+
+                processor.store(address as usize, *size, value)
+            }
+
+            Destination::Invalid(error) => Err(error.clone()),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+/// Program shared by every [`Processor`] of a [`Vm`](crate::Vm), decoded once on load.
+///
+/// Every executable with an [`Instruction`] runs from its decoded form, anything else is an
+/// extension and runs through [`Execute::execute`].
+pub(crate) struct Program {
+    executables: Vec<Box<dyn Execute>>,
+    ops: Vec<Op>,
+
+    /// Loop tail starting at every index, run instead of [`ops`](Program::ops) when fusion is enabled.
+    fused: Vec<Option<Fused>>,
+
+    #[cfg(feature = "jit")]
+    jit: crate::jit::Cache,
+}
+
+impl Program {
+    #[must_use]
+    /// Constructs a new [`Program`], decoding the given executables.
+    pub(crate) fn new(executables: Vec<Box<dyn Execute>>) -> Self {
+        let ops = executables
+            .iter()
+            .map(|executable| {
+                executable
+                    .instruction()
+                    .map_or(Op::Extension, |instruction| decode(&instruction))
+            })
+            .collect::<Vec<_>>();
+        let fused = (0..ops.len()).map(|index| fuse(&ops[index..])).collect();
+
+        Program {
+            #[cfg(feature = "jit")]
+            jit: crate::jit::Cache::new(
+                executables
+                    .iter()
+                    .map(|executable| executable.instruction())
+                    .collect(),
+            ),
+            executables,
+            ops,
+            fused,
+        }
+    }
+
+    #[cfg(feature = "jit")]
+    #[must_use]
+    /// Returns the blocks compiled for self.
+    pub(crate) fn jit(&self) -> &crate::jit::Cache {
+        &self.jit
+    }
+
+    #[must_use]
+    /// Returns the executables self was decoded from.
+    pub(crate) fn executables(&self) -> &[Box<dyn Execute>] {
+        &self.executables
+    }
+
+    #[must_use]
+    pub(crate) fn len(&self) -> usize {
+        self.ops.len()
+    }
+
+    #[inline]
+    /// Executes the instructions fused at the given index, or the single instruction there when
+    /// none are or when they exceed the given budget, returning how many were executed.
+    pub(crate) fn execute_fused(
+        &self,
+        index: usize,
+        processor: &mut Processor,
+        budget: u64,
+    ) -> Result<u64, Error> {
+        match &self.fused[index] {
+            Some(fused) if fused.length() <= budget => {
+                fused.execute(index, processor);
+
+                Ok(fused.length())
+            }
+
+            _ => self.execute(index, processor).map(|_| 1),
+        }
+    }
+
+    #[inline]
+    /// Executes the instruction at the given index on the given [`Processor`].
+    pub(crate) fn execute(&self, index: usize, processor: &mut Processor) -> Result<(), Error> {
+        match &self.ops[index] {
+            Op::Call(call_index) => {
+                let call_index = call_index.read(processor)?;
+
+                call::dispatch(processor, call_index)
+            }
+            Op::Mov(source, destination) => {
+                let value = source.read(processor)?;
+
+                destination.write(processor, value)
+            }
+            Op::Jmp(source) => jump(processor, source),
+            Op::Jz(source) => {
+                if !processor.flag(Flag::Zero) {
+                    return Ok(());
+                }
+
+                processor.set_flag(Flag::Zero, false);
+
+                jump(processor, source)
+            }
+            Op::Jnz(source) => {
+                if processor.flag(Flag::Zero) {
+                    return Ok(());
+                }
+
+                jump(processor, source)
+            }
+            Op::Cmp(value, comparator) => {
+                let value = value.read(processor)?;
+                let comparator = comparator.read(processor)?;
+
+                compare(processor, value, comparator);
+
+                Ok(())
+            }
+            Op::Add(value, source, destination) => {
+                let source = source.read(processor)?;
+                let value = value.read(processor)?;
+                let (result, overflow) = source.overflowing_add(value);
+
+                if overflow {
+                    processor.set_flag(Flag::Overflow, true);
+                }
+
+                destination.write(processor, result)
+            }
+
+            Op::MovValueRegister { value, index, size } => {
+                processor.write_register(*index, *size, *value);
+
+                Ok(())
+            }
+            Op::AddValueRegister(add) => {
+                add.execute(processor);
+
+                Ok(())
+            }
+            Op::CmpRegisters(cmp) => {
+                cmp.execute(processor);
+
+                Ok(())
+            }
+            Op::JumpValue(jump) => {
+                jump.execute(processor);
+
+                Ok(())
+            }
+
+            Op::Extension => self.executables[index].execute(processor),
+        }
+    }
+}
+
+impl AddValueRegister {
+    #[inline]
+    fn execute(&self, processor: &mut Processor) {
+        let source = processor.read_register(self.source, self.size);
+        let (result, overflow) = source.overflowing_add(self.value);
+
+        if overflow {
+            processor.set_flag(Flag::Overflow, true);
+        }
+
+        processor.write_register(self.destination, self.size, result);
+    }
+}
+
+impl CmpRegisters {
+    #[inline]
+    fn execute(&self, processor: &mut Processor) {
+        let value = processor.read_register(self.value, self.size);
+        let comparator = processor.read_register(self.comparator, self.size);
+
+        compare(processor, value, comparator);
+    }
+}
+
+impl JumpValue {
+    #[inline]
+    /// Jumps if the condition holds, returning whether the jump was taken.
+    fn execute(&self, processor: &mut Processor) -> bool {
+        let taken = match self.condition {
+            Condition::Always => true,
+            Condition::Zero => processor.flag(Flag::Zero),
+            Condition::NotZero => !processor.flag(Flag::Zero),
+        };
+
+        if taken {
+            if self.condition == Condition::Zero {
+                processor.set_flag(Flag::Zero, false);
+            }
+
+            processor.write_register(COUNTER, 8, self.target);
+        }
+
+        taken
+    }
+}
+
+impl Fused {
+    #[must_use]
+    /// Returns the number of instructions fused into self.
+    fn length(&self) -> u64 {
+        if self.add.is_some() {
+            3
+        } else {
+            2
+        }
+    }
+
+    #[inline]
+    /// Executes every fused instruction, leaving the instruction counter on the last one when
+    /// the jump isn't taken, same as executing them one by one.
+    fn execute(&self, index: usize, processor: &mut Processor) {
+        if let Some(add) = &self.add {
+            add.execute(processor);
+        }
+
+        self.cmp.execute(processor);
+
+        if !self.jump.execute(processor) {
+            processor.write_register(COUNTER, 8, index as u64 + self.length() - 1);
+        }
+    }
+}
+
+/// Returns the loop tail starting at the given op, if any.
+fn fuse(ops: &[Op]) -> Option<Fused> {
+    let (add, rest) = match ops {
+        [Op::AddValueRegister(add), rest @ ..] => (Some(*add), rest),
+
+        _ => (None, ops),
+    };
+
+    match rest {
+        [Op::CmpRegisters(cmp), Op::JumpValue(jump), ..] if jump.condition != Condition::Always => {
+            Some(Fused {
+                add,
+                cmp: *cmp,
+                jump: *jump,
+            })
+        }
+
+        _ => None,
+    }
+}
+
+/// Sets the Zero and Greater flags from comparing the given values.
+#[inline]
+fn compare(processor: &mut Processor, value: u64, comparator: u64) {
+    let mut state = processor.read_register(FLAGS, 8);
+
+    state &= !(Flag::Zero as u64 | Flag::Greater as u64);
+
+    if value == comparator {
+        state |= Flag::Zero as u64;
+    }
+
+    if value > comparator {
+        state |= Flag::Greater as u64;
+    }
+
+    processor.write_register(FLAGS, 8, state);
+}
+
+#[inline]
+fn jump(processor: &mut Processor, source: &Source) -> Result<(), Error> {
+    let target = source.read(processor)?;
+
+    processor.write_register(COUNTER, 8, target);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::Assembler;
+    use crate::profiler::Profiler;
+    use crate::Vm;
+
+    use std::sync::Arc;
+
+    #[derive(Debug)]
+    struct Double;
+
+    impl Execute for Double {
+        fn execute(&self, processor: &mut Processor) -> Result<(), Error> {
+            let register = processor.register_mut(0)?;
+
+            register.assign_u64(register.as_u64() * 2);
+
+            Ok(())
+        }
+    }
+
+    /// Runs the given program once decoded and once through [`Execute`] only, returning both register files.
+    fn run_both(source: &str) -> (Vec<u64>, Vec<u64>) {
+        let instructions = Assembler::parse(source).unwrap().instructions().to_vec();
+
+        let mut vm = Vm::new();
+        vm.load_instructions(
+            instructions
+                .iter()
+                .cloned()
+                .map(Instruction::executable)
+                .collect(),
+        )
+        .unwrap();
+
+        let handle = vm.new_processor();
+        let processor = vm.processor_mut(handle).unwrap();
+
+        processor.start().unwrap();
+
+        let decoded = (0..16)
+            .map(|index| processor.register(index).unwrap().as_u64())
+            .collect();
+
+        let mut vm = Vm::new();
+        let handle = vm.new_processor();
+        let processor = vm.processor_mut(handle).unwrap();
+        let executables: Vec<_> = instructions
+            .into_iter()
+            .map(Instruction::executable)
+            .collect();
+
+        while let Some(executable) =
+            executables.get(processor.register(15).unwrap().as_u64() as usize)
+        {
+            executable.execute(processor).unwrap();
+
+            let counter = processor.register_mut(15).unwrap();
+            counter.assign_u64(counter.as_u64() + 1);
+        }
+
+        let boxed = (0..16)
+            .map(|index| processor.register(index).unwrap().as_u64())
+            .collect();
+
+        (decoded, boxed)
+    }
+
+    #[test]
+    pub fn decode_matches_execute() {
+        let (decoded, boxed) = run_both(
+            "mov 0xffff, rq0\n\
+             mov 0x1234, rb0\n\
+             add -1, rq0, rq1\n\
+             mov 0x80, rq2\n\
+             mov rq1, [rq2]\n\
+             mov mw0x80, rq3\n\
+             add 1, rd3, rw4\n\
+             add 0x1ff, rb0, rb0\n\
+             cmp rq4, rq3\n\
+             jz done\n\
+             mov 1, rq5\n\
+             done: cmp 2, 2\n\
+             jnz done",
+        );
+
+        assert_eq!(decoded, boxed);
+    }
+
+    #[test]
+    pub fn decode_fusion_budget() {
+        let source = "mov 0, rq0\n\
+                      mov 10, rq1\n\
+                      loop: add 1, rq0, rq0\n\
+                      cmp rq0, rq1\n\
+                      jnz loop\n\
+                      mov 1, rq2";
+
+        let mut fused = Vm::new();
+        let mut unfused = Vm::new();
+
+        fused.set_fusion(true);
+        fused
+            .load_instructions(Assembler::parse(source).unwrap().compile())
+            .unwrap();
+        unfused
+            .load_instructions(Assembler::parse(source).unwrap().compile())
+            .unwrap();
+
+        let fused_handle = fused.new_processor();
+        let unfused_handle = unfused.new_processor();
+
+        for steps in [1, 2, 4, 3, 5, 7].into_iter().cycle().take(20) {
+            let fused = fused.processor_mut(fused_handle).unwrap();
+            let unfused = unfused.processor_mut(unfused_handle).unwrap();
+
+            assert_eq!(fused.run(steps).unwrap(), unfused.run(steps).unwrap());
+            assert_eq!(
+                (0..16)
+                    .map(|index| fused.register(index).unwrap().as_u64())
+                    .collect::<Vec<_>>(),
+                (0..16)
+                    .map(|index| unfused.register(index).unwrap().as_u64())
+                    .collect::<Vec<_>>()
+            );
+        }
+
+        assert_eq!(
+            fused
+                .processor(fused_handle)
+                .unwrap()
+                .register(2)
+                .unwrap()
+                .as_u64(),
+            1
+        );
+    }
+
+    #[test]
+    pub fn decode_fusion_counter() {
+        // The add jumps past the cmp, and the cmp reads its own index.
+        for source in [
+            "add 1, rq15, rq15; cmp rq0, rq1; jnz 100; mov 7, rq2; mov 8, rq3",
+            "mov 1, rq1; add 0, rq0, rq0; cmp rq15, rq1; jz 100; mov 7, rq2",
+        ] {
+            let [fused, unfused] = [true, false].map(|fusion| {
+                let mut vm = Vm::new();
+
+                vm.set_fusion(fusion);
+                vm.load_instructions(Assembler::parse(source).unwrap().compile())
+                    .unwrap();
+
+                let handle = vm.new_processor();
+                let processor = vm.processor_mut(handle).unwrap();
+
+                processor.start().unwrap();
+
+                (0..16)
+                    .map(|index| processor.register(index).unwrap().as_u64())
+                    .collect::<Vec<_>>()
+            });
+
+            assert_eq!(fused, unfused, "{source}");
+        }
+    }
+
+    #[test]
+    pub fn decode_fusion_observed() {
+        let mut vm = Vm::new();
+        let profiler = Arc::new(Profiler::new());
+
+        vm.set_fusion(true);
+        vm.register_observer(profiler.clone());
+        vm.load_instructions(
+            Assembler::parse("mov 5, rq1\nloop: add 1, rq0, rq0\ncmp rq0, rq1\njnz loop")
+                .unwrap()
+                .compile(),
+        )
+        .unwrap();
+
+        let handle = vm.new_processor();
+
+        vm.processor_mut(handle).unwrap().start().unwrap();
+
+        assert_eq!(profiler.hits(), [1, 5, 5, 5]);
+    }
+
+    #[test]
+    pub fn decode_extension() {
+        let mut vm = Vm::new();
+
+        vm.load_instructions(Vec::from([
+            Instruction::Mov(Operand::Value(3), Operand::Register(Width::QWord(0))).executable(),
+            Box::new(Double) as Box<dyn Execute>,
+        ]))
+        .unwrap();
+
+        let handle = vm.new_processor();
+        let processor = vm.processor_mut(handle).unwrap();
+
+        processor.start().unwrap();
+
+        assert_eq!(processor.register(0).unwrap().as_u64(), 6);
+    }
+
+    #[test]
+    pub fn decode_invalid_operands() {
+        let mut vm = Vm::new();
+
+        vm.load_instructions(Vec::from([
+            Instruction::Jz(Operand::None).executable(),
+            Instruction::Mov(Operand::Value(1), Operand::Value(2)).executable(),
+        ]))
+        .unwrap();
+
+        let handle = vm.new_processor();
+
+        assert_eq!(
+            vm.processor_mut(handle).unwrap().start(),
+            Err(Error::InvalidOperand)
+        );
+        assert_eq!(
+            vm.processor(handle).unwrap().register(15).unwrap().as_u64(),
+            1
+        );
+    }
+}
@@ -0,0 +1,955 @@
+//! Decompiles programs into C-like pseudocode.
+//!
+//! Structures are recovered from the [`Cfg`] in program order: a block jumped back to from a later
+//! one heads a loop, and a conditional jump forward over blocks guards them, with an `else` when the
+//! guarded blocks end jumping over more. Anything else falls back to `goto`. Within a block, values
+//! used once are folded into the expression using them. Flags aren't shown, conditional jumps test
+//! the comparison before them in the block, or the `zero` flag when there is none. A comparison
+//! whose zero flag later blocks test too stays as `compare(a, b);`, the jumps testing `zero`.
+//!
+//! A jump sets the instruction counter before it increments, so a computed `goto *(r1 + 1)`
+//! continues at the instruction after the index held in `r1`.
+
+use crate::analysis::cfg::{Cfg, Target};
+use crate::analysis::dataflow::{solve, Analysis, Direction, Liveness, RegisterSet};
+use crate::assembler::label_names;
+use crate::instructions::{Instruction, Operand};
+use crate::register::{mask, Width, COUNTER, FLAGS};
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::{self, Display, Formatter};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// A value computed from operands.
+enum Expr {
+    Constant(u64),
+    /// A register read with the given size in bytes.
+    Register(usize, usize),
+    /// Memory at an address, read with the given size in bytes.
+    Memory(Box<Expr>, usize),
+    Add(Box<Expr>, Box<Expr>),
+    /// Whether both sides are equal, or differ when false.
+    Compare(Box<Expr>, Box<Expr>, bool),
+    /// Whether the zero flag is set, or clear when false.
+    Zero(bool),
+    Invalid,
+}
+
+impl Expr {
+    /// Returns the condition holding exactly when self doesn't.
+    fn negate(self) -> Self {
+        match self {
+            Expr::Compare(left, right, equal) => Expr::Compare(left, right, !equal),
+            Expr::Zero(set) => Expr::Zero(!set),
+            expr => expr,
+        }
+    }
+
+    /// Appends the registers self reads, with the size of each read.
+    fn reads(&self, reads: &mut Vec<(usize, usize)>) {
+        match self {
+            Expr::Register(index, size) => reads.push((*index, *size)),
+            Expr::Memory(address, _) => address.reads(reads),
+            Expr::Add(left, right) | Expr::Compare(left, right, _) => {
+                left.reads(reads);
+                right.reads(reads);
+            }
+            Expr::Zero(_) => reads.push((FLAGS, 8)),
+            Expr::Constant(_) | Expr::Invalid => {}
+        }
+    }
+
+    fn reads_memory(&self) -> bool {
+        match self {
+            Expr::Memory(..) => true,
+            Expr::Add(left, right) | Expr::Compare(left, right, _) => {
+                left.reads_memory() || right.reads_memory()
+            }
+            Expr::Register(..) | Expr::Constant(_) | Expr::Zero(_) | Expr::Invalid => false,
+        }
+    }
+
+    /// Replaces full reads of the given register with the given expression.
+    fn substitute(&mut self, register: usize, value: &Expr) {
+        match self {
+            Expr::Register(index, 8) if *index == register => *self = value.clone(),
+            Expr::Memory(address, _) => address.substitute(register, value),
+            Expr::Add(left, right) | Expr::Compare(left, right, _) => {
+                left.substitute(register, value);
+                right.substitute(register, value);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Returns the suffix of a read or write of the given size in bytes.
+fn suffix(size: usize) -> &'static str {
+    match size {
+        1 => ".u8",
+        2 => ".u16",
+        4 => ".u32",
+        _ => "",
+    }
+}
+
+impl Display for Expr {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Expr::Constant(value) if *value <= 0xffff => write!(f, "{value}"),
+            Expr::Constant(value) => write!(f, "{value:#x}"),
+            Expr::Register(FLAGS, size) => write!(f, "flags{}", suffix(*size)),
+            Expr::Register(COUNTER, size) => write!(f, "pc{}", suffix(*size)),
+            Expr::Register(index, size) => write!(f, "r{index}{}", suffix(*size)),
+            Expr::Memory(address, size) => write!(f, "mem{}[{address}]", size * 8),
+            Expr::Add(left, right) => write!(f, "{left} + {right}"),
+            Expr::Compare(left, right, true) => write!(f, "{left} == {right}"),
+            Expr::Compare(left, right, false) => write!(f, "{left} != {right}"),
+            Expr::Zero(true) => f.write_str("zero"),
+            Expr::Zero(false) => f.write_str("!zero"),
+            Expr::Invalid => f.write_str("<invalid>"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+/// An instruction other than a jump.
+enum Statement {
+    /// Writes a value to a register or memory, the destination held as an expression reading it.
+    Assign(Expr, Expr),
+    Call(Expr),
+    /// A comparison no conditional jump in the block tests.
+    Compare(Expr, Expr),
+}
+
+impl Statement {
+    /// Returns the registers read, with the size of each read; narrow writes read their register.
+    fn reads(&self) -> Vec<(usize, usize)> {
+        let mut reads = Vec::new();
+
+        match self {
+            Statement::Assign(destination, value) => {
+                match destination {
+                    Expr::Register(index, size) if *size != 8 => reads.push((*index, *size)),
+                    Expr::Memory(address, _) => address.reads(&mut reads),
+                    _ => {}
+                }
+
+                value.reads(&mut reads);
+            }
+            Statement::Call(call_index) => {
+                call_index.reads(&mut reads);
+                reads.extend((0..16).map(|index| (index, 8)));
+            }
+            Statement::Compare(value, comparator) => {
+                value.reads(&mut reads);
+                comparator.reads(&mut reads);
+            }
+        }
+
+        reads
+    }
+
+    /// Returns whether self may write the given register.
+    fn writes(&self, register: usize) -> bool {
+        match self {
+            Statement::Assign(Expr::Register(index, _), _) => *index == register,
+            Statement::Assign(..) => false,
+            Statement::Call(_) => true,
+            Statement::Compare(..) => register == FLAGS,
+        }
+    }
+
+    fn writes_memory(&self) -> bool {
+        matches!(
+            self,
+            Statement::Assign(Expr::Memory(..), _) | Statement::Call(_)
+        )
+    }
+
+    fn substitute(&mut self, register: usize, value: &Expr) {
+        match self {
+            Statement::Assign(destination, source) => {
+                if let Expr::Memory(address, _) = destination {
+                    address.substitute(register, value);
+                }
+
+                source.substitute(register, value);
+            }
+            Statement::Call(expr) => expr.substitute(register, value),
+            Statement::Compare(left, right) => {
+                left.substitute(register, value);
+                right.substitute(register, value);
+            }
+        }
+    }
+}
+
+impl Display for Statement {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Statement::Assign(destination, value) => write!(f, "{destination} = {value};"),
+            Statement::Call(call_index) => write!(f, "call({call_index});"),
+            Statement::Compare(value, comparator) => write!(f, "compare({value}, {comparator});"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// Where a jump leads.
+enum Destination {
+    /// The block with the given number, the number of blocks when leaving the program.
+    Block(usize),
+    Computed(Expr),
+}
+
+#[derive(Debug, Clone)]
+/// How control leaves a block.
+enum Terminator {
+    Fall,
+    Goto(Destination),
+    If(Expr, Destination),
+    /// A write to the instruction counter, shown as a statement.
+    Unknown,
+}
+
+impl Terminator {
+    fn expressions(&mut self) -> Vec<&mut Expr> {
+        match self {
+            Terminator::Goto(Destination::Computed(target)) => vec![target],
+            Terminator::If(condition, Destination::Computed(target)) => vec![condition, target],
+            Terminator::If(condition, _) => vec![condition],
+            Terminator::Fall | Terminator::Goto(_) | Terminator::Unknown => Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+/// A block as statements.
+struct Block {
+    statements: Vec<Statement>,
+    terminator: Terminator,
+}
+
+/// Returns the given operand of the instruction at the given index as an expression.
+fn operand(operand: &Operand, index: usize) -> Expr {
+    let register = |width: &Width| match (width.index(), width.size()) {
+        (COUNTER, size) => Expr::Constant(index as u64 & mask(size)),
+        (register, size) => Expr::Register(register, size),
+    };
+
+    match operand {
+        Operand::Value(value) => Expr::Constant(*value),
+        Operand::Register(width) => register(width),
+        Operand::Memory(width) => {
+            let (address, size) = (width.index(), width.size());
+
+            Expr::Memory(Box::new(Expr::Constant(address as u64)), size)
+        }
+        Operand::MemoryRegister(width) => Expr::Memory(Box::new(register(width)), width.size()),
+
+        Operand::None => Expr::Invalid,
+    }
+}
+
+/// Returns the destination operand of the instruction at the given index as an expression.
+fn destination(destination: &Operand, index: usize) -> Expr {
+    match destination {
+        Operand::Register(width) => {
+            let (register, size) = (width.index(), width.size());
+
+            Expr::Register(register, size)
+        }
+        destination => operand(destination, index),
+    }
+}
+
+/// Returns whether the given operand reads the flags register, as a value or an address.
+fn reads_flags(operand: &Operand) -> bool {
+    matches!(
+        operand,
+        Operand::Register(width) | Operand::MemoryRegister(width) if width.index() == FLAGS
+    )
+}
+
+/// Whether the zero flag may be tested before a comparison sets it again, a backward [`Analysis`].
+///
+/// Register liveness can't tell, `cmp` reads the flags register since it keeps the overflow flag.
+struct ZeroTested;
+
+impl Analysis for ZeroTested {
+    type Fact = bool;
+
+    const DIRECTION: Direction = Direction::Backward;
+
+    fn bottom(&self) -> bool {
+        false
+    }
+
+    fn boundary(&self) -> bool {
+        false
+    }
+
+    fn join(&self, fact: &mut bool, other: &bool) {
+        *fact |= *other;
+    }
+
+    fn transfer(&self, _index: usize, instruction: &Instruction, tested: &mut bool) {
+        *tested = match instruction {
+            Instruction::Jz(_) | Instruction::Jnz(_) => true,
+            Instruction::Cmp(value, comparator) => reads_flags(value) || reads_flags(comparator),
+            // `add` only sets the overflow flag.
+            Instruction::Add(value, source, destination) => {
+                *tested || [value, source, destination].into_iter().any(reads_flags)
+            }
+            Instruction::Mov(source, Operand::Register(Width::QWord(FLAGS))) => reads_flags(source),
+            Instruction::Mov(source, destination) => {
+                *tested || reads_flags(source) || reads_flags(destination)
+            }
+            // What a host call reads isn't shown, only its index.
+            Instruction::Call(index) => *tested || reads_flags(index),
+            Instruction::Jmp(source) => *tested || reads_flags(source),
+        };
+    }
+}
+
+/// Builds the statements of every block, folding values used once into their use.
+fn blocks(cfg: &Cfg) -> Vec<Block> {
+    let instructions = cfg.instructions();
+    let live = solve(cfg, &Liveness::new(RegisterSet::from_iter(0..FLAGS)));
+    let zero_tested = solve(cfg, &ZeroTested);
+    let exit = cfg.blocks().len();
+
+    cfg.blocks()
+        .iter()
+        .map(|block| {
+            let mut statements = Vec::new();
+            let mut terminator = Terminator::Fall;
+
+            let target = |source: &Operand| match source {
+                Operand::Value(value) => {
+                    let target = value.wrapping_add(1);
+
+                    Destination::Block(
+                        usize::try_from(target)
+                            .ok()
+                            .and_then(|target| cfg.block_of(target))
+                            .unwrap_or(exit),
+                    )
+                }
+                source => Destination::Computed(operand(source, block.end - 1)),
+            };
+
+            for (index, instruction) in instructions
+                .iter()
+                .enumerate()
+                .take(block.end)
+                .skip(block.start)
+            {
+                let statement = match instruction {
+                    Instruction::Call(call_index) => Statement::Call(operand(call_index, index)),
+                    Instruction::Mov(source, to) => {
+                        Statement::Assign(destination(to, index), operand(source, index))
+                    }
+                    Instruction::Add(value, source, to) => Statement::Assign(
+                        destination(to, index),
+                        Expr::Add(
+                            Box::new(operand(source, index)),
+                            Box::new(operand(value, index)),
+                        ),
+                    ),
+                    Instruction::Cmp(value, comparator) => {
+                        Statement::Compare(operand(value, index), operand(comparator, index))
+                    }
+                    Instruction::Jmp(source) => {
+                        terminator = Terminator::Goto(target(source));
+                        continue;
+                    }
+                    Instruction::Jz(source) => {
+                        terminator = Terminator::If(Expr::Zero(true), target(source));
+                        continue;
+                    }
+                    Instruction::Jnz(source) => {
+                        terminator = Terminator::If(Expr::Zero(false), target(source));
+                        continue;
+                    }
+                };
+
+                if matches!(&statement, Statement::Assign(Expr::Register(COUNTER, _), _)) {
+                    terminator = Terminator::Unknown;
+                }
+
+                statements.push(statement);
+            }
+
+            let live = *live.after(block.end - 1);
+            let tested = *zero_tested.after(block.end - 1);
+            let mut block = Block {
+                statements,
+                terminator,
+            };
+
+            test_comparison(&mut block, tested);
+
+            while fold(&mut block, live) {}
+
+            block
+        })
+        .collect()
+}
+
+/// Turns the zero flag test ending the given block into the comparison setting it, if in the block.
+///
+/// When the zero flag is `tested` again after the block, the comparison stays for those tests.
+fn test_comparison(block: &mut Block, tested: bool) {
+    let Terminator::If(Expr::Zero(set), _) = block.terminator else {
+        return;
+    };
+
+    if tested {
+        return;
+    }
+
+    let Some(position) = block
+        .statements
+        .iter()
+        .rposition(|statement| statement.writes(FLAGS))
+    else {
+        return;
+    };
+    let Statement::Compare(value, comparator) = block.statements[position].clone() else {
+        return;
+    };
+
+    let mut reads = Vec::new();
+
+    value.reads(&mut reads);
+    comparator.reads(&mut reads);
+
+    let memory = value.reads_memory() || comparator.reads_memory();
+    let clobbered = block.statements[position + 1..].iter().any(|statement| {
+        reads
+            .iter()
+            .any(|(register, _)| statement.writes(*register))
+            || (memory && statement.writes_memory())
+    });
+
+    if clobbered {
+        return;
+    }
+
+    block.statements.remove(position);
+    block.terminator = match std::mem::replace(&mut block.terminator, Terminator::Fall) {
+        Terminator::If(_, target) => Terminator::If(
+            Expr::Compare(Box::new(value), Box::new(comparator), set),
+            target,
+        ),
+        terminator => terminator,
+    };
+}
+
+/// Folds one register value used exactly once into its use, returning whether one was.
+///
+/// Registers in `live` are read after the block, so values left in them stay.
+fn fold(block: &mut Block, live: RegisterSet) -> bool {
+    let length = block.statements.len();
+
+    for position in 0..length {
+        let Statement::Assign(Expr::Register(register, 8), value) = &block.statements[position]
+        else {
+            continue;
+        };
+        let (register, value) = (*register, value.clone());
+
+        if register >= FLAGS {
+            continue;
+        }
+
+        let mut reads = Vec::new();
+
+        value.reads(&mut reads);
+
+        let memory = value.reads_memory();
+        let clobbers = |statement: &Statement| {
+            reads.iter().any(|(read, _)| statement.writes(*read))
+                || (memory && statement.writes_memory())
+        };
+
+        // Statements using the value once per use, the terminator as [`None`]. Narrow reads
+        // count twice, since only full reads can be replaced.
+        let mut uses = Vec::new();
+        let mut redefined = false;
+        let mut blocked = false;
+
+        for later in position + 1..length {
+            let statement = &block.statements[later];
+
+            if matches!(statement, Statement::Call(_)) {
+                blocked = true;
+                break;
+            }
+
+            for (read, size) in statement.reads() {
+                if read == register {
+                    uses.extend(std::iter::repeat_n(
+                        Some(later),
+                        if size == 8 { 1 } else { 2 },
+                    ));
+                }
+            }
+
+            if statement.writes(register) {
+                redefined = true;
+                break;
+            }
+
+            blocked |= uses.is_empty() && clobbers(statement);
+        }
+
+        if !redefined {
+            let mut terminator_reads = Vec::new();
+
+            for expr in block.terminator.expressions() {
+                expr.reads(&mut terminator_reads);
+            }
+
+            for (read, size) in terminator_reads {
+                if read == register {
+                    uses.extend(std::iter::repeat_n(None, if size == 8 { 1 } else { 2 }));
+                }
+            }
+
+            blocked |= live.contains(register);
+        }
+
+        if blocked || uses.len() != 1 {
+            continue;
+        }
+
+        match uses[0] {
+            Some(later) => block.statements[later].substitute(register, &value),
+            None => {
+                for expr in block.terminator.expressions() {
+                    expr.substitute(register, &value);
+                }
+            }
+        }
+
+        block.statements.remove(position);
+
+        return true;
+    }
+
+    false
+}
+
+#[derive(Debug, Clone, Copy)]
+/// The innermost loop around a region.
+struct Loop {
+    header: usize,
+    /// Block after the loop, which `break` leads to.
+    exit: usize,
+    /// Whether `continue` leads to the header.
+    continues: bool,
+    /// Block jumping back to the header, its jump shown by the loop itself.
+    latch: usize,
+}
+
+/// A line of pseudocode.
+enum Line {
+    Text(usize, String),
+    /// Start of a block, labelled if a `goto` leads there.
+    Label(usize, usize),
+}
+
+struct Decompiler<'a> {
+    blocks: Vec<Block>,
+    cfg: &'a Cfg,
+    names: BTreeMap<usize, &'a str>,
+    gotos: BTreeSet<usize>,
+    lines: Vec<Line>,
+}
+
+impl Decompiler<'_> {
+    fn name(&self, block: usize) -> String {
+        let start = self.cfg.blocks()[block].start;
+
+        match self.names.get(&start) {
+            Some(name) => (*name).to_string(),
+            None => format!("L{start}"),
+        }
+    }
+
+    /// Returns the statement jumping to the given block, [`None`] when the next block is it.
+    fn jump(&mut self, target: usize, next: usize, inner: Option<Loop>) -> Option<String> {
+        if target == next {
+            return None;
+        }
+
+        if let Some(inner) = inner {
+            if target == inner.exit {
+                return Some("break;".to_string());
+            }
+
+            if inner.continues && target == inner.header {
+                return Some("continue;".to_string());
+            }
+        }
+
+        if target == self.blocks.len() {
+            return Some("return;".to_string());
+        }
+
+        self.gotos.insert(target);
+
+        Some(format!("goto {};", self.name(target)))
+    }
+
+    fn text(&mut self, depth: usize, text: String) {
+        self.lines.push(Line::Text(depth, text));
+    }
+
+    /// Returns the latest block in the given range jumping back to the given block.
+    fn latch(&self, header: usize, to: usize) -> Option<usize> {
+        (header..to).rev().find(|number| {
+            self.cfg.blocks()[*number]
+                .successors
+                .iter()
+                .any(|edge| edge.target == Target::Block(header))
+        })
+    }
+
+    /// Writes the blocks from `from` up to `to`, after which control continues at `follow`.
+    fn region(&mut self, from: usize, to: usize, follow: usize, depth: usize, inner: Option<Loop>) {
+        let mut number = from;
+
+        while number < to {
+            let next = if number + 1 < to { number + 1 } else { follow };
+            let entered = inner.is_some_and(|inner| inner.header == number);
+
+            if !entered {
+                self.lines.push(Line::Label(depth, number));
+
+                if let Some(latch) = self.latch(number, to) {
+                    if self.structure_loop(number, latch, depth) {
+                        let after = if latch + 1 < to { latch + 1 } else { follow };
+
+                        if let Some(jump) = self.jump(latch + 1, after, inner) {
+                            self.text(depth, jump);
+                        }
+
+                        number = latch + 1;
+                        continue;
+                    }
+                }
+            }
+
+            for statement in self.blocks[number].statements.clone() {
+                self.text(depth, statement.to_string());
+            }
+
+            if inner.is_some_and(|inner| inner.latch == number) {
+                number += 1;
+                continue;
+            }
+
+            match self.blocks[number].terminator.clone() {
+                Terminator::Fall => {
+                    if let Some(jump) = self.jump(number + 1, next, inner) {
+                        self.text(depth, jump);
+                    }
+                }
+                Terminator::Goto(Destination::Block(target)) => {
+                    if let Some(jump) = self.jump(target, next, inner) {
+                        self.text(depth, jump);
+                    }
+                }
+                Terminator::Goto(Destination::Computed(target)) => {
+                    self.text(depth, format!("goto *({target} + 1);"));
+                }
+                Terminator::If(condition, Destination::Block(target))
+                    if target > number + 1
+                        && target <= to
+                        && !inner.is_some_and(|inner| {
+                            target == inner.exit || target == inner.header
+                        }) =>
+                {
+                    number = self.structure_if(condition, number, target, to, depth, inner);
+                    continue;
+                }
+                Terminator::If(condition, destination) => {
+                    let jump = match destination {
+                        Destination::Block(target) => self.jump(target, number + 1, inner),
+                        Destination::Computed(target) => Some(format!("goto *({target} + 1);")),
+                    };
+
+                    if let Some(jump) = jump {
+                        self.text(depth, format!("if ({condition}) {jump}"));
+                    }
+
+                    if let Some(jump) = self.jump(number + 1, next, inner) {
+                        self.text(depth, jump);
+                    }
+                }
+                Terminator::Unknown => {}
+            }
+
+            number += 1;
+        }
+    }
+
+    /// Writes a conditional jump forward over blocks as `if`, returning the block after it.
+    fn structure_if(
+        &mut self,
+        condition: Expr,
+        number: usize,
+        target: usize,
+        to: usize,
+        depth: usize,
+        inner: Option<Loop>,
+    ) -> usize {
+        let guarded = target - 1;
+        let end = match self.blocks[guarded].terminator {
+            Terminator::Goto(Destination::Block(end))
+                if end > target
+                    && end <= to
+                    && inner.is_none_or(|inner| inner.latch != guarded) =>
+            {
+                Some(end)
+            }
+            _ => None,
+        };
+
+        self.text(depth, format!("if ({}) {{", condition.negate()));
+
+        match end {
+            Some(end) => {
+                self.region(number + 1, target, end, depth + 1, inner);
+                self.text(depth, "} else {".to_string());
+                self.region(target, end, end, depth + 1, inner);
+                self.text(depth, "}".to_string());
+
+                end
+            }
+            None => {
+                self.region(number + 1, target, target, depth + 1, inner);
+                self.text(depth, "}".to_string());
+
+                target
+            }
+        }
+    }
+
+    /// Writes the loop from the given header to the given latch, returning whether it has a shape
+    /// a loop can show.
+    fn structure_loop(&mut self, header: usize, latch: usize, depth: usize) -> bool {
+        let exit = latch + 1;
+        let back = Destination::Block(header);
+
+        match (&self.blocks[header], &self.blocks[latch].terminator) {
+            (
+                Block {
+                    statements,
+                    terminator: Terminator::If(condition, Destination::Block(target)),
+                },
+                Terminator::Goto(to),
+            ) if statements.is_empty() && *target == exit && *to == back && latch > header => {
+                let condition = condition.clone().negate();
+                let inner = Loop {
+                    header,
+                    exit,
+                    continues: true,
+                    latch,
+                };
+
+                self.text(depth, format!("while ({condition}) {{"));
+                self.region(header + 1, exit, header, depth + 1, Some(inner));
+                self.text(depth, "}".to_string());
+            }
+            (_, Terminator::Goto(to)) if *to == back => {
+                let inner = Loop {
+                    header,
+                    exit,
+                    continues: true,
+                    latch,
+                };
+
+                self.text(depth, "while (true) {".to_string());
+                self.region(header, exit, header, depth + 1, Some(inner));
+                self.text(depth, "}".to_string());
+            }
+            (_, Terminator::If(condition, to)) if *to == back => {
+                let condition = condition.clone();
+                let inner = Loop {
+                    header,
+                    exit,
+                    continues: false,
+                    latch,
+                };
+
+                self.text(depth, "do {".to_string());
+                self.region(header, exit, usize::MAX, depth + 1, Some(inner));
+                self.text(depth, format!("}} while ({condition});"));
+            }
+
+            _ => return false,
+        }
+
+        true
+    }
+}
+
+/// Decompiles the given program into C-like pseudocode, naming jump targets after the labels
+/// pointing at them.
+///
+/// # Example
+/// ```
+/// use vm::assembler::Assembler;
+/// use vm::decompile::decompile;
+/// let assembler = Assembler::parse("mov 0, rq0\nloop: add 1, rq0, rq0\ncmp rq0, 10\njnz loop").unwrap();
+/// let pseudocode = decompile(assembler.instructions(), assembler.labels());
+/// assert!(pseudocode.contains("do {\n        r0 = r0 + 1;\n    } while (r0 != 10);\n"));
+/// ```
+#[must_use]
+pub fn decompile(instructions: &[Instruction], labels: &BTreeMap<String, usize>) -> String {
+    let cfg = Cfg::new(instructions);
+
+    let names = label_names(labels);
+
+    let mut decompiler = Decompiler {
+        blocks: blocks(&cfg),
+        cfg: &cfg,
+        names,
+        gotos: BTreeSet::new(),
+        lines: Vec::new(),
+    };
+    let length = decompiler.blocks.len();
+
+    decompiler.region(0, length, length, 1, None);
+
+    let mut source = String::from("// Decompiled by `vm::decompile`.\nvoid main() {\n");
+
+    for line in &decompiler.lines {
+        match line {
+            Line::Text(depth, text) => {
+                source += &format!("{}{text}\n", "    ".repeat(*depth));
+            }
+            Line::Label(depth, number) if decompiler.gotos.contains(number) => {
+                source += &format!(
+                    "{}{}:\n",
+                    "    ".repeat(depth - 1),
+                    decompiler.name(*number)
+                );
+            }
+            Line::Label(..) => {}
+        }
+    }
+
+    source += "}\n";
+
+    source
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::assembler::Assembler;
+    use crate::decompile::decompile;
+
+    fn decompiled(source: &str) -> String {
+        let assembler = Assembler::parse(source).unwrap();
+
+        decompile(assembler.instructions(), assembler.labels())
+    }
+
+    #[test]
+    pub fn decompile_structures() {
+        let source = "mov 0, rq0
+            mov [rq3], rq1
+            head: cmp rq0, 10
+            jz done
+            cmp rq1, 3
+            jnz other
+            add 2, rq0, rq0
+            jmp next
+            other: add 1, rq0, rq0
+            next: cmp rq0, 7
+            jz done
+            mov rb0, [rq0]
+            jmp head
+            done: call 0
+            mov 5, rq15";
+
+        assert_eq!(
+            decompiled(source),
+            "// Decompiled by `vm::decompile`.
+void main() {
+    r0 = 0;
+    r1 = mem64[r3];
+    while (r0 != 10) {
+        if (r1 == 3) {
+            r0 = r0 + 2;
+        } else {
+            r0 = r0 + 1;
+        }
+        if (r0 == 7) break;
+        mem64[r0] = r0.u8;
+    }
+    call(0);
+    pc = 5;
+}
+"
+        );
+    }
+
+    #[test]
+    pub fn decompile_gotos() {
+        let source = "cmp rq0, 0
+            jz inside
+            top: add 1, rq1, rq1
+            inside: add 1, rq0, rq0
+            cmp rq0, 5
+            jnz top
+            jmp rq4";
+
+        assert_eq!(
+            decompiled(source),
+            "// Decompiled by `vm::decompile`.
+void main() {
+    if (r0 != 0) {
+    top:
+        r1 = r1 + 1;
+    }
+    r0 = r0 + 1;
+    if (r0 != 5) goto top;
+    goto *(r4 + 1);
+}
+"
+        );
+    }
+
+    #[test]
+    pub fn decompile_shared_comparison() {
+        let source = "cmp rq0, 5
+            jz big
+            add 1, rq1, rq1
+            big: jz done
+            mov 3, rq1
+            done: mov rq1, rq2";
+
+        assert_eq!(
+            decompiled(source),
+            "// Decompiled by `vm::decompile`.
+void main() {
+    compare(r0, 5);
+    if (!zero) {
+        r1 = r1 + 1;
+    }
+    if (!zero) {
+        r1 = 3;
+    }
+    r2 = r1;
+}
+"
+        );
+    }
+}
@@ -1,13 +1,45 @@
-#[derive(Debug, Default, Eq, PartialEq)]
+#[derive(Debug, Default, Clone, Eq, PartialEq)]
 pub enum Error {
     #[default]
     Unknown,
 
     RegisterIndexOutOfBounds,
     ProcessorIndexOutOfBounds,
+    StaleProcessorHandle,
 
     InstructionsPoisoned,
     MemoryPoisoned,
+    CallsPoisoned,
 
     InvalidOperand,
+
+    TraceFailed,
+
+    /// Neither a built-in [`CallIndex`](crate::instructions::call::CallIndex) nor a registered host call.
+    UnknownCall(u64),
+    /// The call index belongs to a built-in [`CallIndex`](crate::instructions::call::CallIndex).
+    ReservedCall(u64),
+
+    /// Malformed assembly source at the given 1-based line.
+    Syntax(usize),
+
+    /// The program failed verification with the given problems.
+    Verification(Vec<crate::verify::Problem>),
+
+    /// No module is loaded under the given name.
+    UnknownModule,
+    /// A module doesn't declare or export the given symbol.
+    UnknownSymbol,
+
+    /// An executable has no [`Instruction`](crate::instructions::Instruction) to serialize.
+    Unserializable,
+    /// Snapshot bytes are truncated or malformed.
+    InvalidSnapshot,
+    /// Snapshot was written by an unsupported format version.
+    UnsupportedSnapshotVersion(u16),
+
+    /// Malformed WebAssembly binary at the given byte offset.
+    InvalidWasm(usize),
+    /// WebAssembly feature outside of the translated subset, at the given byte offset.
+    UnsupportedWasm(usize),
 }
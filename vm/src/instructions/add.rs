@@ -1,5 +1,5 @@
 use crate::error::Error;
-use crate::instructions::{Execute, Operand};
+use crate::instructions::{Execute, Instruction, Operand};
 use crate::processor::Processor;
 use crate::register::{Flag, Width};
 use crate::{
@@ -79,4 +79,12 @@ impl Execute for Add {
 
         Ok(())
     }
+
+    fn instruction(&self) -> Option<Instruction> {
+        Some(Instruction::Add(
+            self.value.clone(),
+            self.source.clone(),
+            self.destination.clone(),
+        ))
+    }
 }
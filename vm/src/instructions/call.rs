@@ -1,9 +1,12 @@
 use crate::error::Error;
 use crate::get_register_value;
-use crate::instructions::{Execute, Operand};
+use crate::instructions::{Execute, Instruction, Operand};
 use crate::processor::Processor;
 use crate::register::Width;
 
+use std::fmt::{self, Debug, Formatter};
+use std::sync::Arc;
+
 #[repr(u64)]
 #[derive(Debug, PartialEq, Eq)]
 /// Enum containing the call indices for external code.
@@ -12,16 +15,63 @@ pub enum CallIndex {
     PrintProcessor = 0,
 }
 
-impl From<u64> for CallIndex {
-    fn from(value: u64) -> Self {
+impl TryFrom<u64> for CallIndex {
+    type Error = Error;
+
+    fn try_from(value: u64) -> Result<Self, Error> {
         match value {
-            0 => CallIndex::PrintProcessor,
+            0 => Ok(CallIndex::PrintProcessor),
 
-            _ => todo!(),
+            _ => Err(Error::UnknownCall(value)),
         }
     }
 }
 
+/// Signature of a host function, see [`HostCall`].
+type HostFn = dyn Fn(&mut Processor) -> Result<(), Error> + Send + Sync;
+
+#[derive(Clone)]
+/// Host function registered with [`Vm::register_call`](crate::Vm::register_call), called with the calling [`Processor`].
+pub struct HostCall(Arc<HostFn>);
+
+impl HostCall {
+    #[must_use]
+    /// Constructs a new [`HostCall`].
+    pub fn new(call: impl Fn(&mut Processor) -> Result<(), Error> + Send + Sync + 'static) -> Self {
+        HostCall(Arc::new(call))
+    }
+
+    /// Calls the host function on the given [`Processor`].
+    ///
+    /// # Errors
+    /// Returns whatever error the host function returns.
+    pub fn call(&self, processor: &mut Processor) -> Result<(), Error> {
+        (self.0)(processor)
+    }
+}
+
+impl Debug for HostCall {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str("HostCall")
+    }
+}
+
+/// Calls the built-in [`CallIndex`] or registered [`HostCall`] at the given call index.
+pub(crate) fn dispatch(processor: &mut Processor, call_index: u64) -> Result<(), Error> {
+    processor.record_call(call_index);
+
+    match CallIndex::try_from(call_index) {
+        Ok(CallIndex::PrintProcessor) => println!("{processor:#?}"),
+
+        Err(error) => processor
+            .host_call(call_index)?
+            .ok_or(error)?
+            .call(processor)?,
+    }
+
+    Ok(())
+}
+
 #[derive(Debug, Default)]
 /// Call into external code to access the [`Processor`] in a mutable state.
 pub struct Call {
@@ -38,18 +88,17 @@ impl Call {
 
 impl Execute for Call {
     fn execute(&self, processor: &mut Processor) -> Result<(), Error> {
-        let call_index: CallIndex = match self.call_index {
+        let call_index = match self.call_index {
             Operand::Value(value) => value,
             Operand::Register(ref register) => get_register_value!(processor, register),
 
             _ => return Err(Error::InvalidOperand),
-        }
-        .into();
+        };
 
-        match call_index {
-            CallIndex::PrintProcessor => println!("{processor:#?}"),
-        }
+        dispatch(processor, call_index)
+    }
 
-        Ok(())
+    fn instruction(&self) -> Option<Instruction> {
+        Some(Instruction::Call(self.call_index.clone()))
     }
 }
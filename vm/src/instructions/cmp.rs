@@ -1,5 +1,5 @@
 use crate::error::Error;
-use crate::instructions::{Execute, Operand};
+use crate::instructions::{Execute, Instruction, Operand};
 use crate::processor::Processor;
 use crate::register::{Flag, Width};
 use crate::{get_memory_value, get_memory_value_by_width, get_register_value};
@@ -52,4 +52,11 @@ impl Execute for Cmp {
 
         Ok(())
     }
+
+    fn instruction(&self) -> Option<Instruction> {
+        Some(Instruction::Cmp(
+            self.value.clone(),
+            self.comparator.clone(),
+        ))
+    }
 }
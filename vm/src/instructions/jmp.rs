@@ -1,5 +1,5 @@
 use crate::error::Error;
-use crate::instructions::{Execute, Operand};
+use crate::instructions::{Execute, Instruction, Operand};
 use crate::processor::Processor;
 use crate::register::{ReservedIndex, Width};
 use crate::{get_memory_value, get_memory_value_by_width, get_register_value};
@@ -54,4 +54,8 @@ impl Execute for Jmp {
 
         Ok(())
     }
+
+    fn instruction(&self) -> Option<Instruction> {
+        Some(Instruction::Jmp(self.source.clone()))
+    }
 }
@@ -1,6 +1,6 @@
 use crate::error::Error;
 use crate::instructions::jmp::Jmp;
-use crate::instructions::{Execute, Operand};
+use crate::instructions::{Execute, Instruction, Operand};
 use crate::processor::Processor;
 use crate::register::Flag;
 
@@ -30,4 +30,8 @@ impl Execute for Jz {
         //       Impossible for me to move source even though it does quite literally nothing but be moved.
         Jmp::new(self.source.clone()).execute(processor)
     }
+
+    fn instruction(&self) -> Option<Instruction> {
+        Some(Instruction::Jz(self.source.clone()))
+    }
 }
@@ -16,6 +16,11 @@ use std::fmt::Debug;
 pub trait Execute: Debug {
     /// Executes the [`Instruction`] modifying the state of the [`Processor`].
     fn execute(&self, processor: &mut Processor) -> Result<(), Error>;
+
+    /// Returns the [`Instruction`] self was built from, if it has one.
+    fn instruction(&self) -> Option<Instruction> {
+        None
+    }
 }
 
 #[derive(Debug, Default, PartialEq, Eq, Clone)]
@@ -31,7 +36,7 @@ pub enum Operand {
     MemoryRegister(Width),
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, PartialEq, Eq, Clone)]
 /// Abstracted pseudo-type for [`Execute`].
 pub enum Instruction {
     /// Depending on call index range, calls either user defined or vm defined function.
@@ -75,51 +80,99 @@ impl Instruction {
 #[macro_export]
 /// Macro for matching the [`Memory`] type and getting the value.
 macro_rules! get_memory_value_by_width {
-    ($processor:expr, $memory:expr) => {
-        match $memory {
-            Width::Byte(index) => $processor.memory()?.get_u8(*index) as u64,
-            Width::Word(index) => $processor.memory()?.get_u16(*index) as u64,
-            Width::DWord(index) => $processor.memory()?.get_u32(*index) as u64,
-            Width::QWord(index) => $processor.memory()?.get_u64(*index),
-        }
-    };
+    ($processor:expr, $memory:expr) => {{
+        let (address, size, value) = match $memory {
+            Width::Byte(index) => (*index, 1, $processor.memory()?.get_u8(*index) as u64),
+            Width::Word(index) => (*index, 2, $processor.memory()?.get_u16(*index) as u64),
+            Width::DWord(index) => (*index, 4, $processor.memory()?.get_u32(*index) as u64),
+            Width::QWord(index) => (*index, 8, $processor.memory()?.get_u64(*index)),
+        };
+
+        $processor.record_memory_read(address, size, value);
+
+        value
+    }};
 }
 
 #[macro_export]
 /// Macro for matching the [`Register`] type and getting the value. Omits the value of [`Width`].
 macro_rules! get_memory_value {
-    ($processor:expr, $memory:expr, $index:expr) => {
-        match $memory {
-            Width::Byte(_) => $processor.memory()?.get_u8($index) as u64,
-            Width::Word(_) => $processor.memory()?.get_u16($index) as u64,
-            Width::DWord(_) => $processor.memory()?.get_u32($index) as u64,
-            Width::QWord(_) => $processor.memory()?.get_u64($index),
-        }
-    };
+    ($processor:expr, $memory:expr, $index:expr) => {{
+        let address: usize = $index;
+        let (size, value) = match $memory {
+            Width::Byte(_) => (1, $processor.memory()?.get_u8(address) as u64),
+            Width::Word(_) => (2, $processor.memory()?.get_u16(address) as u64),
+            Width::DWord(_) => (4, $processor.memory()?.get_u32(address) as u64),
+            Width::QWord(_) => (8, $processor.memory()?.get_u64(address)),
+        };
+
+        $processor.record_memory_read(address, size, value);
+
+        value
+    }};
 }
 
 #[macro_export]
 /// Macro for matching the [`Memory`] type and setting the value.
 macro_rules! assign_memory_value_by_width {
-    ($processor:expr, $memory:expr, $source:expr) => {
-        match $memory {
-            Width::Byte(index) => $processor.memory_mut()?.put_u8(*index, $source as u8),
-            Width::Word(index) => $processor.memory_mut()?.put_u16(*index, $source as u16),
-            Width::DWord(index) => $processor.memory_mut()?.put_u32(*index, $source as u32),
-            Width::QWord(index) => $processor.memory_mut()?.put_u64(*index, $source),
-        }
-    };
+    ($processor:expr, $memory:expr, $source:expr) => {{
+        let source: u64 = $source;
+        let (address, size, value) = match $memory {
+            Width::Byte(index) => {
+                $processor.memory_mut()?.put_u8(*index, source as u8);
+
+                (*index, 1, source as u8 as u64)
+            }
+            Width::Word(index) => {
+                $processor.memory_mut()?.put_u16(*index, source as u16);
+
+                (*index, 2, source as u16 as u64)
+            }
+            Width::DWord(index) => {
+                $processor.memory_mut()?.put_u32(*index, source as u32);
+
+                (*index, 4, source as u32 as u64)
+            }
+            Width::QWord(index) => {
+                $processor.memory_mut()?.put_u64(*index, source);
+
+                (*index, 8, source)
+            }
+        };
+
+        $processor.record_memory_write(address, size, value);
+    }};
 }
 
 #[macro_export]
 /// Macro for matching the [`Memory`] type and setting the value. Omits the value of [`Width`].
 macro_rules! assign_memory_value {
-    ($processor:expr, $memory:expr, $index:expr, $source:expr) => {
-        match $memory {
-            Width::Byte(_) => $processor.memory_mut()?.put_u8($index, $source as u8),
-            Width::Word(_) => $processor.memory_mut()?.put_u16($index, $source as u16),
-            Width::DWord(_) => $processor.memory_mut()?.put_u32($index, $source as u32),
-            Width::QWord(_) => $processor.memory_mut()?.put_u64($index, $source),
-        }
-    };
+    ($processor:expr, $memory:expr, $index:expr, $source:expr) => {{
+        let address: usize = $index;
+        let source: u64 = $source;
+        let (size, value) = match $memory {
+            Width::Byte(_) => {
+                $processor.memory_mut()?.put_u8(address, source as u8);
+
+                (1, source as u8 as u64)
+            }
+            Width::Word(_) => {
+                $processor.memory_mut()?.put_u16(address, source as u16);
+
+                (2, source as u16 as u64)
+            }
+            Width::DWord(_) => {
+                $processor.memory_mut()?.put_u32(address, source as u32);
+
+                (4, source as u32 as u64)
+            }
+            Width::QWord(_) => {
+                $processor.memory_mut()?.put_u64(address, source);
+
+                (8, source)
+            }
+        };
+
+        $processor.record_memory_write(address, size, value);
+    }};
 }
@@ -1,5 +1,5 @@
 use crate::error::Error;
-use crate::instructions::{Execute, Operand};
+use crate::instructions::{Execute, Instruction, Operand};
 use crate::processor::Processor;
 use crate::register::Width;
 use crate::{
@@ -55,4 +55,11 @@ impl Execute for Mov {
 
         Ok(())
     }
+
+    fn instruction(&self) -> Option<Instruction> {
+        Some(Instruction::Mov(
+            self.source.clone(),
+            self.destination.clone(),
+        ))
+    }
 }
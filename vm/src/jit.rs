@@ -0,0 +1,598 @@
+//! Compiles hot basic blocks of register-only [`Instruction`]s to x86-64 machine code.
+//!
+//! A block starts at the instruction the interpreter is about to run and extends over `mov`, `add`
+//! and `cmp` on registers and values, ending after a jump to a value or before the first
+//! instruction it can't compile, e.g. a memory access or a `call`. The interpreter runs anything
+//! outside of blocks. A block ending in a jump back to its own start loops natively, for as long
+//! as the step budget of [`Processor::run`](crate::processor::Processor::run) allows.
+//!
+//! Compiled code reads and writes the register file of the [`Processor`] in place and is called as
+//! `extern "sysv64" fn(registers: *mut u8, budget: u64) -> u64`, returning the number of instructions
+//! executed after storing the index of the next one into the instruction counter.
+
+use crate::instructions::{Instruction, Operand};
+use crate::processor::Processor;
+use crate::register::{ReservedIndex, Width};
+
+use std::ffi::c_void;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::OnceLock;
+
+/// Times the interpreter reaches an instruction before a block starting there is compiled.
+pub const HOT_THRESHOLD: u32 = 64;
+
+/// Most instructions compiled into a single block.
+const MAX_BLOCK_LENGTH: usize = 256;
+
+const PROT_READ: i32 = 1;
+const PROT_WRITE: i32 = 2;
+const PROT_EXEC: i32 = 4;
+const MAP_PRIVATE: i32 = 2;
+const MAP_ANONYMOUS: i32 = 0x20;
+
+extern "C" {
+    fn mmap(
+        address: *mut c_void,
+        length: usize,
+        prot: i32,
+        flags: i32,
+        fd: i32,
+        offset: i64,
+    ) -> *mut c_void;
+    fn mprotect(address: *mut c_void, length: usize, prot: i32) -> i32;
+    fn munmap(address: *mut c_void, length: usize) -> i32;
+}
+
+/// Signature of a compiled block.
+type Entry = unsafe extern "sysv64" fn(*mut u8, u64) -> u64;
+
+/// Read-only, executable pages holding the code of a single block.
+#[derive(Debug)]
+struct ExecutableMemory {
+    address: *mut c_void,
+    length: usize,
+}
+
+// SAFETY: the pages are never written after construction.
+unsafe impl Send for ExecutableMemory {}
+unsafe impl Sync for ExecutableMemory {}
+
+impl ExecutableMemory {
+    /// Maps the given code as executable, never writable and executable at once.
+    fn new(code: &[u8]) -> Option<Self> {
+        let length = code.len();
+
+        // SAFETY: an anonymous private mapping aliases no existing memory; the pages are only
+        // written through the pointer returned for them, before becoming executable.
+        unsafe {
+            let address = mmap(
+                std::ptr::null_mut(),
+                length,
+                PROT_READ | PROT_WRITE,
+                MAP_PRIVATE | MAP_ANONYMOUS,
+                -1,
+                0,
+            );
+
+            if address as isize == -1 {
+                return None;
+            }
+
+            std::ptr::copy_nonoverlapping(code.as_ptr(), address.cast::<u8>(), length);
+
+            let memory = ExecutableMemory { address, length };
+
+            if mprotect(address, length, PROT_READ | PROT_EXEC) != 0 {
+                return None;
+            }
+
+            Some(memory)
+        }
+    }
+}
+
+impl Drop for ExecutableMemory {
+    fn drop(&mut self) {
+        // SAFETY: the mapping is owned by self and no block runs once self is dropped.
+        unsafe {
+            munmap(self.address, self.length);
+        }
+    }
+}
+
+/// Value read by compiled code.
+#[derive(Debug, Clone, Copy)]
+enum Value {
+    Immediate(u64),
+    Register { index: usize, size: usize },
+}
+
+/// Register written by compiled code.
+#[derive(Debug, Clone, Copy)]
+struct Target {
+    index: usize,
+    size: usize,
+}
+
+/// Instruction compiled into a block.
+#[derive(Debug, Clone, Copy)]
+enum Node {
+    Mov(Value, Target),
+    Add(Value, Value, Target),
+    Cmp(Value, Value),
+    /// Jump to a value, `None` when unconditional, else taken when the Zero flag is in the given state.
+    Jump(Option<bool>, u64),
+}
+
+/// Returns the register index and size of the given width, unless it is the instruction counter,
+/// which compiled code doesn't update per instruction.
+fn register(width: &Width) -> Option<(usize, usize)> {
+    let (index, size) = (width.index(), width.size());
+
+    (index < ReservedIndex::InstructionCounter as usize).then_some((index, size))
+}
+
+fn value(operand: &Operand) -> Option<Value> {
+    match operand {
+        Operand::Value(value) => Some(Value::Immediate(*value)),
+        Operand::Register(width) => {
+            register(width).map(|(index, size)| Value::Register { index, size })
+        }
+
+        _ => None,
+    }
+}
+
+fn target(operand: &Operand) -> Option<Target> {
+    match operand {
+        Operand::Register(width) => register(width).map(|(index, size)| Target { index, size }),
+
+        _ => None,
+    }
+}
+
+/// Returns the node compiled for the given instruction, if it can be compiled.
+fn node(instruction: &Instruction) -> Option<Node> {
+    match instruction {
+        Instruction::Mov(source, destination) => {
+            Some(Node::Mov(value(source)?, target(destination)?))
+        }
+        Instruction::Add(value_operand, source, destination) => Some(Node::Add(
+            value(source)?,
+            value(value_operand)?,
+            target(destination)?,
+        )),
+        Instruction::Cmp(value_operand, comparator) => {
+            Some(Node::Cmp(value(value_operand)?, value(comparator)?))
+        }
+        Instruction::Jmp(Operand::Value(target)) => Some(Node::Jump(None, *target)),
+        Instruction::Jz(Operand::Value(target)) => Some(Node::Jump(Some(true), *target)),
+        Instruction::Jnz(Operand::Value(target)) => Some(Node::Jump(Some(false), *target)),
+
+        _ => None,
+    }
+}
+
+const RAX: u8 = 0;
+const RCX: u8 = 1;
+const RDX: u8 = 2;
+
+/// Offset of the Flags register within the register file.
+const FLAGS: i32 = ReservedIndex::Flags as i32 * 8;
+
+/// Offset of the InstructionCounter register within the register file.
+const COUNTER: i32 = ReservedIndex::InstructionCounter as i32 * 8;
+
+/// x86-64 machine code under construction. The register file is addressed through `rdi`, the
+/// budget is held in `rsi` and the executed instruction count in `r8`.
+#[derive(Debug, Default)]
+struct Code {
+    bytes: Vec<u8>,
+}
+
+impl Code {
+    fn emit(&mut self, bytes: &[u8]) {
+        self.bytes.extend_from_slice(bytes);
+    }
+
+    fn emit_u32(&mut self, value: u32) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    /// Emits the ModRM byte and displacement for `[rdi + displacement]`.
+    fn rdi_displacement(&mut self, reg: u8, displacement: i32) {
+        self.emit(&[0x87 | (reg << 3)]);
+        self.emit_u32(displacement as u32);
+    }
+
+    /// Loads the given value zero-extended into `reg`.
+    fn load(&mut self, reg: u8, value: Value) {
+        match value {
+            Value::Immediate(immediate) => {
+                // mov reg, imm64
+                self.emit(&[0x48, 0xB8 + reg]);
+                self.emit(&immediate.to_le_bytes());
+            }
+            Value::Register { index, size } => {
+                match size {
+                    8 => self.emit(&[0x48, 0x8B]), // mov reg, qword
+                    4 => self.emit(&[0x8B]),       // mov reg32, dword
+                    2 => self.emit(&[0x0F, 0xB7]), // movzx reg32, word
+                    _ => self.emit(&[0x0F, 0xB6]), // movzx reg32, byte
+                }
+
+                self.rdi_displacement(reg, index as i32 * 8);
+            }
+        }
+    }
+
+    /// Stores the low bytes of `rax` into the given register, leaving its other bytes untouched.
+    fn store(&mut self, target: Target) {
+        match target.size {
+            8 => self.emit(&[0x48, 0x89]),
+            4 => self.emit(&[0x89]),
+            2 => self.emit(&[0x66, 0x89]),
+            _ => self.emit(&[0x88]),
+        }
+
+        self.rdi_displacement(RAX, target.index as i32 * 8);
+    }
+
+    /// Emits a conditional jump with a placeholder offset, returning where to patch it.
+    fn jump_forward(&mut self, opcode: u8) -> usize {
+        self.emit(&[0x0F, opcode]);
+        self.emit_u32(0);
+
+        self.bytes.len() - 4
+    }
+
+    /// Points the jump emitted at the given patch position to the current position.
+    fn land(&mut self, patch: usize) {
+        let offset = (self.bytes.len() - (patch + 4)) as u32;
+
+        self.bytes[patch..patch + 4].copy_from_slice(&offset.to_le_bytes());
+    }
+
+    /// Stores the given next instruction index and returns the executed count.
+    fn exit(&mut self, next: u64) {
+        self.load(RAX, Value::Immediate(next));
+        // mov [rdi + COUNTER], rax
+        self.emit(&[0x48, 0x89]);
+        self.rdi_displacement(RAX, COUNTER);
+        // mov rax, r8; ret
+        self.emit(&[0x4C, 0x89, 0xC0, 0xC3]);
+    }
+
+    fn node(&mut self, node: Node) {
+        match node {
+            Node::Mov(source, destination) => {
+                self.load(RAX, source);
+                self.store(destination);
+            }
+            Node::Add(source, value, destination) => {
+                self.load(RAX, source);
+                self.load(RCX, value);
+                // add rax, rcx; setc dl; movzx edx, dl; shl edx, 2
+                self.emit(&[
+                    0x48, 0x01, 0xC8, 0x0F, 0x92, 0xC2, 0x0F, 0xB6, 0xD2, 0xC1, 0xE2, 0x02,
+                ]);
+                // or [rdi + FLAGS], rdx
+                self.emit(&[0x48, 0x09]);
+                self.rdi_displacement(RDX, FLAGS);
+                self.store(destination);
+            }
+            Node::Cmp(value, comparator) => {
+                self.load(RAX, value);
+                self.load(RCX, comparator);
+                // cmp rax, rcx; sete al; seta cl; movzx eax, al; movzx ecx, cl; add ecx, ecx; or eax, ecx
+                self.emit(&[
+                    0x48, 0x39, 0xC8, 0x0F, 0x94, 0xC0, 0x0F, 0x97, 0xC1, 0x0F, 0xB6, 0xC0, 0x0F,
+                    0xB6, 0xC9, 0x01, 0xC9, 0x09, 0xC8,
+                ]);
+                // mov rdx, [rdi + FLAGS]; and rdx, -4; or rdx, rax; mov [rdi + FLAGS], rdx
+                self.emit(&[0x48, 0x8B]);
+                self.rdi_displacement(RDX, FLAGS);
+                self.emit(&[0x48, 0x83, 0xE2, 0xFC, 0x48, 0x09, 0xC2, 0x48, 0x89]);
+                self.rdi_displacement(RDX, FLAGS);
+            }
+            Node::Jump(..) => unreachable!("jumps end a block"),
+        }
+    }
+}
+
+#[derive(Debug)]
+/// Compiled block of instructions.
+struct Block {
+    memory: ExecutableMemory,
+    /// Instructions executed by a single pass through the block.
+    length: u64,
+}
+
+impl Block {
+    /// Compiles the block starting at the given index, if its first instruction can be compiled.
+    fn compile(instructions: &[Option<Instruction>], start: usize) -> Option<Self> {
+        let mut nodes = Vec::new();
+
+        for instruction in instructions.iter().skip(start).take(MAX_BLOCK_LENGTH) {
+            let Some(node) = instruction.as_ref().and_then(node) else {
+                break;
+            };
+
+            nodes.push(node);
+
+            if let Node::Jump(..) = node {
+                break;
+            }
+        }
+
+        if nodes.is_empty() {
+            return None;
+        }
+
+        let length = nodes.len() as u64;
+        let next = (start + nodes.len()) as u64;
+        let mut code = Code::default();
+
+        // xor r8d, r8d
+        code.emit(&[0x45, 0x31, 0xC0]);
+
+        let entry = code.bytes.len();
+
+        for node in &nodes {
+            if let Node::Jump(..) = node {
+                break;
+            }
+
+            code.node(*node);
+        }
+
+        // add r8, length
+        code.emit(&[0x49, 0x81, 0xC0]);
+        code.emit_u32(length as u32);
+
+        let Some(Node::Jump(condition, target)) = nodes.last().copied() else {
+            code.exit(next);
+
+            return Self::map(&code, length);
+        };
+
+        let not_taken = condition.map(|zero| {
+            // test byte [rdi + FLAGS], Zero
+            code.emit(&[0xF6]);
+            code.rdi_displacement(0, FLAGS);
+            code.emit(&[0x01]);
+
+            // Skip the taken path when the Zero flag isn't in the state jumped on.
+            let patch = code.jump_forward(if zero { 0x84 } else { 0x85 });
+
+            if zero {
+                // and qword [rdi + FLAGS], -2
+                code.emit(&[0x48, 0x83]);
+                code.rdi_displacement(4, FLAGS);
+                code.emit(&[0xFE]);
+            }
+
+            patch
+        });
+
+        let taken = target.wrapping_add(1);
+
+        if taken == start as u64 {
+            // mov rax, r8; add rax, length; cmp rax, rsi; jbe entry
+            code.emit(&[0x4C, 0x89, 0xC0, 0x48, 0x05]);
+            code.emit_u32(length as u32);
+            code.emit(&[0x48, 0x39, 0xF0, 0x0F, 0x86]);
+
+            let offset = entry as i64 - (code.bytes.len() + 4) as i64;
+
+            code.emit_u32(offset as i32 as u32);
+        }
+
+        code.exit(taken);
+
+        if let Some(patch) = not_taken {
+            code.land(patch);
+            code.exit(next);
+        }
+
+        Self::map(&code, length)
+    }
+
+    fn map(code: &Code, length: u64) -> Option<Self> {
+        Some(Block {
+            memory: ExecutableMemory::new(&code.bytes)?,
+            length,
+        })
+    }
+
+    /// Runs self on the given [`Processor`] for at most the given number of instructions, which
+    /// must cover at least one pass.
+    fn run(&self, processor: &mut Processor, budget: u64) -> u64 {
+        // SAFETY: the code was emitted by `Block::compile` for the `Entry` signature, and only
+        // accesses the 16 registers behind the given pointer.
+        unsafe {
+            let entry: Entry = std::mem::transmute(self.memory.address);
+
+            entry(processor.registers_ptr(), budget)
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+/// Hit counters and compiled blocks of a [`Program`](crate::decode::Program).
+pub(crate) struct Cache {
+    instructions: Vec<Option<Instruction>>,
+    hits: Vec<AtomicU32>,
+    blocks: Vec<OnceLock<Option<Block>>>,
+}
+
+impl Cache {
+    #[must_use]
+    /// Constructs a new, empty, [`Cache`] for the given instructions.
+    pub(crate) fn new(instructions: Vec<Option<Instruction>>) -> Self {
+        Cache {
+            hits: instructions.iter().map(|_| AtomicU32::new(0)).collect(),
+            blocks: instructions.iter().map(|_| OnceLock::new()).collect(),
+            instructions,
+        }
+    }
+
+    /// Runs the block starting at the given index once it is hot, returning how many instructions
+    /// it executed, or `None` when the interpreter should run the instruction instead.
+    pub(crate) fn run(&self, index: usize, processor: &mut Processor, budget: u64) -> Option<u64> {
+        let block = match self.blocks[index].get() {
+            Some(block) => block.as_ref()?,
+
+            None => {
+                if self.hits[index].fetch_add(1, Ordering::Relaxed) < HOT_THRESHOLD {
+                    return None;
+                }
+
+                self.blocks[index]
+                    .get_or_init(|| Block::compile(&self.instructions, index))
+                    .as_ref()?
+            }
+        };
+
+        (block.length <= budget).then(|| block.run(processor, budget))
+    }
+
+    #[cfg(test)]
+    /// Returns the number of compiled blocks.
+    fn compiled(&self) -> usize {
+        self.blocks
+            .iter()
+            .filter(|block| matches!(block.get(), Some(Some(_))))
+            .count()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::assembler::Assembler;
+    use crate::testing::random;
+    use crate::Vm;
+
+    /// Runs the given source to completion, or until the given budget runs out, with and without
+    /// the JIT, returning every register and the first 64 bytes of memory of both runs.
+    fn run_both(source: &str, budget: u64) -> [(Vec<u64>, Vec<u64>); 2] {
+        [true, false].map(|jit| {
+            let mut vm = Vm::new();
+
+            vm.set_jit(jit);
+            vm.load_instructions(Assembler::parse(source).unwrap().compile())
+                .unwrap();
+
+            let handle = vm.new_processor();
+            let processor = vm.processor_mut(handle).unwrap();
+
+            processor.run(budget).unwrap();
+
+            let registers = (0..16)
+                .map(|index| processor.register(index).unwrap().as_u64())
+                .collect();
+            let memory = (0..8)
+                .map(|index| processor.memory().unwrap().get_u64(index * 8))
+                .collect();
+
+            (registers, memory)
+        })
+    }
+
+    #[test]
+    pub fn jit_counting_loop() {
+        let source = "mov 0, rq0\n\
+                      mov 100000, rq1\n\
+                      loop: add 1, rq0, rq0\n\
+                      cmp rq0, rq1\n\
+                      jnz loop\n\
+                      mov rq0, mq0";
+
+        let [jit, interpreted] = run_both(source, u64::MAX);
+
+        assert_eq!(jit, interpreted);
+        assert_eq!(jit.0[0], 100_000);
+
+        let mut vm = Vm::new();
+
+        vm.set_jit(true);
+        vm.load_instructions(Assembler::parse(source).unwrap().compile())
+            .unwrap();
+
+        let handle = vm.new_processor();
+
+        vm.processor_mut(handle).unwrap().start().unwrap();
+
+        let program = vm.ctx.instructions.read().unwrap();
+
+        assert_eq!(program.jit().compiled(), 1);
+    }
+
+    #[test]
+    pub fn jit_budget() {
+        let source = "mov 0, rq0\n\
+                      loop: add 3, rq0, rq0\n\
+                      jmp loop";
+
+        for budget in [1, 2, 3, 100, 199, 200, 201, 1001] {
+            let [jit, interpreted] = run_both(source, budget);
+
+            assert_eq!(jit, interpreted, "budget {budget}");
+        }
+    }
+
+    /// Returns a random register below the loop counters, or, unless a destination, a random value
+    /// or memory operand.
+    fn operand(seed: &mut u64, destination: bool) -> String {
+        let width = ["b", "w", "d", "q"][random(seed, 4) as usize];
+
+        if !destination && random(seed, 4) == 0 {
+            format!("{}", random(seed, u64::MAX))
+        } else if !destination && random(seed, 8) == 0 {
+            format!("m{width}{}", random(seed, 8) * 8)
+        } else {
+            format!("r{width}{}", random(seed, 12))
+        }
+    }
+
+    #[test]
+    pub fn jit_differential() {
+        let mut seed = 0x2545_F491_4F6C_DD1D_u64;
+
+        for _ in 0..64 {
+            let mut source = String::from("mov 0, rq13\nloop:\n");
+
+            for _ in 0..12 {
+                let line = match random(&mut seed, 3) {
+                    0 => format!(
+                        "mov {}, {}\n",
+                        operand(&mut seed, false),
+                        operand(&mut seed, true)
+                    ),
+                    1 => format!(
+                        "add {}, {}, {}\n",
+                        operand(&mut seed, false),
+                        operand(&mut seed, false),
+                        operand(&mut seed, true)
+                    ),
+                    _ => format!(
+                        "cmp {}, {}\n",
+                        operand(&mut seed, false),
+                        operand(&mut seed, false)
+                    ),
+                };
+
+                source.push_str(&line);
+            }
+
+            source
+                .push_str("jz skip\nmov 1, rq12\nskip: add 1, rq13, rq13\ncmp rq13, 200\njnz loop");
+
+            for budget in [u64::MAX, 1000, 777] {
+                let [jit, interpreted] = run_both(&source, budget);
+
+                assert_eq!(jit, interpreted, "{source}");
+            }
+        }
+    }
+}
@@ -0,0 +1,264 @@
+use crate::lang::parser::{Expr, Function, Statement, StatementKind, Variable};
+
+use std::ops::{Range, RangeInclusive};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Where a value is kept.
+pub(crate) enum Location {
+    Register(usize),
+    /// The word at the given offset from the frame of the running function.
+    Frame(u64),
+}
+
+#[derive(Debug, Clone)]
+/// Locations of the variables of a [`Function`] and the layout of its frame.
+///
+/// A frame starts with the return address, followed by the parameters, spilled variables, arrays
+/// and the registers saved around calls. Temporaries not fitting in registers come last.
+pub(crate) struct Allocation {
+    pub(crate) locations: Vec<Location>,
+    /// Statement positions each variable is live over.
+    pub(crate) intervals: Vec<RangeInclusive<usize>>,
+    /// Offset of the slots registers are saved to around calls, one per register.
+    pub(crate) saves: u64,
+    /// Offset of the temporaries not fitting in registers.
+    pub(crate) temporaries: u64,
+}
+
+/// Appends the variables read by the given expression.
+fn reads(expr: &Expr, variables: &mut Vec<usize>) {
+    match expr {
+        Expr::Variable(index) | Expr::Address(index) => variables.push(*index),
+        Expr::Index(left, right) | Expr::Binary(_, left, right) => {
+            reads(left, variables);
+            reads(right, variables);
+        }
+        Expr::Call { arguments, .. } => {
+            for argument in arguments {
+                reads(argument, variables);
+            }
+        }
+        Expr::Number(_) => {}
+    }
+}
+
+/// Records the position of every statement using each variable, and the positions each loop spans.
+fn uses(
+    statements: &[Statement],
+    positions: &mut [Vec<usize>],
+    loops: &mut Vec<RangeInclusive<usize>>,
+) {
+    for statement in statements {
+        let mut variables = Vec::new();
+
+        match &statement.kind {
+            StatementKind::Assign(index, value) => {
+                variables.push(*index);
+                reads(value, &mut variables);
+            }
+            StatementKind::Store(address, index, value) => {
+                reads(address, &mut variables);
+                reads(index, &mut variables);
+                reads(value, &mut variables);
+            }
+            StatementKind::If(condition, then, otherwise) => {
+                reads(condition, &mut variables);
+                uses(then, positions, loops);
+                uses(otherwise, positions, loops);
+            }
+            StatementKind::While(condition, body) => {
+                reads(condition, &mut variables);
+                uses(body, positions, loops);
+
+                let end = last(body).unwrap_or(statement.position);
+
+                loops.push(statement.position..=end);
+            }
+            StatementKind::Return(value) => {
+                if let Some(value) = value {
+                    reads(value, &mut variables);
+                }
+            }
+            StatementKind::Expr(expr) => reads(expr, &mut variables),
+        }
+
+        for variable in variables {
+            positions[variable].push(statement.position);
+        }
+    }
+}
+
+/// Returns the position of the last statement in the given ones, nested ones included.
+fn last(statements: &[Statement]) -> Option<usize> {
+    let statement = statements.last()?;
+
+    let nested = match &statement.kind {
+        StatementKind::If(_, then, otherwise) => last(otherwise).or(last(then)),
+        StatementKind::While(_, body) => last(body),
+
+        _ => None,
+    };
+
+    Some(nested.unwrap_or(statement.position))
+}
+
+/// Assigns registers to the scalar variables of the given function by linear scan over the
+/// statements they are live over, spilling the longest lived to its frame when they run out.
+pub(crate) fn allocate(function: &Function, registers: Range<usize>) -> Allocation {
+    let mut positions = vec![Vec::new(); function.variables.len()];
+    let mut loops = Vec::new();
+
+    uses(&function.body, &mut positions, &mut loops);
+
+    // Parameters are written on entry, before the first statement.
+    for parameter in &mut positions[..function.parameters] {
+        parameter.push(0);
+    }
+
+    let intervals: Vec<_> = positions
+        .iter()
+        .map(|positions| {
+            let start = positions.iter().copied().min().unwrap_or(0);
+            let mut end = positions.iter().copied().max().unwrap_or(0);
+
+            // A variable used in a loop it was declared before must survive every iteration.
+            for span in &loops {
+                if start < *span.start() && positions.iter().any(|position| span.contains(position))
+                {
+                    end = end.max(*span.end());
+                }
+            }
+
+            start..=end
+        })
+        .collect();
+
+    let mut order: Vec<_> = (0..function.variables.len())
+        .filter(|index| function.variables[*index] == Variable::Scalar)
+        .collect();
+
+    order.sort_by_key(|index| *intervals[*index].start());
+
+    let mut locations = vec![None; function.variables.len()];
+    let mut free: Vec<_> = registers.rev().collect();
+    // Variables holding a register, in no particular order.
+    let mut active: Vec<usize> = Vec::new();
+
+    for variable in order {
+        let start = *intervals[variable].start();
+
+        active.retain(|other| {
+            let expired = *intervals[*other].end() < start;
+
+            if expired {
+                if let Some(Location::Register(register)) = locations[*other] {
+                    free.push(register);
+                }
+            }
+
+            !expired
+        });
+
+        if let Some(register) = free.pop() {
+            locations[variable] = Some(Location::Register(register));
+            active.push(variable);
+
+            continue;
+        }
+
+        let longest = active
+            .iter()
+            .copied()
+            .enumerate()
+            .max_by_key(|(_, other)| *intervals[*other].end());
+
+        if let Some((slot, other)) = longest {
+            if intervals[other].end() > intervals[variable].end() {
+                locations[variable] = locations[other].take();
+                active[slot] = variable;
+            }
+        }
+    }
+
+    // Spilled parameters stay where the caller wrote them.
+    let mut offset = 8;
+
+    for (index, location) in locations.iter_mut().enumerate() {
+        if index < function.parameters && location.is_none() {
+            *location = Some(Location::Frame(offset));
+        }
+
+        offset += 8 * u64::from(index < function.parameters);
+    }
+
+    let locations = locations
+        .into_iter()
+        .zip(&function.variables)
+        .map(|(location, variable)| {
+            location.unwrap_or_else(|| {
+                let slot = Location::Frame(offset);
+
+                offset += match variable {
+                    Variable::Scalar => 8,
+                    Variable::Array(length) => 8 * length,
+                };
+
+                slot
+            })
+        })
+        .collect();
+
+    // One slot per register, indexed by its number.
+    let saves = offset;
+
+    Allocation {
+        locations,
+        intervals,
+        saves,
+        temporaries: saves + 8 * 16,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lang::parser::parse;
+
+    #[test]
+    pub fn allocate_linear_scan() {
+        let program = parse(
+            "fn f(a, b) {
+                var c = a + b;
+                var d = 0;
+                while (d < c) {
+                    var e = d + 1;
+                    d = e;
+                }
+                var f = d;
+                var g = f + a;
+                return g;
+            }",
+        )
+        .unwrap();
+        let allocation = allocate(&program.functions[0], 0..3);
+
+        // `c` is used in the loop so it lives through it, `e` only within an iteration.
+        assert_eq!(allocation.intervals[0], 0..=7);
+        assert_eq!(allocation.intervals[2], 1..=5);
+        assert_eq!(allocation.intervals[4], 4..=5);
+
+        // `a` lives longest when `e` needs a fourth register, so it stays in its parameter slot.
+        assert_eq!(
+            allocation.locations,
+            [
+                Location::Frame(8),
+                Location::Register(1),
+                Location::Register(2),
+                Location::Register(1),
+                Location::Register(0),
+                Location::Register(2),
+                Location::Register(1),
+            ]
+        );
+    }
+}
@@ -0,0 +1,730 @@
+use crate::error::Error;
+use crate::instructions::{Instruction, Operand};
+use crate::lang::allocate::{allocate, Allocation, Location};
+use crate::lang::parser::{Expr, Function, Operator, Program, Statement, StatementKind};
+use crate::lang::STACK;
+use crate::lowering::{self, complements, first, register};
+use crate::register::{Width, FLAGS};
+
+use std::collections::BTreeMap;
+use std::ops::Range;
+
+/// Registers holding variables.
+const VARIABLES: Range<usize> = 0..7;
+/// Registers holding the first temporaries, by nesting depth.
+const TEMPORARIES: Range<usize> = 7..10;
+/// Holds the first operand of an operation, and the result of calls.
+const FIRST: usize = 10;
+/// Holds the second operand of an operation, and computed addresses.
+const SECOND: usize = 11;
+/// Holds addresses within the frame.
+const FRAME: usize = 12;
+/// Holds the address of the frame of the running function.
+const STACK_POINTER: usize = 13;
+
+fn at(index: usize) -> Operand {
+    Operand::MemoryRegister(Width::QWord(index))
+}
+
+#[derive(Debug, Clone, Copy)]
+/// An operand not yet in a register.
+enum Value {
+    Constant(u64),
+    Location(Location),
+}
+
+#[derive(Debug, Clone, Copy)]
+/// Value patched into the first operand of an instruction once known.
+enum Fixup {
+    /// A jump to the label with the given number.
+    Label(usize),
+    /// The frame size of the running function plus the given offset.
+    Frame(u64),
+    /// The negated frame size of the running function.
+    Release,
+}
+
+struct Generator<'a> {
+    functions: BTreeMap<&'a str, (usize, &'a Function)>,
+
+    instructions: Vec<Instruction>,
+    /// Index of the instruction each label names, once placed.
+    labels: Vec<usize>,
+    fixups: Vec<(usize, Fixup)>,
+
+    /// Allocation of the function being generated.
+    allocation: Option<Allocation>,
+    /// Position of the statement being generated.
+    position: usize,
+    /// Temporaries of the function being generated not fitting in registers.
+    spilled: u64,
+}
+
+impl<'a> Generator<'a> {
+    fn push(&mut self, instruction: Instruction) {
+        self.instructions.push(instruction);
+    }
+
+    fn push_fixup(&mut self, instruction: Instruction, fixup: Fixup) {
+        self.fixups.push((self.instructions.len(), fixup));
+        self.push(instruction);
+    }
+
+    fn label(&mut self) -> usize {
+        self.labels.push(usize::MAX);
+
+        self.labels.len() - 1
+    }
+
+    fn place(&mut self, label: usize) {
+        self.labels[label] = self.instructions.len();
+    }
+
+    fn jump(&mut self, jump: fn(Operand) -> Instruction, label: usize) {
+        self.push_fixup(jump(Operand::Value(0)), Fixup::Label(label));
+    }
+
+    fn allocation(&self) -> &Allocation {
+        self.allocation
+            .as_ref()
+            .expect("allocated before generating a function")
+    }
+
+    /// Returns the location of the temporary at the given depth.
+    fn temporary(&mut self, depth: usize) -> Location {
+        if let Some(register) = TEMPORARIES.clone().nth(depth) {
+            return Location::Register(register);
+        }
+
+        let slot = (depth - TEMPORARIES.len()) as u64;
+
+        self.spilled = self.spilled.max(slot + 1);
+
+        Location::Frame(self.allocation().temporaries + 8 * slot)
+    }
+
+    /// Writes the address of the given frame offset into [`FRAME`], returning it as memory.
+    fn frame(&mut self, offset: u64) -> Operand {
+        self.push(Instruction::Add(
+            Operand::Value(offset),
+            register(STACK_POINTER),
+            register(FRAME),
+        ));
+
+        at(FRAME)
+    }
+
+    /// Returns the given value as an operand, loading it into the given register from the frame.
+    fn load(&mut self, value: Value, into: usize) -> Operand {
+        match value {
+            Value::Constant(value) => Operand::Value(value),
+            Value::Location(Location::Register(index)) => register(index),
+            Value::Location(Location::Frame(offset)) => {
+                let slot = self.frame(offset);
+
+                self.push(Instruction::Mov(slot, register(into)));
+
+                register(into)
+            }
+        }
+    }
+
+    /// Loads the given value into the given register.
+    fn load_into(&mut self, value: Value, into: usize) {
+        let operand = self.load(value, into);
+
+        if operand != register(into) {
+            self.push(Instruction::Mov(operand, register(into)));
+        }
+    }
+
+    fn store(&mut self, operand: Operand, location: Location) {
+        let destination = match location {
+            Location::Register(index) => register(index),
+            Location::Frame(offset) => self.frame(offset),
+        };
+
+        if operand != destination {
+            self.push(Instruction::Mov(operand, destination));
+        }
+    }
+
+    /// Returns the given expression as a value, evaluating it into the temporary at the given
+    /// depth unless it is a constant or variable, along with the depth of the next free temporary.
+    fn value(&mut self, expr: &Expr, depth: usize) -> Result<(Value, usize), Error> {
+        match expr {
+            Expr::Number(number) => Ok((Value::Constant(*number), depth)),
+            Expr::Variable(index) => {
+                Ok((Value::Location(self.allocation().locations[*index]), depth))
+            }
+            expr => {
+                let temporary = self.temporary(depth);
+
+                self.evaluate(expr, temporary, depth + 1)?;
+
+                Ok((Value::Location(temporary), depth + 1))
+            }
+        }
+    }
+
+    /// Writes the address of the indexed word into [`SECOND`].
+    fn address(&mut self, address: &Expr, index: &Expr, depth: usize) -> Result<(), Error> {
+        let (address, depth) = self.value(address, depth)?;
+        let (index, _) = self.value(index, depth)?;
+
+        self.load_into(index, SECOND);
+
+        for _ in 0..3 {
+            self.push(Instruction::Add(
+                register(SECOND),
+                register(SECOND),
+                register(SECOND),
+            ));
+        }
+
+        let address = self.load(address, FIRST);
+
+        self.push(Instruction::Add(
+            address,
+            register(SECOND),
+            register(SECOND),
+        ));
+
+        Ok(())
+    }
+
+    /// Evaluates the given expression into the given location, with temporaries below the given
+    /// depth in use.
+    fn evaluate(&mut self, expr: &Expr, into: Location, depth: usize) -> Result<(), Error> {
+        match expr {
+            Expr::Number(_) | Expr::Variable(_) => {
+                let (value, _) = self.value(expr, depth)?;
+                let operand = self.load(value, FIRST);
+
+                self.store(operand, into);
+            }
+            Expr::Address(index) => {
+                let Location::Frame(offset) = self.allocation().locations[*index] else {
+                    unreachable!("arrays are kept in the frame");
+                };
+
+                self.push(Instruction::Add(
+                    Operand::Value(offset),
+                    register(STACK_POINTER),
+                    register(FIRST),
+                ));
+                self.store(register(FIRST), into);
+            }
+            Expr::Index(address, index) => {
+                self.address(address, index, depth)?;
+                self.push(Instruction::Mov(at(SECOND), register(FIRST)));
+                self.store(register(FIRST), into);
+            }
+            Expr::Binary(operator, _, _) if operator.compares() => {
+                let otherwise = self.label();
+                let end = self.label();
+
+                self.branch_unless(expr, otherwise, depth)?;
+                self.push(Instruction::Mov(Operand::Value(1), register(FIRST)));
+                self.jump(Instruction::Jmp, end);
+                self.place(otherwise);
+                self.push(Instruction::Mov(Operand::Value(0), register(FIRST)));
+                self.place(end);
+                self.store(register(FIRST), into);
+            }
+            Expr::Binary(operator, left, right) => {
+                let (left, depth) = self.value(left, depth)?;
+                let (right, _) = self.value(right, depth)?;
+
+                match operator {
+                    Operator::Add => {
+                        let right = self.load(right, SECOND);
+                        let left = self.load(left, FIRST);
+
+                        self.add(left, right, into);
+                    }
+                    Operator::Subtract => {
+                        self.load_into(right, SECOND);
+                        self.negate();
+
+                        let left = self.load(left, FIRST);
+
+                        self.add(left, register(SECOND), into);
+                    }
+                    Operator::Multiply => self.multiply(left, right, into),
+
+                    _ => unreachable!("comparisons are evaluated as conditions"),
+                }
+            }
+            Expr::Call {
+                name,
+                arguments,
+                line,
+            } => self.call(name, arguments, *line, into, depth)?,
+        }
+
+        Ok(())
+    }
+
+    /// Adds both operands into the given location.
+    fn add(&mut self, left: Operand, right: Operand, into: Location) {
+        match into {
+            Location::Register(index) => self.push(Instruction::Add(left, right, register(index))),
+            Location::Frame(_) => {
+                self.push(Instruction::Add(left, right, register(FIRST)));
+                self.store(register(FIRST), into);
+            }
+        }
+    }
+
+    /// Negates [`SECOND`] through the complement table, overwriting [`FIRST`].
+    fn negate(&mut self) {
+        self.instructions.extend(lowering::negate(SECOND, FIRST));
+    }
+
+    /// Multiplies both values into the given location, by shifts and adds over the bits of the
+    /// constant when one is constant, and otherwise by a loop over the 64 bits of the right value
+    /// doubling the product and adding the left value for every set bit.
+    fn multiply(&mut self, left: Value, right: Value, into: Location) {
+        let (value, factor) = match (left, right) {
+            (value, Value::Constant(factor)) | (Value::Constant(factor), value) => (value, factor),
+
+            (left, right) => {
+                let head = self.label();
+                let end = self.label();
+
+                // Bits are taken from the top of `SECOND`, adding `FIRST` to the doubled product.
+                // A bit shifted in after the first one reaches the top once every bit was taken.
+                self.load_into(left, FIRST);
+                self.load_into(right, SECOND);
+                self.push(Instruction::Mov(Operand::Value(0), register(FRAME)));
+                self.shift_add();
+                self.push(Instruction::Add(
+                    Operand::Value(1),
+                    register(SECOND),
+                    register(SECOND),
+                ));
+                self.place(head);
+                self.push(Instruction::Cmp(register(SECOND), Operand::Value(1 << 63)));
+                self.jump(Instruction::Jz, end);
+                self.push(Instruction::Add(
+                    register(FRAME),
+                    register(FRAME),
+                    register(FRAME),
+                ));
+                self.shift_add();
+                self.jump(Instruction::Jmp, head);
+                self.place(end);
+                self.push(Instruction::Mov(register(FRAME), register(FIRST)));
+                self.store(register(FIRST), into);
+
+                return;
+            }
+        };
+
+        self.load_into(value, SECOND);
+        self.push(Instruction::Mov(Operand::Value(0), register(FIRST)));
+
+        for bit in 0..64 - factor.leading_zeros() {
+            if factor & (1 << bit) != 0 {
+                self.push(Instruction::Add(
+                    register(SECOND),
+                    register(FIRST),
+                    register(FIRST),
+                ));
+            }
+
+            if factor >> bit > 1 {
+                self.push(Instruction::Add(
+                    register(SECOND),
+                    register(SECOND),
+                    register(SECOND),
+                ));
+            }
+        }
+
+        self.store(register(FIRST), into);
+    }
+
+    /// Doubles [`SECOND`], adding [`FIRST`] into [`FRAME`] when its top bit was set.
+    fn shift_add(&mut self) {
+        let clear = self.label();
+
+        self.push(Instruction::Mov(Operand::Value(0), register(FLAGS)));
+        self.push(Instruction::Add(
+            register(SECOND),
+            register(SECOND),
+            register(SECOND),
+        ));
+        self.push(Instruction::Cmp(register(FLAGS), Operand::Value(0)));
+        self.jump(Instruction::Jz, clear);
+        self.push(Instruction::Add(
+            register(FIRST),
+            register(FRAME),
+            register(FRAME),
+        ));
+        self.place(clear);
+    }
+
+    /// Jumps to the given label when the given [`Greater`](crate::register::Flag::Greater) flag
+    /// is set as wanted by the last comparison, overflow aside.
+    fn branch_greater(&mut self, set: bool, label: usize) {
+        let skip = self.label();
+
+        self.push(Instruction::Mov(register(FLAGS), register(FIRST)));
+
+        // Greater excludes Zero, so the flags are Greater with or without Overflow.
+        if set {
+            self.push(Instruction::Cmp(register(FIRST), Operand::Value(2)));
+            self.jump(Instruction::Jz, label);
+            self.push(Instruction::Cmp(register(FIRST), Operand::Value(6)));
+            self.jump(Instruction::Jz, label);
+        } else {
+            self.push(Instruction::Cmp(register(FIRST), Operand::Value(2)));
+            self.jump(Instruction::Jz, skip);
+            self.push(Instruction::Cmp(register(FIRST), Operand::Value(6)));
+            self.jump(Instruction::Jnz, label);
+        }
+
+        self.place(skip);
+    }
+
+    /// Jumps to the given label unless the given condition holds.
+    fn branch_unless(&mut self, condition: &Expr, label: usize, depth: usize) -> Result<(), Error> {
+        let Expr::Binary(operator, left, right) = condition else {
+            let (value, _) = self.value(condition, depth)?;
+            let value = self.load(value, FIRST);
+
+            self.push(Instruction::Cmp(value, Operand::Value(0)));
+            self.jump(Instruction::Jz, label);
+
+            return Ok(());
+        };
+
+        if !operator.compares() {
+            let (value, _) = self.value(condition, depth)?;
+            let value = self.load(value, FIRST);
+
+            self.push(Instruction::Cmp(value, Operand::Value(0)));
+            self.jump(Instruction::Jz, label);
+
+            return Ok(());
+        }
+
+        let (left, depth) = self.value(left, depth)?;
+        let (right, _) = self.value(right, depth)?;
+        let left = self.load(left, FIRST);
+        let right = self.load(right, SECOND);
+
+        match operator {
+            Operator::Less | Operator::GreaterEqual => {
+                self.push(Instruction::Cmp(right, left));
+            }
+
+            _ => self.push(Instruction::Cmp(left, right)),
+        }
+
+        match operator {
+            Operator::Equal => self.jump(Instruction::Jnz, label),
+            Operator::NotEqual => self.jump(Instruction::Jz, label),
+            Operator::Less | Operator::Greater => self.branch_greater(false, label),
+            Operator::LessEqual | Operator::GreaterEqual => self.branch_greater(true, label),
+
+            _ => unreachable!("only comparisons branch"),
+        }
+
+        Ok(())
+    }
+
+    /// Calls the named function, writing its result into the given location.
+    fn call(
+        &mut self,
+        name: &str,
+        arguments: &[Expr],
+        line: usize,
+        into: Location,
+        depth: usize,
+    ) -> Result<(), Error> {
+        let Some(&(label, function)) = self.functions.get(name) else {
+            return Err(Error::Syntax(line));
+        };
+
+        if function.parameters != arguments.len() {
+            return Err(Error::Syntax(line));
+        }
+
+        let mut values = Vec::new();
+        let mut next = depth;
+
+        for argument in arguments {
+            let (value, depth) = self.value(argument, next)?;
+
+            values.push(value);
+            next = depth;
+        }
+
+        for (index, value) in values.into_iter().enumerate() {
+            let value = self.load(value, FIRST);
+
+            self.push_fixup(
+                Instruction::Add(Operand::Value(0), register(STACK_POINTER), register(FRAME)),
+                Fixup::Frame(8 + 8 * index as u64),
+            );
+            self.push(Instruction::Mov(value, at(FRAME)));
+        }
+
+        // Variables live across the statement, and temporaries of enclosing expressions.
+        let allocation = self.allocation();
+        let mut saved: Vec<_> = allocation
+            .locations
+            .iter()
+            .zip(&allocation.intervals)
+            .filter_map(|(location, interval)| match location {
+                Location::Register(register) if interval.contains(&self.position) => {
+                    Some(*register)
+                }
+
+                _ => None,
+            })
+            .chain(TEMPORARIES.take(depth))
+            .collect();
+
+        saved.sort_unstable();
+        saved.dedup();
+
+        let saves = allocation.saves;
+
+        for index in &saved {
+            let slot = self.frame(saves + 8 * *index as u64);
+
+            self.push(Instruction::Mov(register(*index), slot));
+        }
+
+        let back = self.label();
+
+        self.push_fixup(
+            Instruction::Add(Operand::Value(0), register(STACK_POINTER), register(FRAME)),
+            Fixup::Frame(0),
+        );
+        self.push_fixup(
+            Instruction::Mov(Operand::Value(0), at(FRAME)),
+            Fixup::Label(back),
+        );
+        self.push_fixup(
+            Instruction::Add(
+                Operand::Value(0),
+                register(STACK_POINTER),
+                register(STACK_POINTER),
+            ),
+            Fixup::Frame(0),
+        );
+        self.jump(Instruction::Jmp, label);
+        self.place(back);
+        self.push_fixup(
+            Instruction::Add(
+                Operand::Value(0),
+                register(STACK_POINTER),
+                register(STACK_POINTER),
+            ),
+            Fixup::Release,
+        );
+
+        for index in &saved {
+            let slot = self.frame(saves + 8 * *index as u64);
+
+            self.push(Instruction::Mov(slot, register(*index)));
+        }
+
+        self.store(register(FIRST), into);
+
+        Ok(())
+    }
+
+    /// Returns to the caller with [`FIRST`] as result.
+    fn ret(&mut self) {
+        self.push(Instruction::Mov(at(STACK_POINTER), register(FRAME)));
+        self.push(Instruction::Jmp(register(FRAME)));
+    }
+
+    fn statements(&mut self, statements: &[Statement]) -> Result<(), Error> {
+        for statement in statements {
+            self.position = statement.position;
+
+            match &statement.kind {
+                StatementKind::Assign(index, value) => {
+                    let into = self.allocation().locations[*index];
+
+                    self.evaluate(value, into, 0)?;
+                }
+                StatementKind::Store(address, index, value) => {
+                    let (value, depth) = self.value(value, 0)?;
+
+                    self.address(address, index, depth)?;
+
+                    let value = self.load(value, FIRST);
+
+                    self.push(Instruction::Mov(value, at(SECOND)));
+                }
+                StatementKind::If(condition, then, otherwise) => {
+                    let skip = self.label();
+
+                    self.branch_unless(condition, skip, 0)?;
+                    self.statements(then)?;
+
+                    if otherwise.is_empty() {
+                        self.place(skip);
+                    } else {
+                        let end = self.label();
+
+                        self.jump(Instruction::Jmp, end);
+                        self.place(skip);
+                        self.statements(otherwise)?;
+                        self.place(end);
+                    }
+                }
+                StatementKind::While(condition, body) => {
+                    let head = self.label();
+                    let end = self.label();
+
+                    self.place(head);
+                    self.branch_unless(condition, end, 0)?;
+                    self.statements(body)?;
+                    self.jump(Instruction::Jmp, head);
+                    self.place(end);
+                }
+                StatementKind::Return(value) => {
+                    match value {
+                        Some(value) => self.evaluate(value, Location::Register(FIRST), 0)?,
+                        None => self.push(Instruction::Mov(Operand::Value(0), register(FIRST))),
+                    }
+
+                    self.ret();
+                }
+                StatementKind::Expr(expr) => self.evaluate(expr, Location::Register(FIRST), 0)?,
+            }
+        }
+
+        Ok(())
+    }
+
+    fn function(&mut self, label: usize, function: &Function) -> Result<(), Error> {
+        let allocation = allocate(function, VARIABLES);
+        let start = self.fixups.len();
+
+        self.place(label);
+
+        for (index, location) in allocation.locations[..function.parameters]
+            .iter()
+            .enumerate()
+        {
+            if let Location::Register(parameter) = location {
+                let slot = self.frame(8 + 8 * index as u64);
+
+                self.push(Instruction::Mov(slot, register(*parameter)));
+            }
+        }
+
+        self.allocation = Some(allocation);
+        self.spilled = 0;
+        self.statements(&function.body)?;
+        self.push(Instruction::Mov(Operand::Value(0), register(FIRST)));
+        self.ret();
+
+        let size = self.allocation().temporaries + 8 * self.spilled;
+
+        for (index, fixup) in self.fixups.drain(start..).collect::<Vec<_>>() {
+            match fixup {
+                Fixup::Frame(offset) => {
+                    *first(&mut self.instructions[index]) = Operand::Value(size + offset)
+                }
+                Fixup::Release => {
+                    *first(&mut self.instructions[index]) = Operand::Value(size.wrapping_neg())
+                }
+                Fixup::Label(_) => self.fixups.push((index, fixup)),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Generates instructions for the given program, naming the first instruction of each function
+/// after it.
+pub(crate) fn generate(
+    program: &Program,
+) -> Result<(Vec<Instruction>, BTreeMap<usize, &str>), Error> {
+    let mut generator = Generator {
+        functions: BTreeMap::new(),
+        instructions: Vec::new(),
+        labels: Vec::new(),
+        fixups: Vec::new(),
+        allocation: None,
+        position: 0,
+        spilled: 0,
+    };
+
+    for function in &program.functions {
+        let label = generator.label();
+
+        if generator
+            .functions
+            .insert(&function.name, (label, function))
+            .is_some()
+        {
+            return Err(Error::Syntax(function.line));
+        }
+    }
+
+    let Some(&(main, function)) = generator.functions.get("main") else {
+        return Err(Error::UnknownSymbol);
+    };
+
+    if function.parameters != 0 {
+        return Err(Error::Syntax(function.line));
+    }
+
+    generator.push(Instruction::Mov(
+        Operand::Value(STACK),
+        register(STACK_POINTER),
+    ));
+
+    if program.complements {
+        let start = generator.instructions.len();
+
+        generator
+            .instructions
+            .extend(complements(start, FIRST, SECOND));
+    }
+
+    let back = generator.label();
+    let end = generator.label();
+
+    generator.push_fixup(
+        Instruction::Mov(Operand::Value(0), at(STACK_POINTER)),
+        Fixup::Label(back),
+    );
+    generator.jump(Instruction::Jmp, main);
+    generator.place(back);
+    generator.push(Instruction::Mov(register(FIRST), register(0)));
+    generator.jump(Instruction::Jmp, end);
+
+    let mut names = BTreeMap::new();
+
+    for function in &program.functions {
+        let (label, _) = generator.functions[function.name.as_str()];
+
+        generator.function(label, function)?;
+        names.insert(generator.labels[label], function.name.as_str());
+    }
+
+    generator.place(end);
+
+    for (index, fixup) in std::mem::take(&mut generator.fixups) {
+        if let Fixup::Label(label) = fixup {
+            *first(&mut generator.instructions[index]) =
+                Operand::Value((generator.labels[label] as u64).wrapping_sub(1));
+        }
+    }
+
+    Ok((generator.instructions, names))
+}
@@ -0,0 +1,275 @@
+//! A small structured language compiling to an [`Assembler`].
+//!
+//! A program is a list of functions, run from `main`, which takes no parameters:
+//!
+//! ```text
+//! fn main() {
+//!     var numbers[4];
+//!     var i = 0;
+//!     while (i < 4) {
+//!         numbers[i] = square(i + 1);
+//!         i = i + 1;
+//!     }
+//!     return sum(numbers, 4); // 30
+//! }
+//!
+//! fn square(x) { return x * x; }
+//!
+//! fn sum(array, length) {
+//!     var total = 0;
+//!     while (length != 0) {
+//!         length = length - 1;
+//!         total = total + array[length];
+//!     }
+//!     return total;
+//! }
+//! ```
+//!
+//! Values are unsigned 64-bit integers with wrapping arithmetic; `+`, `-`, `*` and the comparisons
+//! `==`, `!=`, `<`, `>`, `<=`, `>=` giving 1 or 0 are supported. Conditions hold when not 0.
+//! `var name[length];` declares an array of words in the frame of its function, uninitialized;
+//! its name gives its address. Any value can be indexed as the address of an array of words.
+//! Functions return 0 unless they `return` a value, and the result of `main` is left in `rq0`.
+//!
+//! Variables are assigned registers `rq0` to `rq6` by linear scan over the statements they are live
+//! over, and kept in the frame when they run out. Registers `rq7` to `rq12` hold temporaries and
+//! operands, and `rq13` the address of the frame of the running function. Frames are stacked up
+//! from [`STACK`]. Multiplication by a value that isn't constant shifts and adds over the 64 bits of
+//! the right operand. Subtraction complements bytes through a table at address 0, filled on startup
+//! in programs subtracting values that aren't constant.
+
+mod allocate;
+mod codegen;
+mod parser;
+
+use crate::assembler::Assembler;
+use crate::error::Error;
+use crate::lowering;
+
+/// Address of the frame of `main`, the frames of called functions following it.
+pub const STACK: u64 = 0x1000;
+
+/// Compiles the given source into an [`Assembler`], labelling each function by its name.
+///
+/// # Example
+/// ```
+/// use vm::lang::compile;
+/// let assembler = compile("fn main() { return 6 * 7; }").unwrap();
+/// assert!(assembler.labels().contains_key("main"));
+/// ```
+///
+/// # Errors
+/// When the source is malformed, refers to an undeclared variable or function, or calls a function
+/// with the wrong number of arguments, [`Syntax`](Error::Syntax) is returned with its 1-based line
+/// number. When there is no `main` function, [`UnknownSymbol`](Error::UnknownSymbol) is returned.
+pub fn compile(source: &str) -> Result<Assembler, Error> {
+    let program = parser::parse(source)?;
+    let (instructions, names) = codegen::generate(&program)?;
+
+    Ok(lowering::assemble(instructions, &names))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::Error;
+    use crate::lang::compile;
+    use crate::Vm;
+
+    /// Compiles and runs the given source, returning the result of `main`.
+    fn run(source: &str) -> u64 {
+        let mut vm = Vm::new();
+
+        vm.set_verify(true);
+        vm.load_instructions(compile(source).unwrap().compile())
+            .unwrap();
+
+        let handle = vm.new_processor();
+        let processor = vm.processor_mut(handle).unwrap();
+
+        processor.start().unwrap();
+        processor.register(0).unwrap().as_u64()
+    }
+
+    #[test]
+    pub fn lang_arithmetic() {
+        assert_eq!(run("fn main() { return 6 * 7; }"), 42);
+        assert_eq!(
+            run("fn main() { var a = 5; var b = 3; return a * b - (b - a) * 10; }"),
+            35
+        );
+        assert_eq!(
+            run("fn main() { var a = 2; return -a; }"),
+            2u64.wrapping_neg()
+        );
+        assert_eq!(
+            run("fn main() { var a = 0x1234; var b = 0x11ff; return a - b + 0; }"),
+            0x35
+        );
+        assert_eq!(
+            run("fn main() { var a = 3; return (a < 4) + (a > 4) * 2 + (a == 3) * 4; }"),
+            5
+        );
+        assert_eq!(
+            run("fn main() { var a = 3; return (a <= 3) + (a >= 4) * 2 + (a != 3) * 4; }"),
+            1
+        );
+    }
+
+    #[test]
+    pub fn lang_multiplication() {
+        let product = |left: u64, right: u64| {
+            run(&format!(
+                "fn main() {{ var a = {left}; var b = {right}; return a * b; }}"
+            ))
+        };
+
+        for (left, right) in [
+            (0, 0),
+            (7, 0),
+            (0, 7),
+            (6, 7),
+            (3, u64::MAX),
+            (u64::MAX, u64::MAX),
+            (1 << 63, 3),
+            (0x1234_5678_9abc, 0xfedc_ba98_7654),
+        ] {
+            assert_eq!(
+                product(left, right),
+                left.wrapping_mul(right),
+                "{left} * {right}"
+            );
+        }
+
+        assert_eq!(
+            run("fn main() { var a = 3; var b = a - 4; return a * b; }"),
+            3u64.wrapping_neg()
+        );
+    }
+
+    #[test]
+    pub fn lang_control_flow() {
+        let source = "fn main() {
+            var sum = 0;
+            var i = 0;
+            while (i < 10) {
+                if (i == 3) {
+                    sum = sum + 100;
+                } else if (i > 7) {
+                    sum = sum + 1000;
+                } else {
+                    sum = sum + i;
+                }
+                i = i + 1;
+            }
+            return sum;
+        }";
+
+        assert_eq!(run(source), 100 + 2000 + (1 + 2 + 4 + 5 + 6 + 7));
+        assert_eq!(
+            run("fn main() { var x = 7; if (x) { return 1; } return 2; }"),
+            1
+        );
+        assert_eq!(
+            run("fn main() { var x; while (1) { x = x + 1; if (x == 5) { return x; } } }"),
+            5
+        );
+    }
+
+    #[test]
+    pub fn lang_functions() {
+        let source = "fn fibonacci(n) {
+            if (n < 2) { return n; }
+            return fibonacci(n - 1) + fibonacci(n - 2);
+        }
+        fn main() { return fibonacci(15) + twice(add(1, 2), 0); }
+        fn add(a, b) { return a + b; }
+        fn twice(x, unused) { return x + x; }";
+
+        assert_eq!(run(source), 610 + 6);
+        assert_eq!(
+            run("fn main() { nothing(); return 3; } fn nothing() { }"),
+            3
+        );
+    }
+
+    #[test]
+    pub fn lang_arrays() {
+        let source = "fn main() {
+            var numbers[6];
+            var i = 0;
+            while (i < 6) {
+                numbers[i] = 10 - i * i + i;
+                i = i + 1;
+            }
+            sort(numbers, 6);
+            return numbers[0] * 1000000 + numbers[3] * 1000 + sum(numbers, 6);
+        }
+
+        fn sort(array, length) {
+            var i = 0;
+            while (i < length) {
+                var j = i + 1;
+                while (j < length) {
+                    if (array[j] + 100 < array[i] + 100) {
+                        var swap = array[i];
+                        array[i] = array[j];
+                        array[j] = swap;
+                    }
+                    j = j + 1;
+                }
+                i = i + 1;
+            }
+        }
+
+        fn sum(array, length) {
+            var total = 0;
+            while (length != 0) {
+                length = length - 1;
+                total = total + array[length];
+            }
+            return total;
+        }";
+
+        // 10, 10, 8, 4, -2, -10 sorted as signed values offset by 100.
+        assert_eq!(
+            run(source),
+            10u64
+                .wrapping_neg()
+                .wrapping_mul(1_000_000)
+                .wrapping_add(8 * 1000)
+                .wrapping_add(20)
+        );
+    }
+
+    #[test]
+    pub fn lang_spills() {
+        // More live variables than registers, and expressions deeper than the temporaries.
+        let source = "fn main() {
+            var a = 1; var b = 2; var c = 3; var d = 4; var e = 5;
+            var f = 6; var g = 7; var h = 8; var i = 9; var j = 10;
+            var deep = a + (b + (c + (d + (e + (f + (g + (h + id(i + j))))))));
+            return deep * 1000 + a + b + c + d + e + f + g + h + i + j;
+        }
+        fn id(x) { var y = x; return y; }";
+
+        assert_eq!(run(source), 55 * 1000 + 55);
+    }
+
+    #[test]
+    pub fn lang_errors() {
+        assert_eq!(compile("fn f() { }").unwrap_err(), Error::UnknownSymbol);
+        assert_eq!(compile("fn main(a) { }").unwrap_err(), Error::Syntax(1));
+        assert_eq!(
+            compile("fn main() {\n g();\n}").unwrap_err(),
+            Error::Syntax(2)
+        );
+        assert_eq!(
+            compile("fn main() {\n\n f(1);\n}\nfn f(a, b) { }").unwrap_err(),
+            Error::Syntax(3)
+        );
+        assert_eq!(
+            compile("fn main() { }\nfn main() { }").unwrap_err(),
+            Error::Syntax(2)
+        );
+    }
+}
@@ -0,0 +1,607 @@
+use crate::error::Error;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Number(u64),
+    Name(String),
+    Symbol(&'static str),
+}
+
+/// Symbols of the language, longest first so `==` isn't read as two `=`.
+const SYMBOLS: [&str; 18] = [
+    "==", "!=", "<=", ">=", "(", ")", "{", "}", "[", "]", ",", ";", "=", "<", ">", "+", "-", "*",
+];
+
+const KEYWORDS: [&str; 6] = ["fn", "var", "if", "else", "while", "return"];
+
+/// Splits source into tokens, each with its 1-based line.
+fn tokenize(source: &str) -> Result<Vec<(Token, usize)>, Error> {
+    let mut tokens = Vec::new();
+
+    for (line_index, line) in source.lines().enumerate() {
+        let line_number = line_index + 1;
+        let mut rest = line.split("//").next().unwrap_or_default().trim_start();
+
+        while !rest.is_empty() {
+            let length = if let Some(symbol) =
+                SYMBOLS.iter().find(|symbol| rest.starts_with(**symbol))
+            {
+                tokens.push((Token::Symbol(symbol), line_number));
+
+                symbol.len()
+            } else {
+                let length = rest
+                    .find(|character: char| !character.is_ascii_alphanumeric() && character != '_')
+                    .unwrap_or(rest.len());
+                let word = &rest[..length];
+
+                let token = match word.chars().next() {
+                    Some(character) if character.is_ascii_digit() => {
+                        let number = match word.strip_prefix("0x") {
+                            Some(hex) => u64::from_str_radix(hex, 16),
+                            None => word.parse(),
+                        };
+
+                        Token::Number(number.map_err(|_| Error::Syntax(line_number))?)
+                    }
+                    Some(_) => Token::Name(word.to_string()),
+
+                    None => return Err(Error::Syntax(line_number)),
+                };
+
+                tokens.push((token, line_number));
+
+                length
+            };
+
+            rest = rest[length..].trim_start();
+        }
+    }
+
+    Ok(tokens)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Operator {
+    Add,
+    Subtract,
+    Multiply,
+    Equal,
+    NotEqual,
+    Less,
+    Greater,
+    LessEqual,
+    GreaterEqual,
+}
+
+impl Operator {
+    /// Returns whether the operator compares its operands, giving 1 when it holds and 0 otherwise.
+    pub(crate) fn compares(self) -> bool {
+        !matches!(
+            self,
+            Operator::Add | Operator::Subtract | Operator::Multiply
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Expr {
+    Number(u64),
+    /// The value of the scalar variable with the given number.
+    Variable(usize),
+    /// The address of the array variable with the given number.
+    Address(usize),
+    /// The word at the given index from the given address.
+    Index(Box<Expr>, Box<Expr>),
+    Binary(Operator, Box<Expr>, Box<Expr>),
+    Call {
+        name: String,
+        arguments: Vec<Expr>,
+        line: usize,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum StatementKind {
+    /// Writes the value to the scalar variable with the given number.
+    Assign(usize, Expr),
+    /// Writes the value to the word at the given index from the given address.
+    Store(Expr, Expr, Expr),
+    If(Expr, Vec<Statement>, Vec<Statement>),
+    While(Expr, Vec<Statement>),
+    Return(Option<Expr>),
+    Expr(Expr),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Statement {
+    /// Number of the statement within its function in source order, starting at 1.
+    pub(crate) position: usize,
+    pub(crate) kind: StatementKind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Variable {
+    Scalar,
+    /// An array of the given number of words.
+    Array(u64),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Function {
+    pub(crate) name: String,
+    pub(crate) line: usize,
+    /// Number of parameters, the first variables of the function.
+    pub(crate) parameters: usize,
+    pub(crate) variables: Vec<Variable>,
+    pub(crate) body: Vec<Statement>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Program {
+    pub(crate) functions: Vec<Function>,
+    /// Whether a subtraction needs the complement table.
+    pub(crate) complements: bool,
+}
+
+struct Parser {
+    tokens: Vec<(Token, usize)>,
+    next: usize,
+
+    /// Variables declared in each enclosing block, innermost last.
+    scopes: Vec<Vec<(String, usize)>>,
+    variables: Vec<Variable>,
+    positions: usize,
+    complements: bool,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.next).map(|(token, _)| token)
+    }
+
+    /// Returns the line of the next token, or of the last one at the end of the source.
+    fn line(&self) -> usize {
+        self.tokens
+            .get(self.next)
+            .or(self.tokens.last())
+            .map_or(1, |(_, line)| *line)
+    }
+
+    fn error<T>(&self) -> Result<T, Error> {
+        Err(Error::Syntax(self.line()))
+    }
+
+    /// Consumes the next token if it is the given symbol.
+    fn eat(&mut self, symbol: &str) -> bool {
+        let matches = matches!(self.peek(), Some(Token::Symbol(next)) if *next == symbol)
+            || matches!(self.peek(), Some(Token::Name(next)) if next == symbol);
+
+        self.next += usize::from(matches);
+
+        matches
+    }
+
+    fn expect(&mut self, symbol: &str) -> Result<(), Error> {
+        if self.eat(symbol) {
+            Ok(())
+        } else {
+            self.error()
+        }
+    }
+
+    fn name(&mut self) -> Result<String, Error> {
+        match self.peek() {
+            Some(Token::Name(name)) if !KEYWORDS.contains(&name.as_str()) => {
+                let name = name.clone();
+                self.next += 1;
+
+                Ok(name)
+            }
+
+            _ => self.error(),
+        }
+    }
+
+    fn number(&mut self) -> Result<u64, Error> {
+        match self.peek() {
+            Some(Token::Number(number)) => {
+                let number = *number;
+                self.next += 1;
+
+                Ok(number)
+            }
+
+            _ => self.error(),
+        }
+    }
+
+    fn declare(&mut self, name: String, variable: Variable) -> usize {
+        let index = self.variables.len();
+
+        self.variables.push(variable);
+        self.scopes
+            .last_mut()
+            .expect("declarations happen within a scope")
+            .push((name, index));
+
+        index
+    }
+
+    fn resolve(&self, name: &str) -> Result<usize, Error> {
+        self.scopes
+            .iter()
+            .rev()
+            .flat_map(|scope| scope.iter().rev())
+            .find(|(declared, _)| declared == name)
+            .map(|(_, index)| *index)
+            .map_or_else(|| self.error(), Ok)
+    }
+
+    fn function(&mut self) -> Result<Function, Error> {
+        let line = self.line();
+
+        self.expect("fn")?;
+
+        let name = self.name()?;
+
+        self.scopes = vec![Vec::new()];
+        self.variables = Vec::new();
+        self.positions = 0;
+        self.expect("(")?;
+
+        if !self.eat(")") {
+            loop {
+                let parameter = self.name()?;
+                self.declare(parameter, Variable::Scalar);
+
+                if self.eat(")") {
+                    break;
+                }
+
+                self.expect(",")?;
+            }
+        }
+
+        let parameters = self.variables.len();
+        let body = self.block()?;
+
+        Ok(Function {
+            name,
+            line,
+            parameters,
+            variables: std::mem::take(&mut self.variables),
+            body,
+        })
+    }
+
+    fn block(&mut self) -> Result<Vec<Statement>, Error> {
+        self.expect("{")?;
+        self.scopes.push(Vec::new());
+
+        let mut statements = Vec::new();
+
+        while !self.eat("}") {
+            if self.peek().is_none() {
+                return self.error();
+            }
+
+            if let Some(statement) = self.statement()? {
+                statements.push(statement);
+            }
+        }
+
+        self.scopes.pop();
+
+        Ok(statements)
+    }
+
+    /// Parses a statement, returning [`None`] for array declarations which don't execute.
+    fn statement(&mut self) -> Result<Option<Statement>, Error> {
+        self.positions += 1;
+
+        let position = self.positions;
+        let kind = if self.eat("var") {
+            let name = self.name()?;
+
+            if self.eat("[") {
+                let length = self.number()?;
+
+                self.expect("]")?;
+                self.expect(";")?;
+                self.declare(name, Variable::Array(length));
+
+                return Ok(None);
+            }
+
+            let value = if self.eat("=") {
+                self.expression()?
+            } else {
+                Expr::Number(0)
+            };
+
+            self.expect(";")?;
+
+            // Declared after its value, which can't refer to it.
+            StatementKind::Assign(self.declare(name, Variable::Scalar), value)
+        } else if self.eat("if") {
+            let condition = self.condition()?;
+            let then = self.block()?;
+            let otherwise = if !self.eat("else") {
+                Vec::new()
+            } else if matches!(self.peek(), Some(Token::Name(name)) if name == "if") {
+                self.statement()?.into_iter().collect()
+            } else {
+                self.block()?
+            };
+
+            StatementKind::If(condition, then, otherwise)
+        } else if self.eat("while") {
+            let condition = self.condition()?;
+
+            StatementKind::While(condition, self.block()?)
+        } else if self.eat("return") {
+            let value = if self.eat(";") {
+                None
+            } else {
+                let value = self.expression()?;
+
+                self.expect(";")?;
+
+                Some(value)
+            };
+
+            StatementKind::Return(value)
+        } else {
+            let target = self.expression()?;
+            let kind = if self.eat("=") {
+                let value = self.expression()?;
+
+                match target {
+                    Expr::Variable(index) => StatementKind::Assign(index, value),
+                    Expr::Index(address, index) => StatementKind::Store(*address, *index, value),
+
+                    _ => return self.error(),
+                }
+            } else {
+                StatementKind::Expr(target)
+            };
+
+            self.expect(";")?;
+
+            kind
+        };
+
+        Ok(Some(Statement { position, kind }))
+    }
+
+    fn condition(&mut self) -> Result<Expr, Error> {
+        self.expect("(")?;
+
+        let condition = self.expression()?;
+
+        self.expect(")")?;
+
+        Ok(condition)
+    }
+
+    fn expression(&mut self) -> Result<Expr, Error> {
+        let left = self.sum()?;
+        let operator = [
+            ("==", Operator::Equal),
+            ("!=", Operator::NotEqual),
+            ("<=", Operator::LessEqual),
+            (">=", Operator::GreaterEqual),
+            ("<", Operator::Less),
+            (">", Operator::Greater),
+        ]
+        .into_iter()
+        .find(|(symbol, _)| self.eat(symbol));
+
+        match operator {
+            Some((_, operator)) => Ok(Expr::Binary(
+                operator,
+                Box::new(left),
+                Box::new(self.sum()?),
+            )),
+
+            None => Ok(left),
+        }
+    }
+
+    fn subtract(&mut self, left: Expr, right: Expr) -> Expr {
+        match right {
+            Expr::Number(number) => Expr::Binary(
+                Operator::Add,
+                Box::new(left),
+                Box::new(Expr::Number(number.wrapping_neg())),
+            ),
+            right => {
+                self.complements = true;
+
+                Expr::Binary(Operator::Subtract, Box::new(left), Box::new(right))
+            }
+        }
+    }
+
+    fn sum(&mut self) -> Result<Expr, Error> {
+        let mut sum = self.product()?;
+
+        loop {
+            if self.eat("+") {
+                sum = Expr::Binary(Operator::Add, Box::new(sum), Box::new(self.product()?));
+            } else if self.eat("-") {
+                let right = self.product()?;
+
+                sum = self.subtract(sum, right);
+            } else {
+                return Ok(sum);
+            }
+        }
+    }
+
+    fn product(&mut self) -> Result<Expr, Error> {
+        let mut product = self.unary()?;
+
+        while self.eat("*") {
+            product = Expr::Binary(
+                Operator::Multiply,
+                Box::new(product),
+                Box::new(self.unary()?),
+            );
+        }
+
+        Ok(product)
+    }
+
+    fn unary(&mut self) -> Result<Expr, Error> {
+        if self.eat("-") {
+            return match self.unary()? {
+                Expr::Number(number) => Ok(Expr::Number(number.wrapping_neg())),
+                operand => Ok(self.subtract(Expr::Number(0), operand)),
+            };
+        }
+
+        let mut expr = self.primary()?;
+
+        while self.eat("[") {
+            let index = self.expression()?;
+
+            self.expect("]")?;
+            expr = Expr::Index(Box::new(expr), Box::new(index));
+        }
+
+        Ok(expr)
+    }
+
+    fn primary(&mut self) -> Result<Expr, Error> {
+        if self.eat("(") {
+            let expr = self.expression()?;
+
+            self.expect(")")?;
+
+            return Ok(expr);
+        }
+
+        if let Some(Token::Number(_)) = self.peek() {
+            return Ok(Expr::Number(self.number()?));
+        }
+
+        let line = self.line();
+        let name = self.name()?;
+
+        if self.eat("(") {
+            let mut arguments = Vec::new();
+
+            if !self.eat(")") {
+                loop {
+                    arguments.push(self.expression()?);
+
+                    if self.eat(")") {
+                        break;
+                    }
+
+                    self.expect(",")?;
+                }
+            }
+
+            return Ok(Expr::Call {
+                name,
+                arguments,
+                line,
+            });
+        }
+
+        let index = self.resolve(&name)?;
+
+        match self.variables[index] {
+            Variable::Scalar => Ok(Expr::Variable(index)),
+            Variable::Array(_) => Ok(Expr::Address(index)),
+        }
+    }
+}
+
+/// Parses source into functions, resolving variable names.
+///
+/// # Errors
+/// When the source is malformed or refers to an undeclared variable, [`Syntax`](Error::Syntax) is
+/// returned with its 1-based line number.
+pub(crate) fn parse(source: &str) -> Result<Program, Error> {
+    let mut parser = Parser {
+        tokens: tokenize(source)?,
+        next: 0,
+        scopes: Vec::new(),
+        variables: Vec::new(),
+        positions: 0,
+        complements: false,
+    };
+    let mut functions = Vec::new();
+
+    while parser.peek().is_some() {
+        functions.push(parser.function()?);
+    }
+
+    Ok(Program {
+        functions,
+        complements: parser.complements,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn parser_scopes() {
+        let program = parse(
+            "fn f(a) {
+                var b = a - 1; // comment
+                if (b) { var a = 2; a = a * b; }
+                return -a;
+            }",
+        )
+        .unwrap();
+        let function = &program.functions[0];
+
+        assert!(program.complements);
+        assert_eq!(function.parameters, 1);
+        assert_eq!(function.variables.len(), 3);
+        assert_eq!(
+            function.body[0].kind,
+            StatementKind::Assign(
+                1,
+                Expr::Binary(
+                    Operator::Add,
+                    Box::new(Expr::Variable(0)),
+                    Box::new(Expr::Number(u64::MAX))
+                )
+            )
+        );
+        assert!(matches!(
+            &function.body[1].kind,
+            StatementKind::If(_, then, otherwise)
+                if then[1].kind == StatementKind::Assign(
+                    2,
+                    Expr::Binary(
+                        Operator::Multiply,
+                        Box::new(Expr::Variable(2)),
+                        Box::new(Expr::Variable(1))
+                    )
+                ) && otherwise.is_empty()
+        ));
+        assert_eq!(function.body[2].position, 5);
+    }
+
+    #[test]
+    pub fn parser_errors() {
+        assert_eq!(parse("fn f() {\n return x;\n}"), Err(Error::Syntax(2)));
+        assert_eq!(
+            parse("fn f() {\n var a[2];\n a = 1;\n}"),
+            Err(Error::Syntax(3))
+        );
+        assert_eq!(parse("fn f() {\n 1 +;\n}"), Err(Error::Syntax(2)));
+        assert_eq!(parse("fn f() {\n return 1;"), Err(Error::Syntax(2)));
+        assert_eq!(parse("fn f() { return 0x; }"), Err(Error::Syntax(1)));
+        assert_eq!(parse("fn f() { return $; }"), Err(Error::Syntax(1)));
+    }
+}
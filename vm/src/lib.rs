@@ -1,16 +1,46 @@
+pub mod analysis;
 pub mod assembler;
+pub mod brainfuck;
+pub mod coverage;
+mod decode;
+pub mod decompile;
 pub mod error;
 pub mod instructions;
+#[cfg(feature = "jit")]
+pub mod jit;
+pub mod lang;
+mod lowering;
 mod memory;
-mod processor;
+pub mod module;
+pub mod observer;
+pub mod optimize;
+pub mod processor;
+pub mod profiler;
 pub mod register;
+pub mod snapshot;
+pub mod symbolic;
+#[cfg(test)]
+mod testing;
+pub mod trace;
+pub mod transpile;
+pub mod verify;
+pub mod wasm;
+
+#[cfg(all(feature = "jit", not(all(target_arch = "x86_64", target_os = "linux"))))]
+compile_error!("the `jit` feature requires x86-64 Linux");
 
+use crate::decode::Program;
 use crate::error::Error;
+use crate::instructions::call::{CallIndex, HostCall};
 use crate::instructions::Execute;
 use crate::memory::Memory;
-use crate::processor::Processor;
+use crate::module::{LoadedModule, Module};
+use crate::observer::VmObserver;
+use crate::processor::{MemoryGuard, Processor, ProcessorHandle};
 
-use std::collections::BTreeMap;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BinaryHeap};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, RwLock};
 
 #[derive(Debug, Default)]
@@ -18,7 +48,17 @@ use std::sync::{Arc, RwLock};
 pub struct VmCtx {
     memory: RwLock<Memory>,
 
-    instructions: RwLock<Vec<Box<dyn Execute>>>,
+    instructions: RwLock<Arc<Program>>,
+
+    /// Whether uninstrumented processors run fused loop tails in a single dispatch.
+    fusion: AtomicBool,
+
+    #[cfg(feature = "jit")]
+    /// Whether uninstrumented processors run hot code compiled by the [`jit`].
+    jit: AtomicBool,
+
+    /// Host functions by call index, see [`Vm::register_call`].
+    calls: RwLock<BTreeMap<u64, HostCall>>,
 }
 
 #[derive(Debug, Default)]
@@ -26,6 +66,19 @@ pub struct VmCtx {
 pub struct Vm {
     processors: BTreeMap<usize, Processor>,
     ctx: Arc<VmCtx>,
+
+    /// Current generation of every processor index ever handed out.
+    generations: Vec<u64>,
+    /// Freed processor indices, lowest first.
+    free: BinaryHeap<Reverse<usize>>,
+
+    /// Modules linked into the program, in load order.
+    modules: Vec<LoadedModule>,
+
+    observers: Vec<Arc<dyn VmObserver>>,
+
+    /// Whether programs are verified before replacing the current one.
+    verify: bool,
 }
 
 impl Vm {
@@ -43,6 +96,8 @@ impl Vm {
 
     /// Moves the given [`Instruction`](instructions::Instruction) slice into [`VmCtx`] memory.
     ///
+    /// Built-in instructions are decoded once here; any other [`Execute`] runs through its virtual call.
+    ///
     /// # Example
     /// ```
     /// use vm::Vm;
@@ -53,51 +108,379 @@ impl Vm {
     ///
     /// # Errors
     /// When the [`VmCtx`].instructions is poisoned, [`InstructionsPoisoned`](Error::InstructionsPoisoned) is returned.
+    /// When verifying, see [`Vm::set_verify`], a program with problems returns [`Verification`](Error::Verification).
     pub fn load_instructions(&mut self, instructions: Vec<Box<dyn Execute>>) -> Result<(), Error> {
+        self.set_program(instructions)?;
+        self.modules.clear();
+
+        Ok(())
+    }
+
+    /// Replaces the program in [`VmCtx`] memory, leaving the loaded modules untouched.
+    // Vm is !Send through its observers, the Arc only shares the program with forks.
+    #[allow(clippy::arc_with_non_send_sync)]
+    fn set_program(&mut self, instructions: Vec<Box<dyn Execute>>) -> Result<(), Error> {
+        if self.verify {
+            verify::verify_executables(&instructions).map_err(Error::Verification)?;
+        }
+
         let ctx = Arc::clone(&self.ctx);
         let mut guard = ctx
             .instructions
             .write()
             .map_err(|_| Error::InstructionsPoisoned)?;
 
-        *guard = instructions;
+        *guard = Arc::new(Program::new(instructions));
 
         Ok(())
     }
 
-    #[must_use]
-    /// Finds a new handle for the user climbing incrementally.
-    /// # Vulnerabilities
-    /// When you're given a handle, it is **expected you know the lifetime of the handle**.
-    /// Failure to do so will result in nature that is unwarranted. Future implementations may
-    /// include a random handle generator to mitigate this issue. However, it still doesn't ignore
-    /// the issue of lifetime.
-    fn find_next_handle(&self) -> usize {
-        if self.processors.is_empty() {
-            return 0;
+    /// Enables or disables superinstruction fusion for every [`Processor`] of self.
+    ///
+    /// With fusion, a loop tail of `add`, `cmp` and `jz`/`jnz` on registers and values runs in a single
+    /// dispatch. The instruction counter and flags end up as if each instruction ran on its own, and
+    /// processors with a tracer or observers always run one instruction at a time, so debuggers and
+    /// traces are unaffected. Disabled by default.
+    ///
+    /// # Example
+    /// ```
+    /// use vm::Vm;
+    /// let mut vm_inst = Vm::new();
+    /// vm_inst.set_fusion(true);
+    /// ```
+    pub fn set_fusion(&mut self, enabled: bool) {
+        self.ctx.fusion.store(enabled, Ordering::Relaxed);
+    }
+
+    #[cfg(feature = "jit")]
+    /// Enables or disables the [`jit`] for every [`Processor`] of self.
+    ///
+    /// Blocks are compiled once their first instruction ran [`HOT_THRESHOLD`](jit::HOT_THRESHOLD)
+    /// times, and only run by processors without a tracer or observers. Takes precedence over
+    /// fusion where both apply. Disabled by default.
+    ///
+    /// # Example
+    /// ```
+    /// use vm::Vm;
+    /// let mut vm_inst = Vm::new();
+    /// vm_inst.set_jit(true);
+    /// ```
+    pub fn set_jit(&mut self, enabled: bool) {
+        self.ctx.jit.store(enabled, Ordering::Relaxed);
+    }
+
+    /// Enables or disables verifying every program before it replaces the current one.
+    ///
+    /// Applies to [`Vm::load_instructions`], [`Vm::load_module`] and [`Vm::reload_module`], which return
+    /// [`Verification`](Error::Verification) with every [`Problem`](verify::Problem) found and keep the
+    /// current program. Executables without an [`Instruction`](instructions::Instruction) are skipped.
+    /// Disabled by default.
+    ///
+    /// # Example
+    /// ```
+    /// use vm::assembler::Assembler;
+    /// use vm::error::Error;
+    /// use vm::Vm;
+    /// let mut vm_inst = Vm::new();
+    /// vm_inst.set_verify(true);
+    /// let result = vm_inst.load_instructions(Assembler::parse("jmp 7").unwrap().compile());
+    /// assert!(matches!(result, Err(Error::Verification(_))));
+    /// ```
+    pub fn set_verify(&mut self, enabled: bool) {
+        self.verify = enabled;
+    }
+
+    /// Registers a host function under the given call index, replacing any registered before.
+    ///
+    /// A `call` of that index runs the function on the calling [`Processor`], which then continues
+    /// with the instruction after the call, or wherever the function moved its instruction counter.
+    ///
+    /// # Example
+    /// ```
+    /// use vm::assembler::Assembler;
+    /// use vm::Vm;
+    /// let mut vm_inst = Vm::new();
+    /// vm_inst.register_call(1, |processor| Ok(processor.register_mut(0)?.assign_u64(42))).unwrap();
+    /// vm_inst.load_instructions(Assembler::parse("call 1").unwrap().compile()).unwrap();
+    /// let handle = vm_inst.new_processor();
+    /// vm_inst.processor_mut(handle).unwrap().start().unwrap();
+    /// assert_eq!(vm_inst.processor(handle).unwrap().register(0).unwrap().as_u64(), 42);
+    /// ```
+    ///
+    /// # Errors
+    /// When the index belongs to a built-in [`CallIndex`], [`ReservedCall`](Error::ReservedCall) is returned.
+    /// When the [`VmCtx`] calls are poisoned, [`CallsPoisoned`](Error::CallsPoisoned) is returned.
+    pub fn register_call(
+        &mut self,
+        index: u64,
+        call: impl Fn(&mut Processor) -> Result<(), Error> + Send + Sync + 'static,
+    ) -> Result<(), Error> {
+        if CallIndex::try_from(index).is_ok() {
+            return Err(Error::ReservedCall(index));
+        }
+
+        self.ctx
+            .calls
+            .write()
+            .map_err(|_| Error::CallsPoisoned)?
+            .insert(index, HostCall::new(call));
+
+        Ok(())
+    }
+
+    /// Links the given [`Module`] into the program under the given name, after every module already loaded.
+    ///
+    /// Loading a name again replaces that module in place. Imports are resolved against every loaded
+    /// module, including the one being loaded. Replaces any program given to [`Vm::load_instructions`].
+    ///
+    /// # Example
+    /// ```
+    /// use vm::assembler::Assembler;
+    /// use vm::Vm;
+    /// let mut vm_inst = Vm::new();
+    /// let module = Assembler::parse(".export main\nmain: mov 1, rq0").unwrap().module();
+    /// vm_inst.load_module("main", module).unwrap();
+    /// let _handle = vm_inst.new_processor_at("main", "main").unwrap();
+    /// ```
+    ///
+    /// # Errors
+    /// When an import can't be resolved, [`UnknownModule`](Error::UnknownModule) or [`UnknownSymbol`](Error::UnknownSymbol)
+    /// is returned, and the previously loaded program is kept.
+    pub fn load_module(&mut self, name: &str, module: Module) -> Result<(), Error> {
+        let (modules, program) = self.link_module(name, module)?;
+
+        self.set_program(
+            program
+                .into_iter()
+                .map(instructions::Instruction::executable)
+                .collect(),
+        )?;
+        self.modules = modules;
+
+        Ok(())
+    }
+
+    /// Replaces the loaded [`Module`] of the given name while every [`Processor`] is paused,
+    /// relocating their instruction counters into the new program.
+    ///
+    /// A counter is moved relative to the nearest label before it, looked up by name in the new
+    /// module; the distance past that label is kept. Counters into other modules follow their module.
+    /// Jump targets stored in registers or memory are not relocated.
+    ///
+    /// # Example
+    /// ```
+    /// use vm::assembler::Assembler;
+    /// use vm::Vm;
+    /// let mut vm_inst = Vm::new();
+    /// let v1 = Assembler::parse("loop: add 1, rq0, rq0\njmp loop").unwrap().module();
+    /// let v2 = Assembler::parse("loop: add 2, rq0, rq0\njmp loop").unwrap().module();
+    /// vm_inst.load_module("main", v1).unwrap();
+    /// let handle = vm_inst.new_processor_at("main", "loop").unwrap();
+    /// vm_inst.processor_mut(handle).unwrap().run(10).unwrap();
+    /// vm_inst.reload_module("main", v2).unwrap();
+    /// ```
+    ///
+    /// # Errors
+    /// When an import can't be resolved, [`UnknownModule`](Error::UnknownModule) or [`UnknownSymbol`](Error::UnknownSymbol)
+    /// is returned. When a counter has no preceding label declared in the new module, [`UnknownSymbol`](Error::UnknownSymbol)
+    /// is returned. In both cases, the previously loaded program and every counter are kept.
+    pub fn reload_module(&mut self, name: &str, module: Module) -> Result<(), Error> {
+        let (modules, program) = self.link_module(name, module)?;
+        let counter = register::ReservedIndex::InstructionCounter as usize;
+
+        let relocated = self
+            .processors
+            .iter()
+            .map(|(index, processor)| {
+                let old = processor.register(counter)?.as_u64();
+
+                Ok((*index, module::relocate(&self.modules, &modules, old)?))
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        self.set_program(
+            program
+                .into_iter()
+                .map(instructions::Instruction::executable)
+                .collect(),
+        )?;
+        self.modules = modules;
+
+        for (index, value) in relocated {
+            if let Some(processor) = self.processors.get_mut(&index) {
+                processor.register_mut(counter)?.assign_u64(value);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Links the loaded modules with the given [`Module`] loaded under the given name, without
+    /// replacing the current program.
+    fn link_module(
+        &self,
+        name: &str,
+        module: Module,
+    ) -> Result<(Vec<LoadedModule>, Vec<instructions::Instruction>), Error> {
+        let mut modules = self.modules.clone();
+        let loaded = LoadedModule {
+            name: name.to_string(),
+            module,
+            base: 0,
+        };
+
+        match modules.iter_mut().find(|loaded| loaded.name == name) {
+            Some(existing) => *existing = loaded,
+            None => modules.push(loaded),
         }
 
-        // Go through the processors, create another iterator that skips 1 element, find a gap between indices.
-        // Algorithm is quite slow, reaching O(2n) complexity.
-        let index = self
+        module::layout(&mut modules);
+
+        let program = module::link(&modules)?;
+
+        Ok((modules, program))
+    }
+
+    /// Constructs an independent copy of self, sharing memory pages and the program until written to.
+    ///
+    /// Processors keep their handles and registers. Observers stay registered on the copy, tracers don't.
+    ///
+    /// # Example
+    /// ```
+    /// use vm::Vm;
+    /// let mut vm_inst = Vm::new();
+    /// let handle = vm_inst.new_processor();
+    /// let mut fork = vm_inst.fork().unwrap();
+    /// assert!(fork.processor_mut(handle).is_ok());
+    /// ```
+    ///
+    /// # Errors
+    /// When the [`VmCtx`] is poisoned, [`InstructionsPoisoned`](Error::InstructionsPoisoned), [`MemoryPoisoned`](Error::MemoryPoisoned)
+    /// or [`CallsPoisoned`](Error::CallsPoisoned) is returned.
+    // Vm is !Send through its observers, the Arc only shares the context with processors.
+    #[allow(clippy::arc_with_non_send_sync)]
+    pub fn fork(&self) -> Result<Self, Error> {
+        let memory = self.memory()?.clone();
+        let calls = self
+            .ctx
+            .calls
+            .read()
+            .map_err(|_| Error::CallsPoisoned)?
+            .clone();
+        let instructions = Arc::clone(
+            &*self
+                .ctx
+                .instructions
+                .read()
+                .map_err(|_| Error::InstructionsPoisoned)?,
+        );
+
+        let ctx = Arc::new(VmCtx {
+            memory: RwLock::new(memory),
+            instructions: RwLock::new(instructions),
+            fusion: AtomicBool::new(self.ctx.fusion.load(Ordering::Relaxed)),
+            #[cfg(feature = "jit")]
+            jit: AtomicBool::new(self.ctx.jit.load(Ordering::Relaxed)),
+            calls: RwLock::new(calls),
+        });
+
+        let processors = self
             .processors
             .iter()
-            .zip(self.processors.iter().skip(1))
-            .find_map(|(iter, iter_skip)| -> Option<usize> {
-                if iter_skip.0 - iter.0 > 1 {
-                    return Some(iter.0 + 1);
-                }
+            .map(|(handle, processor)| (*handle, processor.fork(&ctx)))
+            .collect();
+
+        let mut fork = Vm {
+            processors,
+            ctx,
+            generations: self.generations.clone(),
+            free: self.free.clone(),
+            modules: self.modules.clone(),
+            observers: self.observers.clone(),
+            verify: self.verify,
+        };
+
+        fork.place_memory();
+
+        Ok(fork)
+    }
+
+    /// Returns a reference to the [`Memory`] of self, wherever it currently lives.
+    ///
+    /// # Errors
+    /// When the [`VmCtx`] memory is poisoned, [`MemoryPoisoned`](Error::MemoryPoisoned) is returned.
+    pub fn memory(&self) -> Result<MemoryGuard<'_>, Error> {
+        match self
+            .processors
+            .values()
+            .find(|processor| processor.owns_memory() && !processor.has_private_memory())
+        {
+            Some(processor) => processor.memory(),
+            None => self
+                .ctx
+                .memory
+                .read()
+                .map(MemoryGuard::Shared)
+                .map_err(|_| Error::MemoryPoisoned),
+        }
+    }
+
+    /// Moves the [`VmCtx`] memory into the only [`Processor`] sharing it, which then accesses it
+    /// without locking, or back into the [`VmCtx`] once more processors share it.
+    pub(crate) fn place_memory(&mut self) {
+        let exclusive = self
+            .processors
+            .values()
+            .filter(|processor| !processor.has_private_memory())
+            .count()
+            == 1;
+
+        for processor in self.processors.values_mut() {
+            if exclusive {
+                processor.own_memory();
+            } else {
+                processor.release_memory();
+            }
+        }
+    }
+
+    #[must_use]
+    /// Finds a new handle for the user, reusing the lowest freed index in O(log n).
+    ///
+    /// A reused index gets a new generation, so handles to the destroyed [`Processor`] stay stale.
+    fn find_next_handle(&mut self) -> ProcessorHandle {
+        match self.free.pop() {
+            Some(Reverse(index)) => ProcessorHandle::new(index, self.generations[index]),
 
-                None
-            });
+            None => {
+                self.generations.push(0);
 
-        index.unwrap_or(self.processors.len())
+                ProcessorHandle::new(self.generations.len() - 1, 0)
+            }
+        }
+    }
+
+    /// Returns the index of the given handle if it refers to a live [`Processor`].
+    fn resolve(&self, handle: ProcessorHandle) -> Result<usize, Error> {
+        match self.generations.get(handle.index()) {
+            None => Err(Error::ProcessorIndexOutOfBounds),
+
+            Some(generation)
+                if *generation == handle.generation()
+                    && self.processors.contains_key(&handle.index()) =>
+            {
+                Ok(handle.index())
+            }
+
+            Some(_) => Err(Error::StaleProcessorHandle),
+        }
     }
 
     #[must_use]
     /// Constructs a new [`Processor`] and returns a unique handle to the [`Processor`].
     ///
-    /// The handle exists with the [`Processor`]. Hence, it shares lifetimes with the [`Vm`].
+    /// The handle exists with the [`Processor`]. Once the [`Processor`] is destroyed, the handle is
+    /// stale and every lookup through it fails, even when its index is reused.
     ///
     /// # Example
     /// ```
@@ -105,36 +488,163 @@ impl Vm {
     /// let mut vm_inst = Vm::new();
     /// let mut _prod_idx = vm_inst.new_processor();
     /// ```
-    pub fn new_processor(&mut self) -> usize {
-        let index = self.find_next_handle();
+    pub fn new_processor(&mut self) -> ProcessorHandle {
+        let handle = self.find_next_handle();
+        let mut processor = Processor::new(&self.ctx);
+
+        for observer in &self.observers {
+            processor.observe(Arc::clone(observer));
+        }
 
-        self.processors.insert(index, Processor::new(&self.ctx));
+        self.processors.insert(handle.index(), processor);
+        self.place_memory();
 
-        index
+        handle
     }
 
-    /// Destroys the [`Processor`] at the given index.
+    /// Constructs a new [`Processor`] starting at the given label of a loaded [`Module`].
+    ///
+    /// # Errors
+    /// When no module has the given name, [`UnknownModule`](Error::UnknownModule) is returned.
+    /// When the module doesn't declare the label, [`UnknownSymbol`](Error::UnknownSymbol) is returned.
+    pub fn new_processor_at(
+        &mut self,
+        module: &str,
+        label: &str,
+    ) -> Result<ProcessorHandle, Error> {
+        let loaded = self
+            .modules
+            .iter()
+            .find(|loaded| loaded.name == module)
+            .ok_or(Error::UnknownModule)?;
+        let index = loaded
+            .module
+            .labels
+            .get(label)
+            .ok_or(Error::UnknownSymbol)?;
+        let entry = (loaded.base + index) as u64;
+
+        let handle = self.new_processor();
+
+        self.processor_mut(handle)?
+            .register_mut(register::ReservedIndex::InstructionCounter as usize)?
+            .assign_u64(entry);
+
+        Ok(handle)
+    }
+
+    /// Destroys the [`Processor`] referred to by the given handle.
     ///
     /// # Example
     /// ```
     /// use vm::Vm;
     /// let mut vm_inst = Vm::new();
     /// let mut prod_idx = vm_inst.new_processor();
-    /// vm_inst.destroy_processor(prod_idx);
+    /// vm_inst.destroy_processor(prod_idx).unwrap();
+    /// ```
+    ///
+    /// # Errors
+    /// When the handle was already destroyed, [`StaleProcessorHandle`](Error::StaleProcessorHandle) is returned.
+    /// When the handle was never handed out, [`ProcessorIndexOutOfBounds`](Error::ProcessorIndexOutOfBounds) is returned.
+    pub fn destroy_processor(&mut self, handle: ProcessorHandle) -> Result<(), Error> {
+        let index = self.resolve(handle)?;
+
+        if let Some(mut processor) = self.processors.remove(&index) {
+            processor.release_memory();
+        }
+
+        self.place_memory();
+        self.generations[index] += 1;
+        self.free.push(Reverse(index));
+
+        Ok(())
+    }
+
+    /// Marks the [`Memory`] of the [`Processor`] referred to by the given handle as private, or
+    /// shared again.
+    ///
+    /// A private processor runs on a copy of the [`VmCtx`] memory taken when it's marked, accessed
+    /// without locking however many processors run. Its writes are invisible to other processors
+    /// and to [`Vm::memory`], and are dropped once it shares memory again. Snapshots only hold the
+    /// shared memory.
+    ///
+    /// # Example
+    /// ```
+    /// use vm::assembler::Assembler;
+    /// use vm::Vm;
+    /// let mut vm_inst = Vm::new();
+    /// vm_inst.load_instructions(Assembler::parse("mov 1, mq0").unwrap().compile()).unwrap();
+    /// let handle = vm_inst.new_processor();
+    /// vm_inst.set_private_memory(handle, true).unwrap();
+    /// vm_inst.processor_mut(handle).unwrap().start().unwrap();
+    /// assert_eq!(vm_inst.memory().unwrap().get_u64(0), 0);
     /// ```
-    pub fn destroy_processor(&mut self, index: usize) {
-        self.processors.remove(&index);
+    ///
+    /// # Errors
+    /// When the handle is stale or was never handed out, [`StaleProcessorHandle`](Error::StaleProcessorHandle)
+    /// or [`ProcessorIndexOutOfBounds`](Error::ProcessorIndexOutOfBounds) is returned.
+    /// When the [`VmCtx`] memory is poisoned, [`MemoryPoisoned`](Error::MemoryPoisoned) is returned.
+    pub fn set_private_memory(
+        &mut self,
+        handle: ProcessorHandle,
+        private: bool,
+    ) -> Result<(), Error> {
+        let index = self.resolve(handle)?;
+
+        if let Some(processor) = self.processors.get_mut(&index) {
+            processor.set_private_memory(private)?;
+        }
+
+        self.place_memory();
+
+        Ok(())
     }
 
-    /// Returns a reference to the [`Processor`] at the given index.
-    pub fn processor(&self, index: usize) -> Result<&Processor, Error> {
+    /// Registers a [`VmObserver`] on every current and future [`Processor`].
+    ///
+    /// Processors without observers skip dispatch entirely, so unobserved execution pays nothing.
+    ///
+    /// # Example
+    /// ```
+    /// use std::sync::Arc;
+    /// use vm::observer::VmObserver;
+    /// use vm::Vm;
+    ///
+    /// #[derive(Debug)]
+    /// struct Silent;
+    ///
+    /// impl VmObserver for Silent {}
+    ///
+    /// let mut vm_inst = Vm::new();
+    /// vm_inst.register_observer(Arc::new(Silent));
+    /// ```
+    pub fn register_observer(&mut self, observer: Arc<dyn VmObserver>) {
+        for processor in self.processors.values_mut() {
+            processor.observe(Arc::clone(&observer));
+        }
+
+        self.observers.push(observer);
+    }
+
+    /// Returns a reference to the [`Processor`] referred to by the given handle.
+    ///
+    /// # Errors
+    /// When the handle was destroyed, [`StaleProcessorHandle`](Error::StaleProcessorHandle) is returned.
+    pub fn processor(&self, handle: ProcessorHandle) -> Result<&Processor, Error> {
+        let index = self.resolve(handle)?;
+
         self.processors
             .get(&index)
             .ok_or(Error::ProcessorIndexOutOfBounds)
     }
 
-    /// Returns a mutable reference to the [`Processor`] at the given index.
-    pub fn processor_mut(&mut self, index: usize) -> Result<&mut Processor, Error> {
+    /// Returns a mutable reference to the [`Processor`] referred to by the given handle.
+    ///
+    /// # Errors
+    /// When the handle was destroyed, [`StaleProcessorHandle`](Error::StaleProcessorHandle) is returned.
+    pub fn processor_mut(&mut self, handle: ProcessorHandle) -> Result<&mut Processor, Error> {
+        let index = self.resolve(handle)?;
+
         self.processors
             .get_mut(&index)
             .ok_or(Error::ProcessorIndexOutOfBounds)
@@ -157,7 +667,7 @@ mod tests {
         let processor_handle = vm.new_processor();
 
         assert_eq!(vm.processors.len(), 1);
-        assert_eq!(vm.processors.len() - 1, processor_handle);
+        assert_eq!(vm.processors.len() - 1, processor_handle.index());
     }
 
     #[test]
@@ -168,7 +678,7 @@ mod tests {
         let second_processor_handle = vm.new_processor();
 
         assert_eq!(vm.processors.len(), 2);
-        assert_eq!(vm.processors.len() - 1, second_processor_handle);
+        assert_eq!(vm.processors.len() - 1, second_processor_handle.index());
     }
 
     #[test]
@@ -176,12 +686,12 @@ mod tests {
         let mut vm = Vm::new();
 
         let processor_handle = vm.new_processor();
-        vm.destroy_processor(processor_handle);
+        vm.destroy_processor(processor_handle).unwrap();
 
         let second_processor_handle = vm.new_processor();
 
         assert_eq!(vm.processors.len(), 1);
-        assert_eq!(vm.processors.len() - 1, second_processor_handle);
+        assert_eq!(vm.processors.len() - 1, second_processor_handle.index());
     }
 
     #[test]
@@ -192,12 +702,12 @@ mod tests {
         let second_processor_handle = vm.new_processor();
         let _third_processor_handle = vm.new_processor();
 
-        vm.destroy_processor(second_processor_handle);
+        vm.destroy_processor(second_processor_handle).unwrap();
 
         let fourth_processor_handle = vm.new_processor();
 
         assert_eq!(vm.processors.len(), 3);
-        assert_eq!(1, fourth_processor_handle);
+        assert_eq!(1, fourth_processor_handle.index());
     }
 
     #[test]
@@ -206,7 +716,7 @@ mod tests {
 
         let processor_handle = vm.new_processor();
 
-        vm.destroy_processor(processor_handle);
+        vm.destroy_processor(processor_handle).unwrap();
 
         assert_eq!(vm.processors.len(), 0);
     }
@@ -218,9 +728,186 @@ mod tests {
         let first_processor_handle = vm.new_processor();
         let second_processor_handle = vm.new_processor();
 
-        vm.destroy_processor(first_processor_handle);
+        vm.destroy_processor(first_processor_handle).unwrap();
 
         assert_eq!(vm.processors.len(), 1);
-        assert_eq!(vm.processors.len(), second_processor_handle);
+        assert_eq!(vm.processors.len(), second_processor_handle.index());
+    }
+
+    #[test]
+    pub fn vm_processor_stale_handle() {
+        let mut vm = Vm::new();
+
+        let first_processor_handle = vm.new_processor();
+        vm.destroy_processor(first_processor_handle).unwrap();
+
+        let second_processor_handle = vm.new_processor();
+
+        assert_eq!(
+            first_processor_handle.index(),
+            second_processor_handle.index()
+        );
+        assert_eq!(
+            vm.processor(first_processor_handle).unwrap_err(),
+            Error::StaleProcessorHandle
+        );
+        assert_eq!(
+            vm.processor_mut(first_processor_handle).unwrap_err(),
+            Error::StaleProcessorHandle
+        );
+        assert_eq!(
+            vm.destroy_processor(first_processor_handle),
+            Err(Error::StaleProcessorHandle)
+        );
+        assert!(vm.processor(second_processor_handle).is_ok());
+    }
+
+    #[test]
+    pub fn vm_processor_unknown_handle() {
+        let vm = Vm::new();
+        let mut other = Vm::new();
+
+        let _ = other.new_processor();
+        let handle = other.new_processor();
+
+        assert_eq!(
+            vm.processor(handle).unwrap_err(),
+            Error::ProcessorIndexOutOfBounds
+        );
+    }
+
+    #[test]
+    pub fn vm_memory_ownership() {
+        let mut vm = Vm::new();
+
+        vm.load_instructions(
+            assembler::Assembler::parse("add 1, mq0, mq0")
+                .unwrap()
+                .compile(),
+        )
+        .unwrap();
+
+        let first = vm.new_processor();
+
+        vm.processor_mut(first).unwrap().start().unwrap();
+
+        assert!(vm.processor(first).unwrap().owns_memory());
+
+        let second = vm.new_processor();
+
+        assert!(!vm.processor(first).unwrap().owns_memory());
+
+        vm.processor_mut(second).unwrap().start().unwrap();
+        vm.destroy_processor(first).unwrap();
+
+        assert!(vm.processor(second).unwrap().owns_memory());
+        assert_eq!(vm.memory().unwrap().get_u64(0), 2);
+    }
+
+    #[test]
+    pub fn vm_private_memory() {
+        let mut vm = Vm::new();
+
+        vm.load_instructions(
+            assembler::Assembler::parse("add 1, mq0, mq0")
+                .unwrap()
+                .compile(),
+        )
+        .unwrap();
+
+        let first = vm.new_processor();
+        let second = vm.new_processor();
+
+        vm.processor_mut(first).unwrap().start().unwrap();
+        vm.set_private_memory(second, true).unwrap();
+
+        // Both own a memory now, the first the shared one as the only processor sharing it.
+        assert!(vm.processor(first).unwrap().owns_memory());
+        assert!(vm.processor(second).unwrap().owns_memory());
+
+        vm.processor_mut(second).unwrap().start().unwrap();
+
+        let private = vm.processor(second).unwrap().memory().unwrap().get_u64(0);
+
+        assert_eq!(private, 2);
+        assert_eq!(vm.memory().unwrap().get_u64(0), 1);
+
+        vm.set_private_memory(second, false).unwrap();
+
+        assert!(!vm.processor(first).unwrap().owns_memory());
+        assert_eq!(
+            vm.processor(second).unwrap().memory().unwrap().get_u64(0),
+            1
+        );
+    }
+
+    #[test]
+    pub fn vm_fork_copy_on_write() {
+        let mut vm = Vm::new();
+
+        let compiled = assembler::Assembler::parse("mov 1, mq0\nmov 2, mq8192\nmov rq0, mq0")
+            .unwrap()
+            .compile();
+
+        vm.load_instructions(compiled).unwrap();
+
+        let handle = vm.new_processor();
+        vm.processor_mut(handle).unwrap().start().unwrap();
+
+        let mut fork = vm.fork().unwrap();
+        let processor = fork.processor_mut(handle).unwrap();
+
+        processor.register_mut(0).unwrap().assign_u64(42);
+        processor
+            .register_mut(register::ReservedIndex::InstructionCounter as usize)
+            .unwrap()
+            .assign_u64(2);
+        processor.start().unwrap();
+
+        let parent_memory = vm.memory().unwrap();
+        let fork_memory = fork.memory().unwrap();
+
+        assert_eq!(parent_memory.get_u64(0), 0);
+        assert_eq!(fork_memory.get_u64(0), 42);
+        assert!(!fork_memory.shares_page(&parent_memory, 0));
+        assert!(fork_memory.shares_page(&parent_memory, 8192));
+        assert!(Arc::ptr_eq(
+            &*vm.ctx.instructions.read().unwrap(),
+            &*fork.ctx.instructions.read().unwrap()
+        ));
+    }
+
+    #[test]
+    pub fn vm_verify_on_load() {
+        let mut vm = Vm::new();
+
+        vm.set_verify(true);
+
+        let valid = assembler::Assembler::parse("mov 1, rq0").unwrap();
+        let invalid = || assembler::Assembler::parse("mov 2, rq0\njmp 9").unwrap();
+
+        vm.load_module("main", valid.module()).unwrap();
+
+        assert_eq!(
+            vm.load_instructions(invalid().compile()),
+            Err(Error::Verification(vec![verify::Problem {
+                index: 1,
+                operand: 0,
+                kind: verify::ProblemKind::JumpOutOfBounds(10),
+            }]))
+        );
+        assert!(matches!(
+            vm.reload_module("main", invalid().module()),
+            Err(Error::Verification(_))
+        ));
+
+        let handle = vm.new_processor();
+
+        vm.processor_mut(handle).unwrap().start().unwrap();
+
+        assert_eq!(
+            vm.processor(handle).unwrap().register(0).unwrap().as_u64(),
+            1
+        );
     }
 }
@@ -0,0 +1,109 @@
+//! Instruction sequences shared by the frontends lowering other languages to instructions.
+//!
+//! The instruction set can't subtract, so both negate values through a complement table at
+//! address 0, holding the complement of each byte at its own address, then add 1.
+
+use crate::assembler::Assembler;
+use crate::instructions::{Instruction, Operand};
+use crate::register::Width;
+
+use std::collections::BTreeMap;
+
+/// Address of the word negated through the complement table, byte by byte.
+pub(crate) const NEGATE: usize = 0x100;
+
+/// Returns the given register read or written as a quad word.
+pub(crate) fn register(index: usize) -> Operand {
+    Operand::Register(Width::QWord(index))
+}
+
+/// Returns the first operand of the given instruction.
+pub(crate) fn first(instruction: &mut Instruction) -> &mut Operand {
+    match instruction {
+        Instruction::Call(operand)
+        | Instruction::Mov(operand, _)
+        | Instruction::Add(operand, _, _)
+        | Instruction::Cmp(operand, _)
+        | Instruction::Jmp(operand)
+        | Instruction::Jz(operand)
+        | Instruction::Jnz(operand) => operand,
+    }
+}
+
+/// Returns an [`Assembler`] holding the given instructions, each labelled by its name, if any.
+pub(crate) fn assemble<S: AsRef<str>>(
+    instructions: Vec<Instruction>,
+    names: &BTreeMap<usize, S>,
+) -> Assembler {
+    instructions.into_iter().enumerate().fold(
+        Assembler::new(),
+        |assembler, (index, instruction)| {
+            let assembler = match names.get(&index) {
+                Some(name) => assembler.label(name.as_ref()),
+                None => assembler,
+            };
+
+            match instruction {
+                Instruction::Call(index) => assembler.call(index),
+                Instruction::Mov(source, destination) => assembler.mov(source, destination),
+                Instruction::Add(value, source, destination) => {
+                    assembler.add(value, source, destination)
+                }
+                Instruction::Cmp(value, comparator) => assembler.cmp(value, comparator),
+                Instruction::Jmp(source) => assembler.jmp(source),
+                Instruction::Jz(source) => assembler.jz(source),
+                Instruction::Jnz(source) => assembler.jnz(source),
+            }
+        },
+    )
+}
+
+/// Returns the instructions filling the complement table, placed from the given index and
+/// overwriting both given registers.
+pub(crate) fn complements(start: usize, counter: usize, value: usize) -> [Instruction; 7] {
+    [
+        Instruction::Mov(Operand::Value(0), register(counter)),
+        Instruction::Mov(Operand::Value(0xff), register(value)),
+        Instruction::Mov(
+            register(value),
+            Operand::MemoryRegister(Width::Byte(counter)),
+        ),
+        Instruction::Add(Operand::Value(1), register(counter), register(counter)),
+        Instruction::Add(Operand::Value(u64::MAX), register(value), register(value)),
+        Instruction::Cmp(register(counter), Operand::Value(0x100)),
+        // Jumps continue after their target, so this one lands on the store.
+        Instruction::Jnz(Operand::Value(start as u64 + 1)),
+    ]
+}
+
+/// Returns the instructions negating the given register by complementing each of its bytes
+/// through the table and adding 1, overwriting the given scratch register.
+pub(crate) fn negate(index: usize, scratch: usize) -> Vec<Instruction> {
+    let mut instructions = vec![Instruction::Mov(
+        register(index),
+        Operand::Memory(Width::QWord(NEGATE)),
+    )];
+
+    for byte in NEGATE..NEGATE + 8 {
+        instructions.push(Instruction::Mov(
+            Operand::Memory(Width::Byte(byte)),
+            register(scratch),
+        ));
+        instructions.push(Instruction::Mov(
+            Operand::MemoryRegister(Width::Byte(scratch)),
+            Operand::Memory(Width::Byte(byte)),
+        ));
+    }
+
+    instructions.push(Instruction::Mov(
+        Operand::Memory(Width::QWord(NEGATE)),
+        register(index),
+    ));
+    instructions.push(Instruction::Add(
+        Operand::Value(1),
+        register(index),
+        register(index),
+    ));
+
+    instructions
+}
@@ -1,14 +1,23 @@
 use std::collections::BTreeMap;
+use std::fmt::{Debug, Formatter};
+use std::sync::Arc;
 
-#[derive(Debug)]
-/// Encapsulated abstraction for memory values.
-enum MemoryCell {
-    ByteArray(Box<[u8]>),
-}
+/// Size in bytes of a single [`Memory`] page.
+pub const PAGE_SIZE: usize = 4096;
+
+/// Fixed-size block of bytes, shared between [`Memory`] clones until written to.
+type Page = [u8; PAGE_SIZE];
 
-#[derive(Debug, Default)]
+#[derive(Default, Clone)]
+/// Byte-addressed, little-endian memory made of copy-on-write pages.
+///
+/// Every address holds a single byte, so values at nearby addresses overlap: writing a `u64` at 8
+/// also changes what a `u8` at 9 reads back.
+///
+/// Cloning only clones the page table; pages are copied the first time either clone writes to them.
+/// Unwritten memory reads as zero.
 pub struct Memory {
-    cells: BTreeMap<usize, MemoryCell>,
+    pages: BTreeMap<usize, Arc<Page>>,
 }
 
 /// Assigns a given type value to the memory cell at the given index.
@@ -16,28 +25,101 @@ pub struct Memory {
 macro_rules! primitive_impl {
     ($put_fn:ident, $get_fn:ident, $type:ty) => {
         pub fn $put_fn(&mut self, index: usize, value: $type) {
-            self.cells
-                .insert(index, MemoryCell::ByteArray(value.to_le_bytes().into()));
+            self.put_bytes(index, &value.to_le_bytes());
         }
 
         #[must_use]
         pub fn $get_fn(&self, index: usize) -> $type {
-            match &self.cells[&index] {
-                MemoryCell::ByteArray(value) => {
-                    <$type>::from_le_bytes(value[..size_of::<$type>()].try_into().unwrap())
-                }
-            }
+            let mut bytes = [0; size_of::<$type>()];
+            self.get_bytes(index, &mut bytes);
+
+            <$type>::from_le_bytes(bytes)
         }
     };
 }
 
 impl Memory {
     #[must_use]
-    /// Constructs a new [`MemoryCell`].
+    /// Constructs a new, zeroed [`Memory`].
     pub fn new() -> Self {
         Self::default()
     }
 
+    /// Returns every allocated page with the address of its first byte, in ascending address order.
+    pub(crate) fn pages(&self) -> impl Iterator<Item = (usize, &[u8])> {
+        self.pages
+            .iter()
+            .map(|(page, bytes)| (page * PAGE_SIZE, &bytes[..]))
+    }
+
+    #[cfg(test)]
+    /// Returns whether the page containing `index` is shared with `other`.
+    pub(crate) fn shares_page(&self, other: &Memory, index: usize) -> bool {
+        match (
+            self.pages.get(&(index / PAGE_SIZE)),
+            other.pages.get(&(index / PAGE_SIZE)),
+        ) {
+            (Some(page), Some(other_page)) => Arc::ptr_eq(page, other_page),
+
+            _ => false,
+        }
+    }
+
+    /// Copies the bytes starting at the given index into `bytes`.
+    pub(crate) fn get_bytes(&self, index: usize, bytes: &mut [u8]) {
+        let offset = index % PAGE_SIZE;
+
+        if let Some(end) = offset
+            .checked_add(bytes.len())
+            .filter(|end| *end <= PAGE_SIZE)
+        {
+            match self.pages.get(&(index / PAGE_SIZE)) {
+                Some(page) => bytes.copy_from_slice(&page[offset..end]),
+                None => bytes.fill(0),
+            }
+
+            return;
+        }
+
+        for (offset, byte) in bytes.iter_mut().enumerate() {
+            let address = index.wrapping_add(offset);
+
+            *byte = self
+                .pages
+                .get(&(address / PAGE_SIZE))
+                .map_or(0, |page| page[address % PAGE_SIZE]);
+        }
+    }
+
+    /// Assigns raw bytes starting at the given index, copying any shared page written to.
+    pub(crate) fn put_bytes(&mut self, index: usize, bytes: &[u8]) {
+        let offset = index % PAGE_SIZE;
+
+        if let Some(end) = offset
+            .checked_add(bytes.len())
+            .filter(|end| *end <= PAGE_SIZE)
+        {
+            let page = self
+                .pages
+                .entry(index / PAGE_SIZE)
+                .or_insert_with(|| Arc::new([0; PAGE_SIZE]));
+
+            Arc::make_mut(page)[offset..end].copy_from_slice(bytes);
+
+            return;
+        }
+
+        for (offset, byte) in bytes.iter().enumerate() {
+            let address = index.wrapping_add(offset);
+            let page = self
+                .pages
+                .entry(address / PAGE_SIZE)
+                .or_insert_with(|| Arc::new([0; PAGE_SIZE]));
+
+            Arc::make_mut(page)[address % PAGE_SIZE] = *byte;
+        }
+    }
+
     primitive_impl!(put_u8, get_u8, u8);
     primitive_impl!(put_u16, get_u16, u16);
     primitive_impl!(put_u32, get_u32, u32);
@@ -51,3 +133,69 @@ impl Memory {
     primitive_impl!(put_f32, get_f32, f32);
     primitive_impl!(put_f64, get_f64, f64);
 }
+
+impl Debug for Memory {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Memory")
+            .field("pages", &self.pages.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn memory_little_endian() {
+        let mut memory = Memory::new();
+
+        memory.put_u32(8, 0x1122_3344);
+
+        assert_eq!(memory.get_u8(8), 0x44);
+        assert_eq!(memory.get_u16(10), 0x1122);
+        assert_eq!(memory.get_u64(8), 0x1122_3344);
+        assert_eq!(memory.get_u64(1 << 40), 0);
+    }
+
+    #[test]
+    pub fn memory_overlapping() {
+        let mut memory = Memory::new();
+
+        memory.put_u64(8, u64::MAX);
+        memory.put_u8(9, 0);
+
+        assert_eq!(memory.get_u64(8), 0xffff_ffff_ffff_00ff);
+        assert_eq!(memory.get_u32(12), u32::MAX);
+    }
+
+    #[test]
+    pub fn memory_crosses_pages() {
+        let mut memory = Memory::new();
+
+        memory.put_u64(PAGE_SIZE - 4, u64::MAX);
+
+        assert_eq!(memory.pages().count(), 2);
+        assert_eq!(memory.get_u64(PAGE_SIZE - 4), u64::MAX);
+        assert_eq!(memory.get_u32(PAGE_SIZE), u32::MAX);
+    }
+
+    #[test]
+    pub fn memory_copy_on_write() {
+        let mut parent = Memory::new();
+
+        parent.put_u64(0, 1);
+        parent.put_u64(PAGE_SIZE, 2);
+
+        let mut child = parent.clone();
+
+        assert!(child.shares_page(&parent, 0));
+
+        child.put_u64(0, 3);
+
+        assert!(!child.shares_page(&parent, 0));
+        assert!(child.shares_page(&parent, PAGE_SIZE));
+        assert_eq!(parent.get_u64(0), 1);
+        assert_eq!(child.get_u64(0), 3);
+    }
+}
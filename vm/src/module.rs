@@ -0,0 +1,344 @@
+use crate::error::Error;
+use crate::instructions::{Instruction, Operand};
+
+use std::collections::{BTreeMap, BTreeSet};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// Reference from an operand of a [`Module`] to a symbol exported by another.
+pub struct Import {
+    /// Index of the instruction within the importing [`Module`].
+    pub index: usize,
+    /// Position of the patched operand within the instruction.
+    pub operand: usize,
+
+    pub module: String,
+    pub symbol: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+/// Relocatable program loaded into a [`Vm`](crate::Vm) under a name.
+///
+/// Jump targets written as [`Value`](Operand::Value) are relative to the module and relocated on
+/// load. Computed jump targets held in registers or memory are absolute, and should be obtained
+/// through an [`Import`].
+pub struct Module {
+    pub(crate) instructions: Vec<Instruction>,
+    pub(crate) labels: BTreeMap<String, usize>,
+    pub(crate) exports: BTreeSet<String>,
+    pub(crate) imports: Vec<Import>,
+}
+
+impl Module {
+    #[must_use]
+    /// Returns the instructions of self, with module-relative jump targets.
+    pub fn instructions(&self) -> &[Instruction] {
+        &self.instructions
+    }
+
+    #[must_use]
+    /// Returns every label of self, mapped to the index of the instruction it names.
+    pub fn labels(&self) -> &BTreeMap<String, usize> {
+        &self.labels
+    }
+
+    #[must_use]
+    /// Returns the labels other modules may import.
+    pub fn exports(&self) -> &BTreeSet<String> {
+        &self.exports
+    }
+
+    #[must_use]
+    /// Returns the operands resolved against other modules on load.
+    pub fn imports(&self) -> &[Import] {
+        &self.imports
+    }
+}
+
+#[derive(Debug, Clone)]
+/// [`Module`] placed into the program of a [`Vm`](crate::Vm).
+pub(crate) struct LoadedModule {
+    pub(crate) name: String,
+    pub(crate) module: Module,
+    pub(crate) base: usize,
+}
+
+/// Returns the mutable operands of an [`Instruction`], in declaration order.
+fn operands_mut(instruction: &mut Instruction) -> Vec<&mut Operand> {
+    match instruction {
+        Instruction::Call(index) => vec![index],
+        Instruction::Mov(source, destination) => vec![source, destination],
+        Instruction::Jmp(source) | Instruction::Jz(source) | Instruction::Jnz(source) => {
+            vec![source]
+        }
+        Instruction::Cmp(value, comparator) => vec![value, comparator],
+        Instruction::Add(value, source, destination) => vec![value, source, destination],
+    }
+}
+
+/// Places the given modules one after another, in order.
+pub(crate) fn layout(modules: &mut [LoadedModule]) {
+    let mut base = 0;
+
+    for loaded in modules {
+        loaded.base = base;
+        base += loaded.module.instructions.len();
+    }
+}
+
+/// Links the given, already laid out, modules into a single program.
+///
+/// # Errors
+/// When an import names a missing module, [`UnknownModule`](Error::UnknownModule) is returned.
+/// When an import names a symbol its module doesn't export, [`UnknownSymbol`](Error::UnknownSymbol) is returned.
+/// When an import patches a missing operand, [`InvalidOperand`](Error::InvalidOperand) is returned.
+pub(crate) fn link(modules: &[LoadedModule]) -> Result<Vec<Instruction>, Error> {
+    let mut program = Vec::new();
+
+    for loaded in modules {
+        let start = program.len();
+
+        for instruction in &loaded.module.instructions {
+            let mut instruction = instruction.clone();
+
+            if let Instruction::Jmp(Operand::Value(target))
+            | Instruction::Jz(Operand::Value(target))
+            | Instruction::Jnz(Operand::Value(target)) = &mut instruction
+            {
+                *target = target.wrapping_add(loaded.base as u64);
+            }
+
+            program.push(instruction);
+        }
+
+        for import in &loaded.module.imports {
+            let target = modules
+                .iter()
+                .find(|module| module.name == import.module)
+                .ok_or(Error::UnknownModule)?;
+
+            let index = target
+                .module
+                .exports
+                .get(&import.symbol)
+                .and_then(|symbol| target.module.labels.get(symbol))
+                .ok_or(Error::UnknownSymbol)?;
+
+            let operand = program
+                .get_mut(start + import.index)
+                .and_then(|instruction| operands_mut(instruction).into_iter().nth(import.operand))
+                .ok_or(Error::InvalidOperand)?;
+
+            // Jumps resume after their target, same as label references.
+            *operand = Operand::Value(((target.base + index) as u64).wrapping_sub(1));
+        }
+    }
+
+    Ok(program)
+}
+
+/// Maps an instruction counter from one layout of modules to another.
+///
+/// A counter within a module is moved relative to the nearest label at or before it that the
+/// module still declares in `new`. A counter right after the last module stays after the last
+/// module. Any other counter isn't part of a module and is returned as is.
+///
+/// # Errors
+/// When the module of the counter was removed, [`UnknownModule`](Error::UnknownModule) is returned.
+/// When no label preceding the counter is declared in `new`, [`UnknownSymbol`](Error::UnknownSymbol) is returned.
+pub(crate) fn relocate(
+    old: &[LoadedModule],
+    new: &[LoadedModule],
+    counter: u64,
+) -> Result<u64, Error> {
+    let end = |modules: &[LoadedModule]| {
+        modules.last().map_or(0, |loaded| {
+            (loaded.base + loaded.module.instructions.len()) as u64
+        })
+    };
+
+    if !old.is_empty() && counter == end(old) {
+        return Ok(end(new));
+    }
+
+    let Some(loaded) = old.iter().find(|loaded| {
+        (loaded.base as u64..(loaded.base + loaded.module.instructions.len()) as u64)
+            .contains(&counter)
+    }) else {
+        return Ok(counter);
+    };
+
+    let target = new
+        .iter()
+        .find(|target| target.name == loaded.name)
+        .ok_or(Error::UnknownModule)?;
+
+    let offset = counter as usize - loaded.base;
+    let mut labels: Vec<_> = loaded
+        .module
+        .labels
+        .iter()
+        .filter(|(_, index)| **index <= offset)
+        .collect();
+
+    labels.sort_by_key(|(_, index)| std::cmp::Reverse(**index));
+
+    labels
+        .into_iter()
+        .find_map(|(label, index)| {
+            target
+                .module
+                .labels
+                .get(label)
+                .map(|new_index| (target.base + new_index + offset - index) as u64)
+        })
+        .ok_or(Error::UnknownSymbol)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::Assembler;
+    use crate::register::Width;
+    use crate::Vm;
+
+    #[test]
+    pub fn module_cross_module_jump() {
+        let math = Assembler::parse(
+            ".export double\n\
+             double: add rq0, rq0, rq0\n\
+             jmp [rq1]",
+        )
+        .unwrap()
+        .module();
+
+        let main = Assembler::parse(
+            ".export main, back\n\
+             main: mov 21, rq0\n\
+             mov 0x100, rq1\n\
+             mov main::back, mq0x100\n\
+             jmp math::double\n\
+             back: mov rq0, rq2",
+        )
+        .unwrap()
+        .module();
+
+        let mut vm = Vm::new();
+
+        vm.load_module("math", math).unwrap();
+        vm.load_module("main", main).unwrap();
+
+        let handle = vm.new_processor_at("main", "main").unwrap();
+        let processor = vm.processor_mut(handle).unwrap();
+
+        processor.start().unwrap();
+
+        assert_eq!(processor.register(2).unwrap().as_u64(), 42);
+    }
+
+    #[test]
+    pub fn module_builder_import() {
+        let callee = Assembler::new()
+            .export("entry")
+            .mov(Operand::Value(7), Operand::Register(Width::QWord(3)))
+            .jmp(Operand::Register(Width::QWord(1)))
+            .module();
+
+        let caller = Assembler::new()
+            .label("start")
+            .mov(Operand::None, Operand::Register(Width::QWord(1)))
+            .import("caller", "done")
+            .jmp(Operand::None)
+            .import("callee", "entry")
+            .export("done")
+            .module();
+
+        let mut vm = Vm::new();
+
+        vm.load_module("callee", callee).unwrap();
+        vm.load_module("caller", caller).unwrap();
+
+        let handle = vm.new_processor_at("caller", "start").unwrap();
+        let processor = vm.processor_mut(handle).unwrap();
+
+        processor.start().unwrap();
+
+        assert_eq!(processor.register(3).unwrap().as_u64(), 7);
+    }
+
+    #[test]
+    pub fn module_hot_reload() {
+        let counter = Assembler::parse("main: mov 0, rq0\nloop: add 1, rq0, rq0\njmp loop")
+            .unwrap()
+            .module();
+        let faster = Assembler::parse(
+            "main: mov 0, rq0\n\
+             mov 0, rq5\n\
+             loop: add 2, rq0, rq0\n\
+             jmp loop",
+        )
+        .unwrap()
+        .module();
+
+        let mut vm = Vm::new();
+
+        vm.load_module("counter", counter).unwrap();
+
+        let handle = vm.new_processor_at("counter", "main").unwrap();
+
+        assert!(vm.processor_mut(handle).unwrap().run(5).unwrap());
+
+        vm.reload_module("counter", faster).unwrap();
+
+        let processor = vm.processor_mut(handle).unwrap();
+
+        assert_eq!(processor.register(15).unwrap().as_u64(), 2);
+        assert!(processor.run(2).unwrap());
+        assert_eq!(processor.register(0).unwrap().as_u64(), 4);
+    }
+
+    #[test]
+    pub fn module_reload_missing_label() {
+        let counter = Assembler::parse("loop: add 1, rq0, rq0\njmp loop")
+            .unwrap()
+            .module();
+        let unlabeled = Assembler::parse("add 1, rq0, rq0").unwrap().module();
+
+        let mut vm = Vm::new();
+
+        vm.load_module("counter", counter.clone()).unwrap();
+
+        let handle = vm.new_processor();
+
+        assert!(vm.processor_mut(handle).unwrap().run(3).unwrap());
+        assert_eq!(
+            vm.reload_module("counter", unlabeled),
+            Err(Error::UnknownSymbol)
+        );
+        assert!(vm.processor_mut(handle).unwrap().run(1).unwrap());
+        assert_eq!(
+            vm.processor(handle).unwrap().register(0).unwrap().as_u64(),
+            2
+        );
+    }
+
+    #[test]
+    pub fn module_unresolved_import() {
+        let caller = Assembler::parse("jmp missing::entry").unwrap().module();
+        let private = Assembler::parse("entry: mov 0, rq0").unwrap().module();
+
+        let mut vm = Vm::new();
+
+        assert_eq!(
+            vm.load_module("caller", caller.clone()),
+            Err(Error::UnknownModule)
+        );
+
+        vm.load_module("missing", private).unwrap();
+
+        assert_eq!(vm.load_module("caller", caller), Err(Error::UnknownSymbol));
+        assert_eq!(
+            vm.new_processor_at("nowhere", "entry").unwrap_err(),
+            Error::UnknownModule
+        );
+    }
+}
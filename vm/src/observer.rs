@@ -0,0 +1,153 @@
+use crate::error::Error;
+use crate::processor::Processor;
+
+use std::fmt::Debug;
+
+/// Callbacks invoked by a [`Processor`] as it executes, registered through
+/// [`Vm::register_observer`](crate::Vm::register_observer).
+///
+/// Every callback defaults to doing nothing, so implementors only override the events they need.
+/// Callbacks receive `&self`; observers keeping state should use interior mutability.
+pub trait VmObserver: Debug {
+    /// Called before the instruction at `index` is executed.
+    fn before_instruction(&self, processor: &Processor, index: usize) {
+        _ = (processor, index);
+    }
+
+    /// Called after the instruction at `index` executed successfully, before the
+    /// [`InstructionCounter`](crate::register::ReservedIndex::InstructionCounter) is advanced.
+    fn after_instruction(&self, processor: &Processor, index: usize) {
+        _ = (processor, index);
+    }
+
+    /// Called after `size` bytes were read from memory at `address`.
+    fn on_memory_read(&self, processor: &Processor, address: usize, size: usize, value: u64) {
+        _ = (processor, address, size, value);
+    }
+
+    /// Called after `size` bytes were written into memory at `address`.
+    fn on_memory_write(&self, processor: &Processor, address: usize, size: usize, value: u64) {
+        _ = (processor, address, size, value);
+    }
+
+    /// Called before a [`Call`](crate::instructions::Instruction::Call) dispatches the given call index.
+    fn on_call(&self, processor: &Processor, call_index: u64) {
+        _ = (processor, call_index);
+    }
+
+    /// Called when the instruction at `index` fails with the given [`Error`].
+    fn on_trap(&self, processor: &Processor, index: usize, error: &Error) {
+        _ = (processor, index, error);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::Assembler;
+    use crate::instructions::Operand;
+    use crate::register::Width;
+    use crate::Vm;
+
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Debug, Default)]
+    struct Recorder {
+        events: Mutex<Vec<String>>,
+    }
+
+    impl VmObserver for Recorder {
+        fn before_instruction(&self, _processor: &Processor, index: usize) {
+            self.events.lock().unwrap().push(format!("before {index}"));
+        }
+
+        fn after_instruction(&self, _processor: &Processor, index: usize) {
+            self.events.lock().unwrap().push(format!("after {index}"));
+        }
+
+        fn on_memory_read(&self, _processor: &Processor, address: usize, size: usize, value: u64) {
+            self.events
+                .lock()
+                .unwrap()
+                .push(format!("read {address} {size} {value}"));
+        }
+
+        fn on_memory_write(&self, _processor: &Processor, address: usize, size: usize, value: u64) {
+            self.events
+                .lock()
+                .unwrap()
+                .push(format!("write {address} {size} {value}"));
+        }
+
+        fn on_call(&self, _processor: &Processor, call_index: u64) {
+            self.events
+                .lock()
+                .unwrap()
+                .push(format!("call {call_index}"));
+        }
+
+        fn on_trap(&self, _processor: &Processor, index: usize, error: &Error) {
+            self.events
+                .lock()
+                .unwrap()
+                .push(format!("trap {index} {error:?}"));
+        }
+    }
+
+    #[test]
+    pub fn observer_events() {
+        let mut vm = Vm::new();
+        let recorder = Arc::new(Recorder::default());
+
+        vm.register_observer(recorder.clone());
+
+        let compiled = Assembler::new()
+            .mov(Operand::Value(300), Operand::Memory(Width::Word(8)))
+            .mov(
+                Operand::Memory(Width::Word(8)),
+                Operand::Register(Width::QWord(0)),
+            )
+            .mov(Operand::Value(0), Operand::None)
+            .compile();
+
+        vm.load_instructions(compiled).unwrap();
+
+        let handle = vm.new_processor();
+
+        assert_eq!(
+            vm.processor_mut(handle).unwrap().start(),
+            Err(Error::InvalidOperand)
+        );
+        assert_eq!(
+            *recorder.events.lock().unwrap(),
+            [
+                "before 0",
+                "write 8 2 300",
+                "after 0",
+                "before 1",
+                "read 8 2 300",
+                "after 1",
+                "before 2",
+                "trap 2 InvalidOperand",
+            ]
+        );
+    }
+
+    #[test]
+    pub fn observer_registered_after_processor() {
+        let mut vm = Vm::new();
+        let handle = vm.new_processor();
+        let recorder = Arc::new(Recorder::default());
+
+        vm.register_observer(recorder.clone());
+        vm.load_instructions(Assembler::new().call(Operand::Value(0)).compile())
+            .unwrap();
+
+        vm.processor_mut(handle).unwrap().start().unwrap();
+
+        assert_eq!(
+            *recorder.events.lock().unwrap(),
+            ["before 0", "call 0", "after 0"]
+        );
+    }
+}
@@ -0,0 +1,781 @@
+//! Optimization pipeline over a program's [`Instruction`] list.
+//!
+//! Optimized programs leave registers, flags and memory as the original does, but may take fewer
+//! steps and end with a different instruction counter. They are entered at index 0; other indices,
+//! such as labels, don't survive optimization. Programs failing [`verify`] are left as they are.
+//!
+//! Passes removing instructions only run on programs without computed jumps or reads of the
+//! instruction counter, since either depends on where instructions are. Calls are assumed to
+//! return to the next instruction, as in the [`Cfg`].
+
+use crate::analysis::cfg::{is_counter, Cfg, Target};
+use crate::analysis::dataflow::{effects, solve, Analysis, Direction, Liveness, RegisterSet};
+use crate::instructions::{Instruction, Operand};
+use crate::register::{mask, Width, COUNTER, FLAGS};
+use crate::verify::verify;
+
+use std::collections::BTreeSet;
+
+/// Rounds of every enabled pass before giving up on reaching a fixed point.
+const MAX_ROUNDS: usize = 16;
+
+#[derive(Debug, Clone, Copy)]
+/// Builder for a pipeline of switchable passes, all enabled by default.
+///
+/// # Example
+/// ```
+/// use vm::assembler::Assembler;
+/// use vm::instructions::{Instruction, Operand};
+/// use vm::optimize::Optimizer;
+/// use vm::register::Width;
+/// let assembler = Assembler::parse("mov 2, rq0\nadd rq0, 3, rq1\njmp end\nmov 9, rq1\nend:").unwrap();
+/// let optimized = Optimizer::new().optimize(assembler.instructions());
+/// assert_eq!(optimized[1], Instruction::Mov(Operand::Value(5), Operand::Register(Width::QWord(1))));
+/// assert_eq!(optimized.len(), 2);
+/// ```
+pub struct Optimizer {
+    fold_constants: bool,
+    thread_jumps: bool,
+    remove_unreachable: bool,
+    remove_dead_stores: bool,
+}
+
+impl Default for Optimizer {
+    fn default() -> Self {
+        Optimizer {
+            fold_constants: true,
+            thread_jumps: true,
+            remove_unreachable: true,
+            remove_dead_stores: true,
+        }
+    }
+}
+
+impl Optimizer {
+    #[must_use]
+    /// Constructs a new [`Optimizer`] with every pass enabled.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    #[must_use]
+    /// Enables or disables constant propagation and folding.
+    ///
+    /// Registers known to hold a constant are read as values, `add`s of constants that can't
+    /// overflow become `mov`s and conditional jumps on known flags are decided.
+    pub fn fold_constants(mut self, enabled: bool) -> Self {
+        self.fold_constants = enabled;
+        self
+    }
+
+    #[must_use]
+    /// Enables or disables jump threading, retargeting jumps to unconditional jumps at the final
+    /// target and removing jumps to the next instruction.
+    pub fn thread_jumps(mut self, enabled: bool) -> Self {
+        self.thread_jumps = enabled;
+        self
+    }
+
+    #[must_use]
+    /// Enables or disables removing blocks no path from the entry reaches.
+    pub fn remove_unreachable(mut self, enabled: bool) -> Self {
+        self.remove_unreachable = enabled;
+        self
+    }
+
+    #[must_use]
+    /// Enables or disables removing writes of registers and flags never read afterwards, and
+    /// moves of a register to itself.
+    pub fn remove_dead_stores(mut self, enabled: bool) -> Self {
+        self.remove_dead_stores = enabled;
+        self
+    }
+
+    #[must_use]
+    /// Runs the enabled passes over the given program until none changes it.
+    pub fn optimize(&self, instructions: &[Instruction]) -> Vec<Instruction> {
+        let mut instructions = instructions.to_vec();
+
+        if verify(&instructions).is_err() {
+            return instructions;
+        }
+
+        let passes: [(bool, Pass); 4] = [
+            (self.fold_constants, fold_constants),
+            (self.thread_jumps, thread_jumps),
+            (self.remove_unreachable, remove_unreachable),
+            (self.remove_dead_stores, remove_dead_stores),
+        ];
+
+        for _ in 0..MAX_ROUNDS {
+            let before = instructions.clone();
+
+            for (enabled, pass) in passes {
+                if !enabled {
+                    continue;
+                }
+
+                let mut removed = vec![false; instructions.len()];
+
+                instructions = pass(instructions, &mut removed);
+
+                if removed.contains(&true) && relocatable(&instructions) {
+                    instructions = compact(&instructions, &removed);
+                }
+            }
+
+            if instructions == before {
+                break;
+            }
+        }
+
+        instructions
+    }
+}
+
+/// A pass rewriting instructions in place and marking those it would remove.
+type Pass = fn(Vec<Instruction>, &mut [bool]) -> Vec<Instruction>;
+
+/// Returns whether instructions can be removed without changing where the others lead.
+fn relocatable(instructions: &[Instruction]) -> bool {
+    let reads_counter = instructions.iter().any(|instruction| {
+        !matches!(instruction, Instruction::Call(_)) && effects(instruction).reads.contains(COUNTER)
+    });
+
+    !reads_counter && !Cfg::new(instructions).has_unknown()
+}
+
+/// Removes the marked instructions, moving jumps to them onto the next instruction kept.
+fn compact(instructions: &[Instruction], removed: &[bool]) -> Vec<Instruction> {
+    // New index of the first instruction kept at or after each index, the end included.
+    let mut next = Vec::with_capacity(instructions.len() + 1);
+    let mut kept = 0;
+
+    for removed in removed {
+        next.push(kept);
+
+        if !removed {
+            kept += 1;
+        }
+    }
+
+    next.push(kept);
+
+    let relocate = |operand: &Operand| match operand {
+        // Jumps past the end stay past the end.
+        Operand::Value(value) => match next.get(value.wrapping_add(1) as usize) {
+            Some(target) => Operand::Value((*target as u64).wrapping_sub(1)),
+            None => Operand::Value(*value),
+        },
+        operand => operand.clone(),
+    };
+
+    instructions
+        .iter()
+        .zip(removed)
+        .filter(|(_, removed)| !**removed)
+        .map(|(instruction, _)| match instruction {
+            Instruction::Jmp(source) => Instruction::Jmp(relocate(source)),
+            Instruction::Jz(source) => Instruction::Jz(relocate(source)),
+            Instruction::Jnz(source) => Instruction::Jnz(relocate(source)),
+            instruction => instruction.clone(),
+        })
+        .collect()
+}
+
+/// Returns a [`Width`] of the given size in bytes over the given index.
+fn widen(index: usize, size: usize) -> Width {
+    match size {
+        1 => Width::Byte(index),
+        2 => Width::Word(index),
+        4 => Width::DWord(index),
+        _ => Width::QWord(index),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Bits of a register known to hold a value.
+struct Bits {
+    known: u64,
+    value: u64,
+}
+
+impl Bits {
+    const UNKNOWN: Bits = Bits { known: 0, value: 0 };
+
+    fn constant(value: u64) -> Self {
+        Bits {
+            known: u64::MAX,
+            value,
+        }
+    }
+
+    /// Returns the bits known on both paths.
+    fn join(self, other: Self) -> Self {
+        let known = self.known & other.known & !(self.value ^ other.value);
+
+        Bits {
+            known,
+            value: self.value & known,
+        }
+    }
+
+    /// Returns the low `size` bytes, as read through a [`Width`] of that size.
+    fn read(self, size: usize) -> Self {
+        Bits {
+            known: self.known | !mask(size),
+            value: self.value & mask(size),
+        }
+    }
+
+    /// Returns self with the low `size` bytes replaced by those of the given bits.
+    fn write(self, size: usize, bits: Self) -> Self {
+        Bits {
+            known: (self.known & !mask(size)) | (bits.known & mask(size)),
+            value: (self.value & !mask(size)) | (bits.value & mask(size)),
+        }
+    }
+
+    fn get(self) -> Option<u64> {
+        (self.known == u64::MAX).then_some(self.value)
+    }
+
+    /// Returns the given bit, if known.
+    fn bit(self, bit: u64) -> Option<bool> {
+        (self.known & bit != 0).then_some(self.value & bit != 0)
+    }
+}
+
+/// Known bits of every register, a forward [`Analysis`]; [`None`] where no path reaches.
+struct Constants;
+
+type Registers = Option<[Bits; 16]>;
+
+/// Returns the known bits of the given source operand of the instruction at the given index.
+fn read(registers: &[Bits; 16], index: usize, operand: &Operand) -> Bits {
+    match operand {
+        Operand::Value(value) => Bits::constant(*value),
+        Operand::Register(register) => match (register.index(), register.size()) {
+            (COUNTER, size) => Bits::constant(index as u64).read(size),
+            (register, size) => registers[register].read(size),
+        },
+        Operand::Memory(width) | Operand::MemoryRegister(width) => Bits::UNKNOWN.read(width.size()),
+
+        Operand::None => Bits::UNKNOWN,
+    }
+}
+
+impl Analysis for Constants {
+    type Fact = Registers;
+
+    const DIRECTION: Direction = Direction::Forward;
+
+    fn bottom(&self) -> Registers {
+        None
+    }
+
+    fn boundary(&self) -> Registers {
+        Some([Bits::UNKNOWN; 16])
+    }
+
+    fn join(&self, fact: &mut Registers, other: &Registers) {
+        *fact = match (*fact, *other) {
+            (Some(mut registers), Some(other)) => {
+                for (register, other) in registers.iter_mut().zip(other) {
+                    *register = register.join(other);
+                }
+
+                Some(registers)
+            }
+            (fact, other) => fact.or(other),
+        };
+    }
+
+    fn transfer(&self, index: usize, instruction: &Instruction, fact: &mut Registers) {
+        let Some(registers) = fact else { return };
+
+        let write = |registers: &mut [Bits; 16], operand: &Operand, bits: Bits| {
+            if let Operand::Register(register) = operand {
+                let (register, size) = (register.index(), register.size());
+
+                if register != COUNTER {
+                    registers[register] = registers[register].write(size, bits);
+                }
+            }
+        };
+
+        match instruction {
+            Instruction::Call(_) => *registers = [Bits::UNKNOWN; 16],
+            Instruction::Mov(source, destination) => {
+                let bits = read(registers, index, source);
+
+                write(registers, destination, bits);
+            }
+            Instruction::Add(value, source, destination) => {
+                let value = read(registers, index, value);
+                let source = read(registers, index, source);
+                let flags = &mut registers[FLAGS];
+
+                let result = match (source.get(), value.get()) {
+                    (Some(source), Some(value)) => {
+                        let (result, overflow) = source.overflowing_add(value);
+
+                        if overflow {
+                            flags.known |= 4;
+                            flags.value |= 4;
+                        }
+
+                        Bits::constant(result)
+                    }
+                    _ => {
+                        if flags.bit(4) != Some(true) {
+                            flags.known &= !4;
+                            flags.value &= !4;
+                        }
+
+                        Bits::UNKNOWN
+                    }
+                };
+
+                write(registers, destination, result);
+            }
+            Instruction::Cmp(value, comparator) => {
+                let value = read(registers, index, value);
+                let comparator = read(registers, index, comparator);
+                let flags = &mut registers[FLAGS];
+
+                match (value.get(), comparator.get()) {
+                    (Some(value), Some(comparator)) => {
+                        let bits =
+                            u64::from(value == comparator) | u64::from(value > comparator) << 1;
+
+                        flags.known |= 3;
+                        flags.value = (flags.value & !3) | bits;
+                    }
+                    _ => {
+                        flags.known &= !3;
+                        flags.value &= !3;
+                    }
+                }
+            }
+            // Either the zero flag was clear already, or the taken jump clears it.
+            Instruction::Jz(_) => {
+                registers[FLAGS].known |= 1;
+                registers[FLAGS].value &= !1;
+            }
+            Instruction::Jmp(_) | Instruction::Jnz(_) => {}
+        }
+    }
+}
+
+/// Propagates known registers into operands, folds `add`s and decides conditional jumps.
+fn fold_constants(instructions: Vec<Instruction>, removed: &mut [bool]) -> Vec<Instruction> {
+    let cfg = Cfg::new(&instructions);
+    let constants = solve(&cfg, &Constants);
+    let live = solve(&cfg, &Liveness::new(RegisterSet::ALL.difference(counter())));
+
+    instructions
+        .into_iter()
+        .enumerate()
+        .map(|(index, instruction)| {
+            let Some(registers) = constants.before(index) else {
+                return instruction;
+            };
+
+            let source = |operand: &Operand| match operand {
+                Operand::Register(width) => match read(registers, index, operand).get() {
+                    Some(value) => Operand::Value(value),
+                    None => Operand::Register(width.clone()),
+                },
+                operand => address(registers, index, operand),
+            };
+            let flags = registers[FLAGS];
+
+            match instruction {
+                Instruction::Call(call_index) => Instruction::Call(source(&call_index)),
+                Instruction::Mov(from, to) => {
+                    Instruction::Mov(source(&from), address(registers, index, &to))
+                }
+                Instruction::Add(value, from, to) => {
+                    let value = source(&value);
+                    let from = source(&from);
+                    let to = address(registers, index, &to);
+
+                    match (&value, &from, &to) {
+                        (Operand::Value(value), Operand::Value(from), to) if !is_counter(to) => {
+                            let (result, overflow) = from.overflowing_add(*value);
+
+                            if overflow && flags.bit(4) != Some(true) {
+                                Instruction::Add(
+                                    Operand::Value(*value),
+                                    Operand::Value(*from),
+                                    to.clone(),
+                                )
+                            } else {
+                                Instruction::Mov(Operand::Value(result), to.clone())
+                            }
+                        }
+                        _ => Instruction::Add(value, from, to),
+                    }
+                }
+                Instruction::Cmp(value, comparator) => {
+                    Instruction::Cmp(source(&value), source(&comparator))
+                }
+                Instruction::Jmp(target) => Instruction::Jmp(source(&target)),
+                Instruction::Jz(target) => match flags.bit(1) {
+                    Some(false) => {
+                        removed[index] = true;
+
+                        Instruction::Jz(target)
+                    }
+                    Some(true) if !live.after(index).contains(FLAGS) => {
+                        Instruction::Jmp(source(&target))
+                    }
+                    _ => Instruction::Jz(source(&target)),
+                },
+                Instruction::Jnz(target) => match flags.bit(1) {
+                    Some(true) => {
+                        removed[index] = true;
+
+                        Instruction::Jnz(target)
+                    }
+                    Some(false) => Instruction::Jmp(source(&target)),
+                    None => Instruction::Jnz(source(&target)),
+                },
+            }
+        })
+        .collect()
+}
+
+/// Returns the set holding only the instruction counter.
+fn counter() -> RegisterSet {
+    RegisterSet::from_iter([COUNTER])
+}
+
+/// Returns the given operand, with a [`MemoryRegister`](Operand::MemoryRegister) of a known address
+/// turned into [`Memory`](Operand::Memory) at that address.
+fn address(registers: &[Bits; 16], index: usize, operand: &Operand) -> Operand {
+    match operand {
+        Operand::MemoryRegister(width) => {
+            let size = width.size();
+
+            match read(registers, index, &Operand::Register(width.clone())).get() {
+                Some(address) => Operand::Memory(widen(address as usize, size)),
+                None => operand.clone(),
+            }
+        }
+        operand => operand.clone(),
+    }
+}
+
+/// Retargets jumps through unconditional jumps and marks jumps to the next instruction.
+fn thread_jumps(instructions: Vec<Instruction>, removed: &mut [bool]) -> Vec<Instruction> {
+    // Index a jump to the given value ends up at, following unconditional jumps.
+    let follow = |value: u64| {
+        let mut target = value.wrapping_add(1);
+        let mut visited = BTreeSet::new();
+
+        while let Some(Instruction::Jmp(Operand::Value(next))) = usize::try_from(target)
+            .ok()
+            .and_then(|target| instructions.get(target))
+        {
+            if !visited.insert(target) {
+                break;
+            }
+
+            target = next.wrapping_add(1);
+        }
+
+        target
+    };
+
+    instructions
+        .iter()
+        .enumerate()
+        .map(|(index, instruction)| {
+            let retarget = |value: &u64| {
+                let target = follow(*value);
+
+                (target, Operand::Value(target.wrapping_sub(1)))
+            };
+
+            // Jumps onto the next instruction change nothing, `jz` excepted as it clears a flag.
+            match instruction {
+                Instruction::Jmp(Operand::Value(value)) => {
+                    let (target, operand) = retarget(value);
+
+                    removed[index] = target == index as u64 + 1;
+
+                    Instruction::Jmp(operand)
+                }
+                Instruction::Jnz(Operand::Value(value)) => {
+                    let (target, operand) = retarget(value);
+
+                    removed[index] = target == index as u64 + 1;
+
+                    Instruction::Jnz(operand)
+                }
+                Instruction::Jz(Operand::Value(value)) => Instruction::Jz(retarget(value).1),
+                instruction => instruction.clone(),
+            }
+        })
+        .collect()
+}
+
+/// Marks every instruction in a block no path from the entry reaches.
+fn remove_unreachable(instructions: Vec<Instruction>, removed: &mut [bool]) -> Vec<Instruction> {
+    let cfg = Cfg::new(&instructions);
+
+    if cfg.has_unknown() || cfg.blocks().is_empty() {
+        return instructions;
+    }
+
+    let mut reached = vec![false; cfg.blocks().len()];
+    let mut stack = vec![0];
+
+    while let Some(number) = stack.pop() {
+        if std::mem::replace(&mut reached[number], true) {
+            continue;
+        }
+
+        for edge in &cfg.blocks()[number].successors {
+            if let Target::Block(target) = edge.target {
+                stack.push(target);
+            }
+        }
+    }
+
+    for (block, reached) in cfg.blocks().iter().zip(reached) {
+        if !reached {
+            removed[block.start..block.end].fill(true);
+        }
+    }
+
+    instructions
+}
+
+/// Marks writes of registers never read afterwards, and moves of a register to itself.
+fn remove_dead_stores(instructions: Vec<Instruction>, removed: &mut [bool]) -> Vec<Instruction> {
+    let cfg = Cfg::new(&instructions);
+    let live = solve(&cfg, &Liveness::new(RegisterSet::ALL.difference(counter())));
+
+    for (index, instruction) in instructions.iter().enumerate() {
+        let live = live.after(index);
+        let dead = |operand: &Operand| match operand {
+            Operand::Register(width) => {
+                let register = width.index();
+
+                register != COUNTER && !live.contains(register)
+            }
+            _ => false,
+        };
+
+        removed[index] = match instruction {
+            Instruction::Mov(source, destination) => source == destination || dead(destination),
+            Instruction::Add(_, _, destination) => dead(destination) && !live.contains(FLAGS),
+            Instruction::Cmp(_, _) => !live.contains(FLAGS),
+
+            _ => false,
+        };
+    }
+
+    instructions
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::assembler::Assembler;
+    use crate::instructions::{Instruction, Operand};
+    use crate::optimize::Optimizer;
+    use crate::register::Width;
+    use crate::testing::random;
+    use crate::Vm;
+
+    fn optimized(source: &str, optimizer: Optimizer) -> Vec<Instruction> {
+        optimizer.optimize(Assembler::parse(source).unwrap().instructions())
+    }
+
+    fn register(index: usize) -> Operand {
+        Operand::Register(Width::QWord(index))
+    }
+
+    /// Runs the given program, returning every register but the instruction counter, and memory.
+    fn run(instructions: Vec<Instruction>) -> (Vec<u64>, Vec<u64>) {
+        let mut vm = Vm::new();
+
+        vm.load_instructions(
+            instructions
+                .into_iter()
+                .map(Instruction::executable)
+                .collect(),
+        )
+        .unwrap();
+
+        let handle = vm.new_processor();
+        let processor = vm.processor_mut(handle).unwrap();
+
+        processor.start().unwrap();
+
+        let registers = (0..15)
+            .map(|index| processor.register(index).unwrap().as_u64())
+            .collect();
+        let memory = (0..8)
+            .map(|index| processor.memory().unwrap().get_u64(index * 8))
+            .collect();
+
+        (registers, memory)
+    }
+
+    #[test]
+    pub fn optimize_fold_constants() {
+        let source = "mov 5, rq0\n\
+                      add rq0, 3, rq1\n\
+                      mov 24, rq2\n\
+                      mov rq1, [rq2]\n\
+                      cmp rq1, 8\n\
+                      jnz skip\n\
+                      mov 1, rq3\n\
+                      skip: mov rq0, mq0";
+
+        assert_eq!(
+            optimized(source, Optimizer::new()),
+            [
+                Instruction::Mov(Operand::Value(5), register(0)),
+                Instruction::Mov(Operand::Value(8), register(1)),
+                Instruction::Mov(Operand::Value(24), register(2)),
+                Instruction::Mov(Operand::Value(8), Operand::Memory(Width::QWord(24))),
+                Instruction::Cmp(Operand::Value(8), Operand::Value(8)),
+                Instruction::Mov(Operand::Value(1), register(3)),
+                Instruction::Mov(Operand::Value(5), Operand::Memory(Width::QWord(0))),
+            ]
+        );
+    }
+
+    #[test]
+    pub fn optimize_dead_stores() {
+        let source = "mov 1, rq0\n\
+                      mov 2, rq0\n\
+                      mov rq1, rq1\n\
+                      mov rq0, mq0";
+
+        assert_eq!(
+            optimized(source, Optimizer::new().fold_constants(false)),
+            [
+                Instruction::Mov(Operand::Value(2), register(0)),
+                Instruction::Mov(register(0), Operand::Memory(Width::QWord(0))),
+            ]
+        );
+    }
+
+    #[test]
+    pub fn optimize_thread_jumps() {
+        let source = "jmp a\n\
+                      mov 1, rq0\n\
+                      a: jmp b\n\
+                      mov 2, rq0\n\
+                      b: mov 3, rq1\n\
+                      jz a";
+
+        let threaded = optimized(source, Optimizer::new());
+
+        assert_eq!(
+            threaded,
+            [
+                Instruction::Mov(Operand::Value(3), register(1)),
+                Instruction::Jz(Operand::Value(u64::MAX)),
+            ]
+        );
+
+        let untouched = optimized(
+            source,
+            Optimizer::new()
+                .thread_jumps(false)
+                .remove_unreachable(false),
+        );
+
+        assert_eq!(untouched, Assembler::parse(source).unwrap().instructions());
+    }
+
+    #[test]
+    pub fn optimize_keeps_computed_jumps() {
+        let source = "add mq0, 3, rq1\n\
+                      jmp rq1\n\
+                      mov 1, rq0\n\
+                      mov rq15, rq2\n\
+                      mov 4, rq0";
+
+        let instructions = optimized(source, Optimizer::new());
+
+        assert_eq!(instructions.len(), 5);
+        assert_eq!(
+            instructions[3],
+            Instruction::Mov(Operand::Value(3), register(2))
+        );
+        assert_eq!(
+            run(instructions),
+            run(Assembler::parse(source).unwrap().instructions().to_vec())
+        );
+    }
+
+    /// Returns a random register, or, unless a destination, a random value or memory operand.
+    fn operand(seed: &mut u64, destination: bool) -> String {
+        let width = ["b", "w", "d", "q"][random(seed, 4) as usize];
+
+        match random(seed, 6) {
+            0 | 1 if !destination => format!("{}", random(seed, 4)),
+            2 => format!("m{width}{}", random(seed, 4) * 8),
+            _ => format!("r{width}{}", random(seed, 4)),
+        }
+    }
+
+    #[test]
+    pub fn optimize_differential() {
+        let mut seed = 0x9E37_79B9_7F4A_7C15_u64;
+        let mut removed = 0;
+
+        for _ in 0..200 {
+            let length = 16;
+            let mut source = String::from("mov 0, rq13\nloop:\n");
+
+            for line in 0..length {
+                let instruction = match random(&mut seed, 8) {
+                    0 | 1 => format!(
+                        "mov {}, {}",
+                        operand(&mut seed, false),
+                        operand(&mut seed, true)
+                    ),
+                    2 | 3 => format!(
+                        "add {}, {}, {}",
+                        operand(&mut seed, false),
+                        operand(&mut seed, false),
+                        operand(&mut seed, true)
+                    ),
+                    4 => format!(
+                        "cmp {}, {}",
+                        operand(&mut seed, false),
+                        operand(&mut seed, false)
+                    ),
+                    // Forward jumps only, so every program ends.
+                    kind => format!(
+                        "{} l{}",
+                        ["jmp", "jz", "jnz"][kind as usize - 5],
+                        line + 1 + random(&mut seed, (length - line) as u64) as usize
+                    ),
+                };
+
+                source += &format!("l{line}: {instruction}\n");
+            }
+
+            source += &format!("l{length}: add 1, rq13, rq13\ncmp rq13, 3\njnz loop\nmov 0, rq13");
+
+            let instructions = Assembler::parse(&source).unwrap().instructions().to_vec();
+            let optimized = Optimizer::new().optimize(&instructions);
+
+            removed += instructions.len() - optimized.len();
+
+            assert_eq!(run(optimized), run(instructions), "{source}");
+        }
+
+        assert!(removed > 0);
+    }
+}
@@ -1,9 +1,90 @@
+use crate::decode::Program;
 use crate::error::Error;
-use crate::register::{Flag, Register, ReservedIndex};
+use crate::instructions::call::{self, HostCall};
+use crate::instructions::Instruction;
+use crate::observer::VmObserver;
+use crate::register::{mask, Flag, Register, ReservedIndex};
+use crate::trace::Tracer;
 use crate::VmCtx;
 
 use crate::memory::Memory;
-use std::sync::{Arc, RwLockReadGuard, RwLockWriteGuard};
+use std::cell::{Ref, RefCell, RefMut};
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, PoisonError, RwLockReadGuard, RwLockWriteGuard};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+/// Generational handle to a [`Processor`] owned by a [`Vm`][crate::Vm].
+///
+/// Indices are reused once a [`Processor`] is destroyed, the generation tells the old and new apart.
+pub struct ProcessorHandle {
+    index: usize,
+    generation: u64,
+}
+
+impl ProcessorHandle {
+    #[must_use]
+    pub(crate) fn new(index: usize, generation: u64) -> Self {
+        ProcessorHandle { index, generation }
+    }
+
+    #[must_use]
+    /// Returns the slot index of the [`Processor`].
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    #[must_use]
+    /// Returns how many processors occupied the slot before this one.
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+}
+
+#[derive(Debug)]
+/// Read access to the [`Memory`] of a [`Processor`], locked only while it's shared.
+pub enum MemoryGuard<'a> {
+    Shared(RwLockReadGuard<'a, Memory>),
+    Owned(Ref<'a, Memory>),
+}
+
+impl Deref for MemoryGuard<'_> {
+    type Target = Memory;
+
+    fn deref(&self) -> &Memory {
+        match self {
+            MemoryGuard::Shared(guard) => guard,
+            MemoryGuard::Owned(memory) => memory,
+        }
+    }
+}
+
+#[derive(Debug)]
+/// Write access to the [`Memory`] of a [`Processor`], locked only while it's shared.
+pub enum MemoryGuardMut<'a> {
+    Shared(RwLockWriteGuard<'a, Memory>),
+    Owned(RefMut<'a, Memory>),
+}
+
+impl Deref for MemoryGuardMut<'_> {
+    type Target = Memory;
+
+    fn deref(&self) -> &Memory {
+        match self {
+            MemoryGuardMut::Shared(guard) => guard,
+            MemoryGuardMut::Owned(memory) => memory,
+        }
+    }
+}
+
+impl DerefMut for MemoryGuardMut<'_> {
+    fn deref_mut(&mut self) -> &mut Memory {
+        match self {
+            MemoryGuardMut::Shared(guard) => guard,
+            MemoryGuardMut::Owned(memory) => memory,
+        }
+    }
+}
 
 #[derive(Debug, Default)]
 /// Single-threaded object running code given by the [`Vm`][crate::Vm].
@@ -13,6 +94,15 @@ pub struct Processor {
     /// "16, why 16!?" - The ISA for the Wednesday VM only permits for 16 registers.
     ///                  16 comes from the lower bound of the 4-bit register index.
     registers: [Register; 16],
+
+    /// [`VmCtx`] memory, moved into self while no other [`Processor`] can access it, or the
+    /// private memory of self.
+    memory: RefCell<Option<Memory>>,
+    /// Whether self runs on a private [`Memory`] of its own instead of the [`VmCtx`] memory.
+    private: bool,
+
+    tracer: Option<Tracer>,
+    observers: Vec<Arc<dyn VmObserver>>,
 }
 
 impl Processor {
@@ -25,37 +115,165 @@ impl Processor {
         }
     }
 
-    /// Starts execution on self, locking [`VmCtx's`](VmCtx) instructions for readonly.
+    #[must_use]
+    /// Constructs a copy of self running on the given [`VmCtx`], without its [`Tracer`].
+    pub(crate) fn fork(&self, vm_ctx: &Arc<VmCtx>) -> Self {
+        Processor {
+            vm_ctx: Arc::clone(vm_ctx),
+            registers: self
+                .registers
+                .each_ref()
+                .map(|register| Register::new(register.as_u64())),
+            // A private memory is copied along, the shared one is placed by the forked Vm.
+            memory: RefCell::new(self.private.then(|| self.memory.borrow().clone()).flatten()),
+            private: self.private,
+            tracer: None,
+            observers: self.observers.clone(),
+        }
+    }
+
+    /// Starts execution on self, running until the instruction counter leaves the program.
+    ///
+    /// The program is shared, not locked, while running, so loading a new one never waits for self.
     ///
     /// # Errors
     /// When the [`VmCtx's`](VmCtx) instructions is poisoned, [`InstructionsPoisoned`](Error::InstructionsPoisoned) is returned.
     pub fn start(&mut self) -> Result<(), Error> {
-        let ctx = Arc::clone(&self.vm_ctx);
-        let executable_slice = ctx
-            .instructions
-            .read()
-            .map_err(|_| Error::InstructionsPoisoned)?;
+        while self.run(u64::MAX)? {}
+
+        Ok(())
+    }
+
+    /// Executes the next instruction on self, returning whether another instruction follows.
+    ///
+    /// # Errors
+    /// When the [`VmCtx's`](VmCtx) instructions is poisoned, [`InstructionsPoisoned`](Error::InstructionsPoisoned) is returned.
+    pub fn step(&mut self) -> Result<bool, Error> {
+        self.run(1)
+    }
+
+    /// Executes at most the given number of instructions on self, then pauses.
+    ///
+    /// Returns whether self paused with another instruction to run, rather than leaving the program.
+    /// While paused, the [`Vm`](crate::Vm) may swap in a new program with
+    /// [`reload_module`](crate::Vm::reload_module).
+    ///
+    /// # Example
+    /// ```
+    /// use vm::assembler::Assembler;
+    /// use vm::Vm;
+    /// let mut vm_inst = Vm::new();
+    /// vm_inst.load_instructions(Assembler::parse("mov 1, rq0\nmov 2, rq0").unwrap().compile()).unwrap();
+    /// let handle = vm_inst.new_processor();
+    /// assert!(vm_inst.processor_mut(handle).unwrap().run(1).unwrap());
+    /// assert!(!vm_inst.processor_mut(handle).unwrap().run(1).unwrap());
+    /// ```
+    ///
+    /// # Errors
+    /// When the [`VmCtx's`](VmCtx) instructions is poisoned, [`InstructionsPoisoned`](Error::InstructionsPoisoned) is returned.
+    pub fn run(&mut self, steps: u64) -> Result<bool, Error> {
+        let program = Arc::clone(
+            &*self
+                .vm_ctx
+                .instructions
+                .read()
+                .map_err(|_| Error::InstructionsPoisoned)?,
+        );
 
-        loop {
+        if self.tracer.is_none() && self.observers.is_empty() {
+            return self.run_uninstrumented(&program, steps);
+        }
+
+        for _ in 0..steps {
             let register_index = self
                 .register(ReservedIndex::InstructionCounter as usize)?
                 .as_u64() as usize;
-            let instruction = executable_slice.get(register_index);
 
-            match instruction {
-                Some(instruction) => {
-                    instruction.execute(self)?;
+            if register_index >= program.len() {
+                return Ok(false);
+            }
+
+            if let Some(tracer) = &mut self.tracer {
+                let instruction = program.executables()[register_index].instruction();
+
+                tracer.begin(register_index, instruction, &self.registers);
+            }
+
+            for observer in &self.observers {
+                observer.before_instruction(self, register_index);
+            }
+
+            if let Err(error) = program.execute(register_index, self) {
+                // The guest's trap is what the caller needs to see, even when a sink fails on it.
+                if let Some(tracer) = &mut self.tracer {
+                    _ = tracer.trap(&self.registers, &error);
+                }
+
+                for observer in &self.observers {
+                    observer.on_trap(self, register_index, &error);
+                }
+
+                return Err(error);
+            }
+
+            if let Some(tracer) = &mut self.tracer {
+                tracer.end(&self.registers)?;
+            }
+
+            for observer in &self.observers {
+                observer.after_instruction(self, register_index);
+            }
+
+            let counter = self.register_mut(ReservedIndex::InstructionCounter as usize)?;
+
+            counter.assign_u64(counter.as_u64().wrapping_add(1));
+        }
+
+        let counter = self
+            .register(ReservedIndex::InstructionCounter as usize)?
+            .as_u64() as usize;
+
+        Ok(counter < program.len())
+    }
+
+    /// Executes at most the given number of instructions without reporting to a tracer or observers.
+    fn run_uninstrumented(&mut self, program: &Program, steps: u64) -> Result<bool, Error> {
+        let counter = ReservedIndex::InstructionCounter as usize;
+        let fusion = self.vm_ctx.fusion.load(Ordering::Relaxed);
+        #[cfg(feature = "jit")]
+        let jit = self.vm_ctx.jit.load(Ordering::Relaxed);
+        let mut remaining = steps;
 
-                    let counter = self.register_mut(ReservedIndex::InstructionCounter as usize)?;
+        while remaining > 0 {
+            let index = self.registers[counter].as_u64() as usize;
 
-                    counter.assign_u64(counter.as_u64() + 1);
+            if index >= program.len() {
+                return Ok(false);
+            }
+
+            #[cfg(feature = "jit")]
+            if jit {
+                // Compiled blocks leave the instruction counter on the next instruction.
+                if let Some(executed) = program.jit().run(index, self, remaining) {
+                    remaining -= executed;
+
+                    continue;
                 }
+            }
 
-                None => break,
+            if fusion {
+                remaining -= program.execute_fused(index, self, remaining)?;
+            } else {
+                program.execute(index, self)?;
+                remaining -= 1;
             }
+
+            let next = self.registers[counter].as_u64().wrapping_add(1);
+
+            self.registers[counter].assign_u64(next);
         }
 
-        Ok(())
+        Ok((self.registers[counter].as_u64() as usize) < program.len())
     }
 
     /// Returns a reference to the [`Register`] at the given index.
@@ -67,25 +285,231 @@ impl Processor {
 
     /// Returns a mutable reference to the [`Register`] at the given index.
     pub fn register_mut(&mut self, index: usize) -> Result<&mut Register, Error> {
+        if let Some(tracer) = &mut self.tracer {
+            if index < self.registers.len() {
+                tracer.touch(index);
+            }
+        }
+
         self.registers
             .get_mut(index)
             .ok_or(Error::RegisterIndexOutOfBounds)
     }
 
-    /// Returns a reference to the [`Memory`] contained in the [`VmCtx`].
-    pub fn memory(&self) -> Result<RwLockReadGuard<Memory>, Error> {
-        self.vm_ctx.memory.read().map_err(|_| Error::MemoryPoisoned)
+    #[must_use]
+    /// Returns the low `size` bytes of the [`Register`] at the given, valid, index.
+    #[inline]
+    pub(crate) fn read_register(&self, index: usize, size: usize) -> u64 {
+        self.registers[index].as_u64() & mask(size)
     }
 
-    /// Returns a mutable reference to the [`Memory`] contained in the [`VmCtx`].
-    pub fn memory_mut(&self) -> Result<RwLockWriteGuard<Memory>, Error> {
-        self.vm_ctx
-            .memory
-            .write()
-            .map_err(|_| Error::MemoryPoisoned)
+    /// Assigns the low `size` bytes of the [`Register`] at the given, valid, index.
+    #[inline]
+    pub(crate) fn write_register(&mut self, index: usize, size: usize, value: u64) {
+        if let Some(tracer) = &mut self.tracer {
+            tracer.touch(index);
+        }
+
+        let register = &mut self.registers[index];
+        let mask = mask(size);
+
+        register.assign_u64((register.as_u64() & !mask) | (value & mask));
+    }
+
+    #[cfg(feature = "jit")]
+    /// Returns a pointer to the 16 little-endian registers of self, laid out contiguously.
+    pub(crate) fn registers_ptr(&mut self) -> *mut u8 {
+        self.registers.as_mut_ptr().cast()
+    }
+
+    /// Reads `size` bytes of [`Memory`] at the given address, reporting the read.
+    pub(crate) fn load(&mut self, address: usize, size: usize) -> Result<u64, Error> {
+        let mut bytes = [0; 8];
+
+        match self.memory.get_mut() {
+            Some(memory) => memory.get_bytes(address, &mut bytes[..size]),
+            None => self.memory()?.get_bytes(address, &mut bytes[..size]),
+        }
+
+        let value = u64::from_le_bytes(bytes);
+
+        self.record_memory_read(address, size, value);
+
+        Ok(value)
+    }
+
+    /// Writes the low `size` bytes of the given value to [`Memory`] at the given address, reporting the write.
+    pub(crate) fn store(&mut self, address: usize, size: usize, value: u64) -> Result<(), Error> {
+        let bytes = &value.to_le_bytes()[..size];
+
+        match self.memory.get_mut() {
+            Some(memory) => memory.put_bytes(address, bytes),
+            None => self.memory_mut()?.put_bytes(address, bytes),
+        }
+
+        self.record_memory_write(address, size, value & mask(size));
+
+        Ok(())
+    }
+
+    /// Returns a reference to the [`Memory`] of self, locking the [`VmCtx`] memory unless self owns it.
+    pub fn memory(&self) -> Result<MemoryGuard<'_>, Error> {
+        match Ref::filter_map(self.memory.borrow(), Option::as_ref) {
+            Ok(memory) => Ok(MemoryGuard::Owned(memory)),
+            Err(_) => self
+                .vm_ctx
+                .memory
+                .read()
+                .map(MemoryGuard::Shared)
+                .map_err(|_| Error::MemoryPoisoned),
+        }
+    }
+
+    /// Returns a mutable reference to the [`Memory`] of self, locking the [`VmCtx`] memory unless self owns it.
+    pub fn memory_mut(&self) -> Result<MemoryGuardMut<'_>, Error> {
+        match RefMut::filter_map(self.memory.borrow_mut(), Option::as_mut) {
+            Ok(memory) => Ok(MemoryGuardMut::Owned(memory)),
+            Err(_) => self
+                .vm_ctx
+                .memory
+                .write()
+                .map(MemoryGuardMut::Shared)
+                .map_err(|_| Error::MemoryPoisoned),
+        }
+    }
+
+    #[must_use]
+    /// Returns whether self owns its [`Memory`], accessing it without locking.
+    pub fn owns_memory(&self) -> bool {
+        self.memory.borrow().is_some()
+    }
+
+    #[must_use]
+    /// Returns whether self runs on a private [`Memory`], see [`Vm::set_private_memory`](crate::Vm::set_private_memory).
+    pub fn has_private_memory(&self) -> bool {
+        self.private
+    }
+
+    /// Replaces the [`VmCtx`] memory of self by a copy of its current contents, or drops the
+    /// private copy to share the [`VmCtx`] memory again.
+    pub(crate) fn set_private_memory(&mut self, private: bool) -> Result<(), Error> {
+        if private == self.private {
+            return Ok(());
+        }
+
+        if private {
+            self.release_memory();
+
+            let copy = self.memory()?.clone();
+
+            *self.memory.get_mut() = Some(copy);
+        } else {
+            *self.memory.get_mut() = None;
+        }
+
+        self.private = private;
+
+        Ok(())
+    }
+
+    /// Moves the [`VmCtx`] memory into self, until [`release_memory`](Processor::release_memory).
+    pub(crate) fn own_memory(&mut self) {
+        let memory = self.memory.get_mut();
+
+        if memory.is_none() {
+            let mut shared = self
+                .vm_ctx
+                .memory
+                .write()
+                .unwrap_or_else(PoisonError::into_inner);
+
+            *memory = Some(std::mem::take(&mut *shared));
+        }
+    }
+
+    /// Moves the [`Memory`] owned by self back into the [`VmCtx`], keeping a private one.
+    pub(crate) fn release_memory(&mut self) {
+        if self.private {
+            return;
+        }
+
+        if let Some(memory) = self.memory.get_mut().take() {
+            *self
+                .vm_ctx
+                .memory
+                .write()
+                .unwrap_or_else(PoisonError::into_inner) = memory;
+        }
+    }
+
+    /// Attaches a [`Tracer`] recording every instruction executed from now on.
+    pub fn set_tracer(&mut self, tracer: Tracer) {
+        self.tracer = Some(tracer);
+    }
+
+    /// Detaches and returns the current [`Tracer`].
+    pub fn take_tracer(&mut self) -> Option<Tracer> {
+        self.tracer.take()
+    }
+
+    /// Registers a [`VmObserver`] notified of every event on self.
+    pub(crate) fn observe(&mut self, observer: Arc<dyn VmObserver>) {
+        self.observers.push(observer);
+    }
+
+    #[doc(hidden)]
+    /// Reports a memory read to the attached [`Tracer`] and observers. Used by the memory macros.
+    pub fn record_memory_read(&mut self, address: usize, size: usize, value: u64) {
+        if let Some(tracer) = &mut self.tracer {
+            tracer.read(address, size, value);
+        }
+
+        for observer in &self.observers {
+            observer.on_memory_read(self, address, size, value);
+        }
+    }
+
+    #[doc(hidden)]
+    /// Reports a memory write to the attached [`Tracer`] and observers. Used by the memory macros.
+    pub fn record_memory_write(&mut self, address: usize, size: usize, value: u64) {
+        if let Some(tracer) = &mut self.tracer {
+            tracer.write(address, size, value);
+        }
+
+        for observer in &self.observers {
+            observer.on_memory_write(self, address, size, value);
+        }
+    }
+
+    /// Calls the built-in or registered host function at the given call index, as a `call` would.
+    ///
+    /// # Errors
+    /// When no function is found under the index, [`UnknownCall`](Error::UnknownCall) is returned.
+    /// Errors of the host function are passed through.
+    pub fn call(&mut self, call_index: u64) -> Result<(), Error> {
+        call::dispatch(self, call_index)
+    }
+
+    /// Returns the host function registered under the given call index.
+    pub(crate) fn host_call(&self, call_index: u64) -> Result<Option<HostCall>, Error> {
+        Ok(self
+            .vm_ctx
+            .calls
+            .read()
+            .map_err(|_| Error::CallsPoisoned)?
+            .get(&call_index)
+            .cloned())
+    }
+
+    /// Reports a call into external code to the observers.
+    pub(crate) fn record_call(&self, call_index: u64) {
+        for observer in &self.observers {
+            observer.on_call(self, call_index);
+        }
     }
 
     /// Sets the given [`Flag`] to the given state.
+    #[inline]
     pub fn set_flag(&mut self, flag: Flag, state: bool) {
         let mut flags = self
             .register_mut(ReservedIndex::Flags as usize)
@@ -101,8 +525,20 @@ impl Processor {
         self.registers[ReservedIndex::Flags as usize].assign_u64(flags);
     }
 
+    /// Returns the [`Instruction`] loaded at the given index, if any.
+    pub(crate) fn instruction(&self, index: usize) -> Option<Instruction> {
+        self.vm_ctx
+            .instructions
+            .read()
+            .ok()?
+            .executables()
+            .get(index)?
+            .instruction()
+    }
+
     #[must_use]
     /// Returns the state of the given [`Flag`].
+    #[inline]
     pub fn flag(&self, flag: Flag) -> bool {
         let flags = self
             .register(ReservedIndex::Flags as usize)
@@ -131,8 +567,24 @@ mod tests {
         vm.load_instructions(compiled).unwrap();
 
         let handle = vm.new_processor();
-        let processor = vm.processors.get_mut(&handle).unwrap();
+        let processor = vm.processors.get_mut(&handle.index()).unwrap();
 
         processor.start().unwrap();
     }
+
+    #[test]
+    pub fn processor_pause() {
+        let mut vm = Vm::new();
+
+        let assembler = Assembler::parse("mov 0, rq0\nloop: add 1, rq0, rq0\njmp loop").unwrap();
+
+        vm.load_instructions(assembler.compile()).unwrap();
+
+        let handle = vm.new_processor();
+        let processor = vm.processor_mut(handle).unwrap();
+
+        assert!(processor.run(5).unwrap());
+        assert!(processor.step().unwrap());
+        assert_eq!(processor.register(0).unwrap().as_u64(), 3);
+    }
 }
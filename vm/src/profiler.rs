@@ -0,0 +1,335 @@
+use crate::assembler::label_names;
+use crate::instructions::{Instruction, Operand};
+use crate::observer::VmObserver;
+use crate::processor::Processor;
+
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+use std::sync::Mutex;
+
+/// Name given to the instructions preceding the first label of a program.
+pub const ENTRY_LABEL: &str = "<entry>";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// Fuel charged for executing an [`Instruction`], measured in cycles.
+pub struct CostModel {
+    /// Cycles charged for every executed instruction.
+    pub base: u64,
+    /// Cycles charged per [`Memory`](Operand::Memory) or [`MemoryRegister`](Operand::MemoryRegister) operand.
+    pub memory: u64,
+    /// Cycles charged per [`Call`](Instruction::Call) into external code.
+    pub call: u64,
+}
+
+impl Default for CostModel {
+    fn default() -> Self {
+        CostModel {
+            base: 1,
+            memory: 2,
+            call: 10,
+        }
+    }
+}
+
+impl CostModel {
+    #[must_use]
+    /// Returns the cycles charged for a single execution of the given [`Instruction`].
+    pub fn cost(&self, instruction: &Instruction) -> u64 {
+        let operands: &[&Operand] = match instruction {
+            Instruction::Call(index) => return self.base + self.call + self.operand(index),
+            Instruction::Mov(source, destination) => &[source, destination],
+            Instruction::Jmp(source) | Instruction::Jz(source) | Instruction::Jnz(source) => {
+                &[source]
+            }
+            Instruction::Cmp(value, comparator) => &[value, comparator],
+            Instruction::Add(value, source, destination) => &[value, source, destination],
+        };
+
+        self.base
+            + operands
+                .iter()
+                .map(|operand| self.operand(operand))
+                .sum::<u64>()
+    }
+
+    fn operand(&self, operand: &Operand) -> u64 {
+        match operand {
+            Operand::Memory(_) | Operand::MemoryRegister(_) => self.memory,
+
+            _ => 0,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+/// [`VmObserver`] counting how many times each instruction index is executed.
+pub struct Profiler {
+    hits: Mutex<Vec<u64>>,
+}
+
+impl Profiler {
+    #[must_use]
+    /// Constructs a new [`Profiler`] with no recorded hits.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    #[must_use]
+    /// Returns the recorded hits indexed by instruction index.
+    pub fn hits(&self) -> Vec<u64> {
+        self.hits
+            .lock()
+            .map(|hits| hits.clone())
+            .unwrap_or_default()
+    }
+
+    #[must_use]
+    /// Builds a [`Report`] from the recorded hits, attributing each instruction to the closest
+    /// preceding label and costing it with the given [`CostModel`].
+    pub fn report(
+        &self,
+        instructions: &[Instruction],
+        labels: &BTreeMap<String, usize>,
+        cost_model: &CostModel,
+    ) -> Report {
+        let hits = self.hits();
+
+        let names = label_names(labels);
+
+        let mut report = Report::default();
+        let mut by_label: BTreeMap<usize, LabelProfile> = BTreeMap::new();
+
+        for (index, instruction) in instructions.iter().enumerate() {
+            let count = hits.get(index).copied().unwrap_or_default();
+            let cycles = count * cost_model.cost(instruction);
+
+            let (label_index, label) = names
+                .range(..=index)
+                .next_back()
+                .map_or((0, ENTRY_LABEL), |(index, name)| (*index, *name));
+
+            let profile = by_label.entry(label_index).or_insert_with(|| LabelProfile {
+                name: label.to_string(),
+                start: label_index,
+                hits: 0,
+                cycles: 0,
+            });
+
+            profile.hits += count;
+            profile.cycles += cycles;
+
+            report.steps += count;
+            report.cycles += cycles;
+            report.instructions.push(InstructionProfile {
+                index,
+                label: label.to_string(),
+                offset: index - label_index,
+                hits: count,
+                cycles,
+            });
+        }
+
+        report.labels = by_label.into_values().collect();
+
+        report
+    }
+}
+
+impl VmObserver for Profiler {
+    fn before_instruction(&self, _processor: &Processor, index: usize) {
+        let Ok(mut hits) = self.hits.lock() else {
+            return;
+        };
+
+        if hits.len() <= index {
+            hits.resize(index + 1, 0);
+        }
+
+        hits[index] += 1;
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// Execution statistics of a single instruction.
+pub struct InstructionProfile {
+    pub index: usize,
+    pub label: String,
+    pub offset: usize,
+    pub hits: u64,
+    pub cycles: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// Execution statistics aggregated over every instruction following a label.
+pub struct LabelProfile {
+    pub name: String,
+    pub start: usize,
+    pub hits: u64,
+    pub cycles: u64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+/// Result of a profiled run, produced by [`Profiler::report`].
+pub struct Report {
+    pub steps: u64,
+    pub cycles: u64,
+
+    pub instructions: Vec<InstructionProfile>,
+    pub labels: Vec<LabelProfile>,
+}
+
+impl Report {
+    #[must_use]
+    /// Returns the cycles per label and instruction in the folded stack format read by flamegraph tools.
+    pub fn folded(&self) -> String {
+        let mut folded = String::new();
+
+        for instruction in &self.instructions {
+            if instruction.cycles == 0 {
+                continue;
+            }
+
+            folded += &format!(
+                "{};{}+{} {}\n",
+                instruction.label, instruction.label, instruction.offset, instruction.cycles
+            );
+        }
+
+        folded
+    }
+}
+
+impl Display for Report {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{} steps, {} cycles", self.steps, self.cycles)?;
+        writeln!(f)?;
+        writeln!(
+            f,
+            "{:<24} {:>14} {:>14} {:>7}",
+            "label", "hits", "cycles", "%"
+        )?;
+
+        let mut labels: Vec<&LabelProfile> = self.labels.iter().collect();
+        labels.sort_by_key(|label| Reverse(label.cycles));
+
+        for label in labels {
+            writeln!(
+                f,
+                "{:<24} {:>14} {:>14} {:>6.2}%",
+                label.name,
+                label.hits,
+                label.cycles,
+                percentage(label.cycles, self.cycles)
+            )?;
+        }
+
+        writeln!(f)?;
+        writeln!(
+            f,
+            "{:>8} {:<24} {:>14} {:>14} {:>7}",
+            "index", "location", "hits", "cycles", "%"
+        )?;
+
+        let mut instructions: Vec<&InstructionProfile> = self.instructions.iter().collect();
+        instructions.sort_by_key(|instruction| Reverse(instruction.cycles));
+
+        for instruction in instructions {
+            writeln!(
+                f,
+                "{:>8} {:<24} {:>14} {:>14} {:>6.2}%",
+                instruction.index,
+                format!("{}+{}", instruction.label, instruction.offset),
+                instruction.hits,
+                instruction.cycles,
+                percentage(instruction.cycles, self.cycles)
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+fn percentage(part: u64, total: u64) -> f64 {
+    if total == 0 {
+        return 0.0;
+    }
+
+    part as f64 * 100.0 / total as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::Assembler;
+    use crate::register::Width;
+    use crate::Vm;
+
+    use std::sync::Arc;
+
+    fn counting_loop() -> Assembler {
+        Assembler::new()
+            .label("main")
+            .mov(Operand::Value(0), Operand::Register(Width::QWord(0)))
+            .label("loop")
+            .add(
+                Operand::Value(1),
+                Operand::Register(Width::QWord(0)),
+                Operand::Register(Width::QWord(0)),
+            )
+            .cmp(Operand::Register(Width::QWord(0)), Operand::Value(10))
+            .jnz(Operand::Value(0))
+            .label("exit")
+            .mov(
+                Operand::Register(Width::QWord(0)),
+                Operand::Memory(Width::QWord(0)),
+            )
+    }
+
+    #[test]
+    pub fn profiler_counts_loop() {
+        let mut vm = Vm::new();
+        let profiler = Arc::new(Profiler::new());
+
+        vm.register_observer(profiler.clone());
+
+        let assembler = counting_loop();
+        let instructions = assembler.instructions().to_vec();
+        let labels = assembler.labels().clone();
+
+        vm.load_instructions(assembler.compile()).unwrap();
+
+        let handle = vm.new_processor();
+        vm.processor_mut(handle).unwrap().start().unwrap();
+
+        assert_eq!(profiler.hits(), [1, 10, 10, 10, 1]);
+
+        let report = profiler.report(&instructions, &labels, &CostModel::default());
+
+        assert_eq!(report.steps, 32);
+        assert_eq!(report.cycles, 1 + 10 + 10 + 10 + 3);
+        assert_eq!(
+            report
+                .labels
+                .iter()
+                .map(|label| (label.name.as_str(), label.hits, label.cycles))
+                .collect::<Vec<_>>(),
+            [("main", 1, 1), ("loop", 30, 30), ("exit", 1, 3)]
+        );
+        assert_eq!(
+            report.folded(),
+            "main;main+0 1\nloop;loop+0 10\nloop;loop+1 10\nloop;loop+2 10\nexit;exit+0 3\n"
+        );
+    }
+
+    #[test]
+    pub fn profiler_entry_label() {
+        let profiler = Profiler::new();
+        let instructions = [Instruction::Call(Operand::Value(0))];
+
+        let report = profiler.report(&instructions, &BTreeMap::new(), &CostModel::default());
+
+        assert_eq!(report.labels[0].name, ENTRY_LABEL);
+        assert_eq!(report.cycles, 0);
+    }
+}
@@ -1,6 +1,23 @@
 use crate::error::Error;
 use crate::processor::Processor;
 
+/// Registers of a [`Processor`], the instruction counter included.
+pub const REGISTERS: usize = 16;
+
+/// Index of the flags register.
+pub const FLAGS: usize = ReservedIndex::Flags as usize;
+
+/// Index of the instruction counter register.
+pub const COUNTER: usize = ReservedIndex::InstructionCounter as usize;
+
+/// Returns the mask selecting the low `size` bytes, 1 to 8, of a 64-bit value.
+#[inline]
+#[must_use]
+pub(crate) fn mask(size: usize) -> u64 {
+    u64::MAX >> (64 - size * 8)
+}
+
+#[repr(transparent)]
 #[derive(Debug, Default, Eq, PartialEq)]
 /// Meta-type containing the byte layout for a 64-bit type.
 pub struct Register([u8; 8]);
@@ -31,6 +48,26 @@ pub enum Width {
 }
 
 impl Width {
+    #[must_use]
+    /// Returns the register index of the [`Width`].
+    pub fn index(&self) -> usize {
+        let (Width::Byte(index) | Width::Word(index) | Width::DWord(index) | Width::QWord(index)) =
+            *self;
+
+        index
+    }
+
+    #[must_use]
+    /// Returns the size in bytes of the [`Width`].
+    pub fn size(&self) -> usize {
+        match self {
+            Width::Byte(_) => 1,
+            Width::Word(_) => 2,
+            Width::DWord(_) => 4,
+            Width::QWord(_) => 8,
+        }
+    }
+
     /// Converts the [`Width`] to an 8-bit value.
     pub fn as_u8(&self, processor: &Processor) -> Result<u8, Error> {
         match self {
@@ -75,10 +112,12 @@ impl Width {
 /// Macro to set the value of the [`Register`] to the given primitive value.
 macro_rules! primitive_impl {
     ($assign_name: ident, $as_name: ident, $type:ty) => {
+        #[inline]
         pub fn $assign_name(&mut self, value: $type) {
             self.0[..std::mem::size_of::<$type>()].copy_from_slice(&value.to_le_bytes());
         }
 
+        #[inline]
         pub fn $as_name(&self) -> $type {
             <$type>::from_le_bytes(self.0[..std::mem::size_of::<$type>()].try_into().unwrap())
         }
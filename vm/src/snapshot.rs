@@ -0,0 +1,549 @@
+//! Versioned byte format for the complete state of a [`Vm`].
+//!
+//! All integers are little-endian. A snapshot is laid out as:
+//!
+//! ```text
+//! magic      b"WDNS"
+//! version    u16
+//! program    u64 count, then each instruction as an opcode u8 followed by its operands
+//! modules    u64 count, then each module as its name, u64 base, instructions, labels, exports and imports
+//! memory     u64 count, then each region as u64 address, u64 length and its bytes
+//! handles    u64 count, then the u64 generation of every processor index
+//! processors u64 count, then each processor as u64 index and 16 u64 registers
+//! ```
+//!
+//! Operands are a tag u8 followed by a u64 value, or by a width tag u8 and a u64 index. Strings
+//! are a u64 length followed by UTF-8 bytes, and every list is a u64 count followed by its items.
+
+use crate::error::Error;
+use crate::instructions::{Instruction, Operand};
+use crate::memory::Memory;
+use crate::module::{Import, LoadedModule, Module};
+use crate::processor::Processor;
+use crate::register::Width;
+use crate::Vm;
+
+use std::cmp::Reverse;
+
+/// Leading bytes of every snapshot.
+pub const MAGIC: &[u8; 4] = b"WDNS";
+
+/// Format version written by [`Vm::snapshot`] and accepted by [`Vm::restore`].
+pub const VERSION: u16 = 1;
+
+#[derive(Debug, Default)]
+struct Writer {
+    bytes: Vec<u8>,
+}
+
+impl Writer {
+    fn u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+
+    fn u64(&mut self, value: u64) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn string(&mut self, value: &str) {
+        self.u64(value.len() as u64);
+        self.bytes.extend_from_slice(value.as_bytes());
+    }
+
+    fn width(&mut self, width: &Width) {
+        let (tag, index) = match width {
+            Width::Byte(index) => (0, index),
+            Width::Word(index) => (1, index),
+            Width::DWord(index) => (2, index),
+            Width::QWord(index) => (3, index),
+        };
+
+        self.u8(tag);
+        self.u64(*index as u64);
+    }
+
+    fn operand(&mut self, operand: &Operand) {
+        match operand {
+            Operand::None => self.u8(0),
+            Operand::Value(value) => {
+                self.u8(1);
+                self.u64(*value);
+            }
+            Operand::Register(width) => {
+                self.u8(2);
+                self.width(width);
+            }
+            Operand::Memory(width) => {
+                self.u8(3);
+                self.width(width);
+            }
+            Operand::MemoryRegister(width) => {
+                self.u8(4);
+                self.width(width);
+            }
+        }
+    }
+
+    fn instruction(&mut self, instruction: &Instruction) {
+        let operands: &[&Operand] = match instruction {
+            Instruction::Call(index) => {
+                self.u8(0);
+                &[index]
+            }
+            Instruction::Mov(source, destination) => {
+                self.u8(1);
+                &[source, destination]
+            }
+            Instruction::Jmp(source) => {
+                self.u8(2);
+                &[source]
+            }
+            Instruction::Jz(source) => {
+                self.u8(3);
+                &[source]
+            }
+            Instruction::Jnz(source) => {
+                self.u8(4);
+                &[source]
+            }
+            Instruction::Cmp(value, comparator) => {
+                self.u8(5);
+                &[value, comparator]
+            }
+            Instruction::Add(value, source, destination) => {
+                self.u8(6);
+                &[value, source, destination]
+            }
+        };
+
+        for operand in operands {
+            self.operand(operand);
+        }
+    }
+
+    fn module(&mut self, loaded: &LoadedModule) {
+        let module = &loaded.module;
+
+        self.string(&loaded.name);
+        self.u64(loaded.base as u64);
+
+        self.u64(module.instructions.len() as u64);
+
+        for instruction in &module.instructions {
+            self.instruction(instruction);
+        }
+
+        self.u64(module.labels.len() as u64);
+
+        for (label, index) in &module.labels {
+            self.string(label);
+            self.u64(*index as u64);
+        }
+
+        self.u64(module.exports.len() as u64);
+
+        for export in &module.exports {
+            self.string(export);
+        }
+
+        self.u64(module.imports.len() as u64);
+
+        for import in &module.imports {
+            self.u64(import.index as u64);
+            self.u64(import.operand as u64);
+            self.string(&import.module);
+            self.string(&import.symbol);
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl Reader<'_> {
+    fn take(&mut self, length: usize) -> Result<&[u8], Error> {
+        if self.bytes.len() < length {
+            return Err(Error::InvalidSnapshot);
+        }
+
+        let (taken, rest) = self.bytes.split_at(length);
+        self.bytes = rest;
+
+        Ok(taken)
+    }
+
+    fn u8(&mut self) -> Result<u8, Error> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, Error> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64, Error> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn usize(&mut self) -> Result<usize, Error> {
+        usize::try_from(self.u64()?).map_err(|_| Error::InvalidSnapshot)
+    }
+
+    fn string(&mut self) -> Result<String, Error> {
+        let length = self.usize()?;
+
+        String::from_utf8(self.take(length)?.to_vec()).map_err(|_| Error::InvalidSnapshot)
+    }
+
+    fn width(&mut self) -> Result<Width, Error> {
+        let tag = self.u8()?;
+        let index = self.usize()?;
+
+        match tag {
+            0 => Ok(Width::Byte(index)),
+            1 => Ok(Width::Word(index)),
+            2 => Ok(Width::DWord(index)),
+            3 => Ok(Width::QWord(index)),
+
+            _ => Err(Error::InvalidSnapshot),
+        }
+    }
+
+    fn operand(&mut self) -> Result<Operand, Error> {
+        match self.u8()? {
+            0 => Ok(Operand::None),
+            1 => Ok(Operand::Value(self.u64()?)),
+            2 => Ok(Operand::Register(self.width()?)),
+            3 => Ok(Operand::Memory(self.width()?)),
+            4 => Ok(Operand::MemoryRegister(self.width()?)),
+
+            _ => Err(Error::InvalidSnapshot),
+        }
+    }
+
+    fn instruction(&mut self) -> Result<Instruction, Error> {
+        match self.u8()? {
+            0 => Ok(Instruction::Call(self.operand()?)),
+            1 => Ok(Instruction::Mov(self.operand()?, self.operand()?)),
+            2 => Ok(Instruction::Jmp(self.operand()?)),
+            3 => Ok(Instruction::Jz(self.operand()?)),
+            4 => Ok(Instruction::Jnz(self.operand()?)),
+            5 => Ok(Instruction::Cmp(self.operand()?, self.operand()?)),
+            6 => Ok(Instruction::Add(
+                self.operand()?,
+                self.operand()?,
+                self.operand()?,
+            )),
+
+            _ => Err(Error::InvalidSnapshot),
+        }
+    }
+
+    fn module(&mut self) -> Result<LoadedModule, Error> {
+        let name = self.string()?;
+        let base = self.usize()?;
+        let mut module = Module::default();
+
+        for _ in 0..self.u64()? {
+            module.instructions.push(self.instruction()?);
+        }
+
+        for _ in 0..self.u64()? {
+            let label = self.string()?;
+
+            module.labels.insert(label, self.usize()?);
+        }
+
+        for _ in 0..self.u64()? {
+            module.exports.insert(self.string()?);
+        }
+
+        for _ in 0..self.u64()? {
+            module.imports.push(Import {
+                index: self.usize()?,
+                operand: self.usize()?,
+                module: self.string()?,
+                symbol: self.string()?,
+            });
+        }
+
+        Ok(LoadedModule { name, base, module })
+    }
+}
+
+impl Vm {
+    /// Serializes every [`Processor`]'s registers, the [`VmCtx`](crate::VmCtx) memory and the loaded program.
+    ///
+    /// Tracers and observers are host-side state and are not part of the snapshot.
+    ///
+    /// # Example
+    /// ```
+    /// use vm::Vm;
+    /// let mut vm_inst = Vm::new();
+    /// let _handle = vm_inst.new_processor();
+    /// let restored = Vm::restore(&vm_inst.snapshot().unwrap()).unwrap();
+    /// ```
+    ///
+    /// # Errors
+    /// When the program contains an executable without an [`Instruction`], [`Unserializable`](Error::Unserializable) is returned.
+    /// When the [`VmCtx`](crate::VmCtx) is poisoned, [`InstructionsPoisoned`](Error::InstructionsPoisoned) or [`MemoryPoisoned`](Error::MemoryPoisoned) is returned.
+    pub fn snapshot(&self) -> Result<Vec<u8>, Error> {
+        let mut writer = Writer::default();
+
+        writer.bytes.extend_from_slice(MAGIC);
+        writer.bytes.extend_from_slice(&VERSION.to_le_bytes());
+
+        let instructions = self
+            .ctx
+            .instructions
+            .read()
+            .map_err(|_| Error::InstructionsPoisoned)?;
+
+        writer.u64(instructions.executables().len() as u64);
+
+        for executable in instructions.executables() {
+            let instruction = executable.instruction().ok_or(Error::Unserializable)?;
+
+            writer.instruction(&instruction);
+        }
+
+        writer.u64(self.modules.len() as u64);
+
+        for loaded in &self.modules {
+            writer.module(loaded);
+        }
+
+        let memory = self.memory()?;
+        let pages: Vec<(usize, &[u8])> = memory.pages().collect();
+
+        writer.u64(pages.len() as u64);
+
+        for (address, bytes) in pages {
+            writer.u64(address as u64);
+            writer.u64(bytes.len() as u64);
+            writer.bytes.extend_from_slice(bytes);
+        }
+
+        writer.u64(self.generations.len() as u64);
+
+        for generation in &self.generations {
+            writer.u64(*generation);
+        }
+
+        writer.u64(self.processors.len() as u64);
+
+        for (index, processor) in &self.processors {
+            writer.u64(*index as u64);
+
+            for index in 0..16 {
+                writer.u64(processor.register(index)?.as_u64());
+            }
+        }
+
+        Ok(writer.bytes)
+    }
+
+    /// Constructs a new [`Vm`] from the bytes produced by [`Vm::snapshot`].
+    ///
+    /// Processors keep the handles they had when the snapshot was taken.
+    ///
+    /// # Errors
+    /// When the bytes are truncated or malformed, [`InvalidSnapshot`](Error::InvalidSnapshot) is returned.
+    /// When the snapshot has an unknown format version, [`UnsupportedSnapshotVersion`](Error::UnsupportedSnapshotVersion) is returned.
+    pub fn restore(bytes: &[u8]) -> Result<Self, Error> {
+        let mut reader = Reader { bytes };
+
+        if reader.take(MAGIC.len())? != MAGIC {
+            return Err(Error::InvalidSnapshot);
+        }
+
+        let version = reader.u16()?;
+
+        if version != VERSION {
+            return Err(Error::UnsupportedSnapshotVersion(version));
+        }
+
+        let mut instructions = Vec::new();
+
+        for _ in 0..reader.u64()? {
+            instructions.push(reader.instruction()?.executable());
+        }
+
+        let mut modules = Vec::new();
+
+        for _ in 0..reader.u64()? {
+            modules.push(reader.module()?);
+        }
+
+        let mut memory = Memory::new();
+
+        for _ in 0..reader.u64()? {
+            let address = reader.usize()?;
+            let length = reader.usize()?;
+
+            memory.put_bytes(address, reader.take(length)?);
+        }
+
+        let mut vm = Vm::new();
+
+        vm.load_instructions(instructions)?;
+        vm.modules = modules;
+        *vm.ctx.memory.write().map_err(|_| Error::MemoryPoisoned)? = memory;
+
+        for _ in 0..reader.u64()? {
+            vm.generations.push(reader.u64()?);
+        }
+
+        for _ in 0..reader.u64()? {
+            let handle = reader.usize()?;
+
+            if handle >= vm.generations.len() || vm.processors.contains_key(&handle) {
+                return Err(Error::InvalidSnapshot);
+            }
+
+            let mut processor = Processor::new(&vm.ctx);
+
+            for index in 0..16 {
+                processor.register_mut(index)?.assign_u64(reader.u64()?);
+            }
+
+            vm.processors.insert(handle, processor);
+        }
+
+        vm.free = (0..vm.generations.len())
+            .filter(|index| !vm.processors.contains_key(index))
+            .map(Reverse)
+            .collect();
+
+        if !reader.bytes.is_empty() {
+            return Err(Error::InvalidSnapshot);
+        }
+
+        vm.place_memory();
+
+        Ok(vm)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::Assembler;
+
+    fn program() -> Assembler {
+        Assembler::parse(
+            "mov 0, rq0\n\
+             loop: add 1, rq0, rq0\n\
+             mov rq0, mq64\n\
+             cmp rq0, 5\n\
+             jnz loop",
+        )
+        .unwrap()
+    }
+
+    #[test]
+    pub fn snapshot_round_trip() {
+        let mut vm = Vm::new();
+        vm.load_instructions(program().compile()).unwrap();
+
+        let _first = vm.new_processor();
+        let second = vm.new_processor();
+
+        vm.processor_mut(second).unwrap().start().unwrap();
+
+        let bytes = vm.snapshot().unwrap();
+        let restored = Vm::restore(&bytes).unwrap();
+
+        assert_eq!(restored.snapshot().unwrap(), bytes);
+        assert_eq!(
+            restored
+                .processor(second)
+                .unwrap()
+                .register(0)
+                .unwrap()
+                .as_u64(),
+            5
+        );
+        assert_eq!(
+            restored
+                .processor(second)
+                .unwrap()
+                .memory()
+                .unwrap()
+                .get_u64(64),
+            5
+        );
+    }
+
+    #[test]
+    pub fn snapshot_warm_start() {
+        let mut vm = Vm::new();
+        vm.load_instructions(program().compile()).unwrap();
+
+        let handle = vm.new_processor();
+        let mut restored = Vm::restore(&vm.snapshot().unwrap()).unwrap();
+
+        restored.processor_mut(handle).unwrap().start().unwrap();
+        vm.processor_mut(handle).unwrap().start().unwrap();
+
+        assert_eq!(restored.snapshot().unwrap(), vm.snapshot().unwrap());
+    }
+
+    #[test]
+    pub fn snapshot_keeps_handles() {
+        let mut vm = Vm::new();
+
+        let first = vm.new_processor();
+        let _second = vm.new_processor();
+
+        vm.destroy_processor(first).unwrap();
+
+        let mut restored = Vm::restore(&vm.snapshot().unwrap()).unwrap();
+
+        assert_eq!(
+            restored.processor(first).unwrap_err(),
+            Error::StaleProcessorHandle
+        );
+        assert_eq!(restored.new_processor(), vm.new_processor());
+    }
+
+    #[test]
+    pub fn snapshot_keeps_modules() {
+        let mut vm = Vm::new();
+
+        vm.load_module("counter", program().module()).unwrap();
+
+        let mut restored = Vm::restore(&vm.snapshot().unwrap()).unwrap();
+        let handle = restored.new_processor_at("counter", "loop").unwrap();
+
+        assert_eq!(restored.modules[0].module, program().module());
+        assert_eq!(
+            restored
+                .processor(handle)
+                .unwrap()
+                .register(15)
+                .unwrap()
+                .as_u64(),
+            1
+        );
+    }
+
+    #[test]
+    pub fn snapshot_rejects_bad_input() {
+        let bytes = Vm::new().snapshot().unwrap();
+
+        let mut other_version = bytes.clone();
+        other_version[4] = 0xff;
+
+        assert_eq!(
+            Vm::restore(&other_version).unwrap_err(),
+            Error::UnsupportedSnapshotVersion(0xff)
+        );
+        assert_eq!(
+            Vm::restore(&bytes[..bytes.len() - 1]).unwrap_err(),
+            Error::InvalidSnapshot
+        );
+        assert_eq!(Vm::restore(b"nope").unwrap_err(), Error::InvalidSnapshot);
+    }
+}
@@ -0,0 +1,161 @@
+//! 64-bit bit-vector expressions over symbolic inputs.
+
+use crate::register::mask;
+
+use std::collections::BTreeMap;
+use std::rc::Rc;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+/// A 64-bit value computed from constants and symbols.
+///
+/// Comparisons give 1 when they hold and 0 otherwise. Build expressions with the associated
+/// functions, which fold constants, rather than the variants.
+pub enum Expr {
+    /// A known value.
+    Constant(u64),
+    /// The input with the given number.
+    Symbol(usize),
+    /// Wrapping sum.
+    Add(Rc<Expr>, Rc<Expr>),
+    /// Bitwise and.
+    And(Rc<Expr>, Rc<Expr>),
+    /// Bitwise or.
+    Or(Rc<Expr>, Rc<Expr>),
+    /// Left shift by a number of bits below 64.
+    Shl(Rc<Expr>, u32),
+    /// Logical right shift by a number of bits below 64.
+    Shr(Rc<Expr>, u32),
+    /// Whether both sides are equal.
+    Eq(Rc<Expr>, Rc<Expr>),
+    /// Whether the left side is greater, unsigned.
+    Ugt(Rc<Expr>, Rc<Expr>),
+    /// Whether the sum of both sides overflows.
+    Carry(Rc<Expr>, Rc<Expr>),
+}
+
+impl Expr {
+    #[must_use]
+    /// Returns the value of self, if it is a constant.
+    pub fn constant(&self) -> Option<u64> {
+        match self {
+            Expr::Constant(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    #[must_use]
+    /// Returns the wrapping sum of both expressions.
+    pub fn add(left: &Expr, right: &Expr) -> Expr {
+        match (left.constant(), right.constant()) {
+            (Some(left), Some(right)) => Expr::Constant(left.wrapping_add(right)),
+            (Some(0), _) => right.clone(),
+            (_, Some(0)) => left.clone(),
+            _ => Expr::Add(Rc::new(left.clone()), Rc::new(right.clone())),
+        }
+    }
+
+    #[must_use]
+    /// Returns the bitwise and of both expressions.
+    pub fn and(left: &Expr, right: &Expr) -> Expr {
+        match (left.constant(), right.constant()) {
+            (Some(left), Some(right)) => Expr::Constant(left & right),
+            (Some(0), _) | (_, Some(0)) => Expr::Constant(0),
+            (Some(u64::MAX), _) => right.clone(),
+            (_, Some(u64::MAX)) => left.clone(),
+            _ => Expr::And(Rc::new(left.clone()), Rc::new(right.clone())),
+        }
+    }
+
+    #[must_use]
+    /// Returns the bitwise or of both expressions.
+    pub fn or(left: &Expr, right: &Expr) -> Expr {
+        match (left.constant(), right.constant()) {
+            (Some(left), Some(right)) => Expr::Constant(left | right),
+            (Some(0), _) => right.clone(),
+            (_, Some(0)) => left.clone(),
+            _ => Expr::Or(Rc::new(left.clone()), Rc::new(right.clone())),
+        }
+    }
+
+    #[must_use]
+    /// Returns the expression shifted left by the given bits, below 64.
+    pub fn shl(expr: &Expr, bits: u32) -> Expr {
+        match expr.constant() {
+            Some(value) => Expr::Constant(value << bits),
+            None if bits == 0 => expr.clone(),
+            None => Expr::Shl(Rc::new(expr.clone()), bits),
+        }
+    }
+
+    #[must_use]
+    /// Returns the expression shifted right by the given bits, below 64.
+    pub fn shr(expr: &Expr, bits: u32) -> Expr {
+        match expr.constant() {
+            Some(value) => Expr::Constant(value >> bits),
+            None if bits == 0 => expr.clone(),
+            None => Expr::Shr(Rc::new(expr.clone()), bits),
+        }
+    }
+
+    #[must_use]
+    /// Returns 1 when both expressions are equal, 0 otherwise.
+    pub fn eq(left: &Expr, right: &Expr) -> Expr {
+        match (left.constant(), right.constant()) {
+            (Some(left), Some(right)) => Expr::Constant(u64::from(left == right)),
+            _ if left == right => Expr::Constant(1),
+            _ => Expr::Eq(Rc::new(left.clone()), Rc::new(right.clone())),
+        }
+    }
+
+    #[must_use]
+    /// Returns 1 when the left expression is greater, unsigned, 0 otherwise.
+    pub fn ugt(left: &Expr, right: &Expr) -> Expr {
+        match (left.constant(), right.constant()) {
+            (Some(left), Some(right)) => Expr::Constant(u64::from(left > right)),
+            (Some(0), _) | (_, Some(u64::MAX)) => Expr::Constant(0),
+            _ if left == right => Expr::Constant(0),
+            _ => Expr::Ugt(Rc::new(left.clone()), Rc::new(right.clone())),
+        }
+    }
+
+    #[must_use]
+    /// Returns 1 when the sum of both expressions overflows, 0 otherwise.
+    pub fn carry(left: &Expr, right: &Expr) -> Expr {
+        match (left.constant(), right.constant()) {
+            (Some(left), Some(right)) => Expr::Constant(u64::from(left.overflowing_add(right).1)),
+            (Some(0), _) | (_, Some(0)) => Expr::Constant(0),
+            _ => Expr::Carry(Rc::new(left.clone()), Rc::new(right.clone())),
+        }
+    }
+
+    #[must_use]
+    /// Returns 1 when the expression is 0, 0 otherwise.
+    pub fn not(expr: &Expr) -> Expr {
+        Expr::eq(expr, &Expr::Constant(0))
+    }
+
+    #[must_use]
+    /// Returns the low `size` bytes of the expression.
+    pub fn truncate(expr: &Expr, size: usize) -> Expr {
+        Expr::and(expr, &Expr::Constant(mask(size)))
+    }
+
+    #[must_use]
+    /// Returns the value of self with the given values for its symbols, 0 for missing ones.
+    pub fn eval(&self, symbols: &BTreeMap<usize, u64>) -> u64 {
+        let eval = |expr: &Rc<Expr>| expr.eval(symbols);
+
+        match self {
+            Expr::Constant(value) => *value,
+            Expr::Symbol(symbol) => symbols.get(symbol).copied().unwrap_or(0),
+            Expr::Add(left, right) => eval(left).wrapping_add(eval(right)),
+            Expr::And(left, right) => eval(left) & eval(right),
+            Expr::Or(left, right) => eval(left) | eval(right),
+            Expr::Shl(expr, bits) => eval(expr) << bits,
+            Expr::Shr(expr, bits) => eval(expr) >> bits,
+            Expr::Eq(left, right) => u64::from(eval(left) == eval(right)),
+            Expr::Ugt(left, right) => u64::from(eval(left) > eval(right)),
+            Expr::Carry(left, right) => u64::from(eval(left).overflowing_add(eval(right)).1),
+        }
+    }
+}
@@ -0,0 +1,688 @@
+//! Symbolic execution, exploring the paths of a program over symbolic registers and memory cells
+//! and generating concrete inputs that take each of them.
+//!
+//! Every conditional jump on a symbolic flag forks, keeping each side its path constraints allow.
+//! Addresses, jump targets and call indices are made concrete with the first value the solver
+//! finds, so paths depending on other values of those are missed. Calls other than
+//! [`PrintProcessor`](crate::instructions::call::CallIndex::PrintProcessor) end a path, since
+//! host calls aren't modelled.
+
+pub mod expr;
+pub mod solver;
+
+use crate::error::Error;
+use crate::instructions::{Instruction, Operand};
+use crate::processor::Processor;
+use crate::register::{mask, Flag, Width, COUNTER, FLAGS};
+
+use expr::Expr;
+use solver::solve;
+
+use std::collections::BTreeMap;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+/// A conditional jump taken or not.
+pub struct Branch {
+    /// Index of the jump in the program.
+    pub index: usize,
+    /// Whether the jump was taken.
+    pub taken: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// How a [`Path`] ends.
+pub enum End {
+    /// Continuing past the end of the program.
+    Exit,
+    /// The instruction at the given index failed with the given error.
+    Trap(usize, Error),
+    /// The instruction at the given index calls the given host call, which isn't modelled.
+    Call(usize, u64),
+    /// The path ran out of steps.
+    StepLimit,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+/// Concrete values for the symbolic registers and memory cells.
+pub struct Input {
+    /// Values of the symbolic registers, by index.
+    pub registers: BTreeMap<usize, u64>,
+    /// Values of the symbolic 8-byte memory cells, by address.
+    pub memory: BTreeMap<usize, u64>,
+}
+
+impl Input {
+    /// Writes self into the given [`Processor`].
+    ///
+    /// # Errors
+    /// When a register index is past the last register, or memory is poisoned.
+    pub fn apply(&self, processor: &mut Processor) -> Result<(), Error> {
+        for (index, value) in &self.registers {
+            processor.register_mut(*index)?.assign_u64(*value);
+        }
+
+        for (address, value) in &self.memory {
+            processor.memory_mut()?.put_u64(*address, *value);
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// A path through the program, with an input taking it.
+pub struct Path {
+    /// Input taking this path from a processor with every other register and memory zeroed.
+    pub input: Input,
+    /// Conditional jumps on the path, in order.
+    pub branches: Vec<Branch>,
+    /// How the path ends.
+    pub end: End,
+    /// Registers when the path ends, for the input.
+    pub registers: [u64; 16],
+}
+
+#[derive(Debug, Clone, Default)]
+/// Every path explored by a [`SymbolicExecutor`].
+pub struct Exploration {
+    paths: Vec<Path>,
+    complete: bool,
+}
+
+impl Exploration {
+    #[must_use]
+    /// Returns the paths explored, in the order they ended.
+    pub fn paths(&self) -> &[Path] {
+        &self.paths
+    }
+
+    #[must_use]
+    /// Returns whether every feasible path was explored, rather than stopping at the path limit.
+    pub fn complete(&self) -> bool {
+        self.complete
+    }
+
+    #[must_use]
+    /// Returns the first input taking each branch.
+    pub fn branches(&self) -> BTreeMap<Branch, &Input> {
+        let mut branches = BTreeMap::new();
+
+        for path in &self.paths {
+            for branch in &path.branches {
+                branches.entry(*branch).or_insert(&path.input);
+            }
+        }
+
+        branches
+    }
+
+    /// Returns the paths ending in a trap.
+    pub fn traps(&self) -> impl Iterator<Item = &Path> {
+        self.paths
+            .iter()
+            .filter(|path| matches!(path.end, End::Trap(..)))
+    }
+}
+
+#[derive(Debug, Clone)]
+/// Builder for a symbolic execution of a program.
+pub struct SymbolicExecutor {
+    registers: Vec<usize>,
+    memory: Vec<usize>,
+    max_steps: u64,
+    max_paths: usize,
+}
+
+impl Default for SymbolicExecutor {
+    fn default() -> Self {
+        SymbolicExecutor {
+            registers: Vec::new(),
+            memory: Vec::new(),
+            max_steps: 10_000,
+            max_paths: 256,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+/// A processor over expressions, partway along a path.
+struct State {
+    registers: [Expr; 16],
+    counter: u64,
+    /// Bytes written or made symbolic, all others are zero.
+    memory: BTreeMap<usize, Expr>,
+    constraints: Vec<Expr>,
+    branches: Vec<Branch>,
+    steps: u64,
+}
+
+/// How executing a single instruction went.
+enum Step {
+    Next,
+    /// The path forks on a flag, taking the jump when the expression is nonzero.
+    Fork(Expr),
+    End(End),
+}
+
+impl State {
+    fn register(&self, width: &Width) -> Result<Expr, Error> {
+        match (width.index(), width.size()) {
+            (index, _) if index > COUNTER => Err(Error::RegisterIndexOutOfBounds),
+            (COUNTER, size) => Ok(Expr::truncate(&Expr::Constant(self.counter), size)),
+            (index, size) => Ok(Expr::truncate(&self.registers[index], size)),
+        }
+    }
+
+    fn set_register(&mut self, width: &Width, value: &Expr) -> Result<(), Error> {
+        let (index, size) = (width.index(), width.size());
+
+        if index > COUNTER {
+            return Err(Error::RegisterIndexOutOfBounds);
+        }
+
+        let old = match index {
+            COUNTER => Expr::Constant(self.counter),
+            index => self.registers[index].clone(),
+        };
+        let kept = Expr::and(&old, &Expr::Constant(!mask(size)));
+        let new = Expr::or(&kept, &Expr::truncate(value, size));
+
+        match index {
+            COUNTER => self.counter = self.concretize(&new),
+            index => self.registers[index] = new,
+        }
+
+        Ok(())
+    }
+
+    fn load(&self, address: usize, size: usize) -> Expr {
+        (0..size).fold(Expr::Constant(0), |value, byte| {
+            let stored = match self.memory.get(&address.wrapping_add(byte)) {
+                Some(stored) => stored.clone(),
+                None => Expr::Constant(0),
+            };
+
+            Expr::or(&value, &Expr::shl(&stored, byte as u32 * 8))
+        })
+    }
+
+    fn store(&mut self, address: usize, size: usize, value: &Expr) {
+        for byte in 0..size {
+            let stored = Expr::truncate(&Expr::shr(value, byte as u32 * 8), 1);
+
+            self.memory.insert(address.wrapping_add(byte), stored);
+        }
+    }
+
+    /// Returns the address of the given memory operand, with the access size.
+    fn address(&mut self, operand: &Operand) -> Result<Option<(usize, usize)>, Error> {
+        match operand {
+            Operand::Memory(width) => Ok(Some((width.index(), width.size()))),
+            Operand::MemoryRegister(width) => {
+                let address = self.register(width)?;
+
+                Ok(Some((self.concretize(&address) as usize, width.size())))
+            }
+
+            _ => Ok(None),
+        }
+    }
+
+    /// Reads a source operand.
+    fn read(&mut self, operand: &Operand) -> Result<Expr, Error> {
+        match operand {
+            Operand::Value(value) => Ok(Expr::Constant(*value)),
+            Operand::Register(width) => self.register(width),
+            Operand::None => Err(Error::InvalidOperand),
+            operand => {
+                let (address, size) = self.address(operand)?.ok_or(Error::InvalidOperand)?;
+
+                Ok(self.load(address, size))
+            }
+        }
+    }
+
+    /// Writes through a destination operand.
+    fn write(&mut self, operand: &Operand, value: &Expr) -> Result<(), Error> {
+        match operand {
+            Operand::Register(width) => self.set_register(width, value),
+            Operand::Memory(_) | Operand::MemoryRegister(_) => {
+                let (address, size) = self.address(operand)?.ok_or(Error::InvalidOperand)?;
+
+                self.store(address, size, value);
+                Ok(())
+            }
+
+            _ => Err(Error::InvalidOperand),
+        }
+    }
+
+    /// Returns a value the given expression may take on this path, constraining it to that value.
+    fn concretize(&mut self, expr: &Expr) -> u64 {
+        if let Some(value) = expr.constant() {
+            return value;
+        }
+
+        let model = solve(&self.constraints).unwrap_or_default();
+        let value = expr.eval(&model);
+
+        self.constraints
+            .push(Expr::eq(expr, &Expr::Constant(value)));
+
+        value
+    }
+
+    fn flag(&self, flag: Flag) -> Expr {
+        Expr::and(&self.registers[FLAGS], &Expr::Constant(flag as u64))
+    }
+
+    /// Executes the given instruction, up to a fork or the end of the path.
+    fn execute(&mut self, index: usize, instruction: &Instruction) -> Result<Step, Error> {
+        match instruction {
+            Instruction::Call(call_index) => {
+                let call_index = match call_index {
+                    Operand::Value(_) | Operand::Register(_) => self.read(call_index)?,
+
+                    _ => return Err(Error::InvalidOperand),
+                };
+
+                match self.concretize(&call_index) {
+                    0 => {}
+                    call_index => return Ok(Step::End(End::Call(index, call_index))),
+                }
+            }
+            Instruction::Mov(source, destination) => {
+                let source = self.read(source)?;
+
+                self.write(destination, &source)?;
+            }
+            Instruction::Jmp(source) => self.jump(source)?,
+            Instruction::Jz(_) => return Ok(Step::Fork(self.flag(Flag::Zero))),
+            Instruction::Jnz(_) => return Ok(Step::Fork(Expr::not(&self.flag(Flag::Zero)))),
+            Instruction::Cmp(value, comparator) => {
+                let value = self.read(value)?;
+                let comparator = self.read(comparator)?;
+                let kept = Expr::and(&self.registers[FLAGS], &Expr::Constant(!3));
+                let zero = Expr::eq(&value, &comparator);
+                let greater = Expr::shl(&Expr::ugt(&value, &comparator), 1);
+
+                self.registers[FLAGS] = Expr::or(&Expr::or(&kept, &zero), &greater);
+            }
+            Instruction::Add(value, source, destination) => {
+                let source = self.read(source)?;
+                let value = self.read(value)?;
+                let overflow = Expr::shl(&Expr::carry(&source, &value), 2);
+
+                self.registers[FLAGS] = Expr::or(&self.registers[FLAGS], &overflow);
+                self.write(destination, &Expr::add(&source, &value))?;
+            }
+        }
+
+        Ok(Step::Next)
+    }
+
+    /// Sets the instruction counter to the given jump operand.
+    fn jump(&mut self, source: &Operand) -> Result<(), Error> {
+        let target = match source {
+            Operand::None => return Err(Error::InvalidOperand),
+            source => self.read(source)?,
+        };
+
+        self.counter = self.concretize(&target);
+
+        Ok(())
+    }
+
+    /// Takes the conditional jump at the given index.
+    fn take(&mut self, instruction: &Instruction) -> Result<(), Error> {
+        match instruction {
+            Instruction::Jz(source) => {
+                let cleared = Expr::and(
+                    &self.registers[FLAGS],
+                    &Expr::Constant(!(Flag::Zero as u64)),
+                );
+
+                self.registers[FLAGS] = cleared;
+                self.jump(source)
+            }
+            Instruction::Jnz(source) => self.jump(source),
+
+            _ => Ok(()),
+        }
+    }
+}
+
+impl SymbolicExecutor {
+    #[must_use]
+    /// Constructs a new [`SymbolicExecutor`] with nothing symbolic.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    #[must_use]
+    /// Makes the register at the given index symbolic, unless it is the instruction counter or
+    /// past the last register.
+    pub fn register(mut self, index: usize) -> Self {
+        if index < COUNTER {
+            self.registers.push(index);
+        }
+
+        self
+    }
+
+    #[must_use]
+    /// Makes the 8 bytes at the given address a symbolic memory cell.
+    pub fn memory(mut self, address: usize) -> Self {
+        self.memory.push(address);
+        self
+    }
+
+    #[must_use]
+    /// Sets the instructions a path may execute before ending, 10 000 by default.
+    pub fn max_steps(mut self, steps: u64) -> Self {
+        self.max_steps = steps;
+        self
+    }
+
+    #[must_use]
+    /// Sets the paths to explore before giving up, 256 by default.
+    pub fn max_paths(mut self, paths: usize) -> Self {
+        self.max_paths = paths;
+        self
+    }
+
+    #[must_use]
+    /// Explores the paths of the given program from index 0, depth first.
+    ///
+    /// # Example
+    /// ```
+    /// use vm::assembler::Assembler;
+    /// use vm::symbolic::{Branch, SymbolicExecutor};
+    /// let assembler = Assembler::parse("add rq0, 7, rq1\ncmp rq1, 50\njz hit\nmov 0, rq2\nhit:").unwrap();
+    /// let exploration = SymbolicExecutor::new().register(0).explore(assembler.instructions());
+    /// let branches = exploration.branches();
+    /// assert_eq!(branches[&Branch { index: 2, taken: true }].registers[&0], 43);
+    /// assert_eq!(exploration.paths().len(), 2);
+    /// ```
+    pub fn explore(&self, instructions: &[Instruction]) -> Exploration {
+        let symbols = self.registers.len();
+        let mut state = State {
+            registers: std::array::from_fn(|_| Expr::Constant(0)),
+            counter: 0,
+            memory: BTreeMap::new(),
+            constraints: Vec::new(),
+            branches: Vec::new(),
+            steps: 0,
+        };
+
+        for (symbol, index) in self.registers.iter().enumerate() {
+            if let Some(register) = state
+                .registers
+                .get_mut(*index)
+                .filter(|_| *index != COUNTER)
+            {
+                *register = Expr::Symbol(symbol);
+            }
+        }
+
+        for (symbol, address) in self.memory.iter().enumerate() {
+            state.store(*address, 8, &Expr::Symbol(symbols + symbol));
+        }
+
+        let mut exploration = Exploration::default();
+        let mut pending = vec![state];
+
+        while let Some(mut state) = pending.pop() {
+            if exploration.paths.len() >= self.max_paths {
+                return exploration;
+            }
+
+            // Ends with [`None`] when the path can't continue past a jump.
+            let end = loop {
+                let Some(instruction) = usize::try_from(state.counter)
+                    .ok()
+                    .and_then(|index| instructions.get(index))
+                else {
+                    break Some(End::Exit);
+                };
+
+                if state.steps >= self.max_steps {
+                    break Some(End::StepLimit);
+                }
+
+                let index = state.counter as usize;
+
+                state.steps += 1;
+
+                match state.execute(index, instruction) {
+                    Ok(Step::Next) => {}
+                    Ok(Step::End(end)) => break Some(end),
+                    Ok(Step::Fork(condition)) => {
+                        let mut taken = state.clone();
+
+                        let (take, skip) = match condition.constant() {
+                            Some(value) => (value != 0, value == 0),
+                            None => {
+                                taken.constraints.push(condition.clone());
+                                state.constraints.push(Expr::not(&condition));
+
+                                (
+                                    solve(&taken.constraints).is_some(),
+                                    solve(&state.constraints).is_some(),
+                                )
+                            }
+                        };
+
+                        if take {
+                            taken.branches.push(Branch { index, taken: true });
+
+                            match taken.take(instruction) {
+                                Ok(()) => {
+                                    taken.counter = taken.counter.wrapping_add(1);
+                                    pending.push(taken);
+                                }
+                                Err(error) => exploration.paths.push(self.finish(
+                                    &taken,
+                                    End::Trap(index, error),
+                                    symbols,
+                                )),
+                            }
+                        }
+
+                        if !skip {
+                            break None;
+                        }
+
+                        state.branches.push(Branch {
+                            index,
+                            taken: false,
+                        });
+                    }
+                    Err(error) => break Some(End::Trap(index, error)),
+                }
+
+                state.counter = state.counter.wrapping_add(1);
+            };
+
+            if let Some(end) = end {
+                exploration.paths.push(self.finish(&state, end, symbols));
+            }
+        }
+
+        exploration.complete = true;
+        exploration
+    }
+
+    /// Solves the constraints of the given state into a [`Path`].
+    fn finish(&self, state: &State, end: End, symbols: usize) -> Path {
+        let model = solve(&state.constraints).unwrap_or_default();
+        let value = |symbol: usize| model.get(&symbol).copied().unwrap_or(0);
+
+        let mut registers = std::array::from_fn(|index| state.registers[index].eval(&model));
+
+        registers[COUNTER] = state.counter;
+
+        Path {
+            input: Input {
+                registers: self
+                    .registers
+                    .iter()
+                    .enumerate()
+                    .map(|(symbol, index)| (*index, value(symbol)))
+                    .collect(),
+                memory: self
+                    .memory
+                    .iter()
+                    .enumerate()
+                    .map(|(symbol, address)| (*address, value(symbols + symbol)))
+                    .collect(),
+            },
+            branches: state.branches.clone(),
+            end,
+            registers,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::assembler::Assembler;
+    use crate::error::Error;
+    use crate::instructions::{Instruction, Operand};
+    use crate::symbolic::{Branch, End, Path, SymbolicExecutor};
+    use crate::Vm;
+
+    /// Runs the given program on the given path's input, returning the error and every register.
+    fn replay(instructions: &[Instruction], path: &Path) -> (Option<Error>, Vec<u64>) {
+        let mut vm = Vm::new();
+
+        vm.load_instructions(
+            instructions
+                .iter()
+                .cloned()
+                .map(Instruction::executable)
+                .collect(),
+        )
+        .unwrap();
+
+        let handle = vm.new_processor();
+        let processor = vm.processor_mut(handle).unwrap();
+
+        path.input.apply(processor).unwrap();
+
+        let error = processor.start().err();
+        let registers = (0..16)
+            .map(|index| processor.register(index).unwrap().as_u64())
+            .collect();
+
+        (error, registers)
+    }
+
+    #[test]
+    pub fn symbolic_reaches_branches_and_traps() {
+        let mut instructions = Assembler::parse(
+            "add rq0, mq64, rq1\n\
+             cmp rq1, 1000\n\
+             jz big\n\
+             cmp rb0, 7\n\
+             jnz end\n\
+             mov 5, rq2\n\
+             jmp end\n\
+             big: add 1, rq3, rq3\n\
+             mov 0, rq9\n\
+             end:",
+        )
+        .unwrap()
+        .instructions()
+        .to_vec();
+
+        instructions[8] = Instruction::Mov(Operand::Value(1), Operand::Value(2));
+
+        let exploration = SymbolicExecutor::new()
+            .register(0)
+            .memory(64)
+            .explore(&instructions);
+
+        assert!(exploration.complete());
+        assert_eq!(exploration.paths().len(), 3);
+        assert_eq!(exploration.branches().len(), 4);
+
+        let trap = exploration.traps().next().unwrap();
+
+        assert_eq!(trap.end, End::Trap(8, Error::InvalidOperand));
+        assert_eq!(
+            trap.input.registers[&0].wrapping_add(trap.input.memory[&64]),
+            1000
+        );
+
+        let seven = exploration.branches()[&Branch {
+            index: 4,
+            taken: false,
+        }];
+
+        assert_eq!(seven.registers[&0] & 0xff, 7);
+
+        for path in exploration.paths() {
+            let error = match &path.end {
+                End::Trap(_, error) => Some(error.clone()),
+                _ => None,
+            };
+
+            assert_eq!(
+                replay(&instructions, path),
+                (error, path.registers.to_vec())
+            );
+        }
+    }
+
+    #[test]
+    pub fn symbolic_path_limit() {
+        let instructions = Assembler::parse(
+            "loop: add 1, rq1, rq1\n\
+             cmp rq1, rq0\n\
+             jnz loop",
+        )
+        .unwrap()
+        .instructions()
+        .to_vec();
+
+        let exploration = SymbolicExecutor::new()
+            .register(0)
+            .max_paths(5)
+            .explore(&instructions);
+        let counts: Vec<u64> = exploration
+            .paths()
+            .iter()
+            .map(|path| path.input.registers[&0])
+            .collect();
+
+        assert!(!exploration.complete());
+        assert_eq!(counts, [1, 2, 3, 4, 5]);
+
+        for path in exploration.paths() {
+            assert_eq!(replay(&instructions, path), (None, path.registers.to_vec()));
+        }
+    }
+
+    #[test]
+    pub fn symbolic_ignores_counter_and_unknown_registers() {
+        let instructions = Assembler::parse("cmp rq0, 3\njz end\nmov 1, rq1\nend:")
+            .unwrap()
+            .instructions()
+            .to_vec();
+
+        let exploration = SymbolicExecutor::new()
+            .register(0)
+            .register(15)
+            .register(16)
+            .explore(&instructions);
+
+        assert!(exploration.complete());
+        assert_eq!(exploration.paths().len(), 2);
+
+        for path in exploration.paths() {
+            assert!(path.input.registers.keys().eq([&0]));
+            assert_eq!(replay(&instructions, path), (None, path.registers.to_vec()));
+        }
+    }
+}
@@ -0,0 +1,550 @@
+//! Bit-vector solver, blasting [`Expr`]s into clauses for a small CDCL SAT solver.
+//!
+//! Decisions are only made on bits of symbols, lowest symbol and bit first and false first, since
+//! every other bit follows from them. Models therefore lean towards small values.
+
+use crate::symbolic::expr::Expr;
+
+use std::collections::{BTreeMap, HashMap};
+use std::rc::Rc;
+
+/// A variable, or its negation in the lowest bit.
+type Lit = u32;
+
+/// Literal of the variable fixed to true.
+const TRUE: Lit = 0;
+const FALSE: Lit = 1;
+
+fn var(lit: Lit) -> usize {
+    (lit >> 1) as usize
+}
+
+fn neg(lit: Lit) -> Lit {
+    lit ^ 1
+}
+
+/// Returns values for the symbols of the given conditions making every condition nonzero, [`None`]
+/// when no values do.
+///
+/// # Example
+/// ```
+/// use vm::symbolic::expr::Expr;
+/// use vm::symbolic::solver::solve;
+/// let x = Expr::Symbol(0);
+/// let sum = Expr::add(&x, &Expr::Constant(5));
+/// let model = solve(&[Expr::eq(&sum, &Expr::Constant(100))]).unwrap();
+/// assert_eq!(model[&0], 95);
+/// assert!(solve(&[Expr::eq(&x, &Expr::Constant(1)), Expr::eq(&x, &Expr::Constant(2))]).is_none());
+/// ```
+#[must_use]
+pub fn solve(conditions: &[Expr]) -> Option<BTreeMap<usize, u64>> {
+    let mut blaster = Blaster::default();
+
+    blaster.sat.new_var();
+    blaster.sat.add_clause(vec![TRUE]);
+
+    for condition in conditions {
+        let bits = blaster.blast(condition);
+        let nonzero = bits.iter().fold(FALSE, |any, bit| blaster.or(any, *bit));
+
+        blaster.sat.add_clause(vec![nonzero]);
+    }
+
+    if !blaster.sat.solve(blaster.decidable) {
+        return None;
+    }
+
+    Some(
+        blaster
+            .symbols
+            .iter()
+            .map(|(symbol, bits)| {
+                let value = bits.iter().enumerate().fold(0, |value, (bit, lit)| {
+                    value | u64::from(blaster.sat.value(*lit) == Some(true)) << bit
+                });
+
+                (*symbol, value)
+            })
+            .collect(),
+    )
+}
+
+#[derive(Default)]
+/// Translates expressions into gates, one literal per bit, lowest bit first.
+struct Blaster {
+    sat: Sat,
+    symbols: BTreeMap<usize, Vec<Lit>>,
+    /// Bits of shared subexpressions, by address.
+    cache: HashMap<*const Expr, Vec<Lit>>,
+    /// Variables below this one include the bits of every symbol.
+    decidable: usize,
+}
+
+impl Blaster {
+    fn and(&mut self, left: Lit, right: Lit) -> Lit {
+        match (left, right) {
+            (FALSE, _) | (_, FALSE) => FALSE,
+            (TRUE, other) | (other, TRUE) => other,
+            _ if left == right => left,
+            _ if left == neg(right) => FALSE,
+            _ => {
+                let out = self.sat.new_var();
+
+                self.sat.add_clause(vec![neg(out), left]);
+                self.sat.add_clause(vec![neg(out), right]);
+                self.sat.add_clause(vec![out, neg(left), neg(right)]);
+
+                out
+            }
+        }
+    }
+
+    fn or(&mut self, left: Lit, right: Lit) -> Lit {
+        neg(self.and(neg(left), neg(right)))
+    }
+
+    fn xor(&mut self, left: Lit, right: Lit) -> Lit {
+        match (left, right) {
+            (FALSE, other) | (other, FALSE) => other,
+            (TRUE, other) | (other, TRUE) => neg(other),
+            _ if left == right => FALSE,
+            _ if left == neg(right) => TRUE,
+            _ => {
+                let out = self.sat.new_var();
+
+                self.sat.add_clause(vec![neg(out), left, right]);
+                self.sat.add_clause(vec![neg(out), neg(left), neg(right)]);
+                self.sat.add_clause(vec![out, neg(left), right]);
+                self.sat.add_clause(vec![out, left, neg(right)]);
+
+                out
+            }
+        }
+    }
+
+    /// Returns the sum bits of both sides, and the carry out of the highest bit.
+    fn adder(&mut self, left: &[Lit], right: &[Lit]) -> (Vec<Lit>, Lit) {
+        let mut carry = FALSE;
+        let mut sum = Vec::with_capacity(64);
+
+        for (left, right) in left.iter().zip(right) {
+            let half = self.xor(*left, *right);
+
+            sum.push(self.xor(half, carry));
+
+            let both = self.and(*left, *right);
+            let carried = self.and(half, carry);
+
+            carry = self.or(both, carried);
+        }
+
+        (sum, carry)
+    }
+
+    fn constant(value: u64) -> Vec<Lit> {
+        (0..64)
+            .map(|bit| if value >> bit & 1 == 1 { TRUE } else { FALSE })
+            .collect()
+    }
+
+    /// Returns the given bit as a 64-bit value.
+    fn flag(bit: Lit) -> Vec<Lit> {
+        let mut bits = vec![FALSE; 64];
+
+        bits[0] = bit;
+        bits
+    }
+
+    fn blast_shared(&mut self, expr: &Rc<Expr>) -> Vec<Lit> {
+        let key = Rc::as_ptr(expr);
+
+        if let Some(bits) = self.cache.get(&key) {
+            return bits.clone();
+        }
+
+        let bits = self.blast(expr);
+
+        self.cache.insert(key, bits.clone());
+        bits
+    }
+
+    fn blast(&mut self, expr: &Expr) -> Vec<Lit> {
+        match expr {
+            Expr::Constant(value) => Self::constant(*value),
+            Expr::Symbol(symbol) => {
+                if let Some(bits) = self.symbols.get(symbol) {
+                    return bits.clone();
+                }
+
+                let bits: Vec<Lit> = (0..64).map(|_| self.sat.new_var()).collect();
+
+                self.decidable = self.sat.assignment.len();
+                self.symbols.insert(*symbol, bits.clone());
+                bits
+            }
+            Expr::Add(left, right) => {
+                let (left, right) = (self.blast_shared(left), self.blast_shared(right));
+
+                self.adder(&left, &right).0
+            }
+            Expr::And(left, right) => {
+                let (left, right) = (self.blast_shared(left), self.blast_shared(right));
+
+                left.iter()
+                    .zip(&right)
+                    .map(|(left, right)| self.and(*left, *right))
+                    .collect()
+            }
+            Expr::Or(left, right) => {
+                let (left, right) = (self.blast_shared(left), self.blast_shared(right));
+
+                left.iter()
+                    .zip(&right)
+                    .map(|(left, right)| self.or(*left, *right))
+                    .collect()
+            }
+            Expr::Shl(expr, bits) => {
+                let expr = self.blast_shared(expr);
+                let bits = *bits as usize;
+                let mut shifted = vec![FALSE; bits];
+
+                shifted.extend_from_slice(&expr[..64 - bits]);
+                shifted
+            }
+            Expr::Shr(expr, bits) => {
+                let expr = self.blast_shared(expr);
+                let mut shifted = expr[*bits as usize..].to_vec();
+
+                shifted.resize(64, FALSE);
+                shifted
+            }
+            Expr::Eq(left, right) => {
+                let (left, right) = (self.blast_shared(left), self.blast_shared(right));
+                let mut equal = TRUE;
+
+                for (left, right) in left.iter().zip(&right) {
+                    let differ = self.xor(*left, *right);
+
+                    equal = self.and(equal, neg(differ));
+                }
+
+                Self::flag(equal)
+            }
+            Expr::Ugt(left, right) => {
+                let (left, right) = (self.blast_shared(left), self.blast_shared(right));
+                let mut greater = FALSE;
+
+                // From the lowest bit up, a higher differing bit decides.
+                for (left, right) in left.iter().zip(&right) {
+                    let above = self.and(*left, neg(*right));
+                    let differ = self.xor(*left, *right);
+                    let kept = self.and(neg(differ), greater);
+
+                    greater = self.or(above, kept);
+                }
+
+                Self::flag(greater)
+            }
+            Expr::Carry(left, right) => {
+                let (left, right) = (self.blast_shared(left), self.blast_shared(right));
+
+                Self::flag(self.adder(&left, &right).1)
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+/// Conflict-driven clause learning over two watched literals per clause.
+struct Sat {
+    clauses: Vec<Vec<Lit>>,
+    /// Clauses watching each literal, visited when it becomes false.
+    watches: Vec<Vec<usize>>,
+    assignment: Vec<Option<bool>>,
+    level: Vec<usize>,
+    reason: Vec<Option<usize>>,
+    trail: Vec<Lit>,
+    /// Length of the trail at each decision.
+    decisions: Vec<usize>,
+    propagated: usize,
+    unsatisfiable: bool,
+}
+
+impl Sat {
+    fn new_var(&mut self) -> Lit {
+        let var = self.assignment.len() as Lit;
+
+        self.assignment.push(None);
+        self.level.push(0);
+        self.reason.push(None);
+        self.watches.extend([Vec::new(), Vec::new()]);
+
+        var << 1
+    }
+
+    fn value(&self, lit: Lit) -> Option<bool> {
+        self.assignment[var(lit)].map(|value| value ^ (lit & 1 == 1))
+    }
+
+    fn assign(&mut self, lit: Lit, reason: Option<usize>) {
+        self.assignment[var(lit)] = Some(lit & 1 == 0);
+        self.level[var(lit)] = self.decisions.len();
+        self.reason[var(lit)] = reason;
+        self.trail.push(lit);
+    }
+
+    /// Adds a clause before solving.
+    fn add_clause(&mut self, mut clause: Vec<Lit>) {
+        clause.sort_unstable();
+        clause.dedup();
+
+        if clause.windows(2).any(|pair| pair[0] == neg(pair[1])) {
+            return;
+        }
+
+        match clause[..] {
+            [] => self.unsatisfiable = true,
+            [lit] => match self.value(lit) {
+                Some(true) => {}
+                Some(false) => self.unsatisfiable = true,
+                None => self.assign(lit, None),
+            },
+            _ => {
+                self.watch(clause);
+            }
+        }
+    }
+
+    fn watch(&mut self, clause: Vec<Lit>) -> usize {
+        let index = self.clauses.len();
+
+        self.watches[clause[0] as usize].push(index);
+        self.watches[clause[1] as usize].push(index);
+        self.clauses.push(clause);
+
+        index
+    }
+
+    /// Propagates every unit clause, returning a conflicting clause if any.
+    fn propagate(&mut self) -> Option<usize> {
+        while self.propagated < self.trail.len() {
+            let falsified = neg(self.trail[self.propagated]);
+            let mut watching = std::mem::take(&mut self.watches[falsified as usize]);
+            let mut kept = 0;
+
+            self.propagated += 1;
+
+            for position in 0..watching.len() {
+                let index = watching[position];
+                let clause = &mut self.clauses[index];
+
+                if clause[0] == falsified {
+                    clause.swap(0, 1);
+                }
+
+                let first = clause[0];
+
+                if self.assignment[var(first)].map(|value| value ^ (first & 1 == 1)) == Some(true) {
+                    watching[kept] = index;
+                    kept += 1;
+                    continue;
+                }
+
+                let replacement = (2..clause.len()).find(|&other| {
+                    let lit = clause[other];
+
+                    self.assignment[var(lit)].map(|value| value ^ (lit & 1 == 1)) != Some(false)
+                });
+
+                if let Some(other) = replacement {
+                    clause.swap(1, other);
+
+                    let lit = clause[1];
+
+                    self.watches[lit as usize].push(index);
+                    continue;
+                }
+
+                watching[kept] = index;
+                kept += 1;
+
+                if self.value(first) == Some(false) {
+                    watching.copy_within(position + 1.., kept);
+                    watching.truncate(kept + watching.len() - position - 1);
+                    self.watches[falsified as usize] = watching;
+
+                    return Some(index);
+                }
+
+                self.assign(first, Some(index));
+            }
+
+            watching.truncate(kept);
+            self.watches[falsified as usize] = watching;
+        }
+
+        None
+    }
+
+    /// Returns the first unique implication point clause of a conflict, and the level to return to.
+    fn analyze(&mut self, conflict: usize) -> (Vec<Lit>, usize) {
+        let mut seen = vec![false; self.assignment.len()];
+        let mut learnt = vec![TRUE];
+        let mut pending = 0;
+        let mut clause = conflict;
+        let mut index = self.trail.len();
+        let mut implied = None;
+
+        loop {
+            let skip = usize::from(implied.is_some());
+
+            for &lit in &self.clauses[clause][skip..] {
+                let var = var(lit);
+
+                if seen[var] || self.level[var] == 0 {
+                    continue;
+                }
+
+                seen[var] = true;
+
+                if self.level[var] == self.decisions.len() {
+                    pending += 1;
+                } else {
+                    learnt.push(lit);
+                }
+            }
+
+            loop {
+                index -= 1;
+
+                if seen[var(self.trail[index])] {
+                    break;
+                }
+            }
+
+            let lit = self.trail[index];
+
+            implied = Some(lit);
+            seen[var(lit)] = false;
+            pending -= 1;
+
+            if pending == 0 {
+                learnt[0] = neg(lit);
+                break;
+            }
+
+            clause = self.reason[var(lit)].expect("only decisions lack a reason");
+        }
+
+        let mut level = 0;
+
+        for position in 1..learnt.len() {
+            if self.level[var(learnt[position])] > level {
+                level = self.level[var(learnt[position])];
+                learnt.swap(1, position);
+            }
+        }
+
+        (learnt, level)
+    }
+
+    fn backtrack(&mut self, level: usize) {
+        let Some(&length) = self.decisions.get(level) else {
+            return;
+        };
+
+        for lit in self.trail.drain(length..) {
+            self.assignment[var(lit)] = None;
+            self.reason[var(lit)] = None;
+        }
+
+        self.decisions.truncate(level);
+        self.propagated = length;
+    }
+
+    /// Searches for an assignment, deciding only variables below the given one.
+    fn solve(&mut self, decidable: usize) -> bool {
+        if self.unsatisfiable {
+            return false;
+        }
+
+        loop {
+            if let Some(conflict) = self.propagate() {
+                if self.decisions.is_empty() {
+                    return false;
+                }
+
+                let (learnt, level) = self.analyze(conflict);
+
+                self.backtrack(level);
+
+                let asserting = learnt[0];
+                let reason = (learnt.len() > 1).then(|| self.watch(learnt));
+
+                self.assign(asserting, reason);
+                continue;
+            }
+
+            let next = (0..decidable).find(|var| self.assignment[*var].is_none());
+
+            // Symbols fixed, propagation leaves no gate open; any left are unconstrained.
+            let next = next.or_else(|| {
+                (decidable..self.assignment.len()).find(|var| self.assignment[*var].is_none())
+            });
+
+            let Some(var) = next else {
+                return true;
+            };
+
+            self.decisions.push(self.trail.len());
+            self.assign((var as Lit) << 1 | 1, None);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::symbolic::expr::Expr;
+    use crate::symbolic::solver::solve;
+
+    #[test]
+    pub fn solver_bit_vectors() {
+        let x = Expr::Symbol(0);
+        let y = Expr::Symbol(1);
+        let constant = Expr::Constant;
+
+        // x + y == 10 and x > y and y > 3
+        let conditions = [
+            Expr::eq(&Expr::add(&x, &y), &constant(10)),
+            Expr::ugt(&x, &y),
+            Expr::ugt(&y, &constant(3)),
+        ];
+        let model = solve(&conditions).unwrap();
+
+        assert!(conditions
+            .iter()
+            .all(|condition| condition.eval(&model) == 1));
+
+        // Sums wrap, and the carry reports it.
+        let conditions = [
+            Expr::carry(&x, &constant(16)),
+            Expr::eq(&Expr::and(&x, &constant(0xff)), &constant(0xf3)),
+        ];
+        let model = solve(&conditions).unwrap();
+
+        assert_eq!(model[&0], u64::MAX - 12);
+
+        let byte = Expr::and(&Expr::shr(&x, 8), &constant(0xff));
+        let conditions = [
+            Expr::eq(
+                &Expr::or(&Expr::shl(&byte, 4), &constant(1)),
+                &constant(0xab1),
+            ),
+            Expr::not(&Expr::ugt(&x, &constant(0xffff))),
+        ];
+        let model = solve(&conditions).unwrap();
+
+        assert_eq!(model[&0] >> 8, 0xab);
+        assert!(solve(&[Expr::ugt(&x, &y), Expr::ugt(&y, &x)]).is_none());
+    }
+}
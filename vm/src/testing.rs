@@ -0,0 +1,10 @@
+//! Helpers shared by the unit tests of several modules.
+
+/// Linear congruential generator, enough to vary the generated programs deterministically.
+pub(crate) fn random(seed: &mut u64, bound: u64) -> u64 {
+    *seed = seed
+        .wrapping_mul(6_364_136_223_846_793_005)
+        .wrapping_add(1_442_695_040_888_963_407);
+
+    (*seed >> 33) % bound
+}
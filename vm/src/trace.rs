@@ -0,0 +1,539 @@
+use crate::error::Error;
+use crate::instructions::Instruction;
+use crate::register::{Register, ReservedIndex};
+
+use std::collections::VecDeque;
+use std::fmt::{Debug, Formatter};
+use std::io::Write;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// A write into a [`Register`] made by a single instruction.
+pub struct RegisterWrite {
+    pub index: usize,
+    pub old: u64,
+    pub new: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// A read from or a write into memory made by a single instruction.
+pub struct MemoryAccess {
+    pub address: usize,
+    pub size: usize,
+    pub value: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// The value of the [`Flags`](ReservedIndex::Flags) register before and after an instruction.
+pub struct FlagChange {
+    pub old: u64,
+    pub new: u64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+/// Everything observed while executing the instruction at [`index`](Step::index).
+pub struct Step {
+    pub index: usize,
+    pub instruction: Option<Instruction>,
+
+    pub register_writes: Vec<RegisterWrite>,
+    pub memory_reads: Vec<MemoryAccess>,
+    pub memory_writes: Vec<MemoryAccess>,
+    pub flags: Option<FlagChange>,
+}
+
+/// Destination for the [`Step`]s recorded by a [`Tracer`].
+pub trait Sink: Debug {
+    /// Records a completed [`Step`].
+    ///
+    /// # Errors
+    /// When the underlying writer fails, [`TraceFailed`](Error::TraceFailed) is returned.
+    fn record(&mut self, step: &Step) -> Result<(), Error>;
+
+    /// Records the [`Step`] which trapped with the given [`Error`].
+    ///
+    /// # Errors
+    /// When the underlying writer fails, [`TraceFailed`](Error::TraceFailed) is returned.
+    fn trap(&mut self, step: &Step, error: &Error) -> Result<(), Error> {
+        _ = error;
+
+        self.record(step)
+    }
+}
+
+#[derive(Debug, Default)]
+/// Collects the effects of each executed instruction and hands them to its [`Sink`]s.
+pub struct Tracer {
+    sinks: Vec<Box<dyn Sink>>,
+
+    step: Step,
+    registers: [u64; 16],
+    touched: u16,
+}
+
+impl Tracer {
+    #[must_use]
+    /// Constructs a new [`Tracer`] without any [`Sink`].
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    #[must_use]
+    /// Consumes [`self`](Tracer) pushing a new [`Sink`] into self.
+    pub fn sink(mut self, sink: impl Sink + 'static) -> Self {
+        self.sinks.push(Box::new(sink));
+
+        self
+    }
+
+    pub(crate) fn begin(
+        &mut self,
+        index: usize,
+        instruction: Option<Instruction>,
+        registers: &[Register; 16],
+    ) {
+        self.step = Step {
+            index,
+            instruction,
+            ..Step::default()
+        };
+        self.touched = 0;
+
+        for (value, register) in self.registers.iter_mut().zip(registers) {
+            *value = register.as_u64();
+        }
+    }
+
+    pub(crate) fn touch(&mut self, index: usize) {
+        self.touched |= 1 << index;
+    }
+
+    pub(crate) fn read(&mut self, address: usize, size: usize, value: u64) {
+        self.step.memory_reads.push(MemoryAccess {
+            address,
+            size,
+            value,
+        });
+    }
+
+    pub(crate) fn write(&mut self, address: usize, size: usize, value: u64) {
+        self.step.memory_writes.push(MemoryAccess {
+            address,
+            size,
+            value,
+        });
+    }
+
+    /// Finishes the current [`Step`] by diffing every touched [`Register`].
+    fn finish(&mut self, registers: &[Register; 16]) {
+        for (index, register) in registers.iter().enumerate() {
+            if self.touched & (1 << index) == 0 {
+                continue;
+            }
+
+            let old = self.registers[index];
+            let new = register.as_u64();
+
+            if index == ReservedIndex::Flags as usize {
+                if old != new {
+                    self.step.flags = Some(FlagChange { old, new });
+                }
+            } else {
+                self.step
+                    .register_writes
+                    .push(RegisterWrite { index, old, new });
+            }
+        }
+    }
+
+    pub(crate) fn end(&mut self, registers: &[Register; 16]) -> Result<(), Error> {
+        self.finish(registers);
+
+        for sink in &mut self.sinks {
+            sink.record(&self.step)?;
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn trap(&mut self, registers: &[Register; 16], error: &Error) -> Result<(), Error> {
+        self.finish(registers);
+
+        for sink in &mut self.sinks {
+            sink.trap(&self.step, error)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Formats a [`Step`] as a single human-readable line.
+fn format_text(step: &Step) -> String {
+    let mut line = format!("{:08}: ", step.index);
+
+    match &step.instruction {
+        Some(instruction) => line += &format!("{instruction:?}"),
+        None => line += "<external>",
+    }
+
+    for write in &step.register_writes {
+        line += &format!(" r{}={:#x}->{:#x}", write.index, write.old, write.new);
+    }
+
+    for read in &step.memory_reads {
+        line += &format!(" [{:#x};{}]->{:#x}", read.address, read.size, read.value);
+    }
+
+    for write in &step.memory_writes {
+        line += &format!(" [{:#x};{}]<-{:#x}", write.address, write.size, write.value);
+    }
+
+    if let Some(flags) = &step.flags {
+        line += &format!(" flags={:#x}->{:#x}", flags.old, flags.new);
+    }
+
+    line
+}
+
+/// Escapes a string for use inside a JSON string literal.
+fn escape_json(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+
+    for character in value.chars() {
+        match character {
+            '"' => escaped += "\\\"",
+            '\\' => escaped += "\\\\",
+            '\n' => escaped += "\\n",
+            character if character.is_control() => {
+                escaped += &format!("\\u{:04x}", character as u32);
+            }
+            character => escaped.push(character),
+        }
+    }
+
+    escaped
+}
+
+/// Formats a list of [`MemoryAccess`] as a JSON array.
+fn format_json_accesses(accesses: &[MemoryAccess]) -> String {
+    let accesses: Vec<String> = accesses
+        .iter()
+        .map(|access| {
+            format!(
+                "{{\"address\":{},\"size\":{},\"value\":{}}}",
+                access.address, access.size, access.value
+            )
+        })
+        .collect();
+
+    format!("[{}]", accesses.join(","))
+}
+
+/// Formats a [`Step`] as a single JSON object.
+fn format_json(step: &Step) -> String {
+    let instruction = match &step.instruction {
+        Some(instruction) => format!("\"{}\"", escape_json(&format!("{instruction:?}"))),
+        None => "null".to_string(),
+    };
+
+    let registers: Vec<String> = step
+        .register_writes
+        .iter()
+        .map(|write| {
+            format!(
+                "{{\"index\":{},\"old\":{},\"new\":{}}}",
+                write.index, write.old, write.new
+            )
+        })
+        .collect();
+
+    let flags = match &step.flags {
+        Some(flags) => format!("{{\"old\":{},\"new\":{}}}", flags.old, flags.new),
+        None => "null".to_string(),
+    };
+
+    format!(
+        "{{\"index\":{},\"instruction\":{},\"registers\":[{}],\"reads\":{},\"writes\":{},\"flags\":{}}}",
+        step.index,
+        instruction,
+        registers.join(","),
+        format_json_accesses(&step.memory_reads),
+        format_json_accesses(&step.memory_writes),
+        flags
+    )
+}
+
+/// [`Sink`] writing one human-readable line per [`Step`].
+pub struct Text<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> Text<W> {
+    #[must_use]
+    /// Constructs a new [`Text`] sink writing into the given writer.
+    pub fn new(writer: W) -> Self {
+        Text { writer }
+    }
+}
+
+impl<W: Write> Debug for Text<W> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Text").finish_non_exhaustive()
+    }
+}
+
+impl<W: Write> Sink for Text<W> {
+    fn record(&mut self, step: &Step) -> Result<(), Error> {
+        writeln!(self.writer, "{}", format_text(step)).map_err(|_| Error::TraceFailed)
+    }
+
+    fn trap(&mut self, step: &Step, error: &Error) -> Result<(), Error> {
+        writeln!(self.writer, "{} trap={error:?}", format_text(step))
+            .map_err(|_| Error::TraceFailed)
+    }
+}
+
+/// [`Sink`] writing one JSON object per line per [`Step`].
+pub struct JsonLines<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> JsonLines<W> {
+    #[must_use]
+    /// Constructs a new [`JsonLines`] sink writing into the given writer.
+    pub fn new(writer: W) -> Self {
+        JsonLines { writer }
+    }
+}
+
+impl<W: Write> Debug for JsonLines<W> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("JsonLines").finish_non_exhaustive()
+    }
+}
+
+impl<W: Write> Sink for JsonLines<W> {
+    fn record(&mut self, step: &Step) -> Result<(), Error> {
+        writeln!(self.writer, "{}", format_json(step)).map_err(|_| Error::TraceFailed)
+    }
+
+    fn trap(&mut self, step: &Step, error: &Error) -> Result<(), Error> {
+        let json = format_json(step);
+
+        // Splice the trap into the step object rather than emitting a second line.
+        writeln!(
+            self.writer,
+            "{},\"trap\":\"{}\"}}",
+            &json[..json.len() - 1],
+            escape_json(&format!("{error:?}"))
+        )
+        .map_err(|_| Error::TraceFailed)
+    }
+}
+
+/// [`Sink`] keeping the last N [`Step`]s in memory, dumping them as text when a trap occurs.
+pub struct RingBuffer<W: Write> {
+    capacity: usize,
+    steps: VecDeque<Step>,
+
+    writer: W,
+}
+
+impl<W: Write> RingBuffer<W> {
+    #[must_use]
+    /// Constructs a new [`RingBuffer`] holding at most `capacity` steps, dumping into the given writer.
+    pub fn new(capacity: usize, writer: W) -> Self {
+        RingBuffer {
+            capacity,
+            steps: VecDeque::with_capacity(capacity),
+            writer,
+        }
+    }
+
+    /// Returns the retained [`Step`]s, oldest first.
+    pub fn steps(&self) -> impl Iterator<Item = &Step> {
+        self.steps.iter()
+    }
+
+    fn push(&mut self, step: &Step) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        if self.steps.len() == self.capacity {
+            self.steps.pop_front();
+        }
+
+        self.steps.push_back(step.clone());
+    }
+}
+
+impl<W: Write> Debug for RingBuffer<W> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RingBuffer")
+            .field("capacity", &self.capacity)
+            .field("steps", &self.steps)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<W: Write> Sink for RingBuffer<W> {
+    fn record(&mut self, step: &Step) -> Result<(), Error> {
+        self.push(step);
+
+        Ok(())
+    }
+
+    fn trap(&mut self, step: &Step, error: &Error) -> Result<(), Error> {
+        self.push(step);
+
+        let mut dump = format!("trap={error:?}, last {} steps:\n", self.steps.len());
+
+        for step in &self.steps {
+            dump += &format_text(step);
+            dump.push('\n');
+        }
+
+        self.writer
+            .write_all(dump.as_bytes())
+            .map_err(|_| Error::TraceFailed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::Assembler;
+    use crate::instructions::Operand;
+    use crate::register::Width;
+    use crate::Vm;
+
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Debug, Clone, Default)]
+    /// Writer sharing its bytes so the test can read them after the processor ran.
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[derive(Debug)]
+    /// Writer failing every write.
+    struct Failing;
+
+    impl Write for Failing {
+        fn write(&mut self, _: &[u8]) -> std::io::Result<usize> {
+            Err(std::io::ErrorKind::BrokenPipe.into())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Shared {
+        fn contents(&self) -> String {
+            String::from_utf8(self.0.lock().unwrap().clone()).unwrap()
+        }
+    }
+
+    fn run(assembler: Assembler, tracer: Tracer) -> Result<(), Error> {
+        let mut vm = Vm::new();
+        vm.load_instructions(assembler.compile()).unwrap();
+
+        let handle = vm.new_processor();
+        let processor = vm.processor_mut(handle).unwrap();
+
+        processor.set_tracer(tracer);
+        processor.start()
+    }
+
+    #[test]
+    pub fn trace_text() {
+        let shared = Shared::default();
+
+        let assembler = Assembler::new()
+            .mov(Operand::Value(7), Operand::Register(Width::QWord(0)))
+            .mov(
+                Operand::Register(Width::QWord(0)),
+                Operand::Memory(Width::DWord(16)),
+            )
+            .cmp(Operand::Memory(Width::DWord(16)), Operand::Value(7));
+
+        run(assembler, Tracer::new().sink(Text::new(shared.clone()))).unwrap();
+
+        let lines: Vec<String> = shared.contents().lines().map(str::to_string).collect();
+
+        assert_eq!(lines.len(), 3);
+        assert_eq!(
+            lines[0],
+            "00000000: Mov(Value(7), Register(QWord(0))) r0=0x0->0x7"
+        );
+        assert_eq!(
+            lines[1],
+            "00000001: Mov(Register(QWord(0)), Memory(DWord(16))) [0x10;4]<-0x7"
+        );
+        assert_eq!(
+            lines[2],
+            "00000002: Cmp(Memory(DWord(16)), Value(7)) [0x10;4]->0x7 flags=0x0->0x1"
+        );
+    }
+
+    #[test]
+    pub fn trace_json_lines() {
+        let shared = Shared::default();
+
+        let assembler = Assembler::new().jmp(Operand::Value(4));
+
+        run(
+            assembler,
+            Tracer::new().sink(JsonLines::new(shared.clone())),
+        )
+        .unwrap();
+
+        assert_eq!(
+            shared.contents(),
+            "{\"index\":0,\"instruction\":\"Jmp(Value(4))\",\"registers\":[{\"index\":15,\"old\":0,\"new\":4}],\"reads\":[],\"writes\":[],\"flags\":null}\n"
+        );
+    }
+
+    #[test]
+    pub fn trace_ring_buffer_dumps_on_trap() {
+        let shared = Shared::default();
+
+        let assembler = Assembler::new()
+            .mov(Operand::Value(1), Operand::Register(Width::QWord(0)))
+            .mov(Operand::Value(2), Operand::Register(Width::QWord(0)))
+            .mov(Operand::Value(3), Operand::Register(Width::QWord(0)))
+            .mov(Operand::Value(4), Operand::Value(0));
+
+        let result = run(
+            assembler,
+            Tracer::new().sink(RingBuffer::new(2, shared.clone())),
+        );
+
+        assert_eq!(result, Err(Error::InvalidOperand));
+        assert_eq!(
+            shared.contents(),
+            "trap=InvalidOperand, last 2 steps:\n\
+             00000002: Mov(Value(3), Register(QWord(0))) r0=0x2->0x3\n\
+             00000003: Mov(Value(4), Value(0))\n"
+        );
+    }
+
+    #[test]
+    pub fn trace_failing_sink_keeps_trap() {
+        let assembler = Assembler::new().mov(Operand::Value(4), Operand::Value(0));
+
+        assert_eq!(
+            run(assembler, Tracer::new().sink(Text::new(Failing))),
+            Err(Error::InvalidOperand)
+        );
+    }
+}
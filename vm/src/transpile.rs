@@ -0,0 +1,588 @@
+//! Ahead-of-time transpiler from [`Instruction`]s to a standalone Rust module.
+//!
+//! The generated module has a single `run` function taking a [`Processor`](crate::processor::Processor),
+//! which runs the program natively from the processor's instruction counter until it leaves the
+//! program, as [`Processor::start`](crate::processor::Processor::start) would. Registers live in
+//! locals while running, memory is accessed through the processor, and `call` goes through
+//! [`Processor::call`](crate::processor::Processor::call), so registered host functions still apply.
+//!
+//! Every basic block becomes a match arm on the instruction counter. Since a host function or an
+//! entry point may move the counter into the middle of a block, every other instruction also gets
+//! an arm of its own, which runs until the next block begins.
+//!
+//! On an error the registers, including the instruction counter, are left as the interpreter would
+//! leave them. Tracers and observers never see the generated code run.
+
+use crate::error::Error;
+use crate::instructions::{Instruction, Operand};
+use crate::register::{mask, Width, COUNTER, FLAGS, REGISTERS};
+
+use std::collections::BTreeSet;
+use std::fmt::Write;
+
+/// Transpiles the given program into the source of a Rust module depending on the `vm` crate.
+///
+/// # Example
+/// ```
+/// use vm::assembler::Assembler;
+/// use vm::transpile::transpile;
+/// let assembler = Assembler::parse("mov 0, rq0\nloop: add 1, rq0, rq0\ncmp rq0, 10\njnz loop").unwrap();
+/// let source = transpile(assembler.instructions());
+/// assert!(source.contains("pub fn run(processor: &mut Processor) -> Result<(), Error>"));
+/// ```
+#[must_use]
+pub fn transpile(instructions: &[Instruction]) -> String {
+    let snippets: Vec<Snippet> = instructions
+        .iter()
+        .enumerate()
+        .map(|(index, instruction)| Snippet::new(index, instruction))
+        .collect();
+
+    let mut leaders = BTreeSet::from([0]);
+
+    for (index, snippet) in snippets.iter().enumerate() {
+        if snippet.ends_block {
+            leaders.insert(index + 1);
+        }
+
+        if let Some(target) = snippet.target {
+            leaders.insert(target);
+        }
+    }
+
+    leaders.retain(|leader| *leader < instructions.len());
+
+    let mut locals: BTreeSet<usize> = BTreeSet::new();
+    let mut written: BTreeSet<usize> = BTreeSet::new();
+    let calls = snippets.iter().any(|snippet| snippet.calls);
+
+    for snippet in &snippets {
+        locals.extend(&snippet.reads);
+        locals.extend(&snippet.writes);
+        written.extend(&snippet.writes);
+    }
+
+    // Host functions may change any register, so every local is reloaded after a call.
+    if calls {
+        written.extend(&locals);
+    }
+
+    let mut source = String::new();
+
+    source += "// Generated by `vm::transpile`, do not edit.\n\n";
+    source += "use vm::error::Error;\n";
+    source += "use vm::processor::Processor;\n\n";
+    source +=
+        "/// Runs the program on the processor until its instruction counter leaves the program.\n";
+    source += "///\n";
+    source += "/// # Errors\n";
+    source +=
+        "/// Returns the error the interpreter would return, with the registers it would leave.\n";
+    source += "pub fn run(processor: &mut Processor) -> Result<(), Error> {\n";
+
+    // Without instructions, every instruction counter is already outside of the program.
+    if instructions.is_empty() {
+        source += "    _ = processor;\n\n";
+        source += "    Ok(())\n";
+        source += "}\n";
+
+        return source;
+    }
+
+    for local in &locals {
+        let mutable = if written.contains(local) { "mut " } else { "" };
+
+        _ = writeln!(
+            source,
+            "    let {mutable}r{local} = processor.register({local})?.as_u64();"
+        );
+    }
+
+    source += "    let mut counter = processor.register(15)?.as_u64();\n\n";
+    source += "    // Writes the locals back, with the instruction counter at the given index.\n";
+    source += "    macro_rules! save {\n";
+    source += "        ($counter:expr) => {\n";
+
+    for local in &written {
+        _ = writeln!(
+            source,
+            "            processor.register_mut({local})?.assign_u64(r{local});"
+        );
+    }
+
+    source += "            processor.register_mut(15)?.assign_u64($counter);\n";
+    source += "        };\n";
+    source += "    }\n\n";
+
+    if calls {
+        source += "    // Reads the locals again, after a host function may have changed them.\n";
+        source += "    macro_rules! load {\n";
+        source += "        () => {\n";
+
+        for local in &written {
+            _ = writeln!(
+                source,
+                "            r{local} = processor.register({local})?.as_u64();"
+            );
+        }
+
+        source += "        };\n";
+        source += "    }\n\n";
+    }
+
+    source += "    loop {\n";
+    source += "        match counter {\n";
+
+    for start in 0..snippets.len() {
+        // Arms for entries into the middle of a block run a single instruction.
+        let end = if leaders.contains(&start) {
+            leaders
+                .range(start + 1..)
+                .next()
+                .copied()
+                .unwrap_or(snippets.len())
+        } else {
+            source += "            // Entry into the middle of a block.\n";
+
+            start + 1
+        };
+
+        _ = writeln!(source, "            {start} => {{");
+
+        for (index, snippet) in snippets.iter().enumerate().take(end).skip(start) {
+            _ = writeln!(
+                source,
+                "                // {index}: {:?}",
+                instructions[index]
+            );
+
+            for line in &snippet.lines {
+                _ = writeln!(source, "                {line}");
+            }
+        }
+
+        if snippets[end - 1].falls_through {
+            _ = writeln!(source, "                counter = {end};");
+        }
+
+        source += "            }\n";
+    }
+
+    source += "            _ => break,\n";
+    source += "        }\n";
+    source += "    }\n\n";
+    source += "    save!(counter);\n\n";
+    source += "    Ok(())\n";
+    source += "}\n";
+
+    source
+}
+
+/// Rust statements running a single [`Instruction`].
+struct Snippet {
+    lines: Vec<String>,
+
+    /// Registers, other than the instruction counter, read as locals.
+    reads: BTreeSet<usize>,
+    /// Registers, other than the instruction counter, written as locals.
+    writes: BTreeSet<usize>,
+
+    /// Whether the instruction may move the instruction counter elsewhere, or always fails.
+    ends_block: bool,
+    /// Whether the instruction may continue with the next one.
+    falls_through: bool,
+    /// Index a constant jump continues at.
+    target: Option<usize>,
+    /// Whether the instruction calls into the host.
+    calls: bool,
+
+    /// Index of the instruction, the value of the instruction counter while it runs.
+    index: usize,
+}
+
+/// A value read by an instruction.
+enum Read {
+    Constant(u64),
+    /// Low bytes of a local, by register index and size in bytes.
+    Register(usize, usize),
+    /// Rust expression of type `u64`.
+    Code(String),
+}
+
+impl Read {
+    /// Returns self as a Rust expression of type `u64`.
+    fn code(&self) -> String {
+        match self {
+            Read::Constant(value) => value.to_string(),
+            Read::Register(index, 8) => format!("r{index}"),
+            Read::Register(index, size) => format!("u64::from(r{index} as u{})", size * 8),
+            Read::Code(code) => code.clone(),
+        }
+    }
+
+    /// Returns the low `size` bytes of self as an expression of the unsigned type of that size.
+    fn truncated(&self, size: usize) -> String {
+        match self {
+            Read::Constant(value) => (value & mask(size)).to_string(),
+            Read::Register(index, register_size) if size <= *register_size => {
+                format!("r{index} as u{}", size * 8)
+            }
+            Read::Register(index, register_size) => {
+                format!("u{}::from(r{index} as u{})", size * 8, register_size * 8)
+            }
+            Read::Code(code) if size == 8 => code.clone(),
+            Read::Code(code) => format!("{code} as u{}", size * 8),
+        }
+    }
+}
+
+impl Snippet {
+    /// Transpiles the [`Instruction`] at the given index.
+    fn new(index: usize, instruction: &Instruction) -> Self {
+        let mut snippet = Snippet {
+            lines: Vec::new(),
+            reads: BTreeSet::new(),
+            writes: BTreeSet::new(),
+            ends_block: false,
+            falls_through: true,
+            target: None,
+            calls: false,
+            index,
+        };
+
+        if let Err(error) = snippet.instruction(instruction) {
+            snippet.trap(&error);
+        }
+
+        snippet
+    }
+
+    /// Appends the statements running the given [`Instruction`], stopping at the first invalid operand.
+    fn instruction(&mut self, instruction: &Instruction) -> Result<(), Error> {
+        match instruction {
+            Instruction::Call(call_index) => {
+                let call_index = match call_index {
+                    Operand::Value(value) => Read::Constant(*value),
+                    Operand::Register(register) => self.register(register)?,
+
+                    _ => return Err(Error::InvalidOperand),
+                };
+
+                self.calls = true;
+                self.ends_block = true;
+                self.falls_through = false;
+
+                self.lines.push(format!("save!({});", self.index));
+                self.lines
+                    .push(format!("processor.call({})?;", call_index.code()));
+                self.lines.push("load!();".into());
+                self.lines
+                    .push("counter = processor.register(15)?.as_u64().wrapping_add(1);".into());
+                self.lines.push("continue;".into());
+            }
+            Instruction::Mov(source, destination) => {
+                let source = self.read(source)?;
+
+                self.write(destination, &source)?;
+            }
+            Instruction::Add(value, source, destination) => {
+                let source = self.read(source)?;
+                let value = self.read(value)?;
+
+                self.lines.push(format!(
+                    "let (result, overflow) = u64::overflowing_add({}, {});",
+                    source.code(),
+                    value.code()
+                ));
+                self.lines.push(format!("if overflow {{ r{FLAGS} |= 4; }}"));
+                self.reads.insert(FLAGS);
+                self.writes.insert(FLAGS);
+
+                self.write(destination, &Read::Code("result".into()))?;
+            }
+            Instruction::Cmp(value, comparator) => {
+                let value = self.read(value)?;
+                let comparator = self.read(comparator)?;
+
+                self.lines
+                    .push(format!("let value: u64 = {};", value.code()));
+                self.lines
+                    .push(format!("let comparator: u64 = {};", comparator.code()));
+                self.lines.push(format!(
+                    "r{FLAGS} = (r{FLAGS} & !3) | u64::from(value == comparator) | (u64::from(value > comparator) << 1);"
+                ));
+                self.reads.insert(FLAGS);
+                self.writes.insert(FLAGS);
+            }
+            Instruction::Jmp(source) => {
+                self.ends_block = true;
+                self.falls_through = false;
+
+                self.jump(source)?;
+            }
+            Instruction::Jz(source) | Instruction::Jnz(source) => {
+                self.ends_block = true;
+                self.reads.insert(FLAGS);
+
+                if let Instruction::Jz(_) = instruction {
+                    self.lines.push(format!("if r{FLAGS} & 1 != 0 {{"));
+                    self.lines.push(format!("    r{FLAGS} &= !1;"));
+                    self.writes.insert(FLAGS);
+                } else {
+                    self.lines.push(format!("if r{FLAGS} & 1 == 0 {{"));
+                }
+
+                let start = self.lines.len();
+                let result = self.jump(source);
+
+                if let Err(error) = &result {
+                    self.trap(error);
+                    self.falls_through = true;
+                }
+
+                for line in &mut self.lines[start..] {
+                    *line = format!("    {line}");
+                }
+
+                self.lines.push("}".into());
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Appends the statements moving the instruction counter to the given source, plus one.
+    fn jump(&mut self, source: &Operand) -> Result<(), Error> {
+        match self.read(source)? {
+            Read::Constant(value) => {
+                let target = value.wrapping_add(1);
+
+                if let Ok(target) = usize::try_from(target) {
+                    self.target = Some(target);
+                }
+
+                self.lines.push(format!("counter = {target};"));
+            }
+            read => self
+                .lines
+                .push(format!("counter = {}.wrapping_add(1);", read.code())),
+        }
+
+        self.lines.push("continue;".into());
+
+        Ok(())
+    }
+
+    /// Appends the statements saving the locals and failing with the given [`Error`].
+    fn trap(&mut self, error: &Error) {
+        self.ends_block = true;
+        self.falls_through = false;
+
+        self.lines.push(format!("save!({});", self.index));
+        self.lines.push(format!("return Err(Error::{error:?});"));
+    }
+
+    /// Returns the value of the given register operand, as read by an instruction.
+    fn register(&mut self, register: &Width) -> Result<Read, Error> {
+        let (index, size) = (register.index(), register.size());
+
+        match index {
+            COUNTER => Ok(Read::Constant(self.index as u64 & mask(size))),
+            index if index < REGISTERS => {
+                self.reads.insert(index);
+
+                Ok(Read::Register(index, size))
+            }
+
+            _ => Err(Error::RegisterIndexOutOfBounds),
+        }
+    }
+
+    /// Returns the value of the given source operand.
+    fn read(&mut self, operand: &Operand) -> Result<Read, Error> {
+        let (address, size) = match operand {
+            Operand::Value(value) => return Ok(Read::Constant(*value)),
+            Operand::Register(register) => return self.register(register),
+            Operand::Memory(memory) => {
+                let (address, size) = (memory.index(), memory.size());
+
+                (address.to_string(), size)
+            }
+            Operand::MemoryRegister(memory_register) => {
+                let size = memory_register.size();
+
+                (self.address(memory_register)?, size)
+            }
+
+            Operand::None => return Err(Error::InvalidOperand),
+        };
+
+        let get = match size {
+            8 => format!("memory.get_u64({address})"),
+            size => format!("u64::from(memory.get_u{}({address}))", size * 8),
+        };
+
+        // A failing access writes the locals back before returning, as every other trap does.
+        Ok(Read::Code(format!(
+            "match processor.memory().map(|memory| {get}) {{ Ok(value) => value, Err(error) => {{ save!({}); return Err(error); }} }}",
+            self.index
+        )))
+    }
+
+    /// Returns the address held by the given [`MemoryRegister`](Operand::MemoryRegister) operand.
+    fn address(&mut self, memory_register: &Width) -> Result<String, Error> {
+        Ok(match self.register(memory_register)? {
+            Read::Register(index, 8) => format!("r{index} as usize"),
+            Read::Register(index, 4) => format!("r{index} as u32 as usize"),
+            Read::Register(index, size) => format!("usize::from(r{index} as u{})", size * 8),
+            read => read.code(),
+        })
+    }
+
+    /// Appends the statements writing the given value to the given destination operand.
+    fn write(&mut self, operand: &Operand, value: &Read) -> Result<(), Error> {
+        match operand {
+            Operand::Register(register) => {
+                let (index, size) = (register.index(), register.size());
+
+                match index {
+                    COUNTER => {
+                        self.ends_block = true;
+                        self.falls_through = false;
+
+                        let kept = self.index as u64 & !mask(size);
+                        let counter = match (value, size) {
+                            (Read::Constant(value), _) => {
+                                (kept | (value & mask(size))).wrapping_add(1).to_string()
+                            }
+                            (value, 8) => format!("{}.wrapping_add(1)", value.code()),
+                            (value, size) if kept == 0 => {
+                                format!("u64::from({}).wrapping_add(1)", value.truncated(size))
+                            }
+                            (value, size) => format!(
+                                "({kept} | u64::from({})).wrapping_add(1)",
+                                value.truncated(size)
+                            ),
+                        };
+
+                        self.lines.push(format!("counter = {counter};"));
+                        self.lines.push("continue;".into());
+                    }
+                    index if index < REGISTERS => {
+                        self.writes.insert(index);
+
+                        let line = match (value, size) {
+                            (value, 8) => format!("r{index} = {};", value.code()),
+                            (Read::Constant(value), size) => format!(
+                                "r{index} = (r{index} & !{:#x}) | {};",
+                                mask(size),
+                                value & mask(size)
+                            ),
+                            (value, size) => format!(
+                                "r{index} = (r{index} & !{:#x}) | u64::from({});",
+                                mask(size),
+                                value.truncated(size)
+                            ),
+                        };
+
+                        if size != 8 {
+                            self.reads.insert(index);
+                        }
+
+                        self.lines.push(line);
+                    }
+
+                    _ => return Err(Error::RegisterIndexOutOfBounds),
+                }
+            }
+            Operand::Memory(memory) => {
+                let (address, size) = (memory.index(), memory.size());
+
+                self.store(address.to_string(), size, value);
+            }
+            Operand::MemoryRegister(memory_register) => {
+                let size = memory_register.size();
+                let address = self.address(memory_register)?;
+
+                self.store(address, size, value);
+            }
+
+            _ => return Err(Error::InvalidOperand),
+        }
+
+        Ok(())
+    }
+
+    /// Appends the statements storing the low `size` bytes of the given value at the given address.
+    fn store(&mut self, address: String, size: usize, value: &Read) {
+        // Reading memory while holding it mutably doesn't borrow check, so the value goes first.
+        let value = match value {
+            Read::Code(code) if code.contains("processor") => {
+                self.lines.push(format!("let value: u64 = {code};"));
+
+                Read::Code("value".into()).truncated(size)
+            }
+            value => value.truncated(size),
+        };
+
+        self.lines.push(format!(
+            "if let Err(error) = processor.memory_mut().map(|mut memory| memory.put_u{}({address}, {value})) {{",
+            size * 8
+        ));
+        self.lines.push(format!("    save!({});", self.index));
+        self.lines.push("    return Err(error);".into());
+        self.lines.push("}".into());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::assembler::Assembler;
+    use crate::instructions::{Instruction, Operand};
+    use crate::register::Width;
+    use crate::transpile::transpile;
+
+    #[test]
+    pub fn transpile_traps() {
+        let source = transpile(&[
+            Instruction::Mov(Operand::Value(1), Operand::Register(Width::QWord(0))),
+            Instruction::Mov(Operand::Value(1), Operand::Value(2)),
+            Instruction::Add(
+                Operand::Value(1),
+                Operand::Value(2),
+                Operand::Register(Width::QWord(16)),
+            ),
+        ]);
+
+        assert!(source.contains("save!(1);\n                return Err(Error::InvalidOperand);"));
+        assert!(source.contains(
+            "r14 |= 4; }\n                save!(2);\n                return Err(Error::RegisterIndexOutOfBounds);"
+        ));
+        assert!(!source.contains("counter = 2;"));
+    }
+
+    #[test]
+    pub fn transpile_memory_traps() {
+        let assembler = Assembler::parse("mov 1, rq0\nadd mq8, 1, mb16").unwrap();
+        let source = transpile(assembler.instructions());
+
+        assert!(source.contains(
+            "match processor.memory().map(|memory| memory.get_u64(8)) { Ok(value) => value, Err(error) => { save!(1); return Err(error); } }"
+        ));
+        assert!(source.contains(
+            "memory.put_u8(16, result as u8)) {\n                    save!(1);\n                    return Err(error);"
+        ));
+        assert!(!source.contains("memory()?"));
+        assert!(!source.contains("memory_mut()?"));
+    }
+
+    #[test]
+    pub fn transpile_dynamic_jumps() {
+        let assembler = Assembler::parse("jz rq0\nmov rb1, rb15\njmp 0").unwrap();
+        let source = transpile(assembler.instructions());
+
+        assert!(source.contains("r14 &= !1;\n                    counter = r0.wrapping_add(1);"));
+        assert!(source.contains("counter = u64::from(r1 as u8).wrapping_add(1);"));
+        assert!(source.contains("counter = 1;\n                continue;"));
+    }
+}
@@ -0,0 +1,224 @@
+//! Static checks over a program, finding operands that can only fail or misbehave at runtime.
+//!
+//! Only what is known before running is checked: jumps to a constant, register indices and operand
+//! kinds. Jumps through registers or memory, and call indices, are left to the runtime.
+
+use crate::instructions::{Execute, Instruction, Operand};
+use crate::register::REGISTERS;
+
+use std::fmt::{self, Display, Formatter};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// A problem with a single operand of a program.
+pub struct Problem {
+    /// Index of the instruction in the program.
+    pub index: usize,
+    /// Position of the operand in the instruction, starting at 0.
+    pub operand: usize,
+    /// What is wrong with the operand.
+    pub kind: ProblemKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// What is wrong with an operand.
+pub enum ProblemKind {
+    /// A jump continues at the given index, past the end of the program.
+    JumpOutOfBounds(u64),
+    /// A register, or a register holding an address, has the given index past the last register.
+    RegisterIndexOutOfBounds(usize),
+    /// An [`Operand::None`] where the instruction needs a value.
+    MissingOperand,
+    /// A [`Value`](Operand::Value) written to, or memory used as a call index.
+    InvalidOperand,
+}
+
+impl Display for Problem {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "instruction {}, operand {}: ", self.index, self.operand)?;
+
+        match self.kind {
+            ProblemKind::JumpOutOfBounds(target) => {
+                write!(f, "jump continues at {target}, past the end of the program")
+            }
+            ProblemKind::RegisterIndexOutOfBounds(index) => {
+                write!(f, "register {index} doesn't exist")
+            }
+            ProblemKind::MissingOperand => f.write_str("operand is missing"),
+            ProblemKind::InvalidOperand => f.write_str("operand kind isn't allowed here"),
+        }
+    }
+}
+
+/// How an instruction uses an operand.
+#[derive(Clone, Copy)]
+enum Role {
+    Source,
+    Destination,
+    CallIndex,
+    Jump,
+}
+
+/// Checks every instruction of the given program, returning all problems in program order.
+///
+/// A jump may continue at the end of the program, which leaves it, but not beyond.
+///
+/// # Example
+/// ```
+/// use vm::assembler::Assembler;
+/// use vm::verify::{verify, ProblemKind};
+/// assert!(verify(Assembler::parse("loop: add 1, rq0, rq0\njmp loop").unwrap().instructions()).is_ok());
+///
+/// let problems = verify(Assembler::parse("mov rq0, 1\njmp 5").unwrap().instructions()).unwrap_err();
+/// assert_eq!(problems[0].kind, ProblemKind::InvalidOperand);
+/// assert_eq!(problems[1].kind, ProblemKind::JumpOutOfBounds(6));
+/// ```
+///
+/// # Errors
+/// When any operand has a problem, every [`Problem`] found is returned.
+pub fn verify(instructions: &[Instruction]) -> Result<(), Vec<Problem>> {
+    let mut problems = Vec::new();
+
+    for (index, instruction) in instructions.iter().enumerate() {
+        check(index, instruction, instructions.len(), &mut problems);
+    }
+
+    finish(problems)
+}
+
+/// Checks every executable of the given program that has an [`Instruction`], skipping the others.
+pub(crate) fn verify_executables(executables: &[Box<dyn Execute>]) -> Result<(), Vec<Problem>> {
+    let mut problems = Vec::new();
+
+    for (index, executable) in executables.iter().enumerate() {
+        if let Some(instruction) = executable.instruction() {
+            check(index, &instruction, executables.len(), &mut problems);
+        }
+    }
+
+    finish(problems)
+}
+
+/// Returns the given problems, if there are any.
+fn finish(problems: Vec<Problem>) -> Result<(), Vec<Problem>> {
+    if problems.is_empty() {
+        Ok(())
+    } else {
+        Err(problems)
+    }
+}
+
+/// Appends the problems of the instruction at the given index.
+fn check(index: usize, instruction: &Instruction, length: usize, problems: &mut Vec<Problem>) {
+    let operands: Vec<(&Operand, Role)> = match instruction {
+        Instruction::Call(call_index) => vec![(call_index, Role::CallIndex)],
+        Instruction::Mov(source, destination) => {
+            vec![(source, Role::Source), (destination, Role::Destination)]
+        }
+        Instruction::Jmp(source) | Instruction::Jz(source) | Instruction::Jnz(source) => {
+            vec![(source, Role::Jump)]
+        }
+        Instruction::Cmp(value, comparator) => {
+            vec![(value, Role::Source), (comparator, Role::Source)]
+        }
+        Instruction::Add(value, source, destination) => vec![
+            (value, Role::Source),
+            (source, Role::Source),
+            (destination, Role::Destination),
+        ],
+    };
+
+    for (position, (operand, role)) in operands.into_iter().enumerate() {
+        let mut report = |kind| {
+            problems.push(Problem {
+                index,
+                operand: position,
+                kind,
+            });
+        };
+
+        match (operand, role) {
+            (Operand::None, _) => report(ProblemKind::MissingOperand),
+
+            (Operand::Value(_), Role::Destination)
+            | (Operand::Memory(_) | Operand::MemoryRegister(_), Role::CallIndex) => {
+                report(ProblemKind::InvalidOperand);
+            }
+
+            (Operand::Value(value), Role::Jump) => {
+                // The instruction counter moves past the jump target before the next instruction.
+                let target = value.wrapping_add(1);
+
+                if target > length as u64 {
+                    report(ProblemKind::JumpOutOfBounds(target));
+                }
+            }
+
+            (Operand::Register(register) | Operand::MemoryRegister(register), _) => {
+                let register = register.index();
+
+                if register >= REGISTERS {
+                    report(ProblemKind::RegisterIndexOutOfBounds(register));
+                }
+            }
+
+            (Operand::Value(_) | Operand::Memory(_), _) => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::assembler::Assembler;
+    use crate::instructions::{Instruction, Operand};
+    use crate::register::Width;
+    use crate::verify::{verify, Problem, ProblemKind};
+
+    #[test]
+    pub fn verify_accepts_program() {
+        let assembler = Assembler::parse(
+            "start: mov 0, rq0\n\
+             loop: add 1, rq0, rq0\n\
+             cmp rq0, 10\n\
+             jz end\n\
+             jnz loop\n\
+             jmp start\n\
+             mov [rq0], rb15\n\
+             end:",
+        )
+        .unwrap();
+
+        assert_eq!(verify(assembler.instructions()), Ok(()));
+    }
+
+    #[test]
+    pub fn verify_reports_every_problem() {
+        let instructions = [
+            Instruction::Mov(Operand::Register(Width::QWord(0)), Operand::Value(1)),
+            Instruction::Add(
+                Operand::None,
+                Operand::MemoryRegister(Width::Byte(16)),
+                Operand::Memory(Width::QWord(99)),
+            ),
+            Instruction::Call(Operand::Memory(Width::QWord(0))),
+            Instruction::Jnz(Operand::Value(5)),
+            Instruction::Jmp(Operand::Value(3)),
+        ];
+
+        let problem = |index, operand, kind| Problem {
+            index,
+            operand,
+            kind,
+        };
+
+        assert_eq!(
+            verify(&instructions),
+            Err(vec![
+                problem(0, 1, ProblemKind::InvalidOperand),
+                problem(1, 0, ProblemKind::MissingOperand),
+                problem(1, 1, ProblemKind::RegisterIndexOutOfBounds(16)),
+                problem(2, 0, ProblemKind::InvalidOperand),
+                problem(3, 0, ProblemKind::JumpOutOfBounds(6)),
+            ])
+        );
+    }
+}
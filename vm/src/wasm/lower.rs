@@ -0,0 +1,1665 @@
+use crate::error::Error;
+use crate::instructions::{Instruction, Operand};
+use crate::lowering::{self, first, register};
+use crate::register::{Width, FLAGS};
+use crate::wasm::reader::{FunctionType, Module, Reader};
+use crate::wasm::{GLOBALS, MEMORY, PAGES, STACK, TRAP};
+
+use std::collections::BTreeMap;
+
+/// Holds the return address of routines.
+const LINK: usize = 6;
+/// Holds bytes looked up in the complement table.
+const SCRATCH: usize = 11;
+/// Holds addresses of frame slots and linear memory.
+const ADDRESS: usize = 12;
+/// Holds the address of the frame of the running function.
+const FRAME: usize = 13;
+
+/// Returns the given register read or written with the given number of bits.
+fn narrow(index: usize, bits: u32) -> Operand {
+    Operand::Register(match bits {
+        8 => Width::Byte(index),
+        16 => Width::Word(index),
+        32 => Width::DWord(index),
+
+        _ => Width::QWord(index),
+    })
+}
+
+fn at(index: usize) -> Operand {
+    Operand::MemoryRegister(Width::QWord(index))
+}
+
+pub(crate) fn memory(address: u64) -> Operand {
+    Operand::Memory(Width::QWord(address as usize))
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Immediate {
+    None,
+    /// Numbers of parameters and results of a block.
+    Block(usize, usize),
+    Index(u32),
+    /// Branch depths by index, and the default one.
+    Table(Vec<u32>, u32),
+    /// Offset added to the address of a memory access.
+    Offset(u32),
+    Constant(u64),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Op {
+    code: u8,
+    immediate: Immediate,
+    /// Offset of the opcode within the module.
+    offset: usize,
+}
+
+/// Reads the next instruction of a function body.
+fn decode(reader: &mut Reader, types: &[FunctionType]) -> Result<Op, Error> {
+    let offset = reader.offset();
+    let mut code = reader.byte()?;
+
+    let immediate = match code {
+        0x00
+        | 0x01
+        | 0x05
+        | 0x0b
+        | 0x0f
+        | 0x1a
+        | 0x1b
+        | 0x45..=0x5a
+        | 0x67..=0x8a
+        | 0xa7
+        | 0xac
+        | 0xad
+        | 0xc0..=0xc4 => Immediate::None,
+        0x02..=0x04 => match reader.peek() {
+            Some(0x40) => {
+                reader.byte()?;
+
+                Immediate::Block(0, 0)
+            }
+            Some(0x7f | 0x7e | 0x7d | 0x7c | 0x7b | 0x70 | 0x6f) => {
+                reader.value_type()?;
+
+                Immediate::Block(0, 1)
+            }
+
+            _ => {
+                let kind = usize::try_from(reader.s33()?)
+                    .ok()
+                    .and_then(|index| types.get(index))
+                    .ok_or(Error::InvalidWasm(offset + 1))?;
+
+                Immediate::Block(kind.parameters.len(), kind.results.len())
+            }
+        },
+        0x0c | 0x0d | 0x10 | 0x20..=0x24 => Immediate::Index(reader.u32()?),
+        0x0e => Immediate::Table(reader.vector(Reader::u32)?, reader.u32()?),
+        0x1c => {
+            if reader.vector(Reader::value_type)?.len() != 1 {
+                return Err(Error::InvalidWasm(offset + 1));
+            }
+
+            code = 0x1b;
+
+            Immediate::None
+        }
+        0x28 | 0x29 | 0x2c..=0x37 | 0x3a..=0x3e => {
+            // The alignment is only a hint.
+            reader.u32()?;
+
+            Immediate::Offset(reader.u32()?)
+        }
+        0x3f | 0x40 => match reader.byte()? {
+            0 => Immediate::None,
+
+            _ => return Err(Error::InvalidWasm(offset + 1)),
+        },
+        0x41 => Immediate::Constant(u64::from(reader.s32()?)),
+        0x42 => Immediate::Constant(reader.s64()?),
+
+        // Indirect and tail calls, tables, floats, references and prefixed instructions.
+        0x11..=0x13
+        | 0x25
+        | 0x26
+        | 0x2a
+        | 0x2b
+        | 0x38
+        | 0x39
+        | 0x43
+        | 0x44
+        | 0x5b..=0x66
+        | 0x8b..=0xa6
+        | 0xa8..=0xab
+        | 0xae..=0xbf
+        | 0xd0..=0xd2
+        | 0xfc
+        | 0xfd => return Err(Error::UnsupportedWasm(offset)),
+        _ => return Err(Error::InvalidWasm(offset)),
+    };
+
+    Ok(Op {
+        code,
+        immediate,
+        offset,
+    })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// An entry of the operand stack.
+enum Entry {
+    /// A constant not yet written to its slot.
+    Constant(u64),
+    /// A value in its slot of the frame.
+    Slot,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Relation {
+    Equal,
+    NotEqual,
+    Greater,
+    GreaterEqual,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Block,
+    Loop,
+    /// An `if` whose `else` hasn't been reached.
+    If,
+}
+
+#[derive(Debug, Clone, Copy)]
+/// A block being lowered, the body of the function being the outermost one.
+struct Control {
+    kind: Kind,
+    /// Height of the operand stack below the parameters of the block.
+    height: usize,
+    parameters: usize,
+    results: usize,
+    /// Label branches to the block continue at.
+    label: usize,
+    /// Label of the `else` branch of an `if`.
+    otherwise: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+/// Code shared by the operations too long to be emitted inline.
+///
+/// Routines take their operands in `rq0` and `rq1`, leave their result in `rq0` and return to the
+/// address in [`LINK`]. They are made of additions, taking the bits a value shifts out of the top
+/// by doubling it with the flags cleared and reading whether it overflowed.
+enum Routine {
+    And,
+    Or,
+    Xor,
+    /// Shifts `rq0` left by `rq1`.
+    ShiftLeft,
+    /// Shifts `rq0` right by `rq1`.
+    ShiftRight,
+    ShiftRightSigned,
+    /// Rotates `rq0` left by 1 until `rq1`, incremented each time, reaches `rq2`.
+    Rotate64,
+    Rotate32,
+    LeadingZeros,
+    TrailingZeros,
+    Population,
+    Multiply,
+    /// Divides `rq0` by `rq1`, leaving the remainder in `rq1`.
+    Divide,
+    DivideSigned,
+}
+
+/// Instructions of every function of a module, starting with a jump to be patched to the code
+/// running an export.
+pub(crate) struct Lowered {
+    pub(crate) instructions: Vec<Instruction>,
+    /// Index of the first instruction of each function.
+    pub(crate) functions: Vec<usize>,
+    /// Whether the complement table is used, and has to be filled before running.
+    pub(crate) complements: bool,
+}
+
+struct Lowerer<'a> {
+    module: &'a Module,
+
+    instructions: Vec<Instruction>,
+    /// Index of the instruction each label names, once placed.
+    labels: Vec<usize>,
+    /// Instructions whose first operand is the index before the given label.
+    fixups: Vec<(usize, usize)>,
+    /// Label of each routine used.
+    routines: BTreeMap<Routine, usize>,
+    complements: bool,
+    /// Label of each function.
+    functions: Vec<usize>,
+
+    /// Number of parameters and locals of the function being lowered.
+    locals: u64,
+    stack: Vec<Entry>,
+    frames: Vec<Control>,
+    /// Whether the code being lowered can't be reached, until the end of its block.
+    dead: bool,
+    /// Blocks nested in dead code.
+    skipping: usize,
+    /// Offset of the instruction being lowered.
+    offset: usize,
+}
+
+impl Lowerer<'_> {
+    fn push(&mut self, instruction: Instruction) {
+        self.instructions.push(instruction);
+    }
+
+    /// Pushes the given instruction, its first operand becoming the index before the given label.
+    fn push_fixup(&mut self, instruction: Instruction, label: usize) {
+        self.fixups.push((self.instructions.len(), label));
+        self.push(instruction);
+    }
+
+    fn label(&mut self) -> usize {
+        self.labels.push(usize::MAX);
+
+        self.labels.len() - 1
+    }
+
+    fn place(&mut self, label: usize) {
+        self.labels[label] = self.instructions.len();
+    }
+
+    fn jump(&mut self, jump: fn(Operand) -> Instruction, label: usize) {
+        self.push_fixup(jump(Operand::Value(0)), label);
+    }
+
+    fn invalid<T>(&self) -> Result<T, Error> {
+        Err(Error::InvalidWasm(self.offset))
+    }
+
+    fn trap(&mut self) {
+        self.push(Instruction::Call(Operand::Value(TRAP)));
+    }
+
+    /// Returns the word at the given offset from the frame, through [`ADDRESS`] unless it is 0.
+    fn frame(&mut self, offset: u64) -> Operand {
+        if offset == 0 {
+            return at(FRAME);
+        }
+
+        self.push(Instruction::Add(
+            Operand::Value(offset),
+            register(FRAME),
+            register(ADDRESS),
+        ));
+
+        at(ADDRESS)
+    }
+
+    /// Returns the slot of the given operand stack entry, after the locals and return address.
+    fn slot(&mut self, index: usize) -> Operand {
+        self.frame(8 * (self.locals + 1 + index as u64))
+    }
+
+    /// Returns the height of the operand stack below the innermost block.
+    fn height(&self) -> usize {
+        self.frames.last().map_or(0, |frame| frame.height)
+    }
+
+    /// Checks the innermost block has at least the given number of operands.
+    fn operands(&self, count: usize) -> Result<(), Error> {
+        match self.stack.len() >= self.height() + count {
+            true => Ok(()),
+            false => self.invalid(),
+        }
+    }
+
+    fn pop(&mut self) -> Result<(usize, Entry), Error> {
+        self.operands(1)?;
+
+        let entry = self.stack.pop().expect("checked operands");
+
+        Ok((self.stack.len(), entry))
+    }
+
+    /// Returns the given popped entry as an operand, loading it into the given register from its
+    /// slot.
+    fn load(&mut self, (index, entry): (usize, Entry), into: usize) -> Operand {
+        match entry {
+            Entry::Constant(value) => Operand::Value(value),
+            Entry::Slot => {
+                let slot = self.slot(index);
+
+                self.push(Instruction::Mov(slot, register(into)));
+
+                register(into)
+            }
+        }
+    }
+
+    /// Loads the given popped entry into the given register.
+    fn load_into(&mut self, popped: (usize, Entry), into: usize) {
+        let operand = self.load(popped, into);
+
+        if operand != register(into) {
+            self.push(Instruction::Mov(operand, register(into)));
+        }
+    }
+
+    /// Pops the top entry as an operand, loading it into the given register from its slot.
+    fn operand(&mut self, into: usize) -> Result<Operand, Error> {
+        let popped = self.pop()?;
+
+        Ok(self.load(popped, into))
+    }
+
+    fn pop_into(&mut self, into: usize) -> Result<(), Error> {
+        let popped = self.pop()?;
+
+        self.load_into(popped, into);
+
+        Ok(())
+    }
+
+    /// Pushes the given register onto the operand stack.
+    fn push_register(&mut self, index: usize) {
+        let slot = self.slot(self.stack.len());
+
+        self.push(Instruction::Mov(register(index), slot));
+        self.stack.push(Entry::Slot);
+    }
+
+    /// Writes every constant on the operand stack to its slot.
+    fn flush(&mut self) {
+        for index in 0..self.stack.len() {
+            if let Entry::Constant(value) = self.stack[index] {
+                let slot = self.slot(index);
+
+                self.push(Instruction::Mov(Operand::Value(value), slot));
+                self.stack[index] = Entry::Slot;
+            }
+        }
+    }
+
+    /// Keeps the given number of low bits of the given register.
+    fn truncate(&mut self, index: usize, bits: u32) {
+        self.push(Instruction::Mov(narrow(index, bits), register(index)));
+    }
+
+    /// Sign extends the given number of low bits of the given register.
+    fn extend(&mut self, index: usize, bits: u32) {
+        let sign = 1 << (bits - 1);
+
+        self.push(Instruction::Add(
+            Operand::Value(sign),
+            register(index),
+            register(index),
+        ));
+        self.truncate(index, bits);
+        self.push(Instruction::Add(
+            Operand::Value(sign.wrapping_neg()),
+            register(index),
+            register(index),
+        ));
+    }
+
+    /// Negates the given register through the complement table, which then has to be filled.
+    fn negate(&mut self, index: usize) {
+        self.complements = true;
+        self.instructions.extend(lowering::negate(index, SCRATCH));
+    }
+
+    /// Doubles the given register with the flags cleared, writing into the other one 4 when its
+    /// top bit was set and 0 otherwise.
+    fn carry(&mut self, index: usize, into: usize) {
+        self.push(Instruction::Mov(Operand::Value(0), register(FLAGS)));
+        self.push(Instruction::Add(
+            register(index),
+            register(index),
+            register(index),
+        ));
+        self.push(Instruction::Mov(register(FLAGS), register(into)));
+    }
+
+    /// Compares both operands with the flags cleared, writing the flags into the given register:
+    /// 0 when less, 1 when equal and 2 when greater.
+    fn order(&mut self, value: Operand, comparator: Operand, into: usize) {
+        self.push(Instruction::Mov(Operand::Value(0), register(FLAGS)));
+        self.push(Instruction::Cmp(value, comparator));
+        self.push(Instruction::Mov(register(FLAGS), register(into)));
+    }
+
+    fn call_routine(&mut self, routine: Routine) {
+        let label = match self.routines.get(&routine) {
+            Some(label) => *label,
+            None => {
+                let label = self.label();
+
+                self.routines.insert(routine, label);
+
+                label
+            }
+        };
+        let back = self.label();
+
+        self.push_fixup(Instruction::Mov(Operand::Value(0), register(LINK)), back);
+        self.jump(Instruction::Jmp, label);
+        self.place(back);
+    }
+
+    /// Branches to the block at the given depth, moving the values it takes to its slots.
+    fn branch(&mut self, depth: u32) -> Result<(), Error> {
+        let Some(target) =
+            (self.frames.len().checked_sub(1 + depth as usize)).map(|index| self.frames[index])
+        else {
+            return self.invalid();
+        };
+
+        let arity = match target.kind {
+            Kind::Loop => target.parameters,
+            Kind::Block | Kind::If => target.results,
+        };
+
+        self.operands(arity)?;
+
+        let base = self.stack.len() - arity;
+
+        for index in 0..arity {
+            let destination = target.height + index;
+
+            match self.stack[base + index] {
+                Entry::Constant(value) => {
+                    let slot = self.slot(destination);
+
+                    self.push(Instruction::Mov(Operand::Value(value), slot));
+                }
+                Entry::Slot if base + index != destination => {
+                    let slot = self.slot(base + index);
+
+                    self.push(Instruction::Mov(slot, register(0)));
+
+                    let slot = self.slot(destination);
+
+                    self.push(Instruction::Mov(register(0), slot));
+                }
+                Entry::Slot => {}
+            }
+        }
+
+        self.jump(Instruction::Jmp, target.label);
+
+        Ok(())
+    }
+
+    /// Returns the address of a memory access at the given offset from the popped address.
+    fn address(&mut self, offset: u32) -> Result<Operand, Error> {
+        let base = MEMORY + u64::from(offset);
+
+        match self.pop()? {
+            (_, Entry::Constant(address)) => Ok(memory(base + address)),
+            popped => {
+                self.load_into(popped, ADDRESS);
+                self.push(Instruction::Add(
+                    Operand::Value(base),
+                    register(ADDRESS),
+                    register(ADDRESS),
+                ));
+
+                Ok(at(ADDRESS))
+            }
+        }
+    }
+
+    fn block(&mut self, code: u8, parameters: usize, results: usize) -> Result<(), Error> {
+        let condition = match code {
+            0x04 => Some(self.operand(0)?),
+            _ => None,
+        };
+
+        self.operands(parameters)?;
+        // Blocks are entered and left with the whole stack in slots.
+        self.flush();
+
+        let label = self.label();
+        let mut frame = Control {
+            kind: Kind::Block,
+            height: self.stack.len() - parameters,
+            parameters,
+            results,
+            label,
+            otherwise: label,
+        };
+
+        match condition {
+            Some(condition) => {
+                // The `else` branch would find its parameters overwritten.
+                if parameters != 0 {
+                    return Err(Error::UnsupportedWasm(self.offset));
+                }
+
+                frame.kind = Kind::If;
+                frame.otherwise = self.label();
+
+                self.push(Instruction::Cmp(condition, Operand::Value(0)));
+                self.jump(Instruction::Jz, frame.otherwise);
+            }
+            None if code == 0x03 => {
+                frame.kind = Kind::Loop;
+
+                self.place(label);
+            }
+            None => {}
+        }
+
+        self.frames.push(frame);
+
+        Ok(())
+    }
+
+    /// Leaves the innermost block the end of which is reached, returning when it is the body.
+    fn end(&mut self) -> Result<(), Error> {
+        let frame = *self.frames.last().expect("lowered within a block");
+
+        if !self.dead {
+            self.flush();
+
+            if self.stack.len() != frame.height + frame.results {
+                return self.invalid();
+            }
+        }
+
+        self.frames.pop();
+        self.dead = false;
+        self.stack.truncate(frame.height);
+        self.stack.resize(frame.height + frame.results, Entry::Slot);
+
+        match frame.kind {
+            Kind::Block => self.place(frame.label),
+            Kind::If => {
+                self.place(frame.otherwise);
+                self.place(frame.label);
+            }
+            Kind::Loop => {}
+        }
+
+        if self.frames.is_empty() {
+            if frame.results != 0 {
+                let slot = self.slot(0);
+
+                self.push(Instruction::Mov(slot, register(0)));
+            }
+
+            // The result goes to the first word of the frame, which holds the return address
+            // when there are no locals.
+            let slot = self.frame(8 * self.locals);
+
+            self.push(Instruction::Mov(slot, register(ADDRESS)));
+
+            if frame.results != 0 {
+                self.push(Instruction::Mov(register(0), at(FRAME)));
+            }
+
+            self.push(Instruction::Jmp(register(ADDRESS)));
+        }
+
+        Ok(())
+    }
+
+    fn call(&mut self, index: u32) -> Result<(), Error> {
+        let Some(kind) = self.module.functions.get(index as usize) else {
+            return self.invalid();
+        };
+
+        let kind = &self.module.types[*kind as usize];
+        let (parameters, results) = (kind.parameters.len(), kind.results.len());
+        let locals = parameters + self.module.bodies[index as usize].locals.len();
+
+        self.operands(parameters)?;
+        self.flush();
+
+        // The frame of the callee starts at the slot of its first argument.
+        let base = self.stack.len() - parameters;
+        let offset = 8 * (self.locals + 1 + base as u64);
+        let back = self.label();
+
+        self.push(Instruction::Add(
+            Operand::Value(offset),
+            register(FRAME),
+            register(FRAME),
+        ));
+        self.push(Instruction::Add(
+            Operand::Value(8 * locals as u64),
+            register(FRAME),
+            register(ADDRESS),
+        ));
+        self.push_fixup(Instruction::Mov(Operand::Value(0), at(ADDRESS)), back);
+        self.jump(Instruction::Jmp, self.functions[index as usize]);
+        self.place(back);
+        self.push(Instruction::Add(
+            Operand::Value(offset.wrapping_neg()),
+            register(FRAME),
+            register(FRAME),
+        ));
+
+        self.stack.truncate(base);
+        self.stack.resize(base + results, Entry::Slot);
+
+        Ok(())
+    }
+
+    fn load_memory(&mut self, code: u8, offset: u32) -> Result<(), Error> {
+        let (bits, signed, wide) = match code {
+            0x28 => (32, false, false),
+            0x29 => (64, false, true),
+            0x2c => (8, true, false),
+            0x2d => (8, false, false),
+            0x2e => (16, true, false),
+            0x2f => (16, false, false),
+            0x30 => (8, true, true),
+            0x31 => (8, false, true),
+            0x32 => (16, true, true),
+            0x33 => (16, false, true),
+            0x34 => (32, true, true),
+
+            _ => (32, false, true),
+        };
+
+        let address = self.address(offset)?;
+
+        self.push(Instruction::Mov(address, register(0)));
+
+        if bits < 64 {
+            self.truncate(0, bits);
+        }
+
+        if signed {
+            self.extend(0, bits);
+
+            if !wide {
+                self.truncate(0, 32);
+            }
+        }
+
+        self.push_register(0);
+
+        Ok(())
+    }
+
+    fn store_memory(&mut self, code: u8, offset: u32) -> Result<(), Error> {
+        let bits = match code {
+            0x37 => 64,
+            0x3a | 0x3c => 8,
+            0x3b | 0x3d => 16,
+
+            _ => 32,
+        };
+
+        self.pop_into(1)?;
+
+        let address = self.address(offset)?;
+
+        // Narrow values are written over the word at the address.
+        if bits == 64 {
+            self.push(Instruction::Mov(register(1), address));
+        } else {
+            self.push(Instruction::Mov(address.clone(), register(2)));
+            self.push(Instruction::Mov(narrow(1, bits), narrow(2, bits)));
+            self.push(Instruction::Mov(register(2), address));
+        }
+
+        Ok(())
+    }
+
+    fn grow(&mut self) -> Result<(), Error> {
+        let failed = self.label();
+        let end = self.label();
+
+        self.pop_into(1)?;
+        self.push(Instruction::Mov(memory(PAGES), register(0)));
+        self.push(Instruction::Add(register(1), register(0), register(2)));
+        self.order(register(2), Operand::Value(self.module.maximum), 3);
+        self.push(Instruction::Cmp(register(3), Operand::Value(2)));
+        self.jump(Instruction::Jz, failed);
+        self.push(Instruction::Mov(register(2), memory(PAGES)));
+        self.jump(Instruction::Jmp, end);
+        self.place(failed);
+        self.push(Instruction::Mov(
+            Operand::Value(u64::from(u32::MAX)),
+            register(0),
+        ));
+        self.place(end);
+        self.push_register(0);
+
+        Ok(())
+    }
+
+    /// Lowers a comparison of the given index from `eq` to `ge_u`, 0 to 9.
+    fn compare(&mut self, wide: bool, comparison: u8) -> Result<(), Error> {
+        let comparator = self.operand(1)?;
+        let value = self.operand(0)?;
+
+        // Signed comparisons compare values offset by the sign bit.
+        let (value, comparator) = match comparison {
+            2 | 4 | 6 | 8 => (self.bias(value, wide), self.bias(comparator, wide)),
+
+            _ => (value, comparator),
+        };
+
+        // Less is greater swapped.
+        let (value, comparator) = match comparison {
+            2 | 3 | 6 | 7 => (comparator, value),
+
+            _ => (value, comparator),
+        };
+
+        let relation = match comparison {
+            0 => Relation::Equal,
+            1 => Relation::NotEqual,
+            2..=5 => Relation::Greater,
+            _ => Relation::GreaterEqual,
+        };
+
+        self.relation(value, comparator, relation);
+
+        Ok(())
+    }
+
+    fn bias(&mut self, operand: Operand, wide: bool) -> Operand {
+        let sign = if wide { 1 << 63 } else { 1 << 31 };
+
+        match operand {
+            Operand::Value(value) if wide => Operand::Value(value ^ sign),
+            Operand::Value(value) => Operand::Value((value ^ sign) & u64::from(u32::MAX)),
+            Operand::Register(Width::QWord(index)) => {
+                self.push(Instruction::Add(
+                    Operand::Value(sign),
+                    register(index),
+                    register(index),
+                ));
+
+                if !wide {
+                    self.truncate(index, 32);
+                }
+
+                register(index)
+            }
+
+            _ => unreachable!("operands are constants or registers"),
+        }
+    }
+
+    /// Pushes 1 when the given relation holds between both operands and 0 otherwise.
+    fn relation(&mut self, value: Operand, comparator: Operand, relation: Relation) {
+        let end = self.label();
+
+        match relation {
+            Relation::Equal | Relation::NotEqual => {
+                let holds = u64::from(relation == Relation::Equal);
+
+                self.push(Instruction::Mov(Operand::Value(holds), register(3)));
+                self.push(Instruction::Cmp(value, comparator));
+                self.jump(Instruction::Jz, end);
+                self.push(Instruction::Mov(Operand::Value(holds ^ 1), register(3)));
+            }
+            Relation::Greater => {
+                self.order(value, comparator, 2);
+                self.push(Instruction::Mov(Operand::Value(1), register(3)));
+                self.push(Instruction::Cmp(register(2), Operand::Value(2)));
+                self.jump(Instruction::Jz, end);
+                self.push(Instruction::Mov(Operand::Value(0), register(3)));
+            }
+            Relation::GreaterEqual => {
+                self.order(value, comparator, 2);
+                self.push(Instruction::Mov(Operand::Value(0), register(3)));
+                self.push(Instruction::Cmp(register(2), Operand::Value(0)));
+                self.jump(Instruction::Jz, end);
+                self.push(Instruction::Mov(Operand::Value(1), register(3)));
+            }
+        }
+
+        self.place(end);
+        self.push_register(3);
+    }
+
+    /// Reduces the given register modulo the given power of 2 up to 64.
+    fn reduce(&mut self, index: usize, bits: u64) {
+        let head = self.label();
+        let end = self.label();
+
+        self.truncate(index, 8);
+        self.place(head);
+        self.order(register(index), Operand::Value(bits - 1), 2);
+        self.push(Instruction::Cmp(register(2), Operand::Value(2)));
+        self.jump(Instruction::Jnz, end);
+        self.push(Instruction::Add(
+            Operand::Value(bits.wrapping_neg()),
+            register(index),
+            register(index),
+        ));
+        self.jump(Instruction::Jmp, head);
+        self.place(end);
+    }
+
+    /// Multiplies `rq1` by the given constant into `rq0` by doubling and adding.
+    fn multiply(&mut self, factor: u64) {
+        self.push(Instruction::Mov(Operand::Value(0), register(0)));
+
+        for bit in 0..64 - factor.leading_zeros() {
+            if factor & (1 << bit) != 0 {
+                self.push(Instruction::Add(register(1), register(0), register(0)));
+            }
+
+            if factor >> bit > 1 {
+                self.push(Instruction::Add(register(1), register(1), register(1)));
+            }
+        }
+    }
+
+    /// Lowers an integer operation of the given index from `clz` to `rotr`, 0 to 17.
+    fn arithmetic(&mut self, wide: bool, operation: u8) -> Result<(), Error> {
+        let bits = if wide { 64 } else { 32 };
+
+        match operation {
+            0..=2 => {
+                self.pop_into(0)?;
+
+                match operation {
+                    0 => {
+                        self.call_routine(Routine::LeadingZeros);
+
+                        if !wide {
+                            self.push(Instruction::Add(
+                                Operand::Value(32u64.wrapping_neg()),
+                                register(0),
+                                register(0),
+                            ));
+                        }
+                    }
+                    1 => {
+                        // A bit past the value bounds the count for zero.
+                        if !wide {
+                            self.push(Instruction::Add(
+                                Operand::Value(1 << 32),
+                                register(0),
+                                register(0),
+                            ));
+                        }
+
+                        self.call_routine(Routine::TrailingZeros);
+                    }
+                    _ => self.call_routine(Routine::Population),
+                }
+            }
+            3 => {
+                let right = self.operand(1)?;
+                let left = self.operand(0)?;
+
+                self.push(Instruction::Add(left, right, register(0)));
+            }
+            4 => {
+                let right = match self.pop()? {
+                    (_, Entry::Constant(value)) => Operand::Value(value.wrapping_neg()),
+                    popped => {
+                        self.load_into(popped, 1);
+                        self.negate(1);
+
+                        register(1)
+                    }
+                };
+                let left = self.operand(0)?;
+
+                self.push(Instruction::Add(left, right, register(0)));
+            }
+            5 => {
+                let right = self.pop()?;
+                let left = self.pop()?;
+
+                match (left, right) {
+                    (value, (_, Entry::Constant(factor)))
+                    | ((_, Entry::Constant(factor)), value) => {
+                        self.load_into(value, 1);
+                        self.multiply(factor);
+                    }
+                    (left, right) => {
+                        self.load_into(left, 0);
+                        self.load_into(right, 1);
+                        self.call_routine(Routine::Multiply);
+                    }
+                }
+            }
+            6..=9 => {
+                let signed = matches!(operation, 6 | 8);
+                let right = self.pop()?;
+                let left = self.pop()?;
+                let valid = self.label();
+
+                self.load_into(left, 0);
+                self.load_into(right, 1);
+
+                if signed && !wide {
+                    self.extend(0, 32);
+                    self.extend(1, 32);
+                }
+
+                self.push(Instruction::Cmp(register(1), Operand::Value(0)));
+                self.jump(Instruction::Jnz, valid);
+                self.trap();
+                self.place(valid);
+
+                // The quotient of the smallest value by -1 doesn't fit.
+                if operation == 6 {
+                    let valid = self.label();
+                    let smallest = if wide { 1 << 63 } else { 0xffff_ffff_8000_0000 };
+
+                    self.push(Instruction::Cmp(register(0), Operand::Value(smallest)));
+                    self.jump(Instruction::Jnz, valid);
+                    self.push(Instruction::Cmp(register(1), Operand::Value(u64::MAX)));
+                    self.jump(Instruction::Jnz, valid);
+                    self.trap();
+                    self.place(valid);
+                }
+
+                self.call_routine(match signed {
+                    true => Routine::DivideSigned,
+                    false => Routine::Divide,
+                });
+
+                if operation >= 8 {
+                    self.push(Instruction::Mov(register(1), register(0)));
+                }
+            }
+            10..=12 => {
+                self.pop_into(1)?;
+                self.pop_into(0)?;
+                self.call_routine(match operation {
+                    10 => Routine::And,
+                    11 => Routine::Or,
+                    _ => Routine::Xor,
+                });
+            }
+            _ => {
+                match self.pop()? {
+                    (_, Entry::Constant(amount)) => {
+                        self.push(Instruction::Mov(Operand::Value(amount % bits), register(1)))
+                    }
+                    popped => {
+                        self.load_into(popped, 1);
+                        self.reduce(1, bits);
+                    }
+                }
+
+                self.pop_into(0)?;
+
+                match operation {
+                    13 => self.call_routine(Routine::ShiftLeft),
+                    14 => {
+                        if !wide {
+                            self.extend(0, 32);
+                        }
+
+                        self.call_routine(Routine::ShiftRightSigned);
+                    }
+                    15 => self.call_routine(Routine::ShiftRight),
+                    16 => {
+                        self.push(Instruction::Mov(register(1), register(2)));
+                        self.push(Instruction::Mov(Operand::Value(0), register(1)));
+                    }
+                    _ => self.push(Instruction::Mov(Operand::Value(bits), register(2))),
+                }
+
+                if operation >= 16 {
+                    self.call_routine(match wide {
+                        true => Routine::Rotate64,
+                        false => Routine::Rotate32,
+                    });
+                }
+            }
+        }
+
+        // Results of 32-bit operations that may not fit are wrapped.
+        if !wide && matches!(operation, 3..=6 | 8 | 13 | 14) {
+            self.truncate(0, 32);
+        }
+
+        self.push_register(0);
+
+        Ok(())
+    }
+
+    fn op(&mut self, op: Op) -> Result<(), Error> {
+        match (op.code, op.immediate) {
+            (0x00, _) => {
+                self.trap();
+                self.dead = true;
+            }
+            (0x01, _) => {}
+            (code @ 0x02..=0x04, Immediate::Block(parameters, results)) => {
+                self.block(code, parameters, results)?
+            }
+            (0x05, _) => {
+                let Some(frame) = self
+                    .frames
+                    .last_mut()
+                    .filter(|frame| frame.kind == Kind::If)
+                else {
+                    return self.invalid();
+                };
+
+                frame.kind = Kind::Block;
+
+                let frame = *frame;
+
+                if !self.dead {
+                    self.flush();
+
+                    if self.stack.len() != frame.height + frame.results {
+                        return self.invalid();
+                    }
+
+                    self.jump(Instruction::Jmp, frame.label);
+                }
+
+                self.dead = false;
+                self.place(frame.otherwise);
+                self.stack.truncate(frame.height);
+            }
+            (0x0b, _) => self.end()?,
+            (0x0c, Immediate::Index(depth)) => {
+                self.branch(depth)?;
+                self.dead = true;
+            }
+            (0x0d, Immediate::Index(depth)) => {
+                let skip = self.label();
+                let condition = self.operand(0)?;
+
+                self.push(Instruction::Cmp(condition, Operand::Value(0)));
+                self.jump(Instruction::Jz, skip);
+                self.branch(depth)?;
+                self.place(skip);
+            }
+            (0x0e, Immediate::Table(depths, default)) => {
+                // Branches move values through `rq0`.
+                let index = self.operand(4)?;
+
+                for (value, depth) in depths.into_iter().enumerate() {
+                    let next = self.label();
+
+                    self.push(Instruction::Cmp(
+                        index.clone(),
+                        Operand::Value(value as u64),
+                    ));
+                    self.jump(Instruction::Jnz, next);
+                    self.branch(depth)?;
+                    self.place(next);
+                }
+
+                self.branch(default)?;
+                self.dead = true;
+            }
+            (0x0f, _) => {
+                self.branch(self.frames.len() as u32 - 1)?;
+                self.dead = true;
+            }
+            (0x10, Immediate::Index(function)) => self.call(function)?,
+            (0x1a, _) => {
+                self.pop()?;
+            }
+            (0x1b, _) => {
+                let end = self.label();
+                let condition = self.operand(2)?;
+                let otherwise = self.operand(1)?;
+                let value = self.operand(0)?;
+
+                self.push(Instruction::Mov(value, register(3)));
+                self.push(Instruction::Cmp(condition, Operand::Value(0)));
+                self.jump(Instruction::Jnz, end);
+                self.push(Instruction::Mov(otherwise, register(3)));
+                self.place(end);
+                self.push_register(3);
+            }
+            (code @ 0x20..=0x22, Immediate::Index(local)) => {
+                if u64::from(local) >= self.locals {
+                    return self.invalid();
+                }
+
+                let local = 8 * u64::from(local);
+
+                if code == 0x20 {
+                    let slot = self.frame(local);
+
+                    self.push(Instruction::Mov(slot, register(0)));
+                    self.push_register(0);
+
+                    return Ok(());
+                }
+
+                let popped = self.pop()?;
+                let value = self.load(popped, 0);
+                let slot = self.frame(local);
+
+                self.push(Instruction::Mov(value, slot));
+
+                // The value stays in its slot.
+                if code == 0x22 {
+                    self.stack.push(popped.1);
+                }
+            }
+            (code @ (0x23 | 0x24), Immediate::Index(global)) => {
+                if global as usize >= self.module.globals.len() {
+                    return self.invalid();
+                }
+
+                let global = memory(GLOBALS + 8 * u64::from(global));
+
+                if code == 0x23 {
+                    self.push(Instruction::Mov(global, register(0)));
+                    self.push_register(0);
+                } else {
+                    let value = self.operand(0)?;
+
+                    self.push(Instruction::Mov(value, global));
+                }
+            }
+            (code @ (0x28 | 0x29 | 0x2c..=0x35), Immediate::Offset(offset)) => {
+                self.load_memory(code, offset)?
+            }
+            (code @ (0x36 | 0x37 | 0x3a..=0x3e), Immediate::Offset(offset)) => {
+                self.store_memory(code, offset)?
+            }
+            (0x3f, _) => {
+                self.push(Instruction::Mov(memory(PAGES), register(0)));
+                self.push_register(0);
+            }
+            (0x40, _) => self.grow()?,
+            (0x41 | 0x42, Immediate::Constant(value)) => self.stack.push(Entry::Constant(value)),
+            (0x45 | 0x50, _) => {
+                let value = self.operand(0)?;
+
+                self.relation(value, Operand::Value(0), Relation::Equal);
+            }
+            (code @ 0x46..=0x4f, _) => self.compare(false, code - 0x46)?,
+            (code @ 0x51..=0x5a, _) => self.compare(true, code - 0x51)?,
+            (code @ 0x67..=0x78, _) => self.arithmetic(false, code - 0x67)?,
+            (code @ 0x79..=0x8a, _) => self.arithmetic(true, code - 0x79)?,
+            (0xad, _) => {
+                // Values of 32 bits are kept zero extended.
+                self.operands(1)?;
+            }
+            (code @ (0xa7 | 0xac | 0xc0..=0xc4), _) => {
+                self.pop_into(0)?;
+
+                match code {
+                    0xa7 => self.truncate(0, 32),
+                    0xac | 0xc4 => self.extend(0, 32),
+                    0xc0 | 0xc2 => self.extend(0, 8),
+                    _ => self.extend(0, 16),
+                }
+
+                if matches!(code, 0xc0 | 0xc1) {
+                    self.truncate(0, 32);
+                }
+
+                self.push_register(0);
+            }
+
+            _ => unreachable!("immediates are decoded with their opcode"),
+        }
+
+        Ok(())
+    }
+
+    fn function(&mut self, index: usize) -> Result<(), Error> {
+        let module = self.module;
+        let kind = &module.types[module.functions[index] as usize];
+        let body = &module.bodies[index];
+        let parameters = kind.parameters.len() as u64;
+        let mut reader = Reader::new(&body.code, body.offset);
+
+        self.locals = parameters + body.locals.len() as u64;
+        self.stack.clear();
+        self.dead = false;
+        self.skipping = 0;
+        self.offset = body.offset;
+
+        let label = self.functions[index];
+        let exit = self.label();
+
+        self.place(label);
+
+        for local in parameters..self.locals {
+            let slot = self.frame(8 * local);
+
+            self.push(Instruction::Mov(Operand::Value(0), slot));
+        }
+
+        self.frames.push(Control {
+            kind: Kind::Block,
+            height: 0,
+            parameters: 0,
+            results: kind.results.len(),
+            label: exit,
+            otherwise: exit,
+        });
+
+        while !self.frames.is_empty() {
+            let op = decode(&mut reader, &module.types)?;
+
+            self.offset = op.offset;
+
+            if self.dead {
+                match op.code {
+                    0x02..=0x04 => {
+                        self.skipping += 1;
+
+                        continue;
+                    }
+                    0x0b if self.skipping > 0 => {
+                        self.skipping -= 1;
+
+                        continue;
+                    }
+                    0x05 | 0x0b if self.skipping == 0 => {}
+
+                    _ => continue,
+                }
+            }
+
+            self.op(op)?;
+        }
+
+        match reader.is_empty() {
+            true => Ok(()),
+            false => reader.invalid(),
+        }
+    }
+
+    /// Emits the given routine.
+    fn routine(&mut self, routine: Routine) {
+        let head = self.label();
+        let end = self.label();
+
+        match routine {
+            Routine::And | Routine::Or | Routine::Xor => {
+                let skip = self.label();
+
+                self.push(Instruction::Mov(Operand::Value(0), register(3)));
+                self.push(Instruction::Mov(Operand::Value(0), register(7)));
+                self.place(head);
+                self.push(Instruction::Add(register(3), register(3), register(3)));
+                self.carry(0, 8);
+                self.carry(1, 9);
+                // 0, 4 or 8 by the number of bits set.
+                self.push(Instruction::Add(register(9), register(8), register(8)));
+
+                match routine {
+                    Routine::And => {
+                        self.push(Instruction::Cmp(register(8), Operand::Value(8)));
+                        self.jump(Instruction::Jnz, skip);
+                    }
+                    Routine::Or => {
+                        self.push(Instruction::Cmp(register(8), Operand::Value(0)));
+                        self.jump(Instruction::Jz, skip);
+                    }
+                    _ => {
+                        self.push(Instruction::Cmp(register(8), Operand::Value(4)));
+                        self.jump(Instruction::Jnz, skip);
+                    }
+                }
+
+                self.push(Instruction::Add(
+                    Operand::Value(1),
+                    register(3),
+                    register(3),
+                ));
+                self.place(skip);
+                self.push(Instruction::Add(
+                    Operand::Value(1),
+                    register(7),
+                    register(7),
+                ));
+                self.push(Instruction::Cmp(register(7), Operand::Value(64)));
+                self.jump(Instruction::Jnz, head);
+                self.push(Instruction::Mov(register(3), register(0)));
+            }
+            Routine::ShiftLeft => {
+                self.place(head);
+                self.push(Instruction::Cmp(register(1), Operand::Value(0)));
+                self.jump(Instruction::Jz, end);
+                self.push(Instruction::Add(register(0), register(0), register(0)));
+                self.push(Instruction::Add(
+                    Operand::Value(u64::MAX),
+                    register(1),
+                    register(1),
+                ));
+                self.jump(Instruction::Jmp, head);
+            }
+            Routine::ShiftRight | Routine::ShiftRightSigned => {
+                // The top bits are taken one by one into the result, after as many sign bits as
+                // are shifted out.
+                self.push(Instruction::Mov(Operand::Value(0), register(3)));
+
+                if routine == Routine::ShiftRightSigned {
+                    self.push(Instruction::Mov(register(0), register(4)));
+                    self.carry(4, 8);
+                    self.push(Instruction::Cmp(register(8), Operand::Value(0)));
+                    self.jump(Instruction::Jz, head);
+                    self.push(Instruction::Mov(Operand::Value(u64::MAX), register(3)));
+                }
+
+                let loop_end = self.label();
+
+                self.place(head);
+                self.push(Instruction::Cmp(register(1), Operand::Value(64)));
+                self.jump(Instruction::Jz, loop_end);
+                self.push(Instruction::Add(register(3), register(3), register(3)));
+                self.carry(0, 8);
+                self.push(Instruction::Add(
+                    Operand::Value(1),
+                    register(1),
+                    register(1),
+                ));
+                self.push(Instruction::Cmp(register(8), Operand::Value(0)));
+                self.jump(Instruction::Jz, head);
+                self.push(Instruction::Add(
+                    Operand::Value(1),
+                    register(3),
+                    register(3),
+                ));
+                self.jump(Instruction::Jmp, head);
+                self.place(loop_end);
+                self.push(Instruction::Mov(register(3), register(0)));
+            }
+            Routine::Rotate64 => {
+                self.place(head);
+                self.push(Instruction::Cmp(register(1), register(2)));
+                self.jump(Instruction::Jz, end);
+                self.carry(0, 8);
+                self.push(Instruction::Add(
+                    Operand::Value(1),
+                    register(1),
+                    register(1),
+                ));
+                self.push(Instruction::Cmp(register(8), Operand::Value(0)));
+                self.jump(Instruction::Jz, head);
+                self.push(Instruction::Add(
+                    Operand::Value(1),
+                    register(0),
+                    register(0),
+                ));
+                self.jump(Instruction::Jmp, head);
+            }
+            Routine::Rotate32 => {
+                self.place(head);
+                self.push(Instruction::Cmp(register(1), register(2)));
+                self.jump(Instruction::Jz, end);
+                self.push(Instruction::Add(register(0), register(0), register(0)));
+                self.push(Instruction::Add(
+                    Operand::Value(1),
+                    register(1),
+                    register(1),
+                ));
+                self.order(register(0), Operand::Value(u64::from(u32::MAX)), 8);
+                self.push(Instruction::Cmp(register(8), Operand::Value(2)));
+                self.jump(Instruction::Jnz, head);
+                // The bit shifted past the low 32 bits comes back as the lowest.
+                self.push(Instruction::Add(
+                    Operand::Value(1u64.wrapping_sub(1 << 32)),
+                    register(0),
+                    register(0),
+                ));
+                self.jump(Instruction::Jmp, head);
+            }
+            Routine::LeadingZeros => {
+                let found = self.label();
+
+                self.push(Instruction::Mov(Operand::Value(64), register(3)));
+                self.push(Instruction::Cmp(register(0), Operand::Value(0)));
+                self.jump(Instruction::Jz, found);
+                self.push(Instruction::Mov(Operand::Value(0), register(3)));
+                self.place(head);
+                self.push(Instruction::Mov(register(0), register(4)));
+                self.carry(4, 8);
+                self.push(Instruction::Cmp(register(8), Operand::Value(0)));
+                self.jump(Instruction::Jnz, found);
+                self.push(Instruction::Mov(register(4), register(0)));
+                self.push(Instruction::Add(
+                    Operand::Value(1),
+                    register(3),
+                    register(3),
+                ));
+                self.jump(Instruction::Jmp, head);
+                self.place(found);
+                self.push(Instruction::Mov(register(3), register(0)));
+            }
+            Routine::TrailingZeros => {
+                // Doubling shifts the lowest bit set out after as many times as bits above it.
+                let found = self.label();
+
+                self.push(Instruction::Mov(Operand::Value(64), register(3)));
+                self.place(head);
+                self.push(Instruction::Cmp(register(0), Operand::Value(0)));
+                self.jump(Instruction::Jz, found);
+                self.push(Instruction::Add(register(0), register(0), register(0)));
+                self.push(Instruction::Add(
+                    Operand::Value(u64::MAX),
+                    register(3),
+                    register(3),
+                ));
+                self.jump(Instruction::Jmp, head);
+                self.place(found);
+                self.push(Instruction::Mov(register(3), register(0)));
+            }
+            Routine::Population => {
+                let found = self.label();
+
+                self.push(Instruction::Mov(Operand::Value(0), register(3)));
+                self.place(head);
+                self.push(Instruction::Cmp(register(0), Operand::Value(0)));
+                self.jump(Instruction::Jz, found);
+                self.carry(0, 8);
+                self.push(Instruction::Cmp(register(8), Operand::Value(0)));
+                self.jump(Instruction::Jz, head);
+                self.push(Instruction::Add(
+                    Operand::Value(1),
+                    register(3),
+                    register(3),
+                ));
+                self.jump(Instruction::Jmp, head);
+                self.place(found);
+                self.push(Instruction::Mov(register(3), register(0)));
+            }
+            Routine::Multiply => {
+                let rest = self.label();
+                let found = self.label();
+
+                // Bits are taken from the top of `rq0`, adding `rq1` to the doubled product.
+                self.push(Instruction::Mov(Operand::Value(0), register(3)));
+                self.push(Instruction::Mov(Operand::Value(0), register(7)));
+                self.place(head);
+                self.push(Instruction::Cmp(register(0), Operand::Value(0)));
+                self.jump(Instruction::Jz, rest);
+                self.push(Instruction::Add(register(3), register(3), register(3)));
+                self.carry(0, 8);
+                self.push(Instruction::Add(
+                    Operand::Value(1),
+                    register(7),
+                    register(7),
+                ));
+                self.push(Instruction::Cmp(register(8), Operand::Value(0)));
+                self.jump(Instruction::Jz, head);
+                self.push(Instruction::Add(register(1), register(3), register(3)));
+                self.jump(Instruction::Jmp, head);
+                // The bits left are all clear.
+                self.place(rest);
+                self.push(Instruction::Cmp(register(7), Operand::Value(64)));
+                self.jump(Instruction::Jz, found);
+                self.push(Instruction::Add(register(3), register(3), register(3)));
+                self.push(Instruction::Add(
+                    Operand::Value(1),
+                    register(7),
+                    register(7),
+                ));
+                self.jump(Instruction::Jmp, rest);
+                self.place(found);
+                self.push(Instruction::Mov(register(3), register(0)));
+            }
+            Routine::Divide => self.divide(),
+            Routine::DivideSigned => {
+                let dividend = self.label();
+                let divisor = self.label();
+                let quotient = self.label();
+
+                self.push(Instruction::Mov(register(0), register(8)));
+                self.carry(8, 4);
+                self.push(Instruction::Cmp(register(4), Operand::Value(0)));
+                self.jump(Instruction::Jz, dividend);
+                self.negate(0);
+                self.place(dividend);
+                self.push(Instruction::Mov(register(1), register(8)));
+                self.carry(8, 10);
+                self.push(Instruction::Cmp(register(10), Operand::Value(0)));
+                self.jump(Instruction::Jz, divisor);
+                self.negate(1);
+                self.place(divisor);
+                self.divide();
+                // The quotient is negative when the signs differ, the remainder has the sign of
+                // the dividend.
+                self.push(Instruction::Cmp(register(4), register(10)));
+                self.jump(Instruction::Jz, quotient);
+                self.negate(0);
+                self.place(quotient);
+                self.push(Instruction::Cmp(register(4), Operand::Value(0)));
+                self.jump(Instruction::Jz, end);
+                self.negate(1);
+            }
+        }
+
+        self.place(end);
+        self.push(Instruction::Jmp(register(LINK)));
+    }
+
+    /// Divides `rq0` by `rq1` without sign by restoring division, leaving the quotient in `rq0`
+    /// and the remainder in `rq1`.
+    fn divide(&mut self) {
+        let skip = self.label();
+        let head = self.label();
+        let bit = self.label();
+        let subtract = self.label();
+        let next = self.label();
+        let end = self.label();
+
+        self.push(Instruction::Mov(register(1), register(5)));
+        self.negate(5);
+        self.push(Instruction::Mov(Operand::Value(0), register(2)));
+        self.push(Instruction::Mov(Operand::Value(0), register(3)));
+        self.push(Instruction::Mov(Operand::Value(0), register(7)));
+        // Leading zeros of the dividend leave the quotient and remainder 0.
+        self.place(skip);
+        self.push(Instruction::Cmp(register(0), Operand::Value(0)));
+        self.jump(Instruction::Jz, end);
+        self.push(Instruction::Mov(register(0), register(8)));
+        self.carry(8, 9);
+        self.push(Instruction::Cmp(register(9), Operand::Value(0)));
+        self.jump(Instruction::Jnz, head);
+        self.push(Instruction::Mov(register(8), register(0)));
+        self.push(Instruction::Add(
+            Operand::Value(1),
+            register(7),
+            register(7),
+        ));
+        self.jump(Instruction::Jmp, skip);
+        self.place(head);
+        self.push(Instruction::Cmp(register(7), Operand::Value(64)));
+        self.jump(Instruction::Jz, end);
+        self.carry(0, 8);
+        self.carry(3, 9);
+        self.push(Instruction::Add(register(2), register(2), register(2)));
+        self.push(Instruction::Add(
+            Operand::Value(1),
+            register(7),
+            register(7),
+        ));
+        self.push(Instruction::Cmp(register(8), Operand::Value(0)));
+        self.jump(Instruction::Jz, bit);
+        self.push(Instruction::Add(
+            Operand::Value(1),
+            register(3),
+            register(3),
+        ));
+        self.place(bit);
+        // A remainder overflowing when doubled exceeds the divisor.
+        self.push(Instruction::Cmp(register(9), Operand::Value(0)));
+        self.jump(Instruction::Jnz, subtract);
+        self.order(register(3), register(1), 9);
+        self.push(Instruction::Cmp(register(9), Operand::Value(0)));
+        self.jump(Instruction::Jz, next);
+        self.place(subtract);
+        self.push(Instruction::Add(register(5), register(3), register(3)));
+        self.push(Instruction::Add(
+            Operand::Value(1),
+            register(2),
+            register(2),
+        ));
+        self.place(next);
+        self.jump(Instruction::Jmp, head);
+        self.place(end);
+        self.push(Instruction::Mov(register(2), register(0)));
+        self.push(Instruction::Mov(register(3), register(1)));
+    }
+}
+
+/// Lowers every function of the given module.
+pub(crate) fn lower(module: &Module) -> Result<Lowered, Error> {
+    if module.globals.len() as u64 > (STACK - GLOBALS) / 8 {
+        return Err(Error::UnsupportedWasm(0));
+    }
+
+    let mut lowerer = Lowerer {
+        module,
+        // Patched to jump to the code running an export.
+        instructions: vec![Instruction::Jmp(Operand::Value(0))],
+        labels: Vec::new(),
+        fixups: Vec::new(),
+        routines: BTreeMap::new(),
+        complements: false,
+        functions: Vec::new(),
+        locals: 0,
+        stack: Vec::new(),
+        frames: Vec::new(),
+        dead: false,
+        skipping: 0,
+        offset: 0,
+    };
+
+    lowerer.functions = (0..module.functions.len())
+        .map(|_| lowerer.label())
+        .collect();
+
+    for index in 0..module.functions.len() {
+        lowerer.function(index)?;
+    }
+
+    for (routine, label) in lowerer.routines.clone() {
+        lowerer.place(label);
+        lowerer.routine(routine);
+    }
+
+    for (index, label) in std::mem::take(&mut lowerer.fixups) {
+        *first(&mut lowerer.instructions[index]) =
+            Operand::Value((lowerer.labels[label] as u64).wrapping_sub(1));
+    }
+
+    let functions = lowerer
+        .functions
+        .iter()
+        .map(|label| lowerer.labels[*label])
+        .collect();
+
+    Ok(Lowered {
+        instructions: lowerer.instructions,
+        functions,
+        complements: lowerer.complements,
+    })
+}
@@ -0,0 +1,817 @@
+//! Translation of WebAssembly modules into an [`Assembler`].
+//!
+//! The subset of the binary format translated covers the integer instructions of the MVP: `i32`
+//! and `i64` arithmetic, comparisons and conversions, locals and globals, blocks, loops, `if`,
+//! `br`, `br_if`, `br_table`, direct calls, and loads and stores to a single linear memory with
+//! its data segments. Imports, tables, floats and the instructions of later proposals are
+//! rejected with [`UnsupportedWasm`](Error::UnsupportedWasm).
+//!
+//! The linear memory lives in the memory of the [`Vm`](crate::Vm) from [`MEMORY`], and frames of
+//! functions, holding their locals and operand stack, are stacked up from [`STACK`]. Values are
+//! 64-bit words, `i32` values kept zero extended. Operations the instruction set lacks, such as
+//! bitwise operations, shifts, multiplications and divisions, are made of additions over the bits
+//! of their operands, so they take up to hundreds of instructions each. Accesses to the linear
+//! memory aren't bounds checked, and traps, such as divisions by zero or `unreachable`, call
+//! [`TRAP`], failing with [`UnknownCall`](Error::UnknownCall) unless a host call is registered
+//! under it.
+
+mod lower;
+mod reader;
+
+use crate::assembler::Assembler;
+use crate::error::Error;
+use crate::instructions::{Instruction, Operand};
+use crate::lowering::{self, complements, register};
+use crate::register::Width;
+use crate::wasm::lower::{lower, memory, Lowered};
+use crate::wasm::reader::{read, Module, ValueType};
+
+use std::collections::BTreeMap;
+
+/// Address of the first byte of the linear memory.
+pub const MEMORY: u64 = 0x1_0000_0000;
+
+/// Address of the frame of the export run, the frames of called functions following it.
+pub const STACK: u64 = 0x10_0000;
+
+/// Call index of traps.
+pub const TRAP: u64 = u64::MAX;
+
+/// Address of the current size of the linear memory in pages.
+const PAGES: u64 = 0x108;
+
+/// Address of the first global, each taking a word.
+const GLOBALS: u64 = 0x200;
+
+/// A parsed WebAssembly module, its functions lowered to instructions.
+pub struct Wasm {
+    module: Module,
+    lowered: Lowered,
+}
+
+impl Wasm {
+    /// Parses the given binary module and lowers its functions.
+    ///
+    /// # Errors
+    /// When the module is malformed, [`InvalidWasm`](Error::InvalidWasm) is returned with the
+    /// offset of the first byte at fault. When it uses features outside of the translated subset,
+    /// [`UnsupportedWasm`](Error::UnsupportedWasm) is returned with the offset of the first one.
+    pub fn parse(bytes: &[u8]) -> Result<Self, Error> {
+        let module = read(bytes)?;
+        let lowered = lower(&module)?;
+
+        Ok(Wasm { module, lowered })
+    }
+
+    /// Returns the names of the exported functions.
+    pub fn exports(&self) -> impl Iterator<Item = &str> {
+        self.module.exports.iter().map(|(name, _)| name.as_str())
+    }
+
+    /// Translates the module into an [`Assembler`] initializing the memory and globals, running
+    /// the start function if any, then the named export with the given arguments. Its result, if
+    /// any, is left in `rq0`. Functions are labelled by the name they are exported under, others
+    /// as `function` followed by their index.
+    ///
+    /// # Example
+    /// ```
+    /// use vm::wasm::Wasm;
+    /// // (func (export "answer") (result i32) i32.const 42)
+    /// let bytes = [
+    ///     0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, 0x01, 0x05, 0x01, 0x60, 0x00, 0x01, 0x7f,
+    ///     0x03, 0x02, 0x01, 0x00, 0x07, 0x0a, 0x01, 0x06, 0x61, 0x6e, 0x73, 0x77, 0x65, 0x72, 0x00,
+    ///     0x00, 0x0a, 0x06, 0x01, 0x04, 0x00, 0x41, 0x2a, 0x0b,
+    /// ];
+    /// let assembler = Wasm::parse(&bytes).unwrap().translate("answer", &[]).unwrap();
+    /// assert!(assembler.labels().contains_key("answer"));
+    /// ```
+    ///
+    /// # Errors
+    /// When no function is exported under the given name, [`UnknownSymbol`](Error::UnknownSymbol)
+    /// is returned. When the number of arguments doesn't match its parameters,
+    /// [`InvalidOperand`](Error::InvalidOperand) is returned.
+    pub fn translate(&self, export: &str, arguments: &[u64]) -> Result<Assembler, Error> {
+        let Some((_, function)) = self.module.exports.iter().find(|(name, _)| name == export)
+        else {
+            return Err(Error::UnknownSymbol);
+        };
+
+        let function = *function as usize;
+        let kind = &self.module.types[self.module.functions[function] as usize];
+
+        if kind.parameters.len() != arguments.len() {
+            return Err(Error::InvalidOperand);
+        }
+
+        let mut instructions = self.lowered.instructions.clone();
+
+        instructions[0] = Instruction::Jmp(Operand::Value(instructions.len() as u64 - 1));
+
+        if self.lowered.complements {
+            instructions.extend(complements(instructions.len(), 0, 1));
+        }
+
+        instructions.push(Instruction::Mov(
+            Operand::Value(self.module.pages),
+            memory(PAGES),
+        ));
+
+        for (index, global) in self.module.globals.iter().enumerate() {
+            instructions.push(Instruction::Mov(
+                Operand::Value(global.value),
+                memory(GLOBALS + 8 * index as u64),
+            ));
+        }
+
+        for data in &self.module.data {
+            let address = (MEMORY + data.offset) as usize;
+            let words = data.bytes.chunks_exact(8);
+            let rest = words.remainder();
+
+            for (index, word) in words.enumerate() {
+                instructions.push(Instruction::Mov(
+                    Operand::Value(u64::from_le_bytes(word.try_into().expect("chunks of 8"))),
+                    Operand::Memory(Width::QWord(address + 8 * index)),
+                ));
+            }
+
+            let address = address + data.bytes.len() - rest.len();
+
+            for (index, byte) in rest.iter().enumerate() {
+                instructions.push(Instruction::Mov(
+                    Operand::Value(u64::from(*byte)),
+                    Operand::Memory(Width::Byte(address + index)),
+                ));
+            }
+        }
+
+        let arguments = arguments
+            .iter()
+            .zip(&kind.parameters)
+            .map(|(argument, kind)| match kind {
+                ValueType::I32 => argument & u64::from(u32::MAX),
+                ValueType::I64 => *argument,
+            })
+            .collect();
+        let start = self.module.start.map(|start| (start as usize, Vec::new()));
+
+        for (function, arguments) in start.into_iter().chain([(function, arguments)]) {
+            let locals = self.module.types[self.module.functions[function] as usize]
+                .parameters
+                .len()
+                + self.module.bodies[function].locals.len();
+
+            instructions.push(Instruction::Mov(Operand::Value(STACK), register(13)));
+
+            for (index, argument) in arguments.into_iter().enumerate() {
+                instructions.push(Instruction::Mov(
+                    Operand::Value(argument),
+                    memory(STACK + 8 * index as u64),
+                ));
+            }
+
+            let back = instructions.len() as u64 + 2;
+
+            instructions.push(Instruction::Mov(
+                Operand::Value(back - 1),
+                memory(STACK + 8 * locals as u64),
+            ));
+            instructions.push(Instruction::Jmp(Operand::Value(
+                self.lowered.functions[function] as u64 - 1,
+            )));
+        }
+
+        if !kind.results.is_empty() {
+            instructions.push(Instruction::Mov(memory(STACK), register(0)));
+        }
+
+        let mut names = BTreeMap::new();
+
+        for (name, function) in &self.module.exports {
+            names
+                .entry(self.lowered.functions[*function as usize])
+                .or_insert_with(|| name.clone());
+        }
+
+        for (function, index) in self.lowered.functions.iter().enumerate() {
+            let name = format!("function{function}");
+
+            if !self
+                .module
+                .exports
+                .iter()
+                .any(|(export, _)| *export == name)
+            {
+                names.entry(*index).or_insert(name);
+            }
+        }
+
+        Ok(lowering::assemble(instructions, &names))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::Error;
+    use crate::testing::random;
+    use crate::wasm::{Wasm, MEMORY, TRAP};
+    use crate::Vm;
+
+    const I32: u8 = 0x7f;
+    const I64: u8 = 0x7e;
+
+    /// A function of a test module, exported as `f` followed by its index.
+    struct Function<'a> {
+        parameters: &'a [u8],
+        results: &'a [u8],
+        locals: &'a [u8],
+        /// Instructions of the body, without its final `end`.
+        code: &'a [u8],
+    }
+
+    fn leb128(mut value: u64) -> Vec<u8> {
+        let mut bytes = Vec::new();
+
+        loop {
+            let byte = (value & 0x7f) as u8;
+
+            value >>= 7;
+
+            if value == 0 {
+                bytes.push(byte);
+
+                return bytes;
+            }
+
+            bytes.push(byte | 0x80);
+        }
+    }
+
+    fn signed(mut value: i64) -> Vec<u8> {
+        let mut bytes = Vec::new();
+
+        loop {
+            let byte = (value & 0x7f) as u8;
+
+            value >>= 7;
+
+            if (value == 0 && byte & 0x40 == 0) || (value == -1 && byte & 0x40 != 0) {
+                bytes.push(byte);
+
+                return bytes;
+            }
+
+            bytes.push(byte | 0x80);
+        }
+    }
+
+    fn vector(items: &[Vec<u8>]) -> Vec<u8> {
+        let mut bytes = leb128(items.len() as u64);
+
+        for item in items {
+            bytes.extend(item);
+        }
+
+        bytes
+    }
+
+    /// Encodes a module of the given functions, with the given other sections by id.
+    fn module(functions: &[Function], sections: &[(u8, Vec<u8>)]) -> Vec<u8> {
+        let types: Vec<_> = functions
+            .iter()
+            .map(|function| {
+                let mut kind = vec![0x60];
+
+                kind.extend(leb128(function.parameters.len() as u64));
+                kind.extend(function.parameters);
+                kind.extend(leb128(function.results.len() as u64));
+                kind.extend(function.results);
+                kind
+            })
+            .collect();
+        let indices: Vec<_> = (0..functions.len() as u64).map(leb128).collect();
+        let exports: Vec<_> = (0..functions.len())
+            .map(|index| {
+                let name = format!("f{index}");
+                let mut export = leb128(name.len() as u64);
+
+                export.extend(name.as_bytes());
+                export.push(0);
+                export.extend(leb128(index as u64));
+                export
+            })
+            .collect();
+        let bodies: Vec<_> = functions
+            .iter()
+            .map(|function| {
+                let locals: Vec<_> = function.locals.iter().map(|kind| vec![1, *kind]).collect();
+                let mut body = vector(&locals);
+
+                body.extend(function.code);
+                body.push(0x0b);
+
+                let mut code = leb128(body.len() as u64);
+
+                code.extend(body);
+                code
+            })
+            .collect();
+
+        let mut sections = sections.to_vec();
+
+        sections.extend([
+            (1, vector(&types)),
+            (3, vector(&indices)),
+            (7, vector(&exports)),
+            (10, vector(&bodies)),
+        ]);
+        sections.sort_by_key(|(id, _)| *id);
+
+        let mut bytes = vec![0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00];
+
+        for (id, section) in sections {
+            bytes.push(id);
+            bytes.extend(leb128(section.len() as u64));
+            bytes.extend(section);
+        }
+
+        bytes
+    }
+
+    /// Runs the given export of the given module, returning `rq0` and the linear memory word at
+    /// the given address.
+    fn run(bytes: &[u8], export: &str, arguments: &[u64]) -> Result<(u64, u64), Error> {
+        let assembler = Wasm::parse(bytes)?.translate(export, arguments)?;
+        let mut vm = Vm::new();
+
+        vm.set_verify(true);
+        vm.load_instructions(assembler.compile())?;
+
+        let handle = vm.new_processor();
+        let processor = vm.processor_mut(handle)?;
+
+        processor.start()?;
+
+        let result = processor.register(0)?.as_u64();
+        let word = processor.memory()?.get_u64(MEMORY as usize);
+
+        Ok((result, word))
+    }
+
+    /// Runs a function of the given code taking and returning values of the given type.
+    fn function(kind: u8, parameters: usize, code: &[u8], arguments: &[u64]) -> Result<u64, Error> {
+        let parameters = vec![kind; parameters];
+        let bytes = module(
+            &[Function {
+                parameters: &parameters,
+                results: &[kind],
+                locals: &[],
+                code,
+            }],
+            &[],
+        );
+
+        run(&bytes, "f0", arguments).map(|(result, _)| result)
+    }
+
+    /// Returns the result of the binary operation of the given opcode, or `None` when it traps.
+    fn reference(code: u8, a: u64, b: u64) -> Option<u64> {
+        let wide = (0x51..=0x5a).contains(&code) || code >= 0x7c;
+        let bits = if wide { 64 } else { 32 };
+        let (a, b) = match wide {
+            true => (a, b),
+            false => (a & 0xffff_ffff, b & 0xffff_ffff),
+        };
+        // Sign extended values.
+        let (x, y) = match wide {
+            true => (a as i64, b as i64),
+            false => (a as u32 as i32 as i64, b as u32 as i32 as i64),
+        };
+        let shift = (b % bits) as u32;
+        let truncate = |value: u64| if wide { value } else { value & 0xffff_ffff };
+
+        let code = match code {
+            0x46..=0x4f => code - 0x46,
+            0x51..=0x5a => code - 0x51,
+            0x6a..=0x78 => code - 0x6a + 10,
+            _ => code - 0x7c + 10,
+        };
+
+        let result = match code {
+            0 => u64::from(a == b),
+            1 => u64::from(a != b),
+            2 => u64::from(x < y),
+            3 => u64::from(a < b),
+            4 => u64::from(x > y),
+            5 => u64::from(a > b),
+            6 => u64::from(x <= y),
+            7 => u64::from(a <= b),
+            8 => u64::from(x >= y),
+            9 => u64::from(a >= b),
+            10 => a.wrapping_add(b),
+            11 => a.wrapping_sub(b),
+            12 => a.wrapping_mul(b),
+            13 if y == 0 || (y == -1 && x == if wide { i64::MIN } else { i32::MIN.into() }) => {
+                return None
+            }
+            13 => x.wrapping_div(y) as u64,
+            14 | 16 if b == 0 => return None,
+            14 => a / b,
+            15 if y == 0 => return None,
+            15 => x.wrapping_rem(y) as u64,
+            16 => a % b,
+            17 => a & b,
+            18 => a | b,
+            19 => a ^ b,
+            20 => a << shift,
+            21 => (x >> shift) as u64,
+            22 => a >> shift,
+            _ if wide && code == 23 => a.rotate_left(shift),
+            _ if wide => a.rotate_right(shift),
+            23 => u64::from((a as u32).rotate_left(shift)),
+            _ => u64::from((a as u32).rotate_right(shift)),
+        };
+
+        Some(truncate(result))
+    }
+
+    /// Returns a random 64-bit operand, [`random`] drawing 31 bits at a time.
+    fn word(seed: &mut u64) -> u64 {
+        (random(seed, 1 << 31) << 42) ^ (random(seed, 1 << 31) << 21) ^ random(seed, 1 << 31)
+    }
+
+    #[test]
+    pub fn wasm_binary_operations() {
+        let mut seed = 0x2545_f491_4f6c_dd1d;
+        let edges = [
+            0,
+            1,
+            2,
+            31,
+            32,
+            63,
+            64,
+            0x7fff_ffff,
+            0x8000_0000,
+            0xffff_ffff,
+            1 << 63,
+        ];
+        let codes = (0x46..=0x4f)
+            .chain(0x51..=0x5a)
+            .chain(0x6a..=0x78)
+            .chain(0x7c..=0x8a);
+
+        for code in codes {
+            let kind = if (0x51..=0x5a).contains(&code) || code >= 0x7c {
+                I64
+            } else {
+                I32
+            };
+            let constant = if kind == I64 { 0x42 } else { 0x41 };
+            let edge = |seed: &mut u64| edges[random(seed, edges.len() as u64) as usize];
+            let mut inputs: Vec<(u64, u64)> =
+                (0..6).map(|_| (word(&mut seed), word(&mut seed))).collect();
+
+            inputs.push((word(&mut seed) >> 40, word(&mut seed) >> 60));
+            inputs.push((word(&mut seed), edge(&mut seed)));
+            inputs.push((edge(&mut seed), u64::MAX));
+            inputs.push((edge(&mut seed), random(&mut seed, 70)));
+
+            for (a, b) in inputs {
+                let expected = reference(code, a, b).ok_or(Error::UnknownCall(TRAP));
+                let (a, b) = match kind {
+                    I32 => (a & 0xffff_ffff, b & 0xffff_ffff),
+                    _ => (a, b),
+                };
+                // Sign extended the way constants are encoded.
+                let extend = |value: u64| match kind {
+                    I32 => value as u32 as i32 as i64,
+                    _ => value as i64,
+                };
+
+                let mut right = vec![0x20, 0x00, constant];
+
+                right.extend(signed(extend(b)));
+                right.push(code);
+
+                let mut left = vec![constant];
+
+                left.extend(signed(extend(a)));
+                left.extend([0x20, 0x00, code]);
+
+                assert_eq!(
+                    function(kind, 2, &[0x20, 0x00, 0x20, 0x01, code], &[a, b]),
+                    expected,
+                    "{code:#x} {a:#x} {b:#x}"
+                );
+                assert_eq!(
+                    function(kind, 1, &right, &[a]),
+                    expected,
+                    "{code:#x} {a:#x} {b:#x}"
+                );
+                assert_eq!(
+                    function(kind, 1, &left, &[b]),
+                    expected,
+                    "{code:#x} {a:#x} {b:#x}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    pub fn wasm_unary_operations() {
+        let inputs = [
+            0,
+            1,
+            0x80,
+            0x8000,
+            0x7fff_ffff,
+            0x8000_0000,
+            0xf0f0,
+            1 << 40,
+            1 << 63,
+            u64::MAX,
+        ];
+
+        for code in [
+            0x45, 0x50, 0x67, 0x68, 0x69, 0x79, 0x7a, 0x7b, 0xa7, 0xac, 0xad, 0xc0, 0xc1, 0xc2,
+            0xc3, 0xc4,
+        ] {
+            let (parameter, result) = match code {
+                0x50 | 0xa7 => (I64, I32),
+                0xac | 0xad => (I32, I64),
+                0x79..=0x7b | 0xc2..=0xc4 => (I64, I64),
+                _ => (I32, I32),
+            };
+
+            for input in inputs {
+                let a = if parameter == I32 {
+                    input & 0xffff_ffff
+                } else {
+                    input
+                };
+                let expected = match code {
+                    0x45 | 0x50 => u64::from(a == 0),
+                    0x67 => u64::from((a as u32).leading_zeros()),
+                    0x68 => u64::from((a as u32).trailing_zeros()),
+                    0x69 => u64::from(a.count_ones()),
+                    0x79 => u64::from(a.leading_zeros()),
+                    0x7a => u64::from(a.trailing_zeros()),
+                    0x7b => u64::from(a.count_ones()),
+                    0xa7 => a & 0xffff_ffff,
+                    0xac => a as u32 as i32 as u64,
+                    0xad => a,
+                    0xc0 => u64::from(a as u8 as i8 as u32),
+                    0xc1 => u64::from(a as u16 as i16 as u32),
+                    0xc2 => a as u8 as i8 as u64,
+                    0xc3 => a as u16 as i16 as u64,
+                    _ => a as u32 as i32 as u64,
+                };
+                let bytes = module(
+                    &[Function {
+                        parameters: &[parameter],
+                        results: &[result],
+                        locals: &[],
+                        code: &[0x20, 0x00, code],
+                    }],
+                    &[],
+                );
+
+                assert_eq!(
+                    run(&bytes, "f0", &[a]),
+                    Ok((expected, 0)),
+                    "{code:#x} {a:#x}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    pub fn wasm_control_flow() {
+        // Sums the numbers up to the parameter in a loop.
+        let sum = [
+            0x02, 0x40, 0x03, 0x40, 0x20, 0x00, 0x45, 0x0d, 0x01, 0x20, 0x01, 0x20, 0x00, 0x6a,
+            0x21, 0x01, 0x20, 0x00, 0x41, 0x01, 0x6b, 0x21, 0x00, 0x0c, 0x00, 0x0b, 0x0b, 0x20,
+            0x01,
+        ];
+
+        assert_eq!(function_with_local(&sum, 100), Ok(5050));
+        assert_eq!(function_with_local(&sum, 0), Ok(0));
+
+        // Returns 10, 20 or 30 by a table of branches, with dead code after returns.
+        let switch = [
+            0x02, 0x40, 0x02, 0x40, 0x02, 0x40, 0x20, 0x00, 0x0e, 0x02, 0x00, 0x01, 0x02, 0x0b,
+            0x41, 0x0a, 0x0f, 0x02, 0x40, 0x41, 0x01, 0x1a, 0x0b, 0x0b, 0x41, 0x14, 0x0f, 0x0b,
+            0x41, 0x1e,
+        ];
+
+        assert_eq!(function(I32, 1, &switch, &[0]), Ok(10));
+        assert_eq!(function(I32, 1, &switch, &[1]), Ok(20));
+        assert_eq!(function(I32, 1, &switch, &[5]), Ok(30));
+
+        // A block left early with a value, followed by a selection.
+        let select = [
+            0x02, 0x7f, 0x41, 0x07, 0x20, 0x00, 0x0d, 0x00, 0x1a, 0x41, 0x08, 0x0b, 0x41, 0xe4,
+            0x00, 0x41, 0xc8, 0x01, 0x20, 0x00, 0x1b, 0x6a,
+        ];
+
+        assert_eq!(function(I32, 1, &select, &[1]), Ok(107));
+        assert_eq!(function(I32, 1, &select, &[0]), Ok(208));
+
+        // Recursive factorial, with an `if` giving a value.
+        let factorial = [
+            0x20, 0x00, 0x50, 0x04, 0x7e, 0x42, 0x01, 0x05, 0x20, 0x00, 0x20, 0x00, 0x42, 0x01,
+            0x7d, 0x10, 0x00, 0x7e, 0x0b,
+        ];
+
+        assert_eq!(
+            function(I64, 1, &factorial, &[20]),
+            Ok(2_432_902_008_176_640_000)
+        );
+        assert_eq!(
+            function(I32, 0, &[0x00], &[]),
+            Err(Error::UnknownCall(TRAP))
+        );
+    }
+
+    /// Runs a function of the given code taking an `i32` and having an `i32` local.
+    fn function_with_local(code: &[u8], argument: u64) -> Result<u64, Error> {
+        let bytes = module(
+            &[Function {
+                parameters: &[I32],
+                results: &[I32],
+                locals: &[I32],
+                code,
+            }],
+            &[],
+        );
+
+        run(&bytes, "f0", &[argument]).map(|(result, _)| result)
+    }
+
+    #[test]
+    pub fn wasm_calls() {
+        // 1000 plus twice the sum of the first argument times 10 and the second.
+        let bytes = module(
+            &[
+                Function {
+                    parameters: &[I32, I32],
+                    results: &[I32],
+                    locals: &[],
+                    code: &[0x41, 0xe8, 0x07, 0x20, 0x00, 0x20, 0x01, 0x10, 0x01, 0x6a],
+                },
+                Function {
+                    parameters: &[I32, I32],
+                    results: &[I32],
+                    locals: &[I32],
+                    code: &[
+                        0x20, 0x00, 0x41, 0x0a, 0x6c, 0x20, 0x01, 0x6a, 0x22, 0x02, 0x20, 0x02,
+                        0x6a,
+                    ],
+                },
+            ],
+            &[],
+        );
+
+        assert_eq!(run(&bytes, "f0", &[3, 4]), Ok((1068, 0)));
+        assert_eq!(run(&bytes, "f1", &[1, 2]), Ok((24, 0)));
+        assert_eq!(
+            Wasm::parse(&bytes).unwrap().exports().collect::<Vec<_>>(),
+            ["f0", "f1"]
+        );
+    }
+
+    #[test]
+    pub fn wasm_memory() {
+        let mut data = vec![1, 0x00, 0x41, 0x10, 0x0b, 10];
+
+        data.extend([0x80, 0xff, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08]);
+
+        let mut stores = vec![0x41, 0x00, 0x42];
+
+        stores.extend(signed(0x1122_3344_5566_7788));
+        stores.extend([0x37, 0x03, 0x00, 0x41, 0x01, 0x41]);
+        stores.extend(signed(0xab));
+        stores.extend([0x3a, 0x00, 0x00, 0x41, 0x02, 0x41]);
+        stores.extend(signed(0xcdef));
+        stores.extend([0x3b, 0x01, 0x00, 0x41, 0x04, 0x41, 0x00, 0x6a, 0x41]);
+        stores.extend(signed(0x7f));
+        stores.extend([0x3a, 0x00, 0x00]);
+
+        let mut grow = vec![
+            0x3f, 0x00, 0x41, 0x02, 0x40, 0x00, 0x41, 0x0a, 0x6c, 0x6a, 0x3f, 0x00, 0x41, 0xe4,
+            0x00, 0x6c, 0x6a, 0x41,
+        ];
+
+        grow.extend(signed(70000));
+        grow.extend([0x40, 0x00, 0x41, 0x01, 0x6a, 0x6a]);
+
+        let bytes = module(
+            &[
+                Function {
+                    parameters: &[],
+                    results: &[I64],
+                    locals: &[],
+                    code: &[
+                        0x41, 0x00, 0x2c, 0x00, 0x10, 0xad, 0x41, 0x10, 0x33, 0x01, 0x00, 0x7c,
+                        0x41, 0x11, 0x34, 0x02, 0x00, 0x7c, 0x41, 0x12, 0x29, 0x03, 0x00, 0x7c,
+                        0x41, 0x10, 0x2e, 0x01, 0x00, 0xac, 0x7c,
+                    ],
+                },
+                Function {
+                    parameters: &[],
+                    results: &[],
+                    locals: &[],
+                    code: &stores,
+                },
+                Function {
+                    parameters: &[],
+                    results: &[I32],
+                    locals: &[],
+                    code: &[0x23, 0x00, 0x41, 0x01, 0x6a, 0x24, 0x00, 0x23, 0x00],
+                },
+                Function {
+                    parameters: &[],
+                    results: &[I32],
+                    locals: &[],
+                    code: &grow,
+                },
+                Function {
+                    parameters: &[],
+                    results: &[],
+                    locals: &[],
+                    code: &[0x41, 0x29, 0x24, 0x00],
+                },
+            ],
+            &[
+                (5, vec![1, 0x00, 0x01]),
+                (6, vec![1, I32, 0x01, 0x41, 0x05, 0x0b]),
+                (8, vec![4]),
+                (11, data),
+            ],
+        );
+
+        let loads = 0xffff_ff80u64
+            .wrapping_add(0xff80)
+            .wrapping_add(0x0302_01ff)
+            .wrapping_add(0x0807_0605_0403_0201)
+            .wrapping_add(0xffff_ffff_ffff_ff80);
+
+        assert_eq!(run(&bytes, "f0", &[]), Ok((loads, 0)));
+        assert_eq!(
+            run(&bytes, "f1", &[]).map(|(_, word)| word),
+            Ok(0x1122_337f_cdef_ab88)
+        );
+        // The start function sets the global to 41 first.
+        assert_eq!(run(&bytes, "f2", &[]), Ok((42, 0)));
+        assert_eq!(run(&bytes, "f3", &[]), Ok((311, 0)));
+    }
+
+    #[test]
+    pub fn wasm_errors() {
+        let bytes = module(
+            &[Function {
+                parameters: &[I32],
+                results: &[I32],
+                locals: &[],
+                code: &[0x20, 0x00],
+            }],
+            &[],
+        );
+
+        assert!(Wasm::parse(&bytes).is_ok());
+        assert_eq!(run(&bytes, "g", &[1]), Err(Error::UnknownSymbol));
+        assert_eq!(run(&bytes, "f0", &[]), Err(Error::InvalidOperand));
+
+        let mut version = bytes.clone();
+
+        version[4] = 2;
+
+        assert_eq!(Wasm::parse(&version).err(), Some(Error::InvalidWasm(0)));
+        assert!(matches!(
+            Wasm::parse(&bytes[..bytes.len() - 1]),
+            Err(Error::InvalidWasm(_))
+        ));
+
+        // Adding values missing from the stack, then a float constant.
+        for (code, error) in [
+            (&[0x20, 0x00, 0x6a][..], Error::InvalidWasm(bytes.len() - 1)),
+            (
+                &[0x43, 0, 0, 0, 0, 0x1a, 0x20, 0x00],
+                Error::UnsupportedWasm(bytes.len() - 3),
+            ),
+        ] {
+            let bytes = module(
+                &[Function {
+                    parameters: &[I32],
+                    results: &[I32],
+                    locals: &[],
+                    code,
+                }],
+                &[],
+            );
+
+            assert_eq!(Wasm::parse(&bytes).err(), Some(error));
+        }
+    }
+}
@@ -0,0 +1,378 @@
+use crate::error::Error;
+
+/// Most pages a 32-bit linear memory can have.
+const PAGES: u64 = 0x10000;
+
+/// Magic number and version 1 opening every module.
+const PREAMBLE: [u8; 8] = [0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ValueType {
+    I32,
+    I64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct FunctionType {
+    pub(crate) parameters: Vec<ValueType>,
+    pub(crate) results: Vec<ValueType>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Global {
+    pub(crate) kind: ValueType,
+    pub(crate) value: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Body {
+    /// Types of the locals after the parameters.
+    pub(crate) locals: Vec<ValueType>,
+    /// Offset of the first instruction in the module.
+    pub(crate) offset: usize,
+    pub(crate) code: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Data {
+    pub(crate) offset: u64,
+    pub(crate) bytes: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct Module {
+    pub(crate) types: Vec<FunctionType>,
+    /// Type of each function.
+    pub(crate) functions: Vec<u32>,
+    pub(crate) bodies: Vec<Body>,
+    /// Initial size of the linear memory in 64KiB pages.
+    pub(crate) pages: u64,
+    /// Most pages the linear memory can grow to.
+    pub(crate) maximum: u64,
+    pub(crate) globals: Vec<Global>,
+    /// Exported functions by name.
+    pub(crate) exports: Vec<(String, u32)>,
+    pub(crate) start: Option<u32>,
+    pub(crate) data: Vec<Data>,
+}
+
+/// Cursor over bytes of a module, reporting errors at offsets within the whole module.
+pub(crate) struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+    /// Offset of the first byte within the module.
+    base: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub(crate) fn new(bytes: &'a [u8], base: usize) -> Self {
+        Reader {
+            bytes,
+            position: 0,
+            base,
+        }
+    }
+
+    /// Returns the offset of the next byte within the module.
+    pub(crate) fn offset(&self) -> usize {
+        self.base + self.position
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.position == self.bytes.len()
+    }
+
+    pub(crate) fn invalid<T>(&self) -> Result<T, Error> {
+        Err(Error::InvalidWasm(self.offset()))
+    }
+
+    pub(crate) fn byte(&mut self) -> Result<u8, Error> {
+        match self.bytes.get(self.position) {
+            Some(byte) => {
+                self.position += 1;
+
+                Ok(*byte)
+            }
+
+            None => self.invalid(),
+        }
+    }
+
+    fn take(&mut self, length: usize) -> Result<&'a [u8], Error> {
+        match self
+            .position
+            .checked_add(length)
+            .and_then(|end| self.bytes.get(self.position..end))
+        {
+            Some(bytes) => {
+                self.position += length;
+
+                Ok(bytes)
+            }
+
+            None => self.invalid(),
+        }
+    }
+
+    /// Reads a LEB128 number of at most the given bits, sign extended when signed.
+    fn leb128(&mut self, bits: u32, signed: bool) -> Result<u64, Error> {
+        let mut value = 0u64;
+        let mut shift = 0;
+
+        loop {
+            let byte = self.byte()?;
+
+            if shift >= bits {
+                return self.invalid();
+            }
+
+            value |= u64::from(byte & 0x7f) << shift;
+            shift += 7;
+
+            if byte & 0x80 == 0 {
+                if signed && shift < 64 && byte & 0x40 != 0 {
+                    value |= u64::MAX << shift;
+                }
+
+                return Ok(value);
+            }
+        }
+    }
+
+    pub(crate) fn u32(&mut self) -> Result<u32, Error> {
+        let offset = self.offset();
+
+        u32::try_from(self.leb128(32, false)?).map_err(|_| Error::InvalidWasm(offset))
+    }
+
+    pub(crate) fn s32(&mut self) -> Result<u32, Error> {
+        // Truncating keeps the two's complement representation.
+        Ok(self.leb128(32, true)? as u32)
+    }
+
+    pub(crate) fn s64(&mut self) -> Result<u64, Error> {
+        self.leb128(64, true)
+    }
+
+    /// Reads a signed 33-bit number, as used by block types.
+    pub(crate) fn s33(&mut self) -> Result<i64, Error> {
+        Ok(self.leb128(33, true)? as i64)
+    }
+
+    pub(crate) fn peek(&self) -> Option<u8> {
+        self.bytes.get(self.position).copied()
+    }
+
+    fn length(&mut self) -> Result<usize, Error> {
+        Ok(self.u32()? as usize)
+    }
+
+    fn name(&mut self) -> Result<String, Error> {
+        let offset = self.offset();
+        let length = self.length()?;
+
+        String::from_utf8(self.take(length)?.to_vec()).map_err(|_| Error::InvalidWasm(offset))
+    }
+
+    pub(crate) fn value_type(&mut self) -> Result<ValueType, Error> {
+        let offset = self.offset();
+
+        match self.byte()? {
+            0x7f => Ok(ValueType::I32),
+            0x7e => Ok(ValueType::I64),
+            0x7d | 0x7c | 0x7b | 0x70 | 0x6f => Err(Error::UnsupportedWasm(offset)),
+
+            _ => Err(Error::InvalidWasm(offset)),
+        }
+    }
+
+    /// Reads a constant expression, only integer constants are supported.
+    fn constant(&mut self) -> Result<u64, Error> {
+        let offset = self.offset();
+        let value = match self.byte()? {
+            0x41 => u64::from(self.s32()?),
+            0x42 => self.s64()?,
+
+            _ => return Err(Error::UnsupportedWasm(offset)),
+        };
+
+        match self.byte()? {
+            0x0b => Ok(value),
+
+            _ => Err(Error::UnsupportedWasm(offset)),
+        }
+    }
+
+    pub(crate) fn vector<T>(
+        &mut self,
+        mut item: impl FnMut(&mut Self) -> Result<T, Error>,
+    ) -> Result<Vec<T>, Error> {
+        let count = self.u32()?;
+
+        (0..count).map(|_| item(self)).collect()
+    }
+}
+
+/// Reads the sections of a module.
+pub(crate) fn read(bytes: &[u8]) -> Result<Module, Error> {
+    let mut reader = Reader::new(bytes, 0);
+    let mut module = Module {
+        maximum: PAGES,
+        ..Module::default()
+    };
+
+    if reader.take(PREAMBLE.len()).ok() != Some(&PREAMBLE[..]) {
+        return Err(Error::InvalidWasm(0));
+    }
+
+    while !reader.is_empty() {
+        let id = reader.byte()?;
+        let length = reader.length()?;
+        let base = reader.offset();
+        let mut section = Reader::new(reader.take(length)?, base);
+
+        match id {
+            // Custom sections carry no semantics.
+            0 => continue,
+            1 => {
+                module.types = section.vector(|reader| {
+                    if reader.byte()? != 0x60 {
+                        return Err(Error::InvalidWasm(reader.offset() - 1));
+                    }
+
+                    Ok(FunctionType {
+                        parameters: reader.vector(Reader::value_type)?,
+                        results: reader.vector(Reader::value_type)?,
+                    })
+                })?;
+            }
+            // Tables are only used by indirect calls, the data count by bulk memory instructions.
+            4 | 12 => section.position = section.bytes.len(),
+            3 => module.functions = section.vector(Reader::u32)?,
+            5 => {
+                let memories = section.vector(|reader| {
+                    let flags = reader.byte()?;
+                    let minimum = reader.u32()?;
+
+                    match flags {
+                        0 => Ok((minimum, PAGES)),
+                        1 => Ok((minimum, u64::from(reader.u32()?).min(PAGES))),
+
+                        _ => reader.invalid(),
+                    }
+                })?;
+
+                match memories[..] {
+                    [] => {}
+                    [(pages, maximum)] => {
+                        module.pages = u64::from(pages);
+                        module.maximum = maximum;
+                    }
+
+                    _ => return Err(Error::UnsupportedWasm(base)),
+                }
+            }
+            6 => {
+                module.globals = section.vector(|reader| {
+                    let kind = reader.value_type()?;
+
+                    if reader.byte()? > 1 {
+                        return reader.invalid();
+                    }
+
+                    Ok(Global {
+                        kind,
+                        value: reader.constant()?,
+                    })
+                })?;
+            }
+            7 => {
+                for (name, kind, index) in
+                    section.vector(|reader| Ok((reader.name()?, reader.byte()?, reader.u32()?)))?
+                {
+                    if kind == 0 {
+                        module.exports.push((name, index));
+                    }
+                }
+            }
+            8 => module.start = Some(section.u32()?),
+            10 => {
+                module.bodies = section.vector(|reader| {
+                    let length = reader.length()?;
+                    let base = reader.offset();
+                    let mut body = Reader::new(reader.take(length)?, base);
+                    let mut locals = Vec::new();
+
+                    for (count, kind) in
+                        body.vector(|reader| Ok((reader.u32()?, reader.value_type()?)))?
+                    {
+                        if locals.len() + count as usize > u16::MAX as usize {
+                            return Err(Error::UnsupportedWasm(base));
+                        }
+
+                        locals.extend(std::iter::repeat_n(kind, count as usize));
+                    }
+
+                    Ok(Body {
+                        locals,
+                        offset: body.offset(),
+                        code: body.bytes[body.position..].to_vec(),
+                    })
+                })?;
+            }
+            11 => {
+                module.data = section.vector(|reader| {
+                    if reader.u32()? != 0 {
+                        return Err(Error::UnsupportedWasm(reader.offset()));
+                    }
+
+                    let offset = reader.constant()?;
+                    let length = reader.length()?;
+
+                    Ok(Data {
+                        offset: u64::from(offset as u32),
+                        bytes: reader.take(length)?.to_vec(),
+                    })
+                })?;
+            }
+
+            // Imports and tables of functions are outside of the subset.
+            2 | 9 => return Err(Error::UnsupportedWasm(base)),
+            _ => return Err(Error::InvalidWasm(base - 1)),
+        }
+
+        if !section.is_empty() {
+            return section.invalid();
+        }
+    }
+
+    if module.functions.len() != module.bodies.len()
+        || module.functions.iter().any(|kind| {
+            module
+                .types
+                .get(*kind as usize)
+                .is_none_or(|kind| kind.results.len() > 1)
+        })
+    {
+        return Err(Error::InvalidWasm(bytes.len()));
+    }
+
+    let exports = module.exports.iter().map(|(_, index)| index);
+
+    if exports
+        .chain(&module.start)
+        .any(|index| *index as usize >= module.functions.len())
+    {
+        return Err(Error::InvalidWasm(bytes.len()));
+    }
+
+    if let Some(start) = module.start {
+        let kind = &module.types[module.functions[start as usize] as usize];
+
+        if !kind.parameters.is_empty() || !kind.results.is_empty() {
+            return Err(Error::InvalidWasm(bytes.len()));
+        }
+    }
+
+    Ok(module)
+}
@@ -0,0 +1,35 @@
+//! Runs every program of the Brainfuck corpus and compares its output.
+//!
+//! Each program `name.b` in `brainfuck/` is fed `name.in` when it exists, and must print `name.out`.
+
+use vm::brainfuck::run;
+
+use std::fs;
+
+const CORPUS: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/brainfuck");
+
+#[test]
+pub fn brainfuck_corpus() {
+    let mut programs = fs::read_dir(CORPUS)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|extension| extension == "b"))
+        .collect::<Vec<_>>();
+
+    programs.sort();
+    assert!(!programs.is_empty());
+
+    for program in programs {
+        let read = |extension: &str| fs::read(program.with_extension(extension));
+        let source = fs::read_to_string(&program).unwrap();
+        let input = read("in").unwrap_or_default();
+        let expected = read("out").unwrap();
+
+        assert_eq!(
+            run(&source, &input).unwrap(),
+            expected,
+            "{}",
+            program.display()
+        );
+    }
+}
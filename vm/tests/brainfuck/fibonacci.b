@@ -0,0 +1,38 @@
+Prints the first thirteen Fibonacci numbers in decimal
+
+Cells
+  0  terms left to print
+  1  a
+  2  b
+  3  scratch for copying a
+  4 to 13  digits of a
+
++++++++++++++ >+ >+ <<
+[
+    copy a into cell 4 through cell 3
+    >[->>+>+<<<] >>[-<<+>>]
+
+    divide cell 4 by ten leaving its remainder in cell 7 and quotient in cell 8
+    >>>++++++++++<<
+    [->+>-[>+>>]>[+[-<+>]>+>>]<<<<<<]
+
+    divide cell 8 by ten leaving its remainder in cell 11 and quotient in cell 12
+    >>>>>>++++++++++<<
+    [->+>-[>+>>]>[+[-<+>]>+>>]<<<<<<]
+
+    hundreds digit when not zero
+    >>>>[>++++++++[<++++++>-]<.[-]]
+    tens digit when cell 9 holding a divided by ten is not zero
+    <<<[>>>++++++++[<++++++>-]<.[-]<<[-]]
+    units digit then a space
+    <++++++++[<++++++>-]<.[-] >++++++++[<++++>-]<.[-]
+
+    clear what is left of the division
+    <[-]<[-] >>>>>[-]
+
+    replace a and b by b and a plus b
+    <<<<<<<<<[->>+<<] >[-<+>>+<] >[-<+>]
+
+    <<<-
+]
+++++++++++.
@@ -0,0 +1,3 @@
+Prints its input backwards
+The first cell stays zero to stop the way back
+>,[>,]<[.<]
@@ -0,0 +1,18 @@
+Prints a triangle of stars five rows high
+
+Cells
+  0  rows left
+  1  stars in the row
+  2  stars left to print in the row
+  3  scratch for copying
+  4  star
+  5  newline
+
++++++ >+ >> ++++++[>+++++++<-] >> ++++++++++ <<<<<
+[
+    copy the stars in the row into cell 2 through cell 3
+    >[->+>+<<] >>[-<<+>>] <
+    [->>.<<] >>>.
+    one more star in the next row
+    <<<<+<-
+]
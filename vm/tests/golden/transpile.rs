@@ -0,0 +1,167 @@
+// Generated by `vm::transpile`, do not edit.
+
+use vm::error::Error;
+use vm::processor::Processor;
+
+/// Runs the program on the processor until its instruction counter leaves the program.
+///
+/// # Errors
+/// Returns the error the interpreter would return, with the registers it would leave.
+pub fn run(processor: &mut Processor) -> Result<(), Error> {
+    let mut r0 = processor.register(0)?.as_u64();
+    let mut r1 = processor.register(1)?.as_u64();
+    let mut r2 = processor.register(2)?.as_u64();
+    let mut r3 = processor.register(3)?.as_u64();
+    let mut r4 = processor.register(4)?.as_u64();
+    let mut r14 = processor.register(14)?.as_u64();
+    let mut counter = processor.register(15)?.as_u64();
+
+    // Writes the locals back, with the instruction counter at the given index.
+    macro_rules! save {
+        ($counter:expr) => {
+            processor.register_mut(0)?.assign_u64(r0);
+            processor.register_mut(1)?.assign_u64(r1);
+            processor.register_mut(2)?.assign_u64(r2);
+            processor.register_mut(3)?.assign_u64(r3);
+            processor.register_mut(4)?.assign_u64(r4);
+            processor.register_mut(14)?.assign_u64(r14);
+            processor.register_mut(15)?.assign_u64($counter);
+        };
+    }
+
+    // Reads the locals again, after a host function may have changed them.
+    macro_rules! load {
+        () => {
+            r0 = processor.register(0)?.as_u64();
+            r1 = processor.register(1)?.as_u64();
+            r2 = processor.register(2)?.as_u64();
+            r3 = processor.register(3)?.as_u64();
+            r4 = processor.register(4)?.as_u64();
+            r14 = processor.register(14)?.as_u64();
+        };
+    }
+
+    loop {
+        match counter {
+            0 => {
+                // 0: Mov(Value(0), Register(QWord(0)))
+                r0 = 0;
+                // 1: Mov(Value(1000), Register(QWord(1)))
+                r1 = 1000;
+                // 2: Mov(Value(24), Register(QWord(3)))
+                r3 = 24;
+                counter = 3;
+            }
+            // Entry into the middle of a block.
+            1 => {
+                // 1: Mov(Value(1000), Register(QWord(1)))
+                r1 = 1000;
+                counter = 2;
+            }
+            // Entry into the middle of a block.
+            2 => {
+                // 2: Mov(Value(24), Register(QWord(3)))
+                r3 = 24;
+                counter = 3;
+            }
+            3 => {
+                // 3: Add(Value(1), Register(QWord(0)), Register(QWord(0)))
+                let (result, overflow) = u64::overflowing_add(r0, 1);
+                if overflow { r14 |= 4; }
+                r0 = result;
+                // 4: Add(Register(QWord(0)), Memory(QWord(8)), Memory(QWord(8)))
+                let (result, overflow) = u64::overflowing_add(match processor.memory().map(|memory| memory.get_u64(8)) { Ok(value) => value, Err(error) => { save!(4); return Err(error); } }, r0);
+                if overflow { r14 |= 4; }
+                if let Err(error) = processor.memory_mut().map(|mut memory| memory.put_u64(8, result)) {
+                    save!(4);
+                    return Err(error);
+                }
+                // 5: Mov(Register(Byte(0)), MemoryRegister(Byte(3)))
+                if let Err(error) = processor.memory_mut().map(|mut memory| memory.put_u8(usize::from(r3 as u8), r0 as u8)) {
+                    save!(5);
+                    return Err(error);
+                }
+                // 6: Cmp(Register(QWord(0)), Register(QWord(1)))
+                let value: u64 = r0;
+                let comparator: u64 = r1;
+                r14 = (r14 & !3) | u64::from(value == comparator) | (u64::from(value > comparator) << 1);
+                // 7: Jnz(Value(2))
+                if r14 & 1 == 0 {
+                    counter = 3;
+                    continue;
+                }
+                counter = 8;
+            }
+            // Entry into the middle of a block.
+            4 => {
+                // 4: Add(Register(QWord(0)), Memory(QWord(8)), Memory(QWord(8)))
+                let (result, overflow) = u64::overflowing_add(match processor.memory().map(|memory| memory.get_u64(8)) { Ok(value) => value, Err(error) => { save!(4); return Err(error); } }, r0);
+                if overflow { r14 |= 4; }
+                if let Err(error) = processor.memory_mut().map(|mut memory| memory.put_u64(8, result)) {
+                    save!(4);
+                    return Err(error);
+                }
+                counter = 5;
+            }
+            // Entry into the middle of a block.
+            5 => {
+                // 5: Mov(Register(Byte(0)), MemoryRegister(Byte(3)))
+                if let Err(error) = processor.memory_mut().map(|mut memory| memory.put_u8(usize::from(r3 as u8), r0 as u8)) {
+                    save!(5);
+                    return Err(error);
+                }
+                counter = 6;
+            }
+            // Entry into the middle of a block.
+            6 => {
+                // 6: Cmp(Register(QWord(0)), Register(QWord(1)))
+                let value: u64 = r0;
+                let comparator: u64 = r1;
+                r14 = (r14 & !3) | u64::from(value == comparator) | (u64::from(value > comparator) << 1);
+                counter = 7;
+            }
+            // Entry into the middle of a block.
+            7 => {
+                // 7: Jnz(Value(2))
+                if r14 & 1 == 0 {
+                    counter = 3;
+                    continue;
+                }
+                counter = 8;
+            }
+            8 => {
+                // 8: Call(Value(1))
+                save!(8);
+                processor.call(1)?;
+                load!();
+                counter = processor.register(15)?.as_u64().wrapping_add(1);
+                continue;
+            }
+            9 => {
+                // 9: Mov(Register(DWord(2)), Memory(DWord(16)))
+                if let Err(error) = processor.memory_mut().map(|mut memory| memory.put_u32(16, r2 as u32)) {
+                    save!(9);
+                    return Err(error);
+                }
+                // 10: Add(Register(QWord(15)), Register(Word(2)), Register(Word(4)))
+                let (result, overflow) = u64::overflowing_add(u64::from(r2 as u16), 10);
+                if overflow { r14 |= 4; }
+                r4 = (r4 & !0xffff) | u64::from(result as u16);
+                counter = 11;
+            }
+            // Entry into the middle of a block.
+            10 => {
+                // 10: Add(Register(QWord(15)), Register(Word(2)), Register(Word(4)))
+                let (result, overflow) = u64::overflowing_add(u64::from(r2 as u16), 10);
+                if overflow { r14 |= 4; }
+                r4 = (r4 & !0xffff) | u64::from(result as u16);
+                counter = 11;
+            }
+            _ => break,
+        }
+    }
+
+    save!(counter);
+
+    Ok(())
+}
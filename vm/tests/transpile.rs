@@ -0,0 +1,99 @@
+//! Runs a transpiled program next to the interpreter and compares their final state.
+//!
+//! The transpiled program is checked in as `golden/transpile.rs`, set `BLESS=1` to regenerate it.
+
+use vm::assembler::Assembler;
+use vm::error::Error;
+use vm::processor::Processor;
+use vm::transpile::transpile;
+use vm::Vm;
+
+mod golden {
+    include!("golden/transpile.rs");
+}
+
+const GOLDEN: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/golden/transpile.rs");
+
+/// Sums the counters of a loop into memory, then doubles the last through a host call.
+fn program() -> Assembler {
+    Assembler::parse(
+        "mov 0, rq0\n\
+         mov 1000, rq1\n\
+         mov 24, rq3\n\
+         loop: add 1, rq0, rq0\n\
+         add rq0, mq8, mq8\n\
+         mov rb0, [rb3]\n\
+         cmp rq0, rq1\n\
+         jnz loop\n\
+         call 1\n\
+         mov rd2, md16\n\
+         add rq15, rw2, rw4",
+    )
+    .unwrap()
+}
+
+/// Host call 1, doubling rq0 into rq2.
+fn double(processor: &mut Processor) -> Result<(), Error> {
+    let value = processor.register(0)?.as_u64();
+
+    processor.register_mut(2)?.assign_u64(value * 2);
+
+    Ok(())
+}
+
+/// Runs the program from the given instruction, interpreted or transpiled, returning registers and memory.
+fn run(start: u64, transpiled: bool) -> (Vec<u64>, Vec<u64>) {
+    let mut vm = Vm::new();
+
+    vm.register_call(1, double).unwrap();
+    vm.load_instructions(program().compile()).unwrap();
+
+    let handle = vm.new_processor();
+    let processor = vm.processor_mut(handle).unwrap();
+
+    // Entering the loop halfway still ends it.
+    processor.register_mut(1).unwrap().assign_u64(1000);
+    processor.register_mut(3).unwrap().assign_u64(24);
+    processor.register_mut(15).unwrap().assign_u64(start);
+
+    if transpiled {
+        golden::run(processor).unwrap();
+    } else {
+        processor.start().unwrap();
+    }
+
+    let registers = (0..16)
+        .map(|index| processor.register(index).unwrap().as_u64())
+        .collect();
+    let memory = (0..4)
+        .map(|index| processor.memory().unwrap().get_u64(index * 8))
+        .collect();
+
+    (registers, memory)
+}
+
+#[test]
+pub fn transpile_golden() {
+    let source = transpile(program().instructions());
+
+    if std::env::var_os("BLESS").is_some() {
+        std::fs::write(GOLDEN, &source).unwrap();
+    }
+
+    assert_eq!(
+        source,
+        std::fs::read_to_string(GOLDEN).unwrap(),
+        "transpiled source changed, rerun with BLESS=1 to accept it"
+    );
+}
+
+#[test]
+pub fn transpile_matches_interpreter() {
+    let length = program().instructions().len() as u64;
+
+    for start in 0..=length {
+        assert_eq!(run(start, true), run(start, false), "start {start}");
+    }
+
+    assert_eq!(run(0, true).0[2], 2000);
+}